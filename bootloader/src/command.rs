@@ -0,0 +1,102 @@
+use std::io::{self, Write};
+
+use pi::info::BoardInfo;
+use pi::uart::{Uart, MiniUart};
+
+/// The bootloader's version, reported by `INFO`.
+pub const VERSION: &str = env!("CARGO_PKG_VERSION");
+
+/// The bytes opening a reply: whether the command was carried out.
+pub const ACK: u8 = 0x06;
+pub const NAK: u8 = 0x15;
+
+/// Command codes.
+mod code {
+    pub const INFO: u8 = b'i';
+    pub const ERASE: u8 = b'e';
+    pub const REBOOT: u8 = b'r';
+    pub const GO: u8 = b'g';
+}
+
+/// The longest payload a frame carries.
+pub const MAX_PAYLOAD: usize = 255;
+
+/// A command from the host.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum Command {
+    /// Report the bootloader's version and the board's details.
+    Info,
+    /// Zero `len` bytes of memory at `address`.
+    Erase { address: usize, len: usize },
+    /// Reset the board.
+    Reboot,
+    /// Jump to `address`.
+    Go(usize),
+}
+
+fn read_u32(bytes: &[u8]) -> usize {
+    bytes.iter().fold(0usize, |value, &byte| value << 8 | byte as usize)
+}
+
+fn checksum(bytes: &[u8]) -> u8 {
+    bytes.iter().fold(0u8, |sum, &byte| sum.wrapping_add(byte))
+}
+
+/// Reads a command frame from `uart`: the command's code, the length of its
+/// payload, the payload, and the low byte of the sum of all of those.
+/// Addresses and lengths in payloads are 4 big-endian bytes.
+///
+/// Returns `None` if the frame is cut short, fails its checksum, or isn't a
+/// command known with the payload it carries.
+pub fn read(uart: &mut MiniUart) -> Option<Command> {
+    let mut frame = [0u8; MAX_PAYLOAD + 2];
+    frame[0] = uart.read_byte_checked().ok()?;
+    frame[1] = uart.read_byte_checked().ok()?;
+
+    let len = frame[1] as usize + 2;
+    for byte in frame[2..len].iter_mut() {
+        *byte = uart.read_byte_checked().ok()?;
+    }
+
+    if uart.read_byte_checked().ok()? != checksum(&frame[..len]) {
+        return None;
+    }
+
+    let payload = &frame[2..len];
+    match frame[0] {
+        code::INFO if payload.is_empty() => Some(Command::Info),
+        code::ERASE if payload.len() == 8 => Some(Command::Erase {
+            address: read_u32(&payload[..4]),
+            len: read_u32(&payload[4..]),
+        }),
+        code::REBOOT if payload.is_empty() => Some(Command::Reboot),
+        code::GO if payload.len() == 4 => Some(Command::Go(read_u32(payload))),
+        _ => None
+    }
+}
+
+/// Writes a reply frame to `uart`: `ACK`, or `NAK` if `ok` is `false`, the
+/// length of `payload`, `payload`, and the low byte of the sum of all of
+/// those. Payloads longer than `MAX_PAYLOAD` are cut short.
+pub fn reply(uart: &mut MiniUart, ok: bool, payload: &[u8]) {
+    let payload = &payload[..::std::cmp::min(payload.len(), MAX_PAYLOAD)];
+    let head = [if ok { ACK } else { NAK }, payload.len() as u8];
+    uart.write_bytes(&head);
+    uart.write_bytes(payload);
+    uart.write_bytes(&[checksum(&head).wrapping_add(checksum(payload))]);
+}
+
+/// Writes the bootloader's version and the board's details, one
+/// `key: value` per line, to `buffer` and returns their length. Whatever
+/// doesn't fit is left out.
+pub fn info(buffer: &mut [u8]) -> usize {
+    let mut cursor = io::Cursor::new(buffer);
+    let _ = write!(cursor, "bootloader: {}\n", VERSION);
+    let _ = match BoardInfo::query() {
+        Ok(board) => write!(cursor, "model: {}\nrevision: {:#x}\nserial: {:016x}\nmemory: {:#x}\n",
+                            board.model(), board.revision, board.serial, board.arm_memory.size),
+        Err(e) => write!(cursor, "board: {:?}\n", e)
+    };
+
+    cursor.position() as usize
+}
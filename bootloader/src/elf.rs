@@ -0,0 +1,134 @@
+use std::ops::Range;
+use std::ptr;
+
+/// The identification and header values of the images accepted: 64-bit
+/// little-endian AArch64 executables.
+const MAGIC: [u8; 4] = [0x7F, b'E', b'L', b'F'];
+const CLASS_64: u8 = 2;
+const DATA_LITTLE_ENDIAN: u8 = 1;
+const TYPE_EXEC: u16 = 2;
+const MACHINE_AARCH64: u16 = 183;
+
+/// The program header type of segments to load.
+const PT_LOAD: u32 = 1;
+
+/// The sizes of the file header and of a program header.
+const HEADER_SIZE: usize = 64;
+const PROGRAM_HEADER_SIZE: usize = 56;
+
+/// An error loading an ELF image.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum ElfError {
+    /// The image isn't a 64-bit little-endian AArch64 executable.
+    Unsupported,
+    /// A header or segment runs past the end of the image.
+    Truncated,
+    /// A segment or the entry point lies outside the memory allowed.
+    BadAddress,
+}
+
+fn read_u16(bytes: &[u8], offset: usize) -> u16 {
+    bytes[offset] as u16 | (bytes[offset + 1] as u16) << 8
+}
+
+fn read_u32(bytes: &[u8], offset: usize) -> u32 {
+    read_u16(bytes, offset) as u32 | (read_u16(bytes, offset + 2) as u32) << 16
+}
+
+fn read_u64(bytes: &[u8], offset: usize) -> u64 {
+    read_u32(bytes, offset) as u64 | (read_u32(bytes, offset + 4) as u64) << 32
+}
+
+/// Returns `true` if `image` starts with the ELF magic number.
+pub fn is_elf(image: &[u8]) -> bool {
+    image.len() >= MAGIC.len() && image[..MAGIC.len()] == MAGIC
+}
+
+/// A `PT_LOAD` segment: `file_size` bytes at `offset` in the image, loaded
+/// at `address` and followed by zeroes up to `memory_size` bytes.
+struct Segment {
+    offset: usize,
+    address: usize,
+    file_size: usize,
+    memory_size: usize,
+}
+
+/// Returns the `PT_LOAD` segment described by the program header at
+/// `header`, if it is one, checking it against `image` and `allowed`.
+fn segment(image: &[u8],
+           header: &[u8],
+           allowed: &Range<usize>) -> Result<Option<Segment>, ElfError> {
+    if read_u32(header, 0) != PT_LOAD {
+        return Ok(None);
+    }
+
+    let segment = Segment {
+        offset: read_u64(header, 8) as usize,
+        address: read_u64(header, 24) as usize,
+        file_size: read_u64(header, 32) as usize,
+        memory_size: read_u64(header, 40) as usize,
+    };
+
+    let file_end = segment.offset.checked_add(segment.file_size).ok_or(ElfError::Truncated)?;
+    if file_end > image.len() {
+        return Err(ElfError::Truncated);
+    }
+
+    let end = segment.address.checked_add(segment.memory_size).ok_or(ElfError::BadAddress)?;
+    if segment.file_size > segment.memory_size || segment.address < allowed.start
+        || end > allowed.end {
+        return Err(ElfError::BadAddress);
+    }
+
+    Ok(Some(segment))
+}
+
+/// Copies each `PT_LOAD` segment of the ELF executable `image` to its
+/// physical address, zeroing the rest of its memory size for `.bss`, and
+/// returns the entry point. Every segment and the entry point must lie in
+/// `allowed`, which mustn't overlap `image`. Nothing is copied unless the
+/// whole image checks out.
+pub fn load(image: &[u8], allowed: Range<usize>) -> Result<usize, ElfError> {
+    if !is_elf(image) {
+        return Err(ElfError::Unsupported);
+    }
+
+    if image.len() < HEADER_SIZE {
+        return Err(ElfError::Truncated);
+    }
+
+    if image[4] != CLASS_64 || image[5] != DATA_LITTLE_ENDIAN
+        || read_u16(image, 16) != TYPE_EXEC || read_u16(image, 18) != MACHINE_AARCH64
+        || read_u16(image, 54) as usize != PROGRAM_HEADER_SIZE {
+        return Err(ElfError::Unsupported);
+    }
+
+    let entry = read_u64(image, 24) as usize;
+    let headers_start = read_u64(image, 32) as usize;
+    let headers_len = read_u16(image, 56) as usize * PROGRAM_HEADER_SIZE;
+    let headers = headers_start.checked_add(headers_len)
+        .and_then(|end| image.get(headers_start..end))
+        .ok_or(ElfError::Truncated)?;
+
+    if entry < allowed.start || entry >= allowed.end {
+        return Err(ElfError::BadAddress);
+    }
+
+    for header in headers.chunks(PROGRAM_HEADER_SIZE) {
+        segment(image, header, &allowed)?;
+    }
+
+    for header in headers.chunks(PROGRAM_HEADER_SIZE) {
+        if let Some(segment) = segment(image, header, &allowed)? {
+            unsafe {
+                let target = segment.address as *mut u8;
+                let source = &image[segment.offset..segment.offset + segment.file_size];
+                ptr::copy_nonoverlapping(source.as_ptr(), target, segment.file_size);
+                ptr::write_bytes(target.offset(segment.file_size as isize), 0,
+                                 segment.memory_size - segment.file_size);
+            }
+        }
+    }
+
+    Ok(entry)
+}
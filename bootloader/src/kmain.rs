@@ -1,16 +1,32 @@
 #![feature(asm, lang_items)]
 
-extern crate xmodem;
+extern crate core;
 extern crate pi;
 
 use std::fmt::Write;
 use std::io;
+use core::time::Duration;
+
+use pi::bootcfg::{self, BootConfig};
+use pi::emmc::Emmc;
+use pi::fat::{self, FatError};
+use pi::{power, resident};
+use pi::timer::{spin_sleep_ms, Instant};
+use pi::uart::{Uart, MiniUart, UartConfig, DataBits, Parity, StopBits, FlowControl};
+use pi::ymodem::{Header, Ymodem};
+
+use command::Command;
+use elf::ElfError;
 
 pub mod lang_items;
+pub mod elf;
+pub mod trailer;
+pub mod lz4;
+pub mod command;
 
 /// Start address of the binary to load and of the bootloader.
 const BINARY_START_ADDR: usize = 0x80000;
-const BOOTLOADER_START_ADDR: usize = 0x4000000;
+const BOOTLOADER_START_ADDR: usize = resident::ADDR;
 
 /// Pointer to where the loaded binary expects to be laoded.
 const BINARY_START: *mut u8 = BINARY_START_ADDR as *mut u8;
@@ -19,11 +35,132 @@ const BINARY_START: *mut u8 = BINARY_START_ADDR as *mut u8;
 const BOOTLOADER_START: *mut u8 = BOOTLOADER_START_ADDR as *mut u8;
 
 /// Assuming the size of the bootloader!
-const BOOTLOADER_SIZE: usize = 0x40000;
+const BOOTLOADER_SIZE: usize = resident::SIZE;
 
 /// Free space between the bootloader and the loaded binary's start address.
 const MAX_BINARY_SIZE: usize = BOOTLOADER_START_ADDR - BINARY_START_ADDR;
 
+/// Where images are received or read before they're loaded: clear of the
+/// bootloader, and of its stack below it.
+const STAGING_ADDR: usize = BOOTLOADER_START_ADDR + BOOTLOADER_SIZE;
+const STAGING: *mut u8 = STAGING_ADDR as *mut u8;
+
+/// The largest image that can be received, or decompressed.
+const MAX_IMAGE_SIZE: usize = 0x1000000;
+
+/// Where compressed images are decompressed to, just past the staging area.
+const UNPACKED_ADDR: usize = STAGING_ADDR + MAX_IMAGE_SIZE;
+const UNPACKED: *mut u8 = UNPACKED_ADDR as *mut u8;
+
+/// The most files a batch may carry: the binary to boot, and files loaded
+/// after it.
+const MAX_FILES: usize = 8;
+
+/// A file received: its header, and its offset from `STAGING` and length.
+type Received = Option<(Header, usize, usize)>;
+
+/// Line settings for the UART the binary is received over, at the BAUD rate
+/// `boot.cfg` sets. Set `flow_control` to `FlowControl::RtsCts` when GPIO 16/17
+/// are wired to the host's RTS/CTS to have the UART throttle the sender
+/// instead of dropping bytes.
+const UART_CONFIG: UartConfig = UartConfig {
+    baud: 115200,
+    data_bits: DataBits::Eight,
+    parity: Parity::None,
+    stop_bits: StopBits::One,
+    flow_control: FlowControl::None
+};
+
+/// The BAUD rates `boot.cfg` may set the UART to.
+const MIN_BAUD: u32 = 1200;
+const MAX_BAUD: u32 = 921600;
+
+/// The BAUD rates a host may ask to send the payload at.
+const FAST_BAUDS: [u32; 4] = [921600, 576000, 460800, 230400];
+
+/// The handshake offering a faster line, or commands. Before each wait for a
+/// transfer the bootloader sends `OFFER`. A host that wants a faster line
+/// answers with `REQUEST`, the BAUD rate as 4 big-endian bytes and the low
+/// byte of their sum, and switches once it reads `ACK`. A host with a
+/// command answers with `COMMAND` and a frame `command::read()` reads, and
+/// reads the reply `command::reply()` writes. Hosts that don't know the
+/// handshake ignore the offer like any other noise before the first `C`.
+mod handshake {
+    pub const OFFER: u8 = b'B';
+    pub const REQUEST: u8 = b'R';
+    pub const COMMAND: u8 = b'X';
+    pub const ACK: u8 = 0x06;
+    pub const NAK: u8 = 0x15;
+
+    /// How long the host has to answer the offer, in milliseconds.
+    pub const TIMEOUT_MS: u64 = 100;
+
+    /// How long the `ACK` is given to leave at the old rate before the
+    /// switch, or a reply before the board resets or jumps away, in
+    /// milliseconds.
+    pub const SWITCH_DELAY_MS: u64 = 2;
+}
+
+/// Reads the BAUD rate a host asked for after `REQUEST` on `uart` and
+/// returns it, if it's valid, once it's been acknowledged.
+fn negotiate_baud(uart: &mut MiniUart) -> Option<u32> {
+    let mut request = [0u8; 5];
+    for byte in request.iter_mut() {
+        *byte = uart.read_byte_checked().ok()?;
+    }
+
+    let baud = request[..4].iter().fold(0u32, |baud, &byte| baud << 8 | byte as u32);
+    let sum = request[..4].iter().fold(0u8, |sum, &byte| sum.wrapping_add(byte));
+    if sum != request[4] || !FAST_BAUDS.contains(&baud) {
+        uart.write_bytes(&[handshake::NAK]);
+        return None;
+    }
+
+    uart.write_bytes(&[handshake::ACK]);
+    spin_sleep_ms(handshake::SWITCH_DELAY_MS);
+    Some(baud)
+}
+
+/// Returns `true` if the `len` bytes at `address` lie where a host may erase
+/// or jump to: between `BINARY_START` and the bootloader, or in the staging
+/// and decompression areas.
+fn is_loadable(address: usize, len: usize) -> bool {
+    let end = match address.checked_add(len) {
+        Some(end) => end,
+        None => return false
+    };
+
+    (address >= BINARY_START_ADDR && end <= BOOTLOADER_START_ADDR)
+        || (address >= STAGING_ADDR && end <= UNPACKED_ADDR + MAX_IMAGE_SIZE)
+}
+
+/// Carries out `command`, if it's one `is_loadable()` allows, and replies
+/// on `uart`. Doesn't return after replying to `Reboot` or `Go`.
+fn run_command(uart: &mut MiniUart, command: Option<Command>) {
+    match command {
+        Some(Command::Info) => {
+            let mut text = [0u8; command::MAX_PAYLOAD];
+            let len = command::info(&mut text);
+            command::reply(uart, true, &text[..len]);
+        }
+        Some(Command::Erase { address, len }) if is_loadable(address, len) => {
+            unsafe { std::ptr::write_bytes(address as *mut u8, 0, len); }
+            command::reply(uart, true, &[]);
+        }
+        Some(Command::Reboot) => {
+            command::reply(uart, true, &[]);
+            spin_sleep_ms(handshake::SWITCH_DELAY_MS);
+            power::reboot()
+        }
+        Some(Command::Go(address)) if is_loadable(address, 1) => {
+            command::reply(uart, true, &[]);
+            spin_sleep_ms(handshake::SWITCH_DELAY_MS);
+            enter(address)
+        }
+        _ => command::reply(uart, false, &[])
+    }
+}
+
 /// Branches to the address `addr` unconditionally.
 fn jump_to(addr: *mut u8) -> ! {
     unsafe {
@@ -32,21 +169,209 @@ fn jump_to(addr: *mut u8) -> ! {
     }
 }
 
+/// Records the bootloader's copy of itself as intact, so the kernel can hand
+/// the board back to it, and jumps to `entry`.
+fn enter(entry: usize) -> ! {
+    unsafe { resident::mark(); }
+    jump_to(entry as *mut u8)
+}
+
+/// Receives a YMODEM batch into `output`. The first file is the binary to
+/// boot, and the rest are loaded after it at 8-byte aligned offsets, each
+/// recorded in `files` without its integrity trailer. The transfer is
+/// cancelled, so that the host sends it again, if a file fails its check.
+fn receive<R: io::Read + io::Write>(ymodem: &mut Ymodem<R>,
+                                    output: &mut [u8],
+                                    files: &mut [Received]) -> io::Result<()> {
+    let mut offset = 0;
+    for file in files.iter_mut() {
+        let header = match ymodem.next_file()? {
+            Some(header) => header,
+            None => return Ok(())
+        };
+
+        let start = std::cmp::min(offset, output.len());
+        let received = ymodem.receive_file(&mut output[start..])?;
+        let len = match trailer::verify(&output[start..start + received]) {
+            Ok(verified) => verified.map_or(received, |image| image.len()),
+            Err(_) => {
+                ymodem.cancel();
+                return Err(io::Error::new(io::ErrorKind::InvalidData,
+                                          "Image failed its integrity check."));
+            }
+        };
+
+        *file = Some((header, start, len));
+        offset = (start + len + 7) & !7;
+    }
+
+    match ymodem.next_file()? {
+        Some(_) => {
+            ymodem.cancel();
+            Err(io::Error::new(io::ErrorKind::Other, "Too many files."))
+        }
+        None => Ok(())
+    }
+}
+
+/// Loads `image`, an ELF executable or a raw binary, and returns its entry
+/// point. ELF segments are loaded at their physical addresses, which must be
+/// between `BINARY_START` and the bootloader, and raw binaries at
+/// `BINARY_START`.
+fn load(image: &[u8]) -> Result<usize, ElfError> {
+    if elf::is_elf(image) {
+        return elf::load(image, BINARY_START_ADDR..BOOTLOADER_START_ADDR);
+    }
+
+    if image.len() > MAX_BINARY_SIZE {
+        return Err(ElfError::BadAddress);
+    }
+
+    unsafe { std::ptr::copy_nonoverlapping(image.as_ptr(), BINARY_START, image.len()); }
+    Ok(BINARY_START_ADDR)
+}
+
+/// Loads `image`, decompressing it first if it's LZ4 compressed, and jumps
+/// to it, or reports on `console` why it couldn't and returns.
+fn boot_image(console: &mut MiniUart, image: &[u8]) {
+    let image = if lz4::is_lz4(image) {
+        let unpacked = unsafe { std::slice::from_raw_parts_mut(UNPACKED, MAX_IMAGE_SIZE) };
+        match lz4::decompress(image, unpacked) {
+            Ok(len) => &unpacked[..len],
+            Err(e) => return write!(console, "failed decompress: {:?}\n", e).unwrap()
+        }
+    } else {
+        image
+    };
+
+    match load(image) {
+        Ok(entry) => {
+            write!(console, "load complete").unwrap();
+            enter(entry)
+        }
+        Err(e) => write!(console, "failed load: {:?}\n", e).unwrap()
+    }
+}
+
+/// Loads the kernel `boot.cfg` names, and its initrd if it names one, from
+/// the SD card and jumps to the kernel, or reports on `console` why it
+/// couldn't and returns. The initrd is left 8-byte aligned after the kernel
+/// in the staging area.
+///
+/// The firmware boots `kernel8.img` too, so to boot that from the card the
+/// bootloader must be installed under another name, with `kernel=` in
+/// `config.txt` naming it.
+fn boot_from_sd(console: &mut MiniUart, config: &BootConfig) {
+    let staging = unsafe { std::slice::from_raw_parts_mut(STAGING, MAX_IMAGE_SIZE) };
+    let mut emmc = match Emmc::new() {
+        Ok(emmc) => emmc,
+        Err(e) => return write!(console, "failed SD boot: {:?}\n", FatError::from(e)).unwrap()
+    };
+
+    let len = match fat::load(&mut emmc, config.kernel, staging) {
+        Ok(len) => len,
+        Err(e) => return write!(console, "failed SD boot: {:?}\n", e).unwrap()
+    };
+
+    if let Some(initrd) = config.initrd {
+        let offset = std::cmp::min((len + 7) & !7, staging.len());
+        match fat::load(&mut emmc, initrd, &mut staging[offset..]) {
+            Ok(initrd_len) => write!(console, "loaded {}: {} bytes at {:#x}\n",
+                                     initrd, initrd_len, STAGING_ADDR + offset).unwrap(),
+            Err(e) => return write!(console, "failed SD boot: {}: {:?}\n", initrd, e).unwrap()
+        }
+    }
+
+    match trailer::verify(&staging[..len]) {
+        Ok(verified) => {
+            let image = verified.unwrap_or(&staging[..len]);
+            write!(console, "booting {} from SD: {} bytes\n", config.kernel, image.len()).unwrap();
+            boot_image(console, image);
+        }
+        Err(e) => write!(console, "failed SD boot: {:?}\n", e).unwrap()
+    }
+}
+
+/// Reads `boot.cfg` from the SD card into `buffer`, or returns the defaults
+/// and the reason if it can't be read.
+fn read_config(buffer: &mut [u8]) -> (BootConfig, Option<FatError>) {
+    let result = match Emmc::new() {
+        Ok(mut emmc) => BootConfig::read(&mut emmc, buffer),
+        Err(e) => Err(FatError::from(e))
+    };
+
+    match result {
+        Ok(config) => (config, None),
+        Err(e) => (BootConfig::default(), Some(e))
+    }
+}
+
 pub fn boot() -> ! {
-    let mut console = pi::uart::MiniUart::new();
+    let mut buffer = [0u8; bootcfg::MAX_SIZE];
+    let (config, error) = read_config(&mut buffer);
+
+    let mut uart_config = UART_CONFIG;
+    if config.baud >= MIN_BAUD && config.baud <= MAX_BAUD {
+        uart_config.baud = config.baud;
+    }
+
+    if let Some(e) = error {
+        let mut console = MiniUart::with_config(uart_config);
+        write!(&mut console, "failed reading {}: {:?}\n", bootcfg::FILE_NAME, e).unwrap();
+    }
+
+    // Once the SD card has been tried, only a host can boot the board.
+    let mut waiting_since = Instant::now();
+    let mut tried_sd = false;
+    // Carried between receivers so that every fourth request to start is a
+    // NAK, which checksum-only senders wait for.
+    let mut unanswered = 0;
 
     loop {
-        let output = unsafe { std::slice::from_raw_parts_mut(BINARY_START, MAX_BINARY_SIZE) };
-        let mut uart = pi::uart::MiniUart::new();
-        uart.set_read_timeout(750);
-
-        match xmodem::Xmodem::receive(uart, output) {
-            Ok(_) => {
-                write!(&mut console, "load complete").unwrap();
-                jump_to(BINARY_START)
+        let staging = unsafe { std::slice::from_raw_parts_mut(STAGING, MAX_IMAGE_SIZE) };
+        let mut uart = MiniUart::with_config(uart_config);
+        uart.set_read_timeout(Duration::from_millis(handshake::TIMEOUT_MS));
+        uart.write_bytes(&[handshake::OFFER]);
+        match uart.read_byte_checked() {
+            Ok(handshake::REQUEST) => if let Some(baud) = negotiate_baud(&mut uart) {
+                uart = MiniUart::with_config(UartConfig { baud, ..uart_config });
+            },
+            Ok(handshake::COMMAND) => {
+                let command = command::read(&mut uart);
+                run_command(&mut uart, command);
+                waiting_since = Instant::now();
+                continue;
+            }
+            _ => {  }
+        }
+
+        uart.set_read_timeout(Duration::from_millis(750));
+
+        let mut ymodem = Ymodem::with_unanswered(uart, unanswered);
+        let mut files: [Received; MAX_FILES] = [None; MAX_FILES];
+        let result = receive(&mut ymodem, staging, &mut files);
+        unanswered = ymodem.unanswered();
+
+        // Back to the console's rate, whatever the payload was sent at.
+        let mut console = MiniUart::with_config(uart_config);
+        match result {
+            Ok(()) => {
+                // Reported only now: the console shares the line.
+                for &(ref header, offset, len) in files.iter().flat_map(|file| file.iter()) {
+                    write!(&mut console, "received {}: {} bytes at {:#x}\n",
+                           header.name(), len, STAGING_ADDR + offset).unwrap();
+                }
+
+                if let Some((_, offset, len)) = files[0] {
+                    boot_image(&mut console, &staging[offset..offset + len]);
+                }
             },
             Err(e) => if e.kind() != io::ErrorKind::TimedOut {
                 write!(&mut console, "failed receive: {:?}\n", e).unwrap();
+                waiting_since = Instant::now();
+            } else if !tried_sd && waiting_since.elapsed().as_secs() >= config.timeout_secs {
+                tried_sd = true;
+                boot_from_sd(&mut console, &config);
             }
         }
     }
@@ -1,8 +1,14 @@
 #[lang = "eh_personality"] pub extern fn eh_personality() {}
 
 use std::panic::PanicInfo;
+use pi::led;
 
-#[panic_handler] #[no_mangle] pub extern fn panic_fmt(info: &PanicInfo) -> ! { loop{} }
+/// The number of ACT LED blinks that signal a bootloader panic.
+const PANIC_BLINKS: u32 = 2;
+
+#[panic_handler] #[no_mangle] pub extern fn panic_fmt(_info: &PanicInfo) -> ! {
+    loop { led::blink_code(PANIC_BLINKS) }
+}
 
 #[no_mangle]
 pub unsafe extern fn memcpy(dest: *mut u8, src: *const u8, n: usize) -> *mut u8 {
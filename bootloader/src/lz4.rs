@@ -0,0 +1,104 @@
+/// The magic number opening the LZ4 legacy frame format, written by
+/// `lz4 -l`: little-endian 0x184C2102.
+pub const MAGIC: [u8; 4] = [0x02, 0x21, 0x4C, 0x18];
+
+/// The fewest bytes a match copies; the token stores the length less this.
+const MIN_MATCH: usize = 4;
+
+/// An error decompressing an image.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum Lz4Error {
+    /// The compressed data is malformed or cut short.
+    Corrupt,
+    /// The decompressed image doesn't fit in the output buffer.
+    TooLarge,
+}
+
+fn read_u32(bytes: &[u8]) -> u32 {
+    bytes[0] as u32 | (bytes[1] as u32) << 8 | (bytes[2] as u32) << 16 | (bytes[3] as u32) << 24
+}
+
+/// Returns `true` if `image` is in the LZ4 legacy frame format.
+pub fn is_lz4(image: &[u8]) -> bool {
+    image.len() >= MAGIC.len() && image[..MAGIC.len()] == MAGIC
+}
+
+/// Reads the extra length bytes following a token's length field of 15
+/// from `input` at `*pos`, and returns the full length.
+fn read_length(input: &[u8], pos: &mut usize, length: usize) -> Result<usize, Lz4Error> {
+    let mut length = length;
+    if length == 15 {
+        loop {
+            let byte = *input.get(*pos).ok_or(Lz4Error::Corrupt)?;
+            *pos += 1;
+            length += byte as usize;
+            if byte != 255 {
+                break;
+            }
+        }
+    }
+
+    Ok(length)
+}
+
+/// Decompresses the LZ4 block `input` into `output` and returns the number
+/// of bytes written.
+pub fn decompress_block(input: &[u8], output: &mut [u8]) -> Result<usize, Lz4Error> {
+    let (mut pos, mut written) = (0, 0);
+    loop {
+        let token = *input.get(pos).ok_or(Lz4Error::Corrupt)?;
+        pos += 1;
+
+        let literals = read_length(input, &mut pos, (token >> 4) as usize)?;
+        let source = input.get(pos..pos + literals).ok_or(Lz4Error::Corrupt)?;
+        output.get_mut(written..written + literals)
+            .ok_or(Lz4Error::TooLarge)?
+            .copy_from_slice(source);
+        pos += literals;
+        written += literals;
+
+        // The last sequence has literals only.
+        if pos == input.len() {
+            return Ok(written);
+        }
+
+        let offset = input.get(pos..pos + 2).ok_or(Lz4Error::Corrupt)?;
+        let offset = offset[0] as usize | (offset[1] as usize) << 8;
+        pos += 2;
+        if offset == 0 || offset > written {
+            return Err(Lz4Error::Corrupt);
+        }
+
+        let length = read_length(input, &mut pos, (token & 0xF) as usize)? + MIN_MATCH;
+        if written + length > output.len() {
+            return Err(Lz4Error::TooLarge);
+        }
+
+        // Matches may overlap what they copy, so go a byte at a time.
+        for i in written..written + length {
+            output[i] = output[i - offset];
+        }
+
+        written += length;
+    }
+}
+
+/// Decompresses `image`, in the LZ4 legacy frame format, into `output` and
+/// returns the decompressed length.
+pub fn decompress(image: &[u8], output: &mut [u8]) -> Result<usize, Lz4Error> {
+    if !is_lz4(image) {
+        return Err(Lz4Error::Corrupt);
+    }
+
+    let (mut pos, mut written) = (MAGIC.len(), 0);
+    while pos < image.len() {
+        let size = image.get(pos..pos + 4).map(read_u32).ok_or(Lz4Error::Corrupt)? as usize;
+        pos += 4;
+
+        let block = image.get(pos..pos + size).ok_or(Lz4Error::Corrupt)?;
+        written += decompress_block(block, &mut output[written..])?;
+        pos += size;
+    }
+
+    Ok(written)
+}
@@ -0,0 +1,41 @@
+use pi::crc::crc32;
+
+/// The integrity trailer `ttywrite` appends to images: the image's length
+/// and CRC-32 as little-endian words, then `MAGIC`.
+pub const MAGIC: [u8; 4] = *b"KCRC";
+const TRAILER_SIZE: usize = 12;
+
+/// An image whose trailer doesn't match it.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum TrailerError {
+    /// The image is shorter or longer than the trailer says: bytes were
+    /// lost or added.
+    Length { expected: usize, actual: usize },
+    /// The image's bytes were corrupted.
+    Checksum { expected: u32, actual: u32 },
+}
+
+fn read_u32(bytes: &[u8]) -> u32 {
+    bytes[0] as u32 | (bytes[1] as u32) << 8 | (bytes[2] as u32) << 16 | (bytes[3] as u32) << 24
+}
+
+/// Checks `image` against its trailer and returns it without the trailer,
+/// or `None` if it has none.
+pub fn verify(image: &[u8]) -> Result<Option<&[u8]>, TrailerError> {
+    if image.len() < TRAILER_SIZE || image[image.len() - MAGIC.len()..] != MAGIC {
+        return Ok(None);
+    }
+
+    let (image, trailer) = image.split_at(image.len() - TRAILER_SIZE);
+    let expected = read_u32(&trailer[0..4]) as usize;
+    if expected != image.len() {
+        return Err(TrailerError::Length { expected, actual: image.len() });
+    }
+
+    let (expected, actual) = (read_u32(&trailer[4..8]), crc32(image));
+    if expected != actual {
+        return Err(TrailerError::Checksum { expected, actual });
+    }
+
+    Ok(Some(image))
+}
@@ -0,0 +1,150 @@
+use pi::gpio::{self, Edge, Gpio, Input};
+use pi::mcp2515::{CanError, Frame, Id, Mcp2515, Mcp2515Config, Mode, FILTERS, MASKS};
+use pi::spi::{ChipSelect, Spi};
+
+use mutex::Mutex;
+
+/// The pin the controller's INT output is wired to, as on most CAN HATs.
+const INT_PIN: u8 = 25;
+
+/// The controller's chip select.
+const CHIP_SELECT: ChipSelect = ChipSelect::Ce0;
+
+/// The controller's crystal frequency. Boards come with 8MHz or 16MHz ones.
+const OSCILLATOR_HZ: u32 = 8_000_000;
+
+/// The number of received frames held for `recv()`.
+const QUEUE_SIZE: usize = 32;
+
+/// The controller, and the frames the interrupt handler has taken from it.
+struct Bus {
+    controller: Mcp2515,
+    int: Gpio<Input>,
+    frames: [Frame; QUEUE_SIZE],
+    head: usize,
+    len: usize,
+    /// The number of frames dropped because the queue was full.
+    dropped: usize,
+}
+
+impl Bus {
+    /// Moves every frame waiting in the controller to the queue.
+    fn drain(&mut self) {
+        while let Some(frame) = self.controller.recv() {
+            if self.len == QUEUE_SIZE {
+                self.dropped += 1;
+                continue;
+            }
+
+            self.frames[(self.head + self.len) % QUEUE_SIZE] = frame;
+            self.len += 1;
+        }
+    }
+
+    fn pop(&mut self) -> Option<Frame> {
+        if self.len == 0 {
+            return None;
+        }
+
+        let frame = self.frames[self.head];
+        self.head = (self.head + 1) % QUEUE_SIZE;
+        self.len -= 1;
+        Some(frame)
+    }
+}
+
+static BUS: Mutex<Option<Bus>> = Mutex::new(None);
+
+/// Drains the controller when its INT pin falls. If the bus is locked, the
+/// frames stay in the controller until the holder calls `recv()`.
+fn on_interrupt(_pin: u8) {
+    if let Some(mut bus) = BUS.try_lock() {
+        if let Some(bus) = bus.as_mut() {
+            bus.drain();
+        }
+    }
+}
+
+/// Starts the MCP2515 on `SPI0` at `bitrate` bits per second in `mode`,
+/// accepting every frame, replacing any bus started before. Received frames
+/// are taken from the controller by the GPIO interrupt and queued for
+/// `recv()`.
+pub fn start(bitrate: u32, mode: Mode) -> Result<(), CanError> {
+    stop();
+
+    let config = Mcp2515Config { oscillator_hz: OSCILLATOR_HZ, bitrate, mode };
+    let controller = Mcp2515::new(Spi::new(), CHIP_SELECT, config)?;
+
+    let mut int = Gpio::new(INT_PIN).into_input_pullup();
+    int.enable_edge_detect(Edge::Falling);
+    gpio::claim(&[INT_PIN], "can");
+
+    let empty = Frame { id: Id::Standard(0), remote: false, len: 0, data: [0; 8] };
+    *BUS.lock() = Some(Bus {
+        controller,
+        int,
+        frames: [empty; QUEUE_SIZE],
+        head: 0,
+        len: 0,
+        dropped: 0,
+    });
+
+    gpio::register_edge_handler(INT_PIN, on_interrupt);
+    Ok(())
+}
+
+/// Stops the bus, if it was started, discarding queued frames.
+pub fn stop() {
+    if let Some(mut bus) = BUS.lock().take() {
+        gpio::unregister_edge_handler(INT_PIN);
+        bus.int.disable_edge_detect();
+        gpio::release_owned(&[INT_PIN], "can");
+    }
+}
+
+/// Returns the oldest received frame, if any. This method does not block.
+pub fn recv() -> Option<Frame> {
+    let mut bus = BUS.lock();
+    let bus = bus.as_mut()?;
+
+    // Picks up frames that arrived while the interrupt handler was locked
+    // out, which leave INT low without another edge.
+    bus.drain();
+    bus.pop()
+}
+
+/// Queues `frame` for sending. This method does not block.
+///
+/// # Errors
+///
+/// Returns `CanError::NotFound` if the bus isn't started, and
+/// `CanError::Busy` if the controller's TX buffers are full.
+pub fn send(frame: &Frame) -> Result<(), CanError> {
+    match BUS.lock().as_mut() {
+        Some(bus) => bus.controller.send(frame),
+        None => Err(CanError::NotFound)
+    }
+}
+
+/// Accepts only frames whose identifiers match `id` in every bit set in
+/// `mask`, and of `id`'s kind.
+pub fn set_filter(id: Id, mask: Id) -> Result<(), CanError> {
+    let mut bus = BUS.lock();
+    let bus = bus.as_mut().ok_or(CanError::NotFound)?;
+
+    for n in 0..MASKS {
+        bus.controller.set_mask(n, mask)?;
+    }
+
+    for n in 0..FILTERS {
+        bus.controller.set_filter(n, id)?;
+    }
+
+    Ok(())
+}
+
+/// Returns the number of frames dropped since the bus started because
+/// `recv()` wasn't called often enough.
+pub fn dropped() -> usize {
+    BUS.lock().as_ref().map_or(0, |bus| bus.dropped)
+}
@@ -1,78 +1,605 @@
 use std::io;
 use std::fmt;
+use core::time::Duration;
+use core::sync::atomic::{AtomicBool, Ordering};
 
-use pi::uart::MiniUart;
+use pi::uart::{self, Uart, MiniUart, WouldBlock, UartError, ErrorCounts};
+use pi::pl011::Pl011Uart;
+use pi::framebuffer::Framebuffer;
 
+use fbcon::FbCon;
+
+use irq;
 use mutex::Mutex;
+use pipe;
+use usb;
+
+/// Writes at least this long are handed to the DMA engine when the console is
+/// backed by the PL011.
+const DMA_MIN_LEN: usize = 128;
+
+/// Selects one of the devices a console can be routed to.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum Device {
+    /// The mini UART, `UART1`.
+    MiniUart,
+    /// The PL011 UART, `UART0`.
+    Pl011,
+    /// A text console on the HDMI framebuffer, reading input from a USB
+    /// keyboard and the mini UART.
+    Framebuffer
+}
+
+/// The device backing a console, chosen at boot.
+pub enum ConsoleUart {
+    /// The mini UART (`UART1`) with its receive FIQ enabled; the default.
+    Mini(MiniUart),
+    /// The full PL011 UART (`UART0`).
+    Pl011(Pl011Uart),
+    /// Output drawn on the framebuffer, with input typed on a USB keyboard
+    /// or received by the mini UART.
+    Framebuffer(FbCon, MiniUart)
+}
+
+impl ConsoleUart {
+    /// Initializes the UART selected by `device`.
+    pub fn new(device: Device) -> ConsoleUart {
+        match device {
+            Device::MiniUart => ConsoleUart::mini_uart(),
+            Device::Pl011 => ConsoleUart::pl011(),
+            Device::Framebuffer => ConsoleUart::framebuffer()
+        }
+    }
+
+    /// Initializes the mini UART and enables its receive interrupt as the FIQ,
+    /// so input isn't lost while IRQs are masked.
+    fn mini_uart_rx() -> MiniUart {
+        let mut uart = MiniUart::new();
+        irq::register_fiq(uart::handle_irq);
+        uart.enable_rx_fiq();
+        uart
+    }
+
+    /// Initializes the mini UART as in `mini_uart_rx()`.
+    pub fn mini_uart() -> ConsoleUart {
+        ConsoleUart::Mini(ConsoleUart::mini_uart_rx())
+    }
+
+    /// Initializes the PL011 UART.
+    pub fn pl011() -> ConsoleUart {
+        ConsoleUart::Pl011(Pl011Uart::new())
+    }
+
+    /// Allocates a 1920x1080 framebuffer and draws the console on it, with
+    /// input from a USB keyboard, if one is attached, and the mini UART.
+    /// Falls back to the mini UART alone if the firmware doesn't provide a
+    /// framebuffer.
+    pub fn framebuffer() -> ConsoleUart {
+        // Without USB the console still works from the mini UART.
+        let _ = usb::init();
+
+        match Framebuffer::new() {
+            Ok(framebuffer) => {
+                ConsoleUart::Framebuffer(FbCon::new(framebuffer), ConsoleUart::mini_uart_rx())
+            }
+            Err(_) => ConsoleUart::mini_uart()
+        }
+    }
+
+    /// Returns the selected UART, the one input is read from, as a trait
+    /// object.
+    fn uart(&mut self) -> &mut dyn Uart {
+        match *self {
+            ConsoleUart::Mini(ref mut uart) => uart,
+            ConsoleUart::Pl011(ref mut uart) => uart,
+            ConsoleUart::Framebuffer(_, ref mut uart) => uart
+        }
+    }
+}
+
+impl Default for ConsoleUart {
+    fn default() -> ConsoleUart {
+        ConsoleUart::mini_uart()
+    }
+}
+
+impl Uart for ConsoleUart {
+    fn set_read_timeout(&mut self, timeout: Duration) {
+        self.uart().set_read_timeout(timeout)
+    }
+
+    fn read_timeout(&self) -> Option<Duration> {
+        match *self {
+            ConsoleUart::Mini(ref uart) => uart.read_timeout(),
+            ConsoleUart::Pl011(ref uart) => uart.read_timeout(),
+            ConsoleUart::Framebuffer(_, ref uart) => uart.read_timeout()
+        }
+    }
+
+    fn try_write_byte(&mut self, byte: u8) -> Result<(), WouldBlock> {
+        if let ConsoleUart::Framebuffer(ref mut fbcon, _) = *self {
+            fbcon.write_byte(byte);
+            return Ok(());
+        }
+
+        self.uart().try_write_byte(byte)
+    }
+
+    fn has_byte(&self) -> bool {
+        match *self {
+            ConsoleUart::Mini(ref uart) => uart.has_byte(),
+            ConsoleUart::Pl011(ref uart) => uart.has_byte(),
+            ConsoleUart::Framebuffer(_, ref uart) => usb::has_key() || uart.has_byte()
+        }
+    }
+
+    fn try_read_byte(&mut self) -> Option<u8> {
+        if let ConsoleUart::Framebuffer(_, ref mut uart) = *self {
+            return usb::read_key().or_else(|| uart.try_read_byte());
+        }
+
+        self.uart().try_read_byte()
+    }
+
+    fn write_byte(&mut self, byte: u8) {
+        if let ConsoleUart::Framebuffer(ref mut fbcon, _) = *self {
+            return fbcon.write_byte(byte);
+        }
+
+        self.uart().write_byte(byte)
+    }
+
+    fn write_bytes(&mut self, bytes: &[u8]) {
+        if let ConsoleUart::Framebuffer(ref mut fbcon, _) = *self {
+            return fbcon.write_bytes(bytes);
+        }
+
+        if bytes.len() >= DMA_MIN_LEN {
+            if let ConsoleUart::Pl011(ref mut uart) = *self {
+                // Queue long writes for DMA so we only wait on earlier ones.
+                let mut remaining = bytes;
+                while !remaining.is_empty() {
+                    let queued = uart.write_dma(remaining);
+                    remaining = &remaining[queued..];
+                }
+
+                return;
+            }
+        }
+
+        self.uart().write_bytes(bytes)
+    }
+
+    fn read_byte(&mut self) -> u8 {
+        if let ConsoleUart::Framebuffer(..) = *self {
+            // The keyboard is polled, so there's no interrupt to sleep on.
+            loop {
+                if let Some(byte) = self.try_read_byte() {
+                    return byte;
+                }
+            }
+        }
+
+        self.uart().read_byte()
+    }
+
+    fn read_byte_checked(&mut self) -> Result<u8, UartError> {
+        if let ConsoleUart::Framebuffer(..) = *self {
+            self.wait_for_byte().map_err(|_| UartError::TimedOut)?;
+            return Ok(self.read_byte());
+        }
+
+        self.uart().read_byte_checked()
+    }
+
+    fn error_counts(&self) -> ErrorCounts {
+        match *self {
+            ConsoleUart::Mini(ref uart) => uart.error_counts(),
+            ConsoleUart::Pl011(ref uart) => uart.error_counts(),
+            ConsoleUart::Framebuffer(_, ref uart) => uart.error_counts()
+        }
+    }
+}
+
+/// The number of bytes of output a `Console` buffers before writing them to
+/// its device.
+const LINE_BUFFER_SIZE: usize = 256;
 
 /// A global singleton allowing read/write access to the console.
-pub struct Console {
-    inner: Option<MiniUart>
+///
+/// The console is backed by any `Uart`. The device is created with
+/// `U::default()` the first time the console is used unless one was installed
+/// earlier with `install()`.
+///
+/// Output is collected in a line buffer and written to the device when a
+/// newline is written, when the buffer fills, before the console reads, or
+/// when `flush()` is called.
+pub struct Console<U> {
+    inner: Option<U>,
+    buffer: [u8; LINE_BUFFER_SIZE],
+    buffered: usize
 }
 
-impl Console {
+impl<U> Console<U> {
     /// Creates a new instance of `Console`.
-    const fn new() -> Console {
-        Console { inner: None }
+    const fn new() -> Console<U> {
+        Console { inner: None, buffer: [0; LINE_BUFFER_SIZE], buffered: 0 }
     }
 
+    /// Makes `uart` the console's device, replacing the current one. Output
+    /// still buffered for the previous device is discarded.
+    pub fn install(&mut self, uart: U) {
+        self.inner = Some(uart);
+        self.buffered = 0;
+    }
+}
+
+impl<U: Uart + Default> Console<U> {
     /// Initializes the console if it's not already initialized.
     #[inline]
     fn initialize(&mut self) {
         if self.inner.is_none() {
-            self.inner = Some(pi::uart::MiniUart::new())
+            self.inner = Some(U::default())
         }
     }
 
-    /// Returns a mutable borrow to the inner `MiniUart`, initializing it as
+    /// Returns a mutable borrow to the inner device, initializing it as
     /// needed.
-    fn inner(&mut self) -> &mut MiniUart {
+    fn inner(&mut self) -> &mut U {
         self.initialize();
         self.inner.as_mut().unwrap()
     }
 
+    /// Appends `byte` to the line buffer, flushing if it's a newline or the
+    /// buffer is full.
+    fn buffer_byte(&mut self, byte: u8) {
+        if self.buffered == LINE_BUFFER_SIZE {
+            self.flush();
+        }
+
+        self.buffer[self.buffered] = byte;
+        self.buffered += 1;
+
+        if byte == b'\n' {
+            self.flush();
+        }
+    }
+
+    /// Writes all buffered output to the UART device.
+    pub fn flush(&mut self) {
+        if self.buffered == 0 {
+            return;
+        }
+
+        self.initialize();
+        if let Some(ref mut uart) = self.inner {
+            uart.write_bytes(&self.buffer[..self.buffered]);
+        }
+
+        self.buffered = 0;
+    }
+
     /// Reads a byte from the UART device, blocking until a byte is available.
     pub fn read_byte(&mut self) -> u8 {
+        self.flush();
         self.inner().read_byte()
     }
 
-    /// Writes the byte `byte` to the UART device.
+    /// Reads a byte from the UART device if one is available. Never blocks.
+    pub fn try_read_byte(&mut self) -> Option<u8> {
+        self.flush();
+        self.inner().try_read_byte()
+    }
+
+    /// Writes the byte `byte` to the console.
     pub fn write_byte(&mut self, byte: u8) {
-        self.inner().write_byte(byte)
+        self.buffer_byte(byte)
+    }
+
+    /// Sets how long reads through `io::Read` wait for a byte before they
+    /// fail with `TimedOut`.
+    pub fn set_read_timeout(&mut self, timeout: Duration) {
+        self.inner().set_read_timeout(timeout)
     }
 }
 
-impl io::Read for Console {
+impl<U: Uart + Default> io::Read for Console<U> {
     fn read(&mut self, buf: &mut [u8]) -> io::Result<usize> {
-        self.inner().read(buf)
+        self.flush();
+
+        let uart = self.inner();
+        match uart.wait_for_byte() {
+            Err(()) => Err(io::Error::new(io::ErrorKind::TimedOut, "Read timed out.")),
+            Ok(()) => {
+                let mut read = 0usize;
+                let mut iter = buf.iter_mut();
+
+                while let (Some(b), true) = (iter.next(), uart.has_byte()) {
+                    *b = uart.read_byte();
+                    read += 1;
+                }
+
+                Ok(read)
+            }
+        }
     }
 }
 
-impl io::Write for Console {
+impl<U: Uart + Default> io::Write for Console<U> {
     fn write(&mut self, buf: &[u8]) -> io::Result<usize> {
-        self.inner().write(buf)
+        for &byte in buf {
+            self.buffer_byte(byte);
+        }
+
+        Ok(buf.len())
     }
 
     fn flush(&mut self) -> io::Result<()> {
+        Console::flush(self);
+        Ok(())
+    }
+}
+
+impl<U: Uart + Default> fmt::Write for Console<U> {
+    fn write_str(&mut self, s: &str) -> fmt::Result {
+        for &byte in s.as_bytes() {
+            if byte == b'\n' {
+                // Must write a CR before a NL.
+                self.buffer_byte(b'\r');
+            }
+
+            self.buffer_byte(byte);
+        }
+
         Ok(())
     }
 }
 
-impl fmt::Write for Console {
+/// Global `Console` singleton carrying the shell's input and output.
+pub static CONSOLE: Mutex<Console<ConsoleUart>> = Mutex::new(Console::new());
+
+/// Global `Console` singleton carrying the kernel log when it has been routed
+/// away from the shell's device.
+pub static LOG: Mutex<Console<ConsoleUart>> = Mutex::new(Console::new());
+
+/// Whether the kernel log goes to `LOG` (`true`) or shares `CONSOLE`.
+static SEPARATE_LOG: AtomicBool = AtomicBool::new(false);
+
+/// Whether the kernel log's device is the PL011 (`true`) or uses the mini
+/// UART, as the framebuffer does for input.
+static LOG_ON_PL011: AtomicBool = AtomicBool::new(false);
+
+/// A function taking the shell's output in place of `CONSOLE`.
+pub type Redirect = fn(&[u8]);
+
+/// Where the shell's output goes.
+#[derive(Clone, Copy)]
+pub enum Sink {
+    /// `CONSOLE`.
+    Console,
+    /// A terminal written to through a function, with a CR before each NL
+    /// as `Console` writes.
+    Terminal(Redirect),
+    /// A pipe to another command, as `pipe::write()` takes.
+    Pipe(usize),
+}
+
+/// Where the shell's output goes.
+static SINK: Mutex<Sink> = Mutex::new(Sink::Console);
+
+/// Why `route()` couldn't split the channels as asked.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum RouteError {
+    /// The devices are the two UARTs, which are both brought out on GPIO
+    /// 14/15. Their alternate pins go to the Bluetooth module and the audio
+    /// jack, not the header, so they can't be used at once.
+    PinConflict,
+}
+
+/// Directs the kernel log (`kprint[ln]!`) to `log` and the shell's I/O
+/// (`print[ln]!` and reads from `CONSOLE`) to `shell`. Until this is called
+/// both share the default device, the mini UART.
+///
+/// This should be called once at boot, before anything is printed. When
+/// `log` and `shell` are the same device, both channels share `CONSOLE` so
+/// their output stays ordered. The log can be split from the shell between
+/// the framebuffer and the mini UART, but not between the two UARTs.
+///
+/// # Errors
+///
+/// Returns `PinConflict` if `log` and `shell` are different UARTs, in which
+/// case the log shares `shell` instead.
+pub fn route(log: Device, shell: Device) -> Result<(), RouteError> {
+    CONSOLE.lock().install(ConsoleUart::new(shell));
+
+    // Every device but the PL011 uses the mini UART.
+    if (log == Device::Pl011) != (shell == Device::Pl011) {
+        SEPARATE_LOG.store(false, Ordering::Release);
+        return Err(RouteError::PinConflict);
+    }
+
+    if log != shell {
+        LOG.lock().install(ConsoleUart::new(log));
+    }
+
+    SEPARATE_LOG.store(log != shell, Ordering::Release);
+    LOG_ON_PL011.store(log == Device::Pl011, Ordering::Release);
+    Ok(())
+}
+
+/// Writes `args` to the kernel log for a last message, as when the kernel
+/// panics, without waiting for the log's lock.
+///
+/// If the lock is free, the message follows the log's buffered output on its
+/// device. Otherwise, as when the code holding it panicked, the buffered
+/// output is lost and the message is written straight to the UART's
+/// registers; a log on the framebuffer then goes to the mini UART instead.
+pub fn write_last(args: fmt::Arguments) {
+    use std::fmt::Write;
+
+    let log = if SEPARATE_LOG.load(Ordering::Acquire) { &LOG } else { &CONSOLE };
+    if let Some(mut console) = log.try_lock() {
+        let _ = console.write_fmt(args);
+        console.flush();
+        return;
+    }
+
+    // Whoever holds the lock has initialized the log's device, and with it
+    // the UART.
+    let _ = if LOG_ON_PL011.load(Ordering::Acquire) {
+        let mut uart = unsafe { Pl011Uart::steal() };
+        uart.write_fmt(args)
+    } else {
+        let mut uart = unsafe { MiniUart::steal() };
+        uart.write_fmt(args)
+    };
+}
+
+/// Runs `f` on the locked `CONSOLE` for a binary transfer, such as a file
+/// sent over XMODEM, and returns what it does. The bytes received meanwhile
+/// aren't watched for `uart::REENTER_MAGIC`, which they may contain.
+pub fn binary_transfer<T, F: FnOnce(&mut Console<ConsoleUart>) -> T>(f: F) -> T {
+    uart::watch_for_reenter(false);
+    let result = f(&mut CONSOLE.lock());
+    uart::watch_for_reenter(true);
+    result
+}
+
+/// Sends the shell's output (`print[ln]!`) to `sink`. The kernel log is
+/// unaffected.
+pub fn redirect_shell(sink: Sink) {
+    *SINK.lock() = sink;
+}
+
+/// Returns where the shell's output is going.
+pub fn shell_sink() -> Sink {
+    *SINK.lock()
+}
+
+/// Whether output may be colored with ANSI escapes. Off for dumb terminals,
+/// which would show the escapes as they are.
+static COLOR: AtomicBool = AtomicBool::new(true);
+
+/// Turns coloring of the kernel log and the shell's output on or off.
+pub fn set_color(on: bool) {
+    COLOR.store(on, Ordering::Relaxed);
+}
+
+/// Returns `true` if coloring is on.
+pub fn color() -> bool {
+    COLOR.load(Ordering::Relaxed)
+}
+
+/// Returns `true` if the shell's output may be colored: coloring is on and
+/// the output goes to a terminal, not through a pipe to another command.
+pub fn shell_color() -> bool {
+    match *SINK.lock() {
+        Sink::Pipe(_) => false,
+        Sink::Console | Sink::Terminal(_) => color()
+    }
+}
+
+/// An ANSI foreground color.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum Color {
+    Red = 31,
+    Green = 32,
+    Yellow = 33,
+    Blue = 34,
+    Magenta = 35,
+    Cyan = 36,
+}
+
+impl Color {
+    /// Returns `value`, to be written in this color if `enabled` is `true`
+    /// and as it is otherwise.
+    pub fn paint<T: fmt::Display>(self, value: T, enabled: bool) -> Painted<T> {
+        Painted { color: self, value, enabled }
+    }
+}
+
+/// A value written in a color, as returned by `Color::paint()`.
+pub struct Painted<T> {
+    color: Color,
+    value: T,
+    enabled: bool,
+}
+
+impl<T: fmt::Display> fmt::Display for Painted<T> {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        if !self.enabled {
+            return self.value.fmt(f);
+        }
+
+        write!(f, "\x1b[{}m", self.color as u8)?;
+        self.value.fmt(f)?;
+        f.write_str("\x1b[0m")
+    }
+}
+
+/// Writes to a `Redirect`, with a CR before each NL as `Console` does.
+struct Redirected(Redirect);
+
+impl fmt::Write for Redirected {
     fn write_str(&mut self, s: &str) -> fmt::Result {
-        self.inner().write_str(s)
+        let mut lines = s.split('\n');
+        if let Some(line) = lines.next() {
+            (self.0)(line.as_bytes());
+        }
+
+        for line in lines {
+            (self.0)(b"\r\n");
+            (self.0)(line.as_bytes());
+        }
+
+        Ok(())
     }
 }
 
-/// Global `Console` singleton.
-pub static CONSOLE: Mutex<Console> = Mutex::new(Console::new());
+/// Writes to a pipe as it is.
+struct Piped(usize);
+
+impl fmt::Write for Piped {
+    fn write_str(&mut self, s: &str) -> fmt::Result {
+        pipe::write(self.0, s.as_bytes());
+        Ok(())
+    }
+}
 
 /// Internal function called by the `kprint[ln]!` macros.
 #[doc(hidden)]
 pub fn _print(args: fmt::Arguments) {
     use std::fmt::Write;
-    let mut console = CONSOLE.lock();
+    let mut console = if SEPARATE_LOG.load(Ordering::Acquire) {
+        LOG.lock()
+    } else {
+        CONSOLE.lock()
+    };
+
     console.write_fmt(args).unwrap();
+    console.flush();
+}
+
+/// Internal function called by the `kerrorln!` macro.
+#[doc(hidden)]
+pub fn _print_error(args: fmt::Arguments) {
+    _print(format_args!("{}\n", Color::Red.paint(args, color())));
+}
+
+/// Internal function called by the `print[ln]!` macros.
+#[doc(hidden)]
+pub fn _print_shell(args: fmt::Arguments) {
+    use std::fmt::Write;
+    let sink = *SINK.lock();
+    match sink {
+        Sink::Console => {
+            let mut console = CONSOLE.lock();
+            console.write_fmt(args).unwrap();
+            console.flush();
+        }
+        Sink::Terminal(redirect) => Redirected(redirect).write_fmt(args).unwrap(),
+        Sink::Pipe(pipe) => Piped(pipe).write_fmt(args).unwrap()
+    }
 }
 
 /// Like `println!`, but for kernel-space.
@@ -86,3 +613,20 @@ pub macro kprintln {
 pub macro kprint($($arg:tt)*) {
     _print(format_args!($($arg)*))
 }
+
+/// Like `kprintln!`, for failures: the line is red if coloring is on.
+pub macro kerrorln($($arg:tt)*) {
+    _print_error(format_args!($($arg)*))
+}
+
+/// Like `println!`, writing to the shell's console.
+pub macro println {
+    () => (print!("\n")),
+    ($fmt:expr) => (print!(concat!($fmt, "\n"))),
+    ($fmt:expr, $($arg:tt)*) => (print!(concat!($fmt, "\n"), $($arg)*))
+}
+
+/// Like `print!`, writing to the shell's console.
+pub macro print($($arg:tt)*) {
+    _print_shell(format_args!($($arg)*))
+}
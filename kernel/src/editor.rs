@@ -0,0 +1,347 @@
+use std::fmt;
+use std::io::{self, Write};
+
+/// The size of the terminal the editor and the pager draw on: rows of text,
+/// then a status line.
+pub const ROWS: usize = 24;
+pub const COLS: usize = 80;
+const TEXT_ROWS: usize = ROWS - 1;
+
+/// Keys the editor handles other than printable characters.
+mod key {
+    pub const CTRL_S: u8 = 0x13;
+    pub const CTRL_X: u8 = 0x18;
+    pub const BACKSPACE: u8 = 0x08;
+    /// What most terminals send for Backspace.
+    pub const DEL: u8 = 0x7F;
+    pub const ESC: u8 = 0x1B;
+}
+
+/// What the caller of `Editor::input()` is asked to do.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum Action {
+    /// Nothing but redraw the editor.
+    None,
+    /// Save the text, then call `saved()`, or `set_message()` with why it
+    /// wasn't.
+    Save,
+    /// Stop editing.
+    Quit,
+}
+
+/// How far through an ANSI escape sequence the input is.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+enum Escape {
+    None,
+    /// After `ESC`.
+    Start,
+    /// After `ESC [` or `ESC O`, with the numeric parameter so far.
+    Csi(usize)
+}
+
+/// A full-screen text editor for a `ROWS` by `COLS` ANSI terminal, editing
+/// text held in a buffer it's given.
+///
+/// Printable characters and Enter are inserted at the cursor, Backspace and
+/// Delete remove the character before and under it, and the arrow keys,
+/// Home and End move it. Ctrl-S saves and Ctrl-X quits, twice in a row if
+/// there are unsaved changes. Lines wider than the terminal scroll
+/// sideways. Tabs show as a space, and bytes outside printable ASCII as
+/// `?`.
+pub struct Editor<'a> {
+    text: &'a mut [u8],
+    len: usize,
+    cursor: usize,
+    /// The column Up and Down keep the cursor at, on lines long enough.
+    column: usize,
+    /// The first line and column shown.
+    top: usize,
+    left: usize,
+    escape: Escape,
+    /// Whether the last input was CR, so an LF after it is ignored.
+    after_cr: bool,
+    modified: bool,
+    /// Whether Ctrl-X was just pressed with unsaved changes.
+    quitting: bool,
+    /// Whether every line shown must be redrawn, rather than the cursor's.
+    stale: bool,
+    message: [u8; COLS],
+    message_len: usize,
+}
+
+impl<'a> Editor<'a> {
+    /// Returns an editor for the first `len` bytes of `text`. The text can
+    /// grow to fill the rest of it.
+    pub fn new(text: &'a mut [u8], len: usize) -> Editor<'a> {
+        Editor {
+            text,
+            len,
+            cursor: 0,
+            column: 0,
+            top: 0,
+            left: 0,
+            escape: Escape::None,
+            after_cr: false,
+            modified: false,
+            quitting: false,
+            stale: true,
+            message: [0; COLS],
+            message_len: 0,
+        }
+    }
+
+    /// Returns the text as edited so far.
+    pub fn text(&self) -> &[u8] {
+        &self.text[..self.len]
+    }
+
+    /// Notes that the text was saved, so there are no unsaved changes.
+    pub fn saved(&mut self) {
+        let len = self.len;
+        self.modified = false;
+        self.set_message(format_args!("saved {} bytes", len));
+    }
+
+    /// Shows `message` on the status line until the next key. Whatever
+    /// doesn't fit is left out.
+    pub fn set_message(&mut self, message: fmt::Arguments) {
+        let mut cursor = io::Cursor::new(&mut self.message[..]);
+        let _ = cursor.write_fmt(message);
+        self.message_len = cursor.position() as usize;
+    }
+
+    /// Returns the offset of the start of the line `pos` is on.
+    fn line_start(&self, pos: usize) -> usize {
+        self.text[..pos].iter().rposition(|&byte| byte == b'\n').map_or(0, |nl| nl + 1)
+    }
+
+    /// Returns the offset of the end of the line `pos` is on, before its NL.
+    fn line_end(&self, pos: usize) -> usize {
+        self.text[pos..self.len].iter().position(|&byte| byte == b'\n')
+            .map_or(self.len, |nl| pos + nl)
+    }
+
+    /// Returns the offset of the start of line `row`, counted from 0, or
+    /// `None` if the text has fewer lines.
+    fn row_start(&self, row: usize) -> Option<usize> {
+        let mut start = 0;
+        for _ in 0..row {
+            start = self.line_end(start) + 1;
+            if start > self.len {
+                return None;
+            }
+        }
+
+        Some(start)
+    }
+
+    /// Moves the cursor to `pos`, and the column Up and Down keep to its.
+    fn go_to(&mut self, pos: usize) {
+        self.cursor = pos;
+        self.column = pos - self.line_start(pos);
+    }
+
+    /// Moves the cursor to the line starting at `start`, at the column Up
+    /// and Down keep to, or the end of the line if it's shorter.
+    fn go_to_line(&mut self, start: usize) {
+        let end = self.line_end(start);
+        self.cursor = ::std::cmp::min(start + self.column, end);
+    }
+
+    /// Inserts `byte` at the cursor and moves the cursor past it.
+    fn insert(&mut self, byte: u8) {
+        if self.len == self.text.len() {
+            return self.set_message(format_args!("file too large"));
+        }
+
+        for i in (self.cursor..self.len).rev() {
+            self.text[i + 1] = self.text[i];
+        }
+
+        self.text[self.cursor] = byte;
+        self.len += 1;
+        self.modified = true;
+        self.stale = self.stale || byte == b'\n';
+        let cursor = self.cursor + 1;
+        self.go_to(cursor);
+    }
+
+    /// Removes the byte at `at`, before the end of the text, moving the
+    /// cursor back if it was past it.
+    fn remove(&mut self, at: usize) {
+        self.stale = self.stale || self.text[at] == b'\n';
+        for i in at..self.len - 1 {
+            self.text[i] = self.text[i + 1];
+        }
+
+        self.len -= 1;
+        self.modified = true;
+        let cursor = if self.cursor > at { self.cursor - 1 } else { self.cursor };
+        self.go_to(cursor);
+    }
+
+    /// Handles the escape sequence ending in `control` with the numeric
+    /// parameter `param`.
+    fn control(&mut self, control: u8, param: usize) {
+        let start = self.line_start(self.cursor);
+        match (control, param) {
+            (b'A', _) if start > 0 => {
+                let previous = self.line_start(start - 1);
+                self.go_to_line(previous);
+            }
+            (b'B', _) if self.line_end(self.cursor) < self.len => {
+                let next = self.line_end(self.cursor) + 1;
+                self.go_to_line(next);
+            }
+            (b'C', _) if self.cursor < self.len => {
+                let cursor = self.cursor + 1;
+                self.go_to(cursor);
+            }
+            (b'D', _) if self.cursor > 0 => {
+                let cursor = self.cursor - 1;
+                self.go_to(cursor);
+            }
+            (b'H', _) | (b'~', 1) | (b'~', 7) => self.go_to(start),
+            (b'F', _) | (b'~', 4) | (b'~', 8) => {
+                let end = self.line_end(self.cursor);
+                self.go_to(end);
+            }
+            (b'~', 3) if self.cursor < self.len => {
+                let cursor = self.cursor;
+                self.remove(cursor);
+            }
+            _ => {  }
+        }
+    }
+
+    /// Handles the byte `input` typed on the terminal, and returns what the
+    /// caller should do next.
+    pub fn input(&mut self, input: u8) -> Action {
+        let after_cr = self.after_cr;
+        self.after_cr = input == b'\r';
+        match self.escape {
+            Escape::None => {  }
+            Escape::Start => {
+                self.escape = match input {
+                    b'[' | b'O' => Escape::Csi(0),
+                    _ => Escape::None
+                };
+                return Action::None;
+            }
+            Escape::Csi(param) => {
+                self.escape = match input {
+                    b'0'...b'9' => {
+                        let digit = (input - b'0') as usize;
+                        Escape::Csi(param.saturating_mul(10).saturating_add(digit))
+                    }
+                    b';' => Escape::Csi(param),
+                    _ => {
+                        self.control(input, param);
+                        Escape::None
+                    }
+                };
+                return Action::None;
+            }
+        }
+
+        let quitting = self.quitting;
+        self.quitting = false;
+        self.message_len = 0;
+        match input {
+            key::CTRL_S => return Action::Save,
+            key::CTRL_X if !self.modified || quitting => return Action::Quit,
+            key::CTRL_X => {
+                self.quitting = true;
+                self.set_message(format_args!("unsaved changes: ^X again to quit anyway"));
+            }
+            key::ESC => self.escape = Escape::Start,
+            key::BACKSPACE | key::DEL if self.cursor > 0 => {
+                let cursor = self.cursor;
+                self.remove(cursor - 1);
+            }
+            b'\r' => self.insert(b'\n'),
+            b'\n' if after_cr => {  }
+            b'\n' | b'\t' | b' '...b'~' => self.insert(input),
+            _ => {  }
+        }
+
+        Action::None
+    }
+
+    /// Writes the part of the line starting at `start` that's in view.
+    fn draw_line<W: Write>(&self, out: &mut W, start: usize) -> io::Result<()> {
+        let end = self.line_end(start);
+        let mut line = [0u8; COLS];
+        let shown = &self.text[::std::cmp::min(start + self.left, end)..end];
+        let len = ::std::cmp::min(shown.len(), COLS);
+        for (byte, &shown) in line.iter_mut().zip(shown) {
+            *byte = match shown {
+                b'\t' => b' ',
+                b' '...b'~' => shown,
+                _ => b'?'
+            };
+        }
+
+        out.write_all(&line[..len])?;
+        out.write_all(b"\x1b[K")
+    }
+
+    /// Draws what's changed on the terminal, named `name` on the status
+    /// line, scrolling the text to keep the cursor in view.
+    pub fn draw<W: Write>(&mut self, out: &mut W, name: &str) -> io::Result<()> {
+        let start = self.line_start(self.cursor);
+        let row = self.text[..start].iter().filter(|&&byte| byte == b'\n').count();
+        let column = self.cursor - start;
+
+        let (top, left) = (self.top, self.left);
+        if row < self.top {
+            self.top = row;
+        } else if row >= self.top + TEXT_ROWS {
+            self.top = row + 1 - TEXT_ROWS;
+        }
+
+        if column < self.left {
+            self.left = column;
+        } else if column >= self.left + COLS {
+            self.left = column + 1 - COLS;
+        }
+
+        if self.stale || (top, left) != (self.top, self.left) {
+            out.write_all(b"\x1b[H")?;
+            let mut line = self.row_start(self.top);
+            for _ in 0..TEXT_ROWS {
+                match line {
+                    Some(start) => {
+                        self.draw_line(out, start)?;
+                        let next = self.line_end(start) + 1;
+                        line = if next <= self.len { Some(next) } else { None };
+                    }
+                    None => out.write_all(b"~\x1b[K")?
+                }
+
+                out.write_all(b"\r\n")?;
+            }
+
+            self.stale = false;
+        } else {
+            write!(out, "\x1b[{};1H", row - self.top + 1)?;
+            self.draw_line(out, start)?;
+        }
+
+        // The status line stops short of the last column, so the terminal
+        // doesn't wrap and scroll.
+        let mut status = [b' '; COLS - 1];
+        {
+            let mut cursor = io::Cursor::new(&mut status[..]);
+            let _ = write!(cursor, " {}{}  line {} col {}  ^S save  ^X quit  ",
+                           name, if self.modified { " [modified]" } else { "" },
+                           row + 1, column + 1);
+            let _ = cursor.write_all(&self.message[..self.message_len]);
+        }
+
+        write!(out, "\x1b[{};1H\x1b[7m", ROWS)?;
+        out.write_all(&status)?;
+        write!(out, "\x1b[0m\x1b[{};{}H", row - self.top + 1, column - self.left + 1)?;
+        out.flush()
+    }
+}
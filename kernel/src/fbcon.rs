@@ -0,0 +1,335 @@
+use core::ptr;
+
+use pi::font::{Font, GLYPH_WIDTH};
+use pi::framebuffer::Framebuffer;
+
+/// The console font: an 8x8 PSF1 font covering printable ASCII.
+pub static FONT: &'static [u8] = include_bytes!("../ext/font.psf");
+
+/// The number of columns between tab stops.
+const TAB_WIDTH: usize = 8;
+
+/// The ANSI colors 0-7 (black, red, green, yellow, blue, magenta, cyan and
+/// white) as `0xAARRGGBB` pixels.
+const PALETTE: [u32; 8] = [
+    0xFF00_0000, 0xFFAA_0000, 0xFF00_AA00, 0xFFAA_5500,
+    0xFF00_00AA, 0xFFAA_00AA, 0xFF00_AAAA, 0xFFAA_AAAA,
+];
+
+/// The bright variants of `PALETTE`, used while bold is set.
+const BRIGHT_PALETTE: [u32; 8] = [
+    0xFF55_5555, 0xFFFF_5555, 0xFF55_FF55, 0xFFFF_FF55,
+    0xFF55_55FF, 0xFFFF_55FF, 0xFF55_FFFF, 0xFFFF_FFFF,
+];
+
+/// The default foreground and background palette indices.
+const DEFAULT_FG: usize = 7;
+const DEFAULT_BG: usize = 0;
+
+/// The most numeric parameters kept from a control sequence. Later ones are
+/// ignored.
+const MAX_PARAMS: usize = 4;
+
+/// Where `FbCon` is in parsing an escape sequence.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+enum State {
+    Normal,
+    /// An `ESC` was written.
+    Escape,
+    /// An `ESC [` was written; parameters follow.
+    Csi,
+}
+
+/// A text console drawn onto a framebuffer.
+///
+/// Bytes are drawn as glyphs at the cursor, which advances, wraps at the
+/// right edge and scrolls the screen at the bottom. `\r`, `\n`, `\t` and
+/// backspace move the cursor, and the following ANSI control sequences are
+/// understood; others are ignored:
+///
+///   * `ESC [ n A/B/C/D`: move the cursor up, down, right or left `n` cells
+///   * `ESC [ row ; col H`: move the cursor to the 1-based `row`, `col`
+///   * `ESC [ n J`: clear below the cursor (0) or the whole screen (2)
+///   * `ESC [ K`: clear to the end of the line
+///   * `ESC [ ... m`: reset (0), bold (1), foreground (30-37, 39) and
+///     background (40-47, 49) colors
+pub struct FbCon {
+    framebuffer: Framebuffer,
+    font: Font,
+    /// The distance between pixel rows, in pixels.
+    stride: usize,
+    cols: usize,
+    rows: usize,
+    col: usize,
+    row: usize,
+    fg: usize,
+    bg: usize,
+    bold: bool,
+    state: State,
+    params: [usize; MAX_PARAMS],
+    param_count: usize,
+    cursor_shown: bool,
+}
+
+impl FbCon {
+    /// Returns a console drawing onto `framebuffer`, which is cleared.
+    pub fn new(framebuffer: Framebuffer) -> FbCon {
+        let font = Font::psf1(FONT);
+        let cols = framebuffer.width() as usize / GLYPH_WIDTH;
+        let rows = framebuffer.height() as usize / font.height();
+        let stride = framebuffer.pitch() as usize / 4;
+
+        let mut console = FbCon {
+            framebuffer, font, stride, cols, rows,
+            col: 0,
+            row: 0,
+            fg: DEFAULT_FG,
+            bg: DEFAULT_BG,
+            bold: false,
+            state: State::Normal,
+            params: [0; MAX_PARAMS],
+            param_count: 0,
+            cursor_shown: false,
+        };
+
+        console.clear();
+        console.toggle_cursor();
+        console
+    }
+
+    /// Returns the number of columns of text.
+    pub fn cols(&self) -> usize {
+        self.cols
+    }
+
+    /// Returns the number of rows of text.
+    pub fn rows(&self) -> usize {
+        self.rows
+    }
+
+    /// Clears the screen and moves the cursor to the top left.
+    pub fn clear(&mut self) {
+        let rows = self.rows;
+        self.clear_rows(0, rows);
+        self.col = 0;
+        self.row = 0;
+    }
+
+    /// Writes `byte`, interpreting control characters and escape sequences.
+    pub fn write_byte(&mut self, byte: u8) {
+        self.write_bytes(&[byte])
+    }
+
+    /// Writes every byte in `bytes`. The cursor is only redrawn once, after
+    /// the last byte.
+    pub fn write_bytes(&mut self, bytes: &[u8]) {
+        if self.cursor_shown {
+            self.toggle_cursor();
+        }
+
+        for &byte in bytes {
+            match self.state {
+                State::Normal => self.write_normal(byte),
+                State::Escape => {
+                    self.state = if byte == b'[' { State::Csi } else { State::Normal };
+                    self.params = [0; MAX_PARAMS];
+                    self.param_count = 0;
+                }
+                State::Csi => self.write_csi(byte),
+            }
+        }
+
+        self.toggle_cursor();
+    }
+
+    /// Handles `byte` outside of an escape sequence.
+    fn write_normal(&mut self, byte: u8) {
+        match byte {
+            b'\r' => self.col = 0,
+            b'\n' => self.line_feed(),
+            b'\t' => {
+                self.col = ::core::cmp::min((self.col / TAB_WIDTH + 1) * TAB_WIDTH, self.cols - 1);
+            }
+            0x08 => self.col = self.col.saturating_sub(1),
+            0x1B => self.state = State::Escape,
+            0x20...0x7E => {
+                if self.col == self.cols {
+                    self.col = 0;
+                    self.line_feed();
+                }
+
+                let (col, row) = (self.col, self.row);
+                self.draw_glyph(col, row, byte);
+                self.col += 1;
+            }
+            _ => {  }
+        }
+    }
+
+    /// Handles `byte` inside a control sequence.
+    fn write_csi(&mut self, byte: u8) {
+        match byte {
+            b'0'...b'9' => {
+                if self.param_count == 0 {
+                    self.param_count = 1;
+                }
+
+                if let Some(param) = self.params.get_mut(self.param_count - 1) {
+                    *param = param.saturating_mul(10).saturating_add((byte - b'0') as usize);
+                }
+                return;
+            }
+            b';' => {
+                self.param_count = ::core::cmp::max(self.param_count, 1) + 1;
+                return;
+            }
+            b'A' => self.row = self.row.saturating_sub(self.param_or_one(0)),
+            b'B' => self.row = ::core::cmp::min(self.row + self.param_or_one(0), self.rows - 1),
+            b'C' => self.col = ::core::cmp::min(self.col + self.param_or_one(0), self.cols - 1),
+            b'D' => self.col = ::core::cmp::min(self.col, self.cols - 1)
+                .saturating_sub(self.param_or_one(0)),
+            b'H' | b'f' => {
+                self.row = ::core::cmp::min(self.param_or_one(0), self.rows) - 1;
+                self.col = ::core::cmp::min(self.param_or_one(1), self.cols) - 1;
+            }
+            b'J' => {
+                let (col, row, cols, rows) = (self.col, self.row, self.cols, self.rows);
+                match self.params[0] {
+                    0 => {
+                        self.clear_cells(row, col, cols);
+                        self.clear_rows(row + 1, rows);
+                    }
+                    2 => self.clear_rows(0, rows),
+                    _ => {  }
+                }
+            }
+            b'K' => {
+                let (col, row, cols) = (self.col, self.row, self.cols);
+                self.clear_cells(row, col, cols);
+            }
+            b'm' => self.select_graphic_rendition(),
+            _ => {  }
+        }
+
+        self.state = State::Normal;
+    }
+
+    /// Returns parameter `index` of the current sequence, treating a missing
+    /// or zero parameter as 1.
+    fn param_or_one(&self, index: usize) -> usize {
+        match self.params.get(index) {
+            Some(&param) if index < self.param_count && param != 0 => param,
+            _ => 1
+        }
+    }
+
+    /// Applies the parameters of an `ESC [ ... m` sequence.
+    fn select_graphic_rendition(&mut self) {
+        let count = ::core::cmp::min(::core::cmp::max(self.param_count, 1), MAX_PARAMS);
+        for i in 0..count {
+            match self.params[i] {
+                0 => {
+                    self.fg = DEFAULT_FG;
+                    self.bg = DEFAULT_BG;
+                    self.bold = false;
+                }
+                1 => self.bold = true,
+                22 => self.bold = false,
+                param @ 30...37 => self.fg = param - 30,
+                39 => self.fg = DEFAULT_FG,
+                param @ 40...47 => self.bg = param - 40,
+                49 => self.bg = DEFAULT_BG,
+                _ => {  }
+            }
+        }
+    }
+
+    /// Moves the cursor down a row, scrolling if it's on the last one.
+    fn line_feed(&mut self) {
+        if self.row + 1 < self.rows {
+            self.row += 1;
+        } else {
+            self.scroll();
+        }
+    }
+
+    /// Moves every row of text up by one and clears the last.
+    fn scroll(&mut self) {
+        let row_words = self.stride * self.font.height();
+        let words = row_words * self.rows;
+        {
+            let pixels = self.framebuffer.pixels();
+            unsafe {
+                let base = pixels.as_mut_ptr();
+                ptr::copy(base.offset(row_words as isize), base, words - row_words);
+            }
+        }
+
+        let rows = self.rows;
+        self.clear_rows(rows - 1, rows);
+    }
+
+    /// Returns the current foreground and background pixels.
+    fn colors(&self) -> (u32, u32) {
+        let fg = if self.bold { BRIGHT_PALETTE[self.fg] } else { PALETTE[self.fg] };
+        (fg, PALETTE[self.bg])
+    }
+
+    /// Draws the glyph for `byte` in the cell at `col`, `row`.
+    fn draw_glyph(&mut self, col: usize, row: usize, byte: u8) {
+        let (fg, bg) = self.colors();
+        let glyph = self.font.glyph(byte);
+        let (x, y) = (col * GLYPH_WIDTH, row * self.font.height());
+        let stride = self.stride;
+        let pixels = self.framebuffer.pixels();
+
+        for (dy, &bits) in glyph.iter().enumerate() {
+            let line = (y + dy) * stride + x;
+            for dx in 0..GLYPH_WIDTH {
+                pixels[line + dx] = if bits & (0x80 >> dx) != 0 { fg } else { bg };
+            }
+        }
+    }
+
+    /// Fills columns `start..end` of `row` with the background color.
+    fn clear_cells(&mut self, row: usize, start: usize, end: usize) {
+        let (_, bg) = self.colors();
+        let (x0, x1) = (start * GLYPH_WIDTH, end * GLYPH_WIDTH);
+        let y = row * self.font.height();
+        let (stride, height) = (self.stride, self.font.height());
+        let pixels = self.framebuffer.pixels();
+
+        for dy in 0..height {
+            let line = (y + dy) * stride;
+            for pixel in &mut pixels[line + x0..line + x1] {
+                *pixel = bg;
+            }
+        }
+    }
+
+    /// Fills rows `start..end` with the background color.
+    fn clear_rows(&mut self, start: usize, end: usize) {
+        let cols = self.cols;
+        for row in start..end {
+            self.clear_cells(row, 0, cols);
+        }
+    }
+
+    /// Shows or hides the cursor by inverting the colors of its cell. The
+    /// cursor sits in the last column while a wrap is pending.
+    fn toggle_cursor(&mut self) {
+        let col = ::core::cmp::min(self.col, self.cols - 1);
+        let (x, y) = (col * GLYPH_WIDTH, self.row * self.font.height());
+        let (stride, height) = (self.stride, self.font.height());
+        let pixels = self.framebuffer.pixels();
+
+        for dy in 0..height {
+            let line = (y + dy) * stride + x;
+            for pixel in &mut pixels[line..line + GLYPH_WIDTH] {
+                *pixel ^= 0x00FF_FFFF;
+            }
+        }
+
+        self.cursor_shown = !self.cursor_shown;
+    }
+}
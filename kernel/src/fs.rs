@@ -0,0 +1,174 @@
+use std::str;
+
+use pi::emmc::Emmc;
+use pi::fat::{self, Entry, FatError};
+
+use mutex::Mutex;
+use time;
+
+/// The SD card, brought up the first time it's used.
+static CARD: Mutex<Option<Emmc>> = Mutex::new(None);
+
+/// The longest path, in bytes.
+pub const MAX_PATH: usize = 256;
+
+/// An absolute path on the SD card, kept normalized: `/`, or `/` followed by
+/// names separated by single `/`s, with no `.` or `..`.
+#[derive(Clone, Copy)]
+pub struct Path {
+    bytes: [u8; MAX_PATH],
+    len: usize,
+    /// Whether the path was written as a directory's, ending in `/`, `.` or
+    /// `..`, so that it can't name a file.
+    dir: bool
+}
+
+impl Path {
+    /// Returns the root directory's path.
+    pub const fn root() -> Path {
+        Path { bytes: [b'/'; MAX_PATH], len: 1, dir: true }
+    }
+
+    /// Returns the path as a string.
+    pub fn as_str(&self) -> &str {
+        str::from_utf8(&self.bytes[..self.len]).unwrap_or("/")
+    }
+
+    /// Returns `path` resolved against this path as the working directory.
+    /// Paths starting with `/` are resolved from the root instead. Empty
+    /// names and `.` are skipped, and `..` goes up a directory, staying at
+    /// the root. Returns `None` if the result is longer than `MAX_PATH`.
+    pub fn join(&self, path: &str) -> Option<Path> {
+        let mut joined = if path.starts_with('/') { Path::root() } else { *self };
+        let mut dir = path.is_empty();
+        for name in path.split('/').filter(|name| !name.is_empty()) {
+            dir = name == "." || name == "..";
+            match name {
+                "." => {  }
+                ".." => joined.pop(),
+                name => joined.push(name)?
+            }
+        }
+
+        joined.dir = dir || path.ends_with('/');
+        Some(joined)
+    }
+
+    /// Appends the name `name`.
+    fn push(&mut self, name: &str) -> Option<()> {
+        let separator = if self.len == 1 { 0 } else { 1 };
+        let end = self.len + separator + name.len();
+        if end > MAX_PATH {
+            return None;
+        }
+
+        self.bytes[self.len] = b'/';
+        self.bytes[self.len + separator..end].copy_from_slice(name.as_bytes());
+        self.len = end;
+        Some(())
+    }
+
+    /// Removes the last name, if there is one.
+    fn pop(&mut self) {
+        let slash = self.bytes[..self.len].iter().rposition(|&byte| byte == b'/').unwrap_or(0);
+        self.len = if slash == 0 { 1 } else { slash };
+    }
+}
+
+/// Calls `f` with the SD card, bringing the card up first if this is the
+/// first use, and returns what it returns.
+pub fn with_card<T, F>(f: F) -> Result<T, FatError>
+    where F: FnOnce(&mut Emmc) -> Result<T, FatError>
+{
+    let mut card = CARD.lock();
+    if card.is_none() {
+        *card = Some(Emmc::new()?);
+    }
+
+    f(card.as_mut().unwrap())
+}
+
+/// Returns the entry of the file or directory at `path`.
+///
+/// # Errors
+///
+/// Returns `NotADirectory` if `path` was written as a directory's but names
+/// a file.
+pub fn stat(path: &Path) -> Result<Entry, FatError> {
+    let entry = with_card(|emmc| fat::stat(emmc, path.as_str()))?;
+    if path.dir && !entry.is_dir() {
+        return Err(FatError::NotADirectory);
+    }
+
+    Ok(entry)
+}
+
+/// Calls `f` with each entry of the directory at `path`.
+pub fn read_dir<F: FnMut(&Entry)>(path: &Path, f: F) -> Result<(), FatError> {
+    with_card(|emmc| fat::read_dir(emmc, path.as_str(), f))
+}
+
+/// Returns an error if `path` was written as a directory's: `IsADirectory`,
+/// or `NotADirectory` if it names a file, as `stat()` does.
+fn check_file(path: &Path) -> Result<(), FatError> {
+    if path.dir {
+        stat(path)?;
+        return Err(FatError::IsADirectory);
+    }
+
+    Ok(())
+}
+
+/// Reads the file at `path` into `output` and returns its length.
+///
+/// # Errors
+///
+/// Returns `NotADirectory` if `path` was written as a directory's but names
+/// a file, as `stat()` does.
+pub fn load(path: &Path, output: &mut [u8]) -> Result<usize, FatError> {
+    check_file(path)?;
+    with_card(|emmc| fat::load(emmc, path.as_str(), output))
+}
+
+/// Calls `f` with the contents of the file at `path` a sector at a time,
+/// and returns its length. Errors are as `load()`'s.
+pub fn stream<F: FnMut(&[u8])>(path: &Path, f: F) -> Result<usize, FatError> {
+    check_file(path)?;
+    with_card(|emmc| fat::stream(emmc, path.as_str(), f))
+}
+
+/// Writes `data` to the file at `path`, replacing what it held or creating
+/// it, stamped with the current time. Errors are as `load()`'s.
+pub fn save(path: &Path, data: &[u8]) -> Result<(), FatError> {
+    check_file(path)?;
+    let now = time::now();
+    with_card(|emmc| fat::save(emmc, path.as_str(), data, &now))
+}
+
+/// Writes `data` to the end of the file at `path`, creating it if there's
+/// none, stamped with the current time. Errors are as `load()`'s.
+pub fn append(path: &Path, data: &[u8]) -> Result<(), FatError> {
+    check_file(path)?;
+    let now = time::now();
+    with_card(|emmc| fat::append(emmc, path.as_str(), data, &now))
+}
+
+/// Creates an empty directory at `path`.
+pub fn mkdir(path: &Path) -> Result<(), FatError> {
+    let now = time::now();
+    with_card(|emmc| fat::mkdir(emmc, path.as_str(), &now))
+}
+
+/// Removes the file or empty directory at `path`.
+///
+/// # Errors
+///
+/// Returns `NotADirectory` if `path` was written as a directory's but names
+/// a file, as `stat()` does.
+pub fn remove(path: &Path) -> Result<(), FatError> {
+    if path.dir {
+        stat(path)?;
+    }
+
+    with_card(|emmc| fat::remove(emmc, path.as_str()))
+}
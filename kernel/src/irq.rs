@@ -0,0 +1,55 @@
+use pi::interrupt::{Controller, Interrupt, NUM_INTERRUPTS};
+
+use traps::{without_irqs, without_fiqs};
+
+/// A function called from the IRQ handler while its interrupt is pending. It
+/// must clear the interrupt's source before returning.
+pub type Handler = fn();
+
+/// The handler registered for each interrupt, indexed by interrupt number.
+/// Only accessed with IRQs masked.
+static mut HANDLERS: [Option<Handler>; NUM_INTERRUPTS] = [None; NUM_INTERRUPTS];
+
+/// The handler for the FIQ. Only accessed with FIQs masked.
+static mut FIQ_HANDLER: Option<Handler> = None;
+
+/// Registers `handler` for `int`, replacing any previous handler. Drivers
+/// enable their interrupts in the controller once they have something to
+/// report, so registering a handler for an unused device is harmless.
+pub fn register(int: Interrupt, handler: Handler) {
+    without_irqs(|| unsafe { HANDLERS[int as usize] = Some(handler); });
+}
+
+/// Removes the handler registered for `int`, if any.
+pub fn unregister(int: Interrupt) {
+    without_irqs(|| unsafe { HANDLERS[int as usize] = None; });
+}
+
+/// Calls the handler of every pending interrupt that has one. Pending
+/// interrupts without a handler are left alone: the GPU claims some of them.
+pub fn dispatch() {
+    let pending = Controller::new().pending();
+
+    for number in 0..NUM_INTERRUPTS {
+        if pending & (1 << number) == 0 {
+            continue;
+        }
+
+        if let Some(handler) = unsafe { HANDLERS[number] } {
+            handler();
+        }
+    }
+}
+
+/// Registers `handler` to be called for the FIQ, replacing any previous
+/// handler. The driver selects which interrupt is raised as the FIQ.
+pub fn register_fiq(handler: Handler) {
+    without_fiqs(|| unsafe { FIQ_HANDLER = Some(handler); });
+}
+
+/// Calls the FIQ handler, if there is one.
+pub fn dispatch_fiq() {
+    if let Some(handler) = unsafe { FIQ_HANDLER } {
+        handler();
+    }
+}
@@ -9,6 +9,7 @@
 #![feature(never_type)]
 #![feature(ptr_internals)]
 
+extern crate core;
 extern crate pi;
 extern crate stack_vec;
 
@@ -16,18 +17,115 @@ pub mod lang_items;
 pub mod mutex;
 pub mod console;
 pub mod shell;
+pub mod traps;
+pub mod irq;
+pub mod rand;
+pub mod tick;
+pub mod timers;
+pub mod fbcon;
+pub mod usb;
+pub mod net;
+pub mod telnet;
+pub mod netboot;
+pub mod time;
+pub mod can;
+pub mod xmodem;
+pub mod fs;
+pub mod pipe;
+pub mod editor;
+pub mod pager;
 
-use console::{kprint, kprintln, CONSOLE};
+use core::time::Duration;
 
-#[no_mangle]
-pub extern "C" fn kmain() {
-    kprintln!("
+use pi::{gpio, soft_pwm};
+use pi::bootcfg::{BootConfig, Console, LogLevel, MAX_SIZE};
+use pi::fat::FatError;
+use pi::interrupt::Interrupt;
+
+use console::{kerrorln, kprint, kprintln, CONSOLE, Device};
+
+const BANNER: &str = "
   ██████╗  ██████╗ ██╗  ██╗██╗   ██╗ ██████╗ ███████╗
   ╚════██╗██╔═████╗╚██╗██╔╝╚██╗ ██╔╝██╔═══██╗██╔════╝
    █████╔╝██║██╔██║ ╚███╔╝  ╚████╔╝ ██║   ██║███████╗
   ██╔═══╝ ████╔╝██║ ██╔██╗   ╚██╔╝  ██║   ██║╚════██║
   ███████╗╚██████╔╝██╔╝ ██╗   ██║   ╚██████╔╝███████║
   ╚══════╝ ╚═════╝ ╚═╝  ╚═╝   ╚═╝    ╚═════╝ ╚══════╝
-");
+";
+
+/// Reads `boot.cfg` from the SD card into `buffer`, or returns the defaults
+/// and the reason if it can't be read.
+fn read_config(buffer: &mut [u8]) -> (BootConfig, Option<FatError>) {
+    match fs::with_card(|emmc| BootConfig::read(emmc, buffer)) {
+        Ok(config) => (config, None),
+        Err(error) => (BootConfig::default(), Some(error))
+    }
+}
+
+#[no_mangle]
+pub extern "C" fn kmain() {
+    // `boot.cfg` puts the kernel log and the shell on the mini UART, or on
+    // HDMI, and sets how much is logged.
+    let mut buffer = [0u8; MAX_SIZE];
+    let (config, config_error) = read_config(&mut buffer);
+    let device = match config.console {
+        Console::Uart => Device::MiniUart,
+        Console::Hdmi => Device::Framebuffer
+    };
+
+    let route_error = console::route(device, device).err();
+    lang_items::set_panic_reboot(config.panic_reboot_secs.map(Duration::from_secs));
+    let info = config.log_level >= LogLevel::Info;
+    if info {
+        kprintln!("{}", BANNER);
+    }
+
+    if let Some(error) = config_error {
+        kerrorln!("boot.cfg: not read: {:?}", error);
+    }
+
+    if let Some(error) = route_error {
+        kerrorln!("console: log not split from the shell: {:?}", error);
+    }
+
+    // The firmware only passes a kernel command line with ATAGs, not with the
+    // device tree it passes by default.
+    if info && pi::info::atag_cmdline().is_none() {
+        kprintln!("cmdline: none passed; ip= and tftp= are only read from boot.cfg");
+    }
+
+    // The console has enabled the UART receive FIQ; let it through along with
+    // the scheduler tick and whatever the other drivers raise.
+    irq::register(Interrupt::Gpio3, gpio::handle_irq);
+    irq::register(Interrupt::Timer1, soft_pwm::handle_irq);
+    tick::start();
+    traps::enable_fiqs();
+    traps::enable_irqs();
+
+    // Boards without an RTC start the wall clock at the epoch until the
+    // shell's `date` sets it.
+    match time::init() {
+        Ok(now) => if info {
+            kprintln!("time: {} UTC", now);
+        },
+        Err(error) => kerrorln!("time: no RTC time: {:?}", error)
+    }
+
+    // Bring the Ethernet up with DHCP, falling back to a static address, and
+    // serve the shell on it too. Boards without it run as before.
+    netboot::configure(config.tftp);
+    match net::init(net::Config::from_boot(config.ip)) {
+        Ok(_) => {
+            if info {
+                kprintln!("net: {} on {}", net::config().address, net::mac_address());
+            }
+
+            if let Err(error) = telnet::listen() {
+                kerrorln!("telnet: not started: {:?}", error);
+            }
+        }
+        Err(error) => kerrorln!("net: not started: {:?}", error)
+    }
+
     shell::shell("> ");
 }
@@ -1,8 +1,47 @@
 #[lang = "eh_personality"] pub extern fn eh_personality() {}
 
+use core::sync::atomic::{AtomicUsize, Ordering};
+use core::time::Duration;
 use std::panic::PanicInfo;
+use pi::{led, power};
+use pi::timer::Instant;
 
-#[panic_handler] #[no_mangle] pub extern fn panic_fmt(info: &PanicInfo) -> ! { loop{} }
+use console::{self, Color};
+
+/// The number of ACT LED blinks that signal a kernel panic.
+const PANIC_BLINKS: u32 = 3;
+
+/// Seconds the panic code is blinked before the board reboots, or `NEVER`.
+static PANIC_REBOOT_SECS: AtomicUsize = AtomicUsize::new(NEVER);
+
+/// `PANIC_REBOOT_SECS` when the panic code is blinked forever.
+const NEVER: usize = usize::max_value();
+
+/// Sets how long the panic code is blinked before the board reboots, as
+/// `boot.cfg` asks. With `None`, the default, it's blinked forever.
+pub fn set_panic_reboot(after: Option<Duration>) {
+    let secs = after.map_or(NEVER, |after| after.as_secs() as usize);
+    PANIC_REBOOT_SECS.store(secs, Ordering::Relaxed);
+}
+
+#[panic_handler] #[no_mangle] pub extern fn panic_fmt(info: &PanicInfo) -> ! {
+    // The console may be locked by the code that panicked, so it isn't waited
+    // on.
+    console::write_last(format_args!("\n\n{}\n",
+        Color::Red.paint(format_args!("kernel panic: {}", info), console::color())));
+
+    match PANIC_REBOOT_SECS.load(Ordering::Relaxed) {
+        NEVER => loop { led::blink_code(PANIC_BLINKS) },
+        secs => {
+            let start = Instant::now();
+            while start.elapsed() < Duration::from_secs(secs as u64) {
+                led::blink_code(PANIC_BLINKS);
+            }
+
+            power::reboot()
+        }
+    }
+}
 
 #[no_mangle]
 pub unsafe extern fn memcpy(dest: *mut u8, src: *const u8, n: usize) -> *mut u8 {
@@ -40,6 +40,17 @@ impl<T> Mutex<T> {
         MutexGuard { lock: &self }
     }
 
+    /// Acquires the lock if it's free, without waiting. Interrupt handlers
+    /// use this for locks the interrupted code may hold.
+    pub fn try_lock(&self) -> Option<MutexGuard<T>> {
+        if self.lock.load(Ordering::Relaxed) {
+            return None;
+        }
+
+        self.lock.store(true, Ordering::Relaxed);
+        Some(MutexGuard { lock: &self })
+    }
+
     fn unlock(&self) {
         self.lock.store(false, Ordering::Relaxed);
     }
@@ -0,0 +1,124 @@
+use pi::net::{NetDevice, MacAddress};
+
+use super::{Config, Error, Ipv4Addr, ethertype, ETHERNET_HEADER};
+use super::{read_u16, write_u16, mac_at, ip_at, write_ethernet_header};
+
+/// The number of addresses the cache remembers.
+const CACHE_SIZE: usize = 8;
+
+/// The length of an ARP packet for IPv4 over Ethernet.
+const PACKET_LEN: usize = 28;
+
+/// ARP operations.
+const REQUEST: u16 = 1;
+const REPLY: u16 = 2;
+
+/// The number of requests sent for an address before giving up on it.
+pub const RETRIES: usize = 3;
+
+/// How long to wait for a reply to each request.
+pub const WAIT_MS: u64 = 500;
+
+/// The MAC addresses of recently seen IPv4 addresses. Entries are replaced
+/// oldest first and never expire.
+pub struct Cache {
+    entries: [Option<(Ipv4Addr, MacAddress)>; CACHE_SIZE],
+    /// The entry replaced by the next new address.
+    next: usize,
+}
+
+impl Cache {
+    /// Returns an empty cache.
+    pub const fn new() -> Cache {
+        Cache { entries: [None; CACHE_SIZE], next: 0 }
+    }
+
+    /// Returns the MAC address of `address`, if it's known.
+    pub fn lookup(&self, address: Ipv4Addr) -> Option<MacAddress> {
+        self.entries.iter()
+            .filter_map(|entry| *entry)
+            .find(|&(ip, _)| ip == address)
+            .map(|(_, mac)| mac)
+    }
+
+    /// Records that `address` is at `mac`.
+    pub fn insert(&mut self, address: Ipv4Addr, mac: MacAddress) {
+        for entry in self.entries.iter_mut() {
+            if let Some((ip, ref mut known)) = *entry {
+                if ip == address {
+                    *known = mac;
+                    return;
+                }
+            }
+        }
+
+        self.entries[self.next] = Some((address, mac));
+        self.next = (self.next + 1) % CACHE_SIZE;
+    }
+}
+
+/// Handles the ARP packet `packet`: learns the sender's address and answers
+/// requests for ours, building the reply in `tx`.
+pub(super) fn handle(cache: &mut Cache,
+                     config: &Config,
+                     mac: MacAddress,
+                     device: &mut dyn NetDevice,
+                     packet: &[u8],
+                     tx: &mut [u8]) -> Result<(), Error> {
+    // Only IPv4 over Ethernet.
+    if packet.len() < PACKET_LEN || read_u16(packet, 0) != 1 || read_u16(packet, 2) != ethertype::IPV4 {
+        return Ok(());
+    }
+
+    let sender_mac = MacAddress(mac_at(packet, 8));
+    let sender = Ipv4Addr(ip_at(packet, 14));
+    let target = Ipv4Addr(ip_at(packet, 24));
+    if sender != Ipv4Addr::UNSPECIFIED {
+        cache.insert(sender, sender_mac);
+    }
+
+    if read_u16(packet, 6) != REQUEST || target != config.address {
+        return Ok(());
+    }
+
+    send(device, tx, REPLY, mac, config.address, sender_mac, sender)
+}
+
+/// Broadcasts a request for the MAC address of `address`, building it in
+/// `tx`.
+pub(super) fn request(config: &Config,
+                      mac: MacAddress,
+                      device: &mut dyn NetDevice,
+                      address: Ipv4Addr,
+                      tx: &mut [u8]) -> Result<(), Error> {
+    send(device, tx, REQUEST, mac, config.address, MacAddress([0; 6]), address)
+}
+
+/// Sends the ARP packet for `operation` from `mac` at `address` to
+/// `target_mac` at `target`. Requests are broadcast.
+fn send(device: &mut dyn NetDevice,
+        tx: &mut [u8],
+        operation: u16,
+        mac: MacAddress,
+        address: Ipv4Addr,
+        target_mac: MacAddress,
+        target: Ipv4Addr) -> Result<(), Error> {
+    let destination = if operation == REQUEST { MacAddress::BROADCAST } else { target_mac };
+    write_ethernet_header(tx, destination, mac, ethertype::ARP);
+
+    {
+        let packet = &mut tx[ETHERNET_HEADER..ETHERNET_HEADER + PACKET_LEN];
+        write_u16(packet, 0, 1);
+        write_u16(packet, 2, ethertype::IPV4);
+        packet[4] = 6;
+        packet[5] = 4;
+        write_u16(packet, 6, operation);
+        packet[8..14].copy_from_slice(&mac.0);
+        packet[14..18].copy_from_slice(&address.0);
+        packet[18..24].copy_from_slice(&target_mac.0);
+        packet[24..28].copy_from_slice(&target.0);
+    }
+
+    device.send_frame(&tx[..ETHERNET_HEADER + PACKET_LEN])?;
+    Ok(())
+}
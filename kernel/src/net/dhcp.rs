@@ -0,0 +1,242 @@
+use core::time::Duration;
+
+use pi::net::MacAddress;
+use pi::timer::Instant;
+
+use rand;
+
+use super::{Config, Error, Ipv4Addr, UdpSocket};
+use super::{poll, read_u32, write_u16, ip_at};
+
+/// The ports of DHCP servers and clients.
+const SERVER_PORT: u16 = 67;
+const CLIENT_PORT: u16 = 68;
+
+/// BOOTP operations.
+const BOOT_REQUEST: u8 = 1;
+const BOOT_REPLY: u8 = 2;
+
+/// The `flags` bit asking the server to broadcast its replies, since the
+/// stack drops unicast packets to addresses it doesn't have yet.
+const FLAG_BROADCAST: u16 = 0x8000;
+
+/// The offset of the magic cookie, and the cookie itself.
+const COOKIE_OFFSET: usize = 236;
+const COOKIE: [u8; 4] = [99, 130, 83, 99];
+
+/// The offset of the options.
+const OPTIONS_OFFSET: usize = COOKIE_OFFSET + 4;
+
+/// The size of the messages sent, the BOOTP minimum.
+const MESSAGE_LEN: usize = 300;
+
+/// DHCP option codes.
+mod option {
+    pub const PAD: u8 = 0;
+    pub const SUBNET_MASK: u8 = 1;
+    pub const ROUTER: u8 = 3;
+    pub const REQUESTED_ADDRESS: u8 = 50;
+    pub const LEASE_TIME: u8 = 51;
+    pub const MESSAGE_TYPE: u8 = 53;
+    pub const SERVER_ID: u8 = 54;
+    pub const PARAMETER_LIST: u8 = 55;
+    pub const END: u8 = 255;
+}
+
+/// DHCP message types.
+mod message {
+    pub const DISCOVER: u8 = 1;
+    pub const OFFER: u8 = 2;
+    pub const REQUEST: u8 = 3;
+    pub const ACK: u8 = 5;
+    pub const NAK: u8 = 6;
+}
+
+/// The number of times each message is sent before giving up.
+const RETRIES: usize = 3;
+
+/// How long to wait for a reply to each message.
+const WAIT_MS: u64 = 2000;
+
+/// A lease on an address from a DHCP server.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct Lease {
+    /// The server that granted the lease.
+    pub server: Ipv4Addr,
+    /// How long the lease was granted for.
+    pub duration: Duration,
+    /// When the lease was granted.
+    pub obtained: Instant,
+}
+
+impl Lease {
+    /// Returns how long is left on the lease, or `None` if it has expired.
+    /// Leases aren't renewed, so an expired lease's address may have been
+    /// handed to another host.
+    pub fn remaining(&self) -> Option<Duration> {
+        (self.obtained + self.duration).checked_duration_since(Instant::now())
+    }
+}
+
+/// The fields of a server's reply the client uses.
+struct Reply {
+    kind: u8,
+    address: Ipv4Addr,
+    server: Ipv4Addr,
+    netmask: Ipv4Addr,
+    gateway: Ipv4Addr,
+    lease: Duration,
+}
+
+/// Acquires an address for the interface with MAC address `mac`: broadcasts
+/// a discover, requests the first address offered, and returns the
+/// interface's addresses and the lease once the server acknowledges.
+///
+/// The interface must have the unspecified address while this runs, so that
+/// messages go out from `0.0.0.0`.
+pub fn acquire(mac: MacAddress) -> Result<(Config, Lease), Error> {
+    let socket = UdpSocket::bind(CLIENT_PORT)?;
+    let xid = rand::next_u32();
+
+    let offer = exchange(&socket, mac, xid, message::DISCOVER, None, message::OFFER)?;
+    let ack = exchange(&socket, mac, xid, message::REQUEST, Some(&offer), message::ACK)?;
+
+    let config = Config {
+        address: ack.address,
+        netmask: ack.netmask,
+        gateway: ack.gateway,
+    };
+
+    let lease = Lease { server: ack.server, duration: ack.lease, obtained: Instant::now() };
+    Ok((config, lease))
+}
+
+/// Broadcasts a message of type `kind`, requesting the address in `offer`
+/// if there is one, until a reply of type `expected` arrives. A NAK fails
+/// immediately.
+fn exchange(socket: &UdpSocket,
+            mac: MacAddress,
+            xid: u32,
+            kind: u8,
+            offer: Option<&Reply>,
+            expected: u8) -> Result<Reply, Error> {
+    let mut buf = [0; 576];
+    let len = build(&mut buf, mac, xid, kind, offer);
+
+    for _ in 0..RETRIES {
+        socket.send_to(&buf[..len], Ipv4Addr::BROADCAST, SERVER_PORT)?;
+
+        let deadline = Instant::now() + Duration::from_millis(WAIT_MS);
+        while Instant::now() < deadline {
+            poll();
+
+            let mut reply = [0; 576];
+            let received = match socket.recv_from(&mut reply) {
+                Some((received, _, SERVER_PORT)) => received,
+                _ => continue
+            };
+
+            match parse(&reply[..received], mac, xid) {
+                Some(ref reply) if reply.kind == message::NAK => return Err(Error::Unreachable),
+                Some(reply) => if reply.kind == expected { return Ok(reply) },
+                None => {  }
+            }
+        }
+    }
+
+    Err(Error::Unreachable)
+}
+
+/// Writes a client message of type `kind` into `buf` and returns its length.
+fn build(buf: &mut [u8], mac: MacAddress, xid: u32, kind: u8, offer: Option<&Reply>) -> usize {
+    for byte in buf[..MESSAGE_LEN].iter_mut() {
+        *byte = 0;
+    }
+
+    buf[0] = BOOT_REQUEST;
+    buf[1] = 1;
+    buf[2] = 6;
+    write_u16(buf, 4, (xid >> 16) as u16);
+    write_u16(buf, 6, xid as u16);
+    write_u16(buf, 10, FLAG_BROADCAST);
+    buf[28..34].copy_from_slice(&mac.0);
+    buf[COOKIE_OFFSET..OPTIONS_OFFSET].copy_from_slice(&COOKIE);
+
+    let mut i = OPTIONS_OFFSET;
+    i = push_option(buf, i, option::MESSAGE_TYPE, &[kind]);
+    if let Some(offer) = offer {
+        i = push_option(buf, i, option::REQUESTED_ADDRESS, &offer.address.0);
+        i = push_option(buf, i, option::SERVER_ID, &offer.server.0);
+    }
+
+    let parameters = [option::SUBNET_MASK, option::ROUTER, option::LEASE_TIME];
+    i = push_option(buf, i, option::PARAMETER_LIST, &parameters);
+    buf[i] = option::END;
+    MESSAGE_LEN
+}
+
+/// Writes the option `code` with `data` at `buf[i..]` and returns the offset
+/// after it.
+fn push_option(buf: &mut [u8], i: usize, code: u8, data: &[u8]) -> usize {
+    buf[i] = code;
+    buf[i + 1] = data.len() as u8;
+    buf[i + 2..i + 2 + data.len()].copy_from_slice(data);
+    i + 2 + data.len()
+}
+
+/// Parses `buf` as a server's reply to the client with MAC address `mac` in
+/// transaction `xid`. Returns `None` if it's malformed or meant for another
+/// client.
+fn parse(buf: &[u8], mac: MacAddress, xid: u32) -> Option<Reply> {
+    if buf.len() < OPTIONS_OFFSET || buf[0] != BOOT_REPLY || read_u32(buf, 4) != xid
+        || buf[28..34] != mac.0 || buf[COOKIE_OFFSET..OPTIONS_OFFSET] != COOKIE {
+        return None;
+    }
+
+    let mut reply = Reply {
+        kind: 0,
+        address: Ipv4Addr(ip_at(buf, 16)),
+        server: Ipv4Addr::UNSPECIFIED,
+        netmask: Ipv4Addr([255, 255, 255, 0]),
+        gateway: Ipv4Addr::UNSPECIFIED,
+        lease: Duration::from_secs(0),
+    };
+
+    let mut i = OPTIONS_OFFSET;
+    while i < buf.len() {
+        let code = buf[i];
+        if code == option::END {
+            break;
+        } else if code == option::PAD {
+            i += 1;
+            continue;
+        }
+
+        if i + 2 > buf.len() || i + 2 + buf[i + 1] as usize > buf.len() {
+            return None;
+        }
+
+        let data = &buf[i + 2..i + 2 + buf[i + 1] as usize];
+        match (code, data.len()) {
+            (option::MESSAGE_TYPE, 1) => reply.kind = data[0],
+            (option::SUBNET_MASK, 4) => reply.netmask = Ipv4Addr(ip_at(data, 0)),
+            (option::ROUTER, n) if n >= 4 => reply.gateway = Ipv4Addr(ip_at(data, 0)),
+            (option::SERVER_ID, 4) => reply.server = Ipv4Addr(ip_at(data, 0)),
+            (option::LEASE_TIME, 4) => reply.lease = Duration::from_secs(read_u32(data, 0) as u64),
+            _ => {  }
+        }
+
+        i += 2 + data.len();
+    }
+
+    if reply.kind == 0 {
+        return None;
+    }
+
+    // Servers that don't name themselves are taken to be the router.
+    if reply.server == Ipv4Addr::UNSPECIFIED {
+        reply.server = reply.gateway;
+    }
+
+    Some(reply)
+}
@@ -0,0 +1,44 @@
+use pi::net::{NetDevice, MacAddress};
+
+use super::{Config, Error, Ipv4Header, protocol, MAX_IPV4_PAYLOAD};
+use super::{checksum, write_u16, write_ipv4_headers};
+
+/// ICMP message types.
+const ECHO_REPLY: u8 = 0;
+const ECHO_REQUEST: u8 = 8;
+
+/// The length of an ICMP header.
+const HEADER_LEN: usize = 8;
+
+/// Handles the ICMP message `message` from `source_mac`: answers echo
+/// requests, building the reply in `tx`. Other messages are ignored.
+pub(super) fn handle(config: &Config,
+                     mac: MacAddress,
+                     device: &mut dyn NetDevice,
+                     source_mac: MacAddress,
+                     header: &Ipv4Header,
+                     message: &[u8],
+                     tx: &mut [u8]) -> Result<(), Error> {
+    if message.len() < HEADER_LEN || message[0] != ECHO_REQUEST || checksum(message, 0) != 0 {
+        return Ok(());
+    }
+
+    if message.len() > MAX_IPV4_PAYLOAD {
+        return Err(Error::TooLarge);
+    }
+
+    // The reply echoes the identifier, sequence number and data.
+    let offset = write_ipv4_headers(tx, config, mac, source_mac, header.source,
+                                    protocol::ICMP, message.len());
+    {
+        let reply = &mut tx[offset..offset + message.len()];
+        reply.copy_from_slice(message);
+        reply[0] = ECHO_REPLY;
+        write_u16(reply, 2, 0);
+        let sum = checksum(reply, 0);
+        write_u16(reply, 2, sum);
+    }
+
+    device.send_frame(&tx[..offset + message.len()])?;
+    Ok(())
+}
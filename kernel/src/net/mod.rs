@@ -0,0 +1,580 @@
+//! A minimal IPv4 network stack over the onboard Ethernet: ARP, ICMP echo
+//! replies, UDP sockets, passive TCP connections, DHCP, and TFTP downloads.
+//!
+//! The stack is polled from the tick every `POLL_PERIOD_MS`, so the kernel
+//! answers pings without anything else running. Received UDP datagrams and
+//! TCP data are queued on their sockets until read, and queued TCP data is
+//! sent and resent by the tick.
+
+pub mod arp;
+pub mod icmp;
+pub mod udp;
+pub mod dhcp;
+pub mod tcp;
+pub mod tftp;
+
+use core::fmt;
+use core::str::FromStr;
+use core::sync::atomic::{AtomicBool, AtomicUsize, Ordering};
+use core::time::Duration;
+
+use pi::info;
+use pi::net::{NetDevice, NetError, MacAddress, MAX_FRAME};
+use pi::timer::Instant;
+
+use mutex::Mutex;
+use timers;
+use usb;
+
+pub use self::udp::UdpSocket;
+pub use self::tcp::{TcpListener, TcpStream};
+
+/// How often the tick polls the device for frames.
+const POLL_PERIOD_MS: u64 = 10;
+
+/// The most frames handled per poll, so a flood can't hold up the tick.
+const FRAMES_PER_POLL: usize = 8;
+
+/// EtherTypes of the protocols the stack understands.
+mod ethertype {
+    pub const IPV4: u16 = 0x0800;
+    pub const ARP: u16 = 0x0806;
+}
+
+/// IP protocol numbers of the protocols the stack understands.
+mod protocol {
+    pub const ICMP: u8 = 1;
+    pub const TCP: u8 = 6;
+    pub const UDP: u8 = 17;
+}
+
+/// The length of an Ethernet header.
+const ETHERNET_HEADER: usize = 14;
+
+/// The length of an IPv4 header without options.
+const IPV4_HEADER: usize = 20;
+
+/// The time to live of sent packets.
+const TTL: u8 = 64;
+
+/// The largest IPv4 payload the stack sends.
+pub const MAX_IPV4_PAYLOAD: usize = 1500 - IPV4_HEADER;
+
+/// The identification of the next IPv4 packet sent.
+static NEXT_ID: AtomicUsize = AtomicUsize::new(0);
+
+/// An IPv4 address.
+#[derive(Debug, Default, Clone, Copy, PartialEq, Eq)]
+pub struct Ipv4Addr(pub [u8; 4]);
+
+impl Ipv4Addr {
+    /// `0.0.0.0`, the address of a host that doesn't have one yet.
+    pub const UNSPECIFIED: Ipv4Addr = Ipv4Addr([0; 4]);
+
+    /// `255.255.255.255`, the limited broadcast address.
+    pub const BROADCAST: Ipv4Addr = Ipv4Addr([0xFF; 4]);
+
+    /// Returns the address as a big-endian integer.
+    pub fn to_u32(self) -> u32 {
+        read_u32(&self.0, 0)
+    }
+
+    /// Returns the address of the big-endian integer `value`.
+    pub fn from_u32(value: u32) -> Ipv4Addr {
+        Ipv4Addr([(value >> 24) as u8, (value >> 16) as u8, (value >> 8) as u8, value as u8])
+    }
+
+    /// Returns `true` if `self` and `other` are on the same subnet under
+    /// `netmask`.
+    pub fn same_subnet(self, other: Ipv4Addr, netmask: Ipv4Addr) -> bool {
+        (self.to_u32() ^ other.to_u32()) & netmask.to_u32() == 0
+    }
+}
+
+impl fmt::Display for Ipv4Addr {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        write!(f, "{}.{}.{}.{}", self.0[0], self.0[1], self.0[2], self.0[3])
+    }
+}
+
+impl FromStr for Ipv4Addr {
+    type Err = ();
+
+    /// Parses a dotted quad such as `10.0.0.2`.
+    fn from_str(s: &str) -> Result<Ipv4Addr, ()> {
+        let mut address = [0; 4];
+        let mut parts = s.split('.');
+        for byte in address.iter_mut() {
+            *byte = parts.next().ok_or(())?.parse().map_err(|_| ())?;
+        }
+
+        match parts.next() {
+            Some(_) => Err(()),
+            None => Ok(Ipv4Addr(address))
+        }
+    }
+}
+
+/// The addresses of the interface.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct Config {
+    pub address: Ipv4Addr,
+    pub netmask: Ipv4Addr,
+    pub gateway: Ipv4Addr,
+}
+
+/// The interface's addresses if DHCP fails and the boot configuration
+/// doesn't give any.
+pub const DEFAULT_CONFIG: Config = Config {
+    address: Ipv4Addr([10, 0, 0, 2]),
+    netmask: Ipv4Addr([255, 255, 255, 0]),
+    gateway: Ipv4Addr([10, 0, 0, 1]),
+};
+
+/// The addresses of an interface that doesn't have any yet.
+const UNCONFIGURED: Config = Config {
+    address: Ipv4Addr::UNSPECIFIED,
+    netmask: Ipv4Addr::UNSPECIFIED,
+    gateway: Ipv4Addr::UNSPECIFIED,
+};
+
+/// The `boot.cfg` and kernel command line key giving a static configuration.
+const BOOT_KEY: &str = "ip";
+
+impl Config {
+    /// Returns the static configuration given as
+    /// `ip=<address>/<prefix>,<gateway>`, e.g. `ip=192.168.1.20/24,192.168.1.1`:
+    /// `setting`, the `ip` value in `boot.cfg`, or else the one on the kernel
+    /// command line. The firmware only passes a command line, read from
+    /// `cmdline.txt`, when it boots with ATAGs rather than a device tree.
+    pub fn from_boot(setting: Option<&str>) -> Option<Config> {
+        setting.or_else(|| info::cmdline_arg(BOOT_KEY))?.parse().ok()
+    }
+}
+
+impl FromStr for Config {
+    type Err = ();
+
+    /// Parses `<address>/<prefix>,<gateway>`.
+    fn from_str(s: &str) -> Result<Config, ()> {
+        let mut parts = s.splitn(2, ',');
+        let mut cidr = parts.next().ok_or(())?.splitn(2, '/');
+        let address = cidr.next().ok_or(())?.parse()?;
+        let prefix: u32 = cidr.next().ok_or(())?.parse().map_err(|_| ())?;
+        let gateway = parts.next().ok_or(())?.parse()?;
+        if prefix > 32 {
+            return Err(());
+        }
+
+        let netmask = Ipv4Addr::from_u32(if prefix == 0 { 0 } else { !0 << (32 - prefix) });
+        Ok(Config { address, netmask, gateway })
+    }
+}
+
+/// Where the interface's addresses came from.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum Origin {
+    /// Not configured yet.
+    None,
+    /// Leased from a DHCP server.
+    Dhcp(dhcp::Lease),
+    /// Given in `boot.cfg` or on the kernel command line.
+    Static,
+    /// `DEFAULT_CONFIG`, since nothing else worked.
+    Default,
+}
+
+/// An error from the network stack.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum Error {
+    /// No network device was found, or `init()` hasn't been called.
+    NoDevice,
+    /// The destination didn't answer ARP requests.
+    Unreachable,
+    /// The payload doesn't fit in one packet.
+    TooLarge,
+    /// Every socket is in use, or the port is already bound.
+    AddressInUse,
+    /// The TCP connection was closed or reset.
+    Closed,
+    /// The TCP peer stopped taking data.
+    TimedOut,
+    /// The device failed.
+    Device(NetError),
+}
+
+impl From<NetError> for Error {
+    fn from(error: NetError) -> Error {
+        Error::Device(error)
+    }
+}
+
+/// The state of the stack.
+struct Stack {
+    config: Config,
+    origin: Origin,
+    mac: MacAddress,
+    arp: arp::Cache,
+    udp: udp::Sockets,
+    tcp: tcp::Connections,
+    rx: [u8; MAX_FRAME],
+    tx: [u8; MAX_FRAME],
+}
+
+/// The stack, shared between the tick and threads using sockets. The tick
+/// only ever `try_lock()`s it.
+static STACK: Mutex<Stack> = Mutex::new(Stack {
+    config: UNCONFIGURED,
+    origin: Origin::None,
+    mac: MacAddress([0; 6]),
+    arp: arp::Cache::new(),
+    udp: udp::Sockets::new(),
+    tcp: tcp::Connections::new(),
+    rx: [0; MAX_FRAME],
+    tx: [0; MAX_FRAME],
+});
+
+/// Whether `init()` has started polling.
+static POLLING: AtomicBool = AtomicBool::new(false);
+
+/// Brings up USB and the Ethernet device on it, starts polling from the
+/// tick, and configures the interface: with a DHCP lease if a server
+/// answers, or else with `fallback`, as `Config::from_boot()` returns, or
+/// else with `DEFAULT_CONFIG`. The tick must already be running.
+///
+/// Returns where the addresses came from.
+pub fn init(fallback: Option<Config>) -> Result<Origin, Error> {
+    usb::init().map_err(|e| Error::Device(NetError::Usb(e)))?;
+
+    let mac = match *usb::ETHERNET.lock() {
+        Some(ref ethernet) => ethernet.mac_address(),
+        None => return Err(Error::NoDevice)
+    };
+
+    configure(UNCONFIGURED, Origin::None);
+    STACK.lock().mac = mac;
+
+    if !POLLING.swap(true, Ordering::Relaxed) {
+        timers::every(Duration::from_millis(POLL_PERIOD_MS), poll);
+    }
+
+    let (config, origin) = match dhcp::acquire(mac) {
+        Ok((config, lease)) => (config, Origin::Dhcp(lease)),
+        Err(_) => match fallback {
+            Some(config) => (config, Origin::Static),
+            None => (DEFAULT_CONFIG, Origin::Default)
+        }
+    };
+
+    configure(config, origin);
+    Ok(origin)
+}
+
+/// Gives the interface the addresses in `config`, which came from `origin`.
+pub fn configure(config: Config, origin: Origin) {
+    let mut stack = STACK.lock();
+    stack.config = config;
+    stack.origin = origin;
+}
+
+/// Returns the interface's addresses.
+pub fn config() -> Config {
+    STACK.lock().config
+}
+
+/// Returns where the interface's addresses came from.
+pub fn origin() -> Origin {
+    STACK.lock().origin
+}
+
+/// Returns the interface's MAC address, once `init()` has found the device.
+pub fn mac_address() -> MacAddress {
+    STACK.lock().mac
+}
+
+/// Handles the frames the device has received. Does nothing if the stack or
+/// the device is in use by the code the tick interrupted; the next poll picks
+/// the frames up.
+pub fn poll() {
+    let mut stack = match STACK.try_lock() {
+        Some(stack) => stack,
+        None => return
+    };
+
+    let mut ethernet = match usb::ETHERNET.try_lock() {
+        Some(ethernet) => ethernet,
+        None => return
+    };
+
+    let mut host = match usb::HOST.try_lock() {
+        Some(host) => host,
+        None => return
+    };
+
+    if let (Some(ethernet), Some(host)) = (ethernet.as_mut(), host.as_mut()) {
+        stack.poll(&mut ethernet.bind(host));
+    }
+}
+
+/// Calls `f` with the stack and the device, waiting for both.
+fn with_device<R, F>(f: F) -> Result<R, Error>
+    where F: FnOnce(&mut Stack, &mut dyn NetDevice) -> Result<R, Error>
+{
+    let mut stack = STACK.lock();
+    let mut ethernet = usb::ETHERNET.lock();
+    let mut host = usb::HOST.lock();
+    match (ethernet.as_mut(), host.as_mut()) {
+        (Some(ethernet), Some(host)) => f(&mut *stack, &mut ethernet.bind(host)),
+        _ => Err(Error::NoDevice)
+    }
+}
+
+impl Stack {
+    /// Handles up to `FRAMES_PER_POLL` frames waiting on `device`, then
+    /// sends what the TCP connections have waiting.
+    fn poll(&mut self, device: &mut dyn NetDevice) {
+        for _ in 0..FRAMES_PER_POLL {
+            match device.recv_frame(&mut self.rx) {
+                Ok(Some(len)) => { let _ = self.handle_frame(device, len); }
+                Ok(None) => break,
+                Err(NetError::Usb(_)) => break,
+                Err(_) => continue
+            }
+        }
+
+        self.tcp.transmit(&self.config, self.mac, device, &mut self.tx);
+    }
+
+    /// Handles the `len` byte frame in `self.rx`.
+    fn handle_frame(&mut self, device: &mut dyn NetDevice, len: usize) -> Result<(), Error> {
+        if len < ETHERNET_HEADER {
+            return Ok(());
+        }
+
+        let destination = MacAddress(mac_at(&self.rx, 0));
+        if destination != self.mac && destination != MacAddress::BROADCAST {
+            return Ok(());
+        }
+
+        let source = MacAddress(mac_at(&self.rx, 6));
+        let payload = &self.rx[ETHERNET_HEADER..len];
+        match read_u16(&self.rx, 12) {
+            ethertype::ARP => {
+                arp::handle(&mut self.arp, &self.config, self.mac, device, payload, &mut self.tx)
+            }
+            ethertype::IPV4 => {
+                let (header, packet) = match Ipv4Header::parse(payload) {
+                    Some(parsed) => parsed,
+                    None => return Ok(())
+                };
+
+                let config = self.config;
+                let ours = header.destination == config.address
+                    || header.destination == Ipv4Addr::BROADCAST
+                    || config.address == Ipv4Addr::UNSPECIFIED;
+                if !ours {
+                    return Ok(());
+                }
+
+                match header.protocol {
+                    protocol::ICMP => {
+                        icmp::handle(&config, self.mac, device, source, &header, packet, &mut self.tx)
+                    }
+                    protocol::UDP => {
+                        self.udp.handle(&header, packet);
+                        Ok(())
+                    }
+                    protocol::TCP => {
+                        self.tcp.handle(&config, self.mac, device, source, &header, packet, &mut self.tx)
+                    }
+                    _ => Ok(())
+                }
+            }
+            _ => Ok(())
+        }
+    }
+
+    /// Returns the next hop of packets for `address`, the address itself if
+    /// it's on the subnet or the gateway otherwise, and the hop's MAC address
+    /// if it's known.
+    fn next_hop(&self, address: Ipv4Addr) -> (Ipv4Addr, Option<MacAddress>) {
+        if address == Ipv4Addr::BROADCAST {
+            return (address, Some(MacAddress::BROADCAST));
+        }
+
+        let config = &self.config;
+        let hop = if address.same_subnet(config.address, config.netmask) {
+            address
+        } else {
+            config.gateway
+        };
+
+        (hop, self.arp.lookup(hop))
+    }
+}
+
+/// Returns the MAC address of `destination`'s next hop, sending ARP requests
+/// and polling for the answer until it arrives or `arp::RETRIES` requests
+/// have gone unanswered.
+fn resolve(destination: Ipv4Addr) -> Result<MacAddress, Error> {
+    for _ in 0..arp::RETRIES {
+        let (hop, mac) = STACK.lock().next_hop(destination);
+        if let Some(mac) = mac {
+            return Ok(mac);
+        }
+
+        with_device(|stack, device| {
+            arp::request(&stack.config, stack.mac, device, hop, &mut stack.tx)
+        })?;
+
+        let deadline = Instant::now() + Duration::from_millis(arp::WAIT_MS);
+        while Instant::now() < deadline {
+            poll();
+            if let Some(mac) = STACK.lock().arp.lookup(hop) {
+                return Ok(mac);
+            }
+        }
+    }
+
+    Err(Error::Unreachable)
+}
+
+/// The fields of a received IPv4 header the stack uses.
+#[derive(Debug, Clone, Copy)]
+pub struct Ipv4Header {
+    pub source: Ipv4Addr,
+    pub destination: Ipv4Addr,
+    pub protocol: u8,
+}
+
+impl Ipv4Header {
+    /// Parses the IPv4 packet `packet`, returning its header and payload.
+    /// Returns `None` for malformed packets and fragments, which the stack
+    /// doesn't reassemble.
+    fn parse(packet: &[u8]) -> Option<(Ipv4Header, &[u8])> {
+        if packet.len() < IPV4_HEADER || packet[0] >> 4 != 4 {
+            return None;
+        }
+
+        let header_len = (packet[0] & 0xF) as usize * 4;
+        let total_len = read_u16(packet, 2) as usize;
+        if header_len < IPV4_HEADER || total_len < header_len || total_len > packet.len() {
+            return None;
+        }
+
+        let fragment = read_u16(packet, 6);
+        if fragment & 0x3FFF != 0 || checksum(&packet[..header_len], 0) != 0 {
+            return None;
+        }
+
+        let header = Ipv4Header {
+            source: Ipv4Addr(ip_at(packet, 12)),
+            destination: Ipv4Addr(ip_at(packet, 16)),
+            protocol: packet[9],
+        };
+
+        Some((header, &packet[header_len..total_len]))
+    }
+}
+
+/// Writes the Ethernet and IPv4 headers of a packet from `config.address`
+/// to `destination` at `mac` into `frame`, for a `payload_len` byte payload
+/// of `protocol`. Returns the offset of the payload.
+fn write_ipv4_headers(frame: &mut [u8],
+                      config: &Config,
+                      source_mac: MacAddress,
+                      mac: MacAddress,
+                      destination: Ipv4Addr,
+                      protocol: u8,
+                      payload_len: usize) -> usize {
+    write_ethernet_header(frame, mac, source_mac, ethertype::IPV4);
+
+    let id = NEXT_ID.fetch_add(1, Ordering::Relaxed) as u16;
+    let header = &mut frame[ETHERNET_HEADER..ETHERNET_HEADER + IPV4_HEADER];
+    header[0] = 0x45;
+    header[1] = 0;
+    write_u16(header, 2, (IPV4_HEADER + payload_len) as u16);
+    write_u16(header, 4, id);
+    write_u16(header, 6, 0x4000);
+    header[8] = TTL;
+    header[9] = protocol;
+    write_u16(header, 10, 0);
+    header[12..16].copy_from_slice(&config.address.0);
+    header[16..20].copy_from_slice(&destination.0);
+    let sum = checksum(header, 0);
+    write_u16(header, 10, sum);
+
+    ETHERNET_HEADER + IPV4_HEADER
+}
+
+/// Writes an Ethernet header into `frame`.
+fn write_ethernet_header(frame: &mut [u8], destination: MacAddress, source: MacAddress, ethertype: u16) {
+    frame[0..6].copy_from_slice(&destination.0);
+    frame[6..12].copy_from_slice(&source.0);
+    write_u16(frame, 12, ethertype);
+}
+
+/// Returns the partial checksum of the pseudo header covering a `len` byte
+/// `protocol` segment from `source` to `destination`, which UDP and TCP
+/// checksums include.
+fn pseudo_header_sum(source: Ipv4Addr, destination: Ipv4Addr, protocol: u8, len: usize) -> u32 {
+    let mut pseudo = [0; 12];
+    pseudo[0..4].copy_from_slice(&source.0);
+    pseudo[4..8].copy_from_slice(&destination.0);
+    pseudo[9] = protocol;
+    write_u16(&mut pseudo, 10, len as u16);
+
+    // Undo the final complement to continue the sum.
+    !checksum(&pseudo, 0) as u32
+}
+
+/// Returns the Internet checksum of `data`, continuing from the partial sum
+/// `sum`. A packet with a correct checksum field sums to 0.
+pub fn checksum(data: &[u8], mut sum: u32) -> u16 {
+    let mut chunks = data.chunks(2);
+    while let Some(chunk) = chunks.next() {
+        sum += (chunk[0] as u32) << 8 | if chunk.len() > 1 { chunk[1] as u32 } else { 0 };
+    }
+
+    while sum >> 16 != 0 {
+        sum = (sum & 0xFFFF) + (sum >> 16);
+    }
+
+    !sum as u16
+}
+
+/// Reads the big-endian `u16` at `bytes[i..i + 2]`.
+fn read_u16(bytes: &[u8], i: usize) -> u16 {
+    (bytes[i] as u16) << 8 | bytes[i + 1] as u16
+}
+
+/// Reads the big-endian `u32` at `bytes[i..i + 4]`.
+fn read_u32(bytes: &[u8], i: usize) -> u32 {
+    (read_u16(bytes, i) as u32) << 16 | read_u16(bytes, i + 2) as u32
+}
+
+/// Writes `value` big-endian at `bytes[i..i + 2]`.
+fn write_u16(bytes: &mut [u8], i: usize, value: u16) {
+    bytes[i] = (value >> 8) as u8;
+    bytes[i + 1] = value as u8;
+}
+
+/// Writes `value` big-endian at `bytes[i..i + 4]`.
+fn write_u32(bytes: &mut [u8], i: usize, value: u32) {
+    write_u16(bytes, i, (value >> 16) as u16);
+    write_u16(bytes, i + 2, value as u16);
+}
+
+/// Returns the MAC address at `bytes[i..i + 6]`.
+fn mac_at(bytes: &[u8], i: usize) -> [u8; 6] {
+    let mut mac = [0; 6];
+    mac.copy_from_slice(&bytes[i..i + 6]);
+    mac
+}
+
+/// Returns the IPv4 address at `bytes[i..i + 4]`.
+fn ip_at(bytes: &[u8], i: usize) -> [u8; 4] {
+    let mut ip = [0; 4];
+    ip.copy_from_slice(&bytes[i..i + 4]);
+    ip
+}
@@ -0,0 +1,808 @@
+use core::cmp::{min, max};
+use core::time::Duration;
+
+use pi::net::{NetDevice, MacAddress};
+use pi::timer::{Instant, Timer};
+
+use super::{Config, Error, Ipv4Addr, Ipv4Header, protocol, MAX_IPV4_PAYLOAD, STACK};
+use super::{checksum, pseudo_header_sum, read_u16, read_u32, write_u16, write_u32};
+use super::{write_ipv4_headers, poll};
+
+/// The length of a TCP header without options.
+const HEADER_LEN: usize = 20;
+
+/// The length of the maximum segment size option sent with SYNs.
+const MSS_OPTION_LEN: usize = 4;
+
+/// The largest segment the stack sends, and the size it asks peers to keep
+/// to.
+const MAX_SEGMENT: usize = MAX_IPV4_PAYLOAD - HEADER_LEN;
+
+/// The segment size assumed for peers that don't give one.
+const DEFAULT_MSS: usize = 536;
+
+/// The number of connections that can be open at once, accepted or not.
+const MAX_CONNECTIONS: usize = 4;
+
+/// The number of ports that can be listened on at once.
+const MAX_LISTENERS: usize = 2;
+
+/// The size of each connection's send and receive buffers.
+const BUFFER_SIZE: usize = 2048;
+
+/// How long a segment goes unacknowledged before it's resent.
+const RETRANSMIT_MS: u64 = 500;
+
+/// The number of times a segment is resent before the connection is reset.
+const MAX_RETRIES: usize = 8;
+
+/// How long `send_all()` waits for room in the send buffer.
+const SEND_TIMEOUT_MS: u64 = 5000;
+
+/// TCP header flags.
+mod flag {
+    pub const FIN: u8 = 0x01;
+    pub const SYN: u8 = 0x02;
+    pub const RST: u8 = 0x04;
+    pub const PSH: u8 = 0x08;
+    pub const ACK: u8 = 0x10;
+}
+
+/// TCP option kinds.
+mod option {
+    pub const END: u8 = 0;
+    pub const NOP: u8 = 1;
+    pub const MSS: u8 = 2;
+}
+
+/// The states of a connection. Connections are only ever opened passively,
+/// so there's no `Listen` or `SynSent`, and `TimeWait` is left as soon as
+/// the peer's FIN is acknowledged.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+enum State {
+    /// The slot is free.
+    Closed,
+    SynReceived,
+    Established,
+    /// The peer has finished sending; we haven't.
+    CloseWait,
+    /// We've sent our FIN after the peer's and are waiting for its ACK.
+    LastAck,
+    /// We've sent our FIN and are waiting for its ACK.
+    FinWait1,
+    /// Our FIN has been acknowledged; the peer is still sending.
+    FinWait2,
+    /// Both sides sent FINs at once; ours is unacknowledged.
+    Closing,
+    /// Both sides are done; the slot is freed once the last ACK is out.
+    TimeWait,
+}
+
+/// A byte queue of `BUFFER_SIZE` bytes.
+#[derive(Clone, Copy)]
+struct Ring {
+    data: [u8; BUFFER_SIZE],
+    head: usize,
+    len: usize,
+}
+
+const EMPTY_RING: Ring = Ring { data: [0; BUFFER_SIZE], head: 0, len: 0 };
+
+impl Ring {
+    /// Returns the room left in the queue.
+    fn free(&self) -> usize {
+        BUFFER_SIZE - self.len
+    }
+
+    /// Appends as much of `bytes` as fits and returns how much did.
+    fn push(&mut self, bytes: &[u8]) -> usize {
+        let n = min(bytes.len(), self.free());
+        for (i, &byte) in bytes[..n].iter().enumerate() {
+            self.data[(self.head + self.len + i) % BUFFER_SIZE] = byte;
+        }
+
+        self.len += n;
+        n
+    }
+
+    /// Copies the bytes from `offset` bytes into the queue into `buf`
+    /// without removing them. Returns the number copied.
+    fn peek(&self, offset: usize, buf: &mut [u8]) -> usize {
+        let n = min(buf.len(), self.len.saturating_sub(offset));
+        for (i, byte) in buf[..n].iter_mut().enumerate() {
+            *byte = self.data[(self.head + offset + i) % BUFFER_SIZE];
+        }
+
+        n
+    }
+
+    /// Removes the first `n` bytes.
+    fn consume(&mut self, n: usize) {
+        let n = min(n, self.len);
+        self.head = (self.head + n) % BUFFER_SIZE;
+        self.len -= n;
+    }
+}
+
+/// The addresses and ports a connection's segments go between.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+struct Endpoints {
+    remote: Ipv4Addr,
+    remote_mac: MacAddress,
+    remote_port: u16,
+    local_port: u16,
+}
+
+/// The fields of a segment being sent that vary.
+struct Segment {
+    seq: u32,
+    ack: u32,
+    flags: u8,
+    window: usize,
+}
+
+/// A connection and its buffers.
+#[derive(Clone, Copy)]
+struct Connection {
+    state: State,
+    /// Distinguishes this connection from earlier ones in the same slot.
+    id: usize,
+    to: Endpoints,
+    /// Whether a `TcpStream` has been handed out for the connection.
+    accepted: bool,
+    /// Whether the stream has been dropped, so a FIN follows the queued
+    /// data.
+    closing: bool,
+    fin_sent: bool,
+    /// The oldest unacknowledged sequence number.
+    snd_una: u32,
+    /// The sequence number of the next new byte sent.
+    snd_nxt: u32,
+    /// The peer's receive window and maximum segment size.
+    snd_wnd: usize,
+    mss: usize,
+    /// The sequence number of the next byte expected from the peer.
+    rcv_nxt: u32,
+    /// Whether the peer is owed an ACK.
+    ack_due: bool,
+    /// When the oldest unacknowledged segment was last sent, if there is
+    /// one.
+    sent_at: Option<Instant>,
+    retries: usize,
+    /// Received bytes waiting to be read.
+    rx: Ring,
+    /// Bytes sent but unacknowledged, followed by bytes not yet sent.
+    tx: Ring,
+}
+
+const FREE_CONNECTION: Connection = Connection {
+    state: State::Closed,
+    id: 0,
+    to: Endpoints {
+        remote: Ipv4Addr::UNSPECIFIED,
+        remote_mac: MacAddress([0; 6]),
+        remote_port: 0,
+        local_port: 0,
+    },
+    accepted: false,
+    closing: false,
+    fin_sent: false,
+    snd_una: 0,
+    snd_nxt: 0,
+    snd_wnd: 0,
+    mss: DEFAULT_MSS,
+    rcv_nxt: 0,
+    ack_due: false,
+    sent_at: None,
+    retries: 0,
+    rx: EMPTY_RING,
+    tx: EMPTY_RING,
+};
+
+/// The stack's TCP listeners and connections.
+pub struct Connections {
+    connections: [Connection; MAX_CONNECTIONS],
+    /// The ports listened on, or 0 for free entries.
+    listeners: [u16; MAX_LISTENERS],
+    next_id: usize,
+}
+
+impl Connections {
+    /// Returns a set with no listeners or connections.
+    pub const fn new() -> Connections {
+        Connections {
+            connections: [FREE_CONNECTION; MAX_CONNECTIONS],
+            listeners: [0; MAX_LISTENERS],
+            next_id: 0,
+        }
+    }
+
+    /// Returns the connection in slot `index` if it's still connection `id`.
+    fn find(&mut self, index: usize, id: usize) -> Option<&mut Connection> {
+        let connection = &mut self.connections[index];
+        if connection.state != State::Closed && connection.id == id {
+            Some(connection)
+        } else {
+            None
+        }
+    }
+
+    /// Starts accepting connections to `port`.
+    fn listen(&mut self, port: u16) -> Result<(), Error> {
+        if port == 0 || self.listeners.contains(&port) {
+            return Err(Error::AddressInUse);
+        }
+
+        match self.listeners.iter_mut().find(|listener| **listener == 0) {
+            Some(listener) => {
+                *listener = port;
+                Ok(())
+            }
+            None => Err(Error::AddressInUse)
+        }
+    }
+
+    /// Stops accepting connections to `port` and closes those not yet
+    /// accepted.
+    fn unlisten(&mut self, port: u16) {
+        for listener in self.listeners.iter_mut().filter(|listener| **listener == port) {
+            *listener = 0;
+        }
+
+        for connection in self.connections.iter_mut() {
+            if connection.to.local_port == port && !connection.accepted {
+                connection.accepted = true;
+                connection.closing = true;
+            }
+        }
+    }
+
+    /// Hands out the oldest connection to `port` not yet accepted, returning
+    /// its slot and id.
+    fn accept(&mut self, port: u16) -> Option<(usize, usize)> {
+        let index = self.connections.iter()
+            .enumerate()
+            .filter(|&(_, c)| c.to.local_port == port && !c.accepted)
+            .filter(|&(_, c)| c.state == State::Established || c.state == State::CloseWait)
+            .min_by_key(|&(_, c)| c.id)
+            .map(|(index, _)| index)?;
+
+        let connection = &mut self.connections[index];
+        connection.accepted = true;
+        Some((index, connection.id))
+    }
+
+    /// Handles the TCP segment `segment` from `source_mac`: opens connections
+    /// to ports being listened on, queues received data, and acknowledges
+    /// it, building segments sent in `tx`. Segments for no connection are
+    /// answered with a reset.
+    pub(super) fn handle(&mut self,
+                         config: &Config,
+                         mac: MacAddress,
+                         device: &mut dyn NetDevice,
+                         source_mac: MacAddress,
+                         header: &Ipv4Header,
+                         segment: &[u8],
+                         tx: &mut [u8]) -> Result<(), Error> {
+        if segment.len() < HEADER_LEN || header.destination != config.address {
+            return Ok(());
+        }
+
+        let header_len = (segment[12] >> 4) as usize * 4;
+        let pseudo = pseudo_header_sum(header.source, header.destination, protocol::TCP, segment.len());
+        if header_len < HEADER_LEN || header_len > segment.len() || checksum(segment, pseudo) != 0 {
+            return Ok(());
+        }
+
+        let to = Endpoints {
+            remote: header.source,
+            remote_mac: source_mac,
+            remote_port: read_u16(segment, 0),
+            local_port: read_u16(segment, 2),
+        };
+
+        let seq = read_u32(segment, 4);
+        let ack = read_u32(segment, 8);
+        let flags = segment[13];
+        let window = read_u16(segment, 14) as usize;
+        let data = &segment[header_len..];
+
+        let found = self.connections.iter().position(|c| {
+            c.state != State::Closed && c.to.remote == to.remote
+                && c.to.remote_port == to.remote_port && c.to.local_port == to.local_port
+        });
+
+        let index = match found {
+            Some(index) => index,
+            None if flags & (flag::SYN | flag::ACK | flag::RST) == flag::SYN => {
+                return self.open(config, mac, device, to, seq, window, &segment[..header_len], tx);
+            }
+            None => return reset(config, mac, device, tx, &to, seq, ack, flags, data.len())
+        };
+
+        let connection = &mut self.connections[index];
+        if flags & flag::RST != 0 {
+            // Only resets in the window are believed.
+            if (seq.wrapping_sub(connection.rcv_nxt) as usize) <= connection.rx.free() {
+                connection.state = State::Closed;
+            }
+
+            return Ok(());
+        }
+
+        if flags & flag::SYN != 0 {
+            // The peer didn't see our SYN-ACK.
+            if connection.state == State::SynReceived {
+                return connection.retransmit(config, mac, device, tx);
+            }
+
+            return Ok(());
+        }
+
+        if flags & flag::ACK == 0 {
+            return Ok(());
+        }
+
+        connection.acknowledge(ack, window);
+        connection.receive(seq, flags, data);
+        let result = connection.output(config, mac, device, tx);
+        if connection.state == State::TimeWait {
+            connection.state = State::Closed;
+        }
+
+        result
+    }
+
+    /// Opens a connection for the SYN from `to` if its port is listened on
+    /// and a slot is free, answering with a SYN-ACK, and resets it otherwise.
+    fn open(&mut self,
+            config: &Config,
+            mac: MacAddress,
+            device: &mut dyn NetDevice,
+            to: Endpoints,
+            seq: u32,
+            window: usize,
+            header: &[u8],
+            tx: &mut [u8]) -> Result<(), Error> {
+        let slot = self.connections.iter().position(|c| c.state == State::Closed);
+        let slot = match slot {
+            Some(slot) if self.listeners.contains(&to.local_port) => slot,
+            _ => return reset(config, mac, device, tx, &to, seq, 0, flag::SYN, 0)
+        };
+
+        // The initial sequence number is clock driven, as RFC 793 suggests.
+        let iss = Timer::new().read() as u32;
+        self.next_id += 1;
+        self.connections[slot] = Connection {
+            state: State::SynReceived,
+            id: self.next_id,
+            to,
+            snd_una: iss,
+            snd_nxt: iss.wrapping_add(1),
+            snd_wnd: window,
+            mss: peer_mss(header),
+            rcv_nxt: seq.wrapping_add(1),
+            ..FREE_CONNECTION
+        };
+
+        self.connections[slot].retransmit(config, mac, device, tx)
+    }
+
+    /// Sends whatever each connection has waiting: new data, FINs, owed
+    /// ACKs, and retransmissions that are due.
+    pub(super) fn transmit(&mut self,
+                           config: &Config,
+                           mac: MacAddress,
+                           device: &mut dyn NetDevice,
+                           tx: &mut [u8]) {
+        for connection in self.connections.iter_mut() {
+            if connection.state != State::Closed {
+                let _ = connection.output(config, mac, device, tx);
+            }
+        }
+    }
+}
+
+impl Connection {
+    /// Returns the number of sequence numbers sent but not acknowledged.
+    fn in_flight(&self) -> usize {
+        self.snd_nxt.wrapping_sub(self.snd_una) as usize
+    }
+
+    /// Handles the acknowledgement `ack` and the peer's window `window`.
+    fn acknowledge(&mut self, ack: u32, window: usize) {
+        let mut acked = ack.wrapping_sub(self.snd_una) as usize;
+        if acked > self.in_flight() {
+            // It acknowledges something never sent.
+            self.ack_due = true;
+            return;
+        }
+
+        self.snd_wnd = window;
+        if acked == 0 {
+            return;
+        }
+
+        // The SYN and FIN each take a sequence number but no buffer space.
+        if self.state == State::SynReceived {
+            self.state = State::Established;
+            acked -= 1;
+        }
+
+        let fin_acked = self.fin_sent && ack == self.snd_nxt;
+        if fin_acked {
+            acked -= 1;
+        }
+
+        self.tx.consume(acked);
+        self.snd_una = ack;
+        self.retries = 0;
+        self.sent_at = if self.in_flight() == 0 { None } else { Some(Instant::now()) };
+
+        if fin_acked {
+            self.state = match self.state {
+                State::FinWait1 => State::FinWait2,
+                State::Closing => State::TimeWait,
+                State::LastAck => State::Closed,
+                state => state
+            };
+        }
+    }
+
+    /// Queues the in-order part of `data`, which starts at `seq`, and handles
+    /// a FIN following it.
+    fn receive(&mut self, seq: u32, flags: u8, data: &[u8]) {
+        match self.state {
+            State::Established | State::FinWait1 | State::FinWait2 => {  }
+            _ => return
+        }
+
+        if !data.is_empty() || flags & flag::FIN != 0 {
+            self.ack_due = true;
+        }
+
+        // Out of order segments are dropped; the ACK asks for a resend.
+        if seq != self.rcv_nxt {
+            return;
+        }
+
+        let accepted = self.rx.push(data);
+        self.rcv_nxt = self.rcv_nxt.wrapping_add(accepted as u32);
+        if flags & flag::FIN != 0 && accepted == data.len() {
+            self.rcv_nxt = self.rcv_nxt.wrapping_add(1);
+            self.state = match self.state {
+                State::Established => State::CloseWait,
+                State::FinWait1 => State::Closing,
+                _ => State::TimeWait
+            };
+        }
+    }
+
+    /// Sends what's waiting: resends the oldest segment if its timer has run
+    /// out, then sends as much new data as the peer's window allows, a FIN
+    /// once all data is out if the stream has been dropped, and an ACK if
+    /// one is owed and nothing else carried it. Resets the connection when
+    /// the peer stops answering.
+    fn output(&mut self,
+              config: &Config,
+              mac: MacAddress,
+              device: &mut dyn NetDevice,
+              tx: &mut [u8]) -> Result<(), Error> {
+        if let Some(sent_at) = self.sent_at {
+            if sent_at.elapsed() >= Duration::from_millis(RETRANSMIT_MS) {
+                if self.retries == MAX_RETRIES {
+                    self.state = State::Closed;
+                    let seq = self.snd_nxt;
+                    return self.send(config, mac, device, tx, seq, flag::RST, 0, 0);
+                }
+
+                self.retries += 1;
+                self.retransmit(config, mac, device, tx)?;
+            }
+        }
+
+        if self.state == State::SynReceived {
+            return Ok(());
+        }
+
+        let mut sent = false;
+        if !self.fin_sent {
+            // A closed window still lets a byte out, which probes for it
+            // opening again.
+            let window = max(self.snd_wnd, 1);
+            loop {
+                let offset = self.in_flight();
+                let len = min(min(self.tx.len - offset, self.mss), window.saturating_sub(offset));
+                if len == 0 {
+                    break;
+                }
+
+                let seq = self.snd_nxt;
+                self.send(config, mac, device, tx, seq, flag::ACK | flag::PSH, offset, len)?;
+                self.snd_nxt = seq.wrapping_add(len as u32);
+                self.sent_at = self.sent_at.or(Some(Instant::now()));
+                sent = true;
+            }
+
+            if self.closing && self.in_flight() == self.tx.len {
+                let seq = self.snd_nxt;
+                self.send(config, mac, device, tx, seq, flag::ACK | flag::FIN, 0, 0)?;
+                self.snd_nxt = seq.wrapping_add(1);
+                self.sent_at = self.sent_at.or(Some(Instant::now()));
+                self.fin_sent = true;
+                self.state = match self.state {
+                    State::CloseWait => State::LastAck,
+                    _ => State::FinWait1
+                };
+                sent = true;
+            }
+        }
+
+        if self.ack_due && !sent {
+            let seq = self.snd_nxt;
+            self.send(config, mac, device, tx, seq, flag::ACK, 0, 0)?;
+        }
+
+        self.ack_due = false;
+        Ok(())
+    }
+
+    /// Resends the oldest unacknowledged segment, up to one segment of data
+    /// with the FIN if it fits, and restarts the timer.
+    fn retransmit(&mut self,
+                  config: &Config,
+                  mac: MacAddress,
+                  device: &mut dyn NetDevice,
+                  tx: &mut [u8]) -> Result<(), Error> {
+        self.sent_at = Some(Instant::now());
+        let seq = self.snd_una;
+        if self.state == State::SynReceived {
+            return self.send(config, mac, device, tx, seq, flag::SYN | flag::ACK, 0, 0);
+        }
+
+        let data = min(self.in_flight(), self.tx.len);
+        let len = min(data, self.mss);
+        let fin = if self.fin_sent && len == data { flag::FIN } else { 0 };
+        self.send(config, mac, device, tx, seq, flag::ACK | flag::PSH | fin, 0, len)
+    }
+
+    /// Sends a segment starting at `seq` with `flags`, carrying the `len`
+    /// bytes `offset` bytes into the send buffer.
+    fn send(&self,
+            config: &Config,
+            mac: MacAddress,
+            device: &mut dyn NetDevice,
+            tx: &mut [u8],
+            seq: u32,
+            flags: u8,
+            offset: usize,
+            len: usize) -> Result<(), Error> {
+        let segment = Segment { seq, ack: self.rcv_nxt, flags, window: self.rx.free() };
+        send_segment(config, mac, device, tx, &self.to, &segment, Some((&self.tx, offset, len)))
+    }
+}
+
+/// Answers a segment from `to` that belongs to no connection with a reset,
+/// as RFC 793 asks. Resets themselves aren't answered.
+fn reset(config: &Config,
+         mac: MacAddress,
+         device: &mut dyn NetDevice,
+         tx: &mut [u8],
+         to: &Endpoints,
+         seq: u32,
+         ack: u32,
+         flags: u8,
+         len: usize) -> Result<(), Error> {
+    if flags & flag::RST != 0 {
+        return Ok(());
+    }
+
+    let segment = if flags & flag::ACK != 0 {
+        Segment { seq: ack, ack: 0, flags: flag::RST, window: 0 }
+    } else {
+        let len = len + (flags & flag::SYN != 0) as usize + (flags & flag::FIN != 0) as usize;
+        Segment { seq: 0, ack: seq.wrapping_add(len as u32), flags: flag::RST | flag::ACK, window: 0 }
+    };
+
+    send_segment(config, mac, device, tx, to, &segment, None)
+}
+
+/// Sends `segment` to `to`, carrying `data`'s `len` bytes from `offset`
+/// bytes into the ring. SYNs carry the maximum segment size option.
+fn send_segment(config: &Config,
+                mac: MacAddress,
+                device: &mut dyn NetDevice,
+                tx: &mut [u8],
+                to: &Endpoints,
+                segment: &Segment,
+                data: Option<(&Ring, usize, usize)>) -> Result<(), Error> {
+    let header_len = HEADER_LEN + if segment.flags & flag::SYN != 0 { MSS_OPTION_LEN } else { 0 };
+    let len = header_len + data.map_or(0, |(_, _, len)| len);
+    let offset = write_ipv4_headers(tx, config, mac, to.remote_mac, to.remote, protocol::TCP, len);
+
+    {
+        let packet = &mut tx[offset..offset + len];
+        write_u16(packet, 0, to.local_port);
+        write_u16(packet, 2, to.remote_port);
+        write_u32(packet, 4, segment.seq);
+        write_u32(packet, 8, segment.ack);
+        packet[12] = ((header_len / 4) << 4) as u8;
+        packet[13] = segment.flags;
+        write_u16(packet, 14, min(segment.window, 0xFFFF) as u16);
+        write_u16(packet, 16, 0);
+        write_u16(packet, 18, 0);
+
+        if header_len > HEADER_LEN {
+            packet[20] = option::MSS;
+            packet[21] = MSS_OPTION_LEN as u8;
+            write_u16(packet, 22, MAX_SEGMENT as u16);
+        }
+
+        if let Some((ring, start, _)) = data {
+            ring.peek(start, &mut packet[header_len..]);
+        }
+
+        let sum = checksum(packet, pseudo_header_sum(config.address, to.remote, protocol::TCP, len));
+        write_u16(packet, 16, sum);
+    }
+
+    device.send_frame(&tx[..offset + len])?;
+    Ok(())
+}
+
+/// Returns the maximum segment size given in the options of the SYN header
+/// `header`, or the default if there isn't one.
+fn peer_mss(header: &[u8]) -> usize {
+    let mut i = HEADER_LEN;
+    while i < header.len() {
+        match header[i] {
+            option::END => break,
+            option::NOP => i += 1,
+            kind => {
+                if i + 2 > header.len() || header[i + 1] < 2 {
+                    break;
+                }
+
+                let len = header[i + 1] as usize;
+                if kind == option::MSS && len == MSS_OPTION_LEN && i + len <= header.len() {
+                    return min(read_u16(header, i + 2) as usize, MAX_SEGMENT);
+                }
+
+                i += len;
+            }
+        }
+    }
+
+    DEFAULT_MSS
+}
+
+/// A port accepting TCP connections. Connections are completed by the tick
+/// and wait to be picked up by `accept()`. The port is released when the
+/// listener is dropped.
+#[derive(Debug)]
+pub struct TcpListener {
+    port: u16,
+}
+
+impl TcpListener {
+    /// Starts accepting connections to `port`.
+    pub fn bind(port: u16) -> Result<TcpListener, Error> {
+        STACK.lock().tcp.listen(port)?;
+        Ok(TcpListener { port })
+    }
+
+    /// Returns the port being listened on.
+    pub fn port(&self) -> u16 {
+        self.port
+    }
+
+    /// Returns the oldest connection made to the port and not yet accepted,
+    /// or `None` if there isn't one. Never blocks.
+    pub fn accept(&self) -> Option<TcpStream> {
+        let mut stack = STACK.lock();
+        let (index, id) = stack.tcp.accept(self.port)?;
+        let peer = {
+            let to = &stack.tcp.connections[index].to;
+            (to.remote, to.remote_port)
+        };
+
+        Some(TcpStream { index, id, peer })
+    }
+}
+
+impl Drop for TcpListener {
+    fn drop(&mut self) {
+        STACK.lock().tcp.unlisten(self.port);
+    }
+}
+
+/// An accepted TCP connection. Writes are queued and sent by the tick, which
+/// also queues received data until read. Dropping the stream sends what's
+/// queued and then closes the connection.
+#[derive(Debug)]
+pub struct TcpStream {
+    index: usize,
+    id: usize,
+    peer: (Ipv4Addr, u16),
+}
+
+impl TcpStream {
+    /// Returns the address and port of the other end.
+    pub fn peer_addr(&self) -> (Ipv4Addr, u16) {
+        self.peer
+    }
+
+    /// Reads received data into `buf` without blocking. Returns the number of
+    /// bytes read, or `None` if nothing has arrived.
+    ///
+    /// # Errors
+    ///
+    /// Returns `Error::Closed` once the peer has closed or reset the
+    /// connection and all it sent has been read.
+    pub fn recv(&self, buf: &mut [u8]) -> Result<Option<usize>, Error> {
+        let mut stack = STACK.lock();
+        let connection = stack.tcp.find(self.index, self.id).ok_or(Error::Closed)?;
+        if connection.rx.len == 0 {
+            return match connection.state {
+                State::Established | State::FinWait1 | State::FinWait2 => Ok(None),
+                _ => Err(Error::Closed)
+            };
+        }
+
+        let was_free = connection.rx.free();
+        let n = connection.rx.peek(0, buf);
+        connection.rx.consume(n);
+
+        // Tell the peer once there's room for a full segment again.
+        if was_free < connection.mss && connection.rx.free() >= connection.mss {
+            connection.ack_due = true;
+        }
+
+        Ok(Some(n))
+    }
+
+    /// Queues as much of `data` as fits in the send buffer without blocking
+    /// and returns how much did.
+    ///
+    /// # Errors
+    ///
+    /// Returns `Error::Closed` if the connection has been closed or reset.
+    pub fn send(&self, data: &[u8]) -> Result<usize, Error> {
+        let mut stack = STACK.lock();
+        let connection = stack.tcp.find(self.index, self.id).ok_or(Error::Closed)?;
+        match connection.state {
+            State::Established | State::CloseWait => Ok(connection.tx.push(data)),
+            _ => Err(Error::Closed)
+        }
+    }
+
+    /// Queues all of `data`, polling the stack while the send buffer is full.
+    ///
+    /// # Errors
+    ///
+    /// Returns `Error::Closed` if the connection is closed or reset, and
+    /// `Error::TimedOut` if the buffer doesn't drain within
+    /// `SEND_TIMEOUT_MS`.
+    pub fn send_all(&self, mut data: &[u8]) -> Result<(), Error> {
+        let deadline = Instant::now() + Duration::from_millis(SEND_TIMEOUT_MS);
+        loop {
+            let sent = self.send(data)?;
+            data = &data[sent..];
+            if data.is_empty() {
+                return Ok(());
+            } else if Instant::now() >= deadline {
+                return Err(Error::TimedOut);
+            }
+
+            poll();
+        }
+    }
+}
+
+impl Drop for TcpStream {
+    fn drop(&mut self) {
+        if let Some(connection) = STACK.lock().tcp.find(self.index, self.id) {
+            connection.closing = true;
+        }
+    }
+}
@@ -0,0 +1,210 @@
+use core::time::Duration;
+
+use pi::timer::Instant;
+
+use super::{Error, Ipv4Addr, UdpSocket};
+use super::{poll, read_u16, write_u16};
+use super::udp::MAX_PAYLOAD;
+
+/// The port TFTP servers take requests on.
+const SERVER_PORT: u16 = 69;
+
+/// TFTP opcodes.
+mod opcode {
+    pub const RRQ: u16 = 1;
+    pub const DATA: u16 = 3;
+    pub const ACK: u16 = 4;
+    pub const ERROR: u16 = 5;
+    pub const OACK: u16 = 6;
+}
+
+/// The block size servers use unless they accept the `blksize` option.
+const DEFAULT_BLOCK_SIZE: usize = 512;
+
+/// The block size asked for: the most that fits in one datagram after the
+/// opcode and block number.
+const BLOCK_SIZE: usize = MAX_PAYLOAD - 4;
+
+/// The number of times a packet is resent before giving up.
+const RETRIES: usize = 5;
+
+/// How long to wait for each packet from the server.
+const WAIT_MS: u64 = 1000;
+
+/// An error from a TFTP transfer.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum TftpError {
+    /// The server sent an error packet with this code, e.g. 1 for a file
+    /// that isn't found.
+    Server(u16),
+    /// The server stopped answering.
+    TimedOut,
+    /// The file doesn't fit in the buffer.
+    TooLarge,
+    /// The server sent something that isn't TFTP.
+    BadPacket,
+    /// The network stack failed.
+    Net(Error),
+}
+
+impl From<Error> for TftpError {
+    fn from(error: Error) -> TftpError {
+        TftpError::Net(error)
+    }
+}
+
+/// Downloads `filename` from the TFTP server at `server` into `buf` in octet
+/// mode, asking for large blocks, and returns its length. `progress` is
+/// called with the number of bytes received after each block.
+pub fn fetch<F: FnMut(usize)>(server: Ipv4Addr,
+                              filename: &str,
+                              buf: &mut [u8],
+                              mut progress: F) -> Result<usize, TftpError> {
+    let socket = UdpSocket::bind(0)?;
+
+    // The request goes to the well-known port; the server answers from the
+    // port the rest of the transfer uses.
+    let mut packet = [0; MAX_PAYLOAD];
+    let mut packet_len = request(&mut packet, filename)?;
+    let mut port = SERVER_PORT;
+    let mut tid = None;
+
+    let mut block_size = DEFAULT_BLOCK_SIZE;
+    let mut block: u16 = 1;
+    let mut received = 0;
+    let mut reply = [0; MAX_PAYLOAD];
+
+    loop {
+        let len = exchange(&socket, &packet[..packet_len], server, port, &mut tid, &mut reply)?;
+        if len < 4 {
+            return Err(TftpError::BadPacket);
+        }
+
+        port = tid.unwrap_or(SERVER_PORT);
+        match read_u16(&reply, 0) {
+            opcode::OACK if block == 1 => {
+                block_size = negotiated_block_size(&reply[2..len]).ok_or(TftpError::BadPacket)?;
+                packet_len = ack(&mut packet, 0);
+            }
+            opcode::DATA if read_u16(&reply, 2) == block => {
+                let data = &reply[4..len];
+                if received + data.len() > buf.len() {
+                    let _ = socket.send_to(error(&mut packet, 3), server, port);
+                    return Err(TftpError::TooLarge);
+                }
+
+                buf[received..received + data.len()].copy_from_slice(data);
+                received += data.len();
+                progress(received);
+
+                packet_len = ack(&mut packet, block);
+                if data.len() < block_size {
+                    socket.send_to(&packet[..packet_len], server, port)?;
+                    return Ok(received);
+                }
+
+                block = block.wrapping_add(1);
+            }
+            opcode::ERROR => return Err(TftpError::Server(read_u16(&reply, 2))),
+            // A duplicate of the previous block: the last ACK is resent.
+            _ => {  }
+        }
+    }
+}
+
+/// Sends `packet` to `port` at `server` until a reply comes back from the
+/// transfer's port `tid`, or from any port if it isn't known yet, in which
+/// case it becomes `tid`. Returns the reply's length.
+fn exchange(socket: &UdpSocket,
+            packet: &[u8],
+            server: Ipv4Addr,
+            port: u16,
+            tid: &mut Option<u16>,
+            reply: &mut [u8]) -> Result<usize, TftpError> {
+    for _ in 0..RETRIES {
+        socket.send_to(packet, server, port)?;
+
+        let deadline = Instant::now() + Duration::from_millis(WAIT_MS);
+        while Instant::now() < deadline {
+            poll();
+
+            match socket.recv_from(reply) {
+                Some((len, source, from)) if source == server && tid.map_or(true, |t| t == from) => {
+                    *tid = Some(from);
+                    return Ok(len);
+                }
+                _ => continue
+            }
+        }
+    }
+
+    Err(TftpError::TimedOut)
+}
+
+/// Writes a read request for `filename` into `packet`, asking for
+/// `BLOCK_SIZE` blocks, and returns its length.
+fn request(packet: &mut [u8], filename: &str) -> Result<usize, TftpError> {
+    let mut digits = [0; 5];
+    let block_size = decimal(BLOCK_SIZE, &mut digits);
+    let fields: [&[u8]; 4] = [filename.as_bytes(), b"octet", b"blksize", block_size];
+
+    write_u16(packet, 0, opcode::RRQ);
+    let mut i = 2;
+    for field in fields.iter() {
+        if i + field.len() + 1 > packet.len() {
+            return Err(TftpError::Net(Error::TooLarge));
+        }
+
+        packet[i..i + field.len()].copy_from_slice(field);
+        packet[i + field.len()] = 0;
+        i += field.len() + 1;
+    }
+
+    Ok(i)
+}
+
+/// Writes an acknowledgement of `block` into `packet` and returns its length.
+fn ack(packet: &mut [u8], block: u16) -> usize {
+    write_u16(packet, 0, opcode::ACK);
+    write_u16(packet, 2, block);
+    4
+}
+
+/// Writes an error packet with `code` and no message into `packet` and
+/// returns it.
+fn error(packet: &mut [u8], code: u16) -> &[u8] {
+    write_u16(packet, 0, opcode::ERROR);
+    write_u16(packet, 2, code);
+    packet[4] = 0;
+    &packet[..5]
+}
+
+/// Returns the block size in the options acknowledgement `options`, or the
+/// default if the server left it out. Returns `None` if the server chose a
+/// larger block than was asked for.
+fn negotiated_block_size(options: &[u8]) -> Option<usize> {
+    let mut fields = options.split(|&byte| byte == 0);
+    while let (Some(name), Some(value)) = (fields.next(), fields.next()) {
+        if name.eq_ignore_ascii_case(b"blksize") {
+            let size = ::core::str::from_utf8(value).ok()?.parse().ok()?;
+            return if size <= BLOCK_SIZE { Some(size) } else { None };
+        }
+    }
+
+    Some(DEFAULT_BLOCK_SIZE)
+}
+
+/// Writes `value` in decimal into `digits` and returns the digits written.
+fn decimal(mut value: usize, digits: &mut [u8]) -> &[u8] {
+    let mut i = digits.len();
+    loop {
+        i -= 1;
+        digits[i] = b'0' + (value % 10) as u8;
+        value /= 10;
+        if value == 0 || i == 0 {
+            break;
+        }
+    }
+
+    &digits[i..]
+}
@@ -0,0 +1,226 @@
+use super::{Error, Ipv4Addr, Ipv4Header, protocol, MAX_IPV4_PAYLOAD, STACK};
+use super::{checksum, pseudo_header_sum, read_u16, write_u16, write_ipv4_headers};
+use super::{with_device, resolve};
+
+/// The length of a UDP header.
+const HEADER_LEN: usize = 8;
+
+/// The largest UDP payload sent or received.
+pub const MAX_PAYLOAD: usize = MAX_IPV4_PAYLOAD - HEADER_LEN;
+
+/// The number of sockets that can be bound at once.
+const MAX_SOCKETS: usize = 4;
+
+/// The number of datagrams a socket queues before dropping new ones.
+const QUEUE_LEN: usize = 4;
+
+/// The range ephemeral ports are taken from.
+const EPHEMERAL_START: u16 = 49152;
+const EPHEMERAL_END: u16 = 65535;
+
+/// A received datagram waiting to be read.
+#[derive(Clone, Copy)]
+struct Datagram {
+    source: Ipv4Addr,
+    port: u16,
+    len: usize,
+    data: [u8; MAX_PAYLOAD],
+}
+
+const EMPTY_DATAGRAM: Datagram = Datagram {
+    source: Ipv4Addr([0; 4]),
+    port: 0,
+    len: 0,
+    data: [0; MAX_PAYLOAD],
+};
+
+/// A bound port and the datagrams received on it.
+#[derive(Clone, Copy)]
+struct Socket {
+    /// The bound port, or 0 if the socket is free.
+    port: u16,
+    queue: [Datagram; QUEUE_LEN],
+    head: usize,
+    queued: usize,
+}
+
+const FREE_SOCKET: Socket = Socket {
+    port: 0,
+    queue: [EMPTY_DATAGRAM; QUEUE_LEN],
+    head: 0,
+    queued: 0,
+};
+
+/// The stack's UDP sockets.
+pub struct Sockets {
+    sockets: [Socket; MAX_SOCKETS],
+    next_ephemeral: u16,
+}
+
+impl Sockets {
+    /// Returns a set of free sockets.
+    pub const fn new() -> Sockets {
+        Sockets { sockets: [FREE_SOCKET; MAX_SOCKETS], next_ephemeral: EPHEMERAL_START }
+    }
+
+    /// Returns the socket bound to `port`.
+    fn find(&mut self, port: u16) -> Option<&mut Socket> {
+        self.sockets.iter_mut().find(|socket| socket.port != 0 && socket.port == port)
+    }
+
+    /// Binds a free socket to `port`, or to a free ephemeral port if `port`
+    /// is 0. Returns the port.
+    fn bind(&mut self, port: u16) -> Result<u16, Error> {
+        let port = if port != 0 { port } else { self.ephemeral_port()? };
+        if self.find(port).is_some() {
+            return Err(Error::AddressInUse);
+        }
+
+        match self.sockets.iter_mut().find(|socket| socket.port == 0) {
+            Some(socket) => {
+                *socket = Socket { port, ..FREE_SOCKET };
+                Ok(port)
+            }
+            None => Err(Error::AddressInUse)
+        }
+    }
+
+    /// Returns an ephemeral port no socket is bound to.
+    fn ephemeral_port(&mut self) -> Result<u16, Error> {
+        for _ in 0..MAX_SOCKETS + 1 {
+            let port = self.next_ephemeral;
+            self.next_ephemeral = if port == EPHEMERAL_END { EPHEMERAL_START } else { port + 1 };
+            if self.find(port).is_none() {
+                return Ok(port);
+            }
+        }
+
+        Err(Error::AddressInUse)
+    }
+
+    /// Queues the UDP datagram `datagram` on the socket bound to its
+    /// destination port. Datagrams for unbound ports, and those arriving to
+    /// a full queue, are dropped.
+    pub(super) fn handle(&mut self, header: &Ipv4Header, datagram: &[u8]) {
+        if datagram.len() < HEADER_LEN {
+            return;
+        }
+
+        let len = read_u16(datagram, 4) as usize;
+        if len < HEADER_LEN || len > datagram.len() || len - HEADER_LEN > MAX_PAYLOAD {
+            return;
+        }
+
+        // A zero checksum means the sender didn't compute one.
+        let pseudo = pseudo_header_sum(header.source, header.destination, protocol::UDP, len);
+        if read_u16(datagram, 6) != 0 && checksum(&datagram[..len], pseudo) != 0 {
+            return;
+        }
+
+        let socket = match self.find(read_u16(datagram, 2)) {
+            Some(socket) => socket,
+            None => return
+        };
+
+        if socket.queued == QUEUE_LEN {
+            return;
+        }
+
+        {
+            let slot = &mut socket.queue[(socket.head + socket.queued) % QUEUE_LEN];
+            slot.source = header.source;
+            slot.port = read_u16(datagram, 0);
+            slot.len = len - HEADER_LEN;
+            slot.data[..slot.len].copy_from_slice(&datagram[HEADER_LEN..len]);
+        }
+
+        socket.queued += 1;
+    }
+}
+
+/// A UDP socket bound to a local port. Sends go out immediately; received
+/// datagrams are queued by the tick until read. The port is released when
+/// the socket is dropped.
+#[derive(Debug)]
+pub struct UdpSocket {
+    port: u16,
+}
+
+impl UdpSocket {
+    /// Binds a socket to `port`, or to a free ephemeral port if `port` is 0.
+    pub fn bind(port: u16) -> Result<UdpSocket, Error> {
+        let port = STACK.lock().udp.bind(port)?;
+        Ok(UdpSocket { port })
+    }
+
+    /// Returns the local port the socket is bound to.
+    pub fn port(&self) -> u16 {
+        self.port
+    }
+
+    /// Sends `data` to `port` at `address`, resolving its next hop with ARP
+    /// first if needed. `Ipv4Addr::BROADCAST` sends to the whole subnet.
+    pub fn send_to(&self, data: &[u8], address: Ipv4Addr, port: u16) -> Result<(), Error> {
+        if data.len() > MAX_PAYLOAD {
+            return Err(Error::TooLarge);
+        }
+
+        let mac = resolve(address)?;
+        with_device(|stack, device| {
+            let len = HEADER_LEN + data.len();
+            let offset = write_ipv4_headers(&mut stack.tx, &stack.config, stack.mac, mac, address,
+                                            protocol::UDP, len);
+
+            {
+                let source = stack.config.address;
+                let datagram = &mut stack.tx[offset..offset + len];
+                write_u16(datagram, 0, self.port);
+                write_u16(datagram, 2, port);
+                write_u16(datagram, 4, len as u16);
+                write_u16(datagram, 6, 0);
+                datagram[HEADER_LEN..].copy_from_slice(data);
+
+                // A computed checksum of 0 is sent as its complement, since 0
+                // means "no checksum".
+                let sum = match checksum(datagram, pseudo_header_sum(source, address, protocol::UDP, len)) {
+                    0 => 0xFFFF,
+                    sum => sum
+                };
+                write_u16(datagram, 6, sum);
+            }
+
+            device.send_frame(&stack.tx[..offset + len])?;
+            Ok(())
+        })
+    }
+
+    /// Reads the oldest datagram received on the socket into `buf` without
+    /// blocking. Returns its length, truncated to `buf.len()`, and the
+    /// address and port it came from, or `None` if nothing has arrived.
+    pub fn recv_from(&self, buf: &mut [u8]) -> Option<(usize, Ipv4Addr, u16)> {
+        let mut stack = STACK.lock();
+        let socket = stack.udp.find(self.port)?;
+        if socket.queued == 0 {
+            return None;
+        }
+
+        let (len, source, port) = {
+            let datagram = &socket.queue[socket.head];
+            let len = ::core::cmp::min(datagram.len, buf.len());
+            buf[..len].copy_from_slice(&datagram.data[..len]);
+            (len, datagram.source, datagram.port)
+        };
+
+        socket.head = (socket.head + 1) % QUEUE_LEN;
+        socket.queued -= 1;
+        Some((len, source, port))
+    }
+}
+
+impl Drop for UdpSocket {
+    fn drop(&mut self) {
+        if let Some(socket) = STACK.lock().udp.find(self.port) {
+            socket.port = 0;
+        }
+    }
+}
@@ -0,0 +1,115 @@
+use core::{mem, ptr, slice};
+
+use pi::{info, power, resident};
+use pi::interrupt::Controller;
+use pi::timer::spin_sleep_ms;
+
+use net::{self, Ipv4Addr, Origin};
+use mutex::Mutex;
+use net::tftp::{self, TftpError};
+use traps;
+
+/// The address kernels are loaded at and entered from, as by the firmware.
+const LOAD_ADDR: usize = 0x80000;
+
+/// Where images are downloaded to before they replace the running kernel:
+/// clear of it, and of where the largest image ends once moved.
+const STAGING_ADDR: usize = 0x2000000;
+
+/// The largest image that can be booted.
+pub const MAX_IMAGE_SIZE: usize = 0x1000000;
+
+/// Where the trampoline is copied to, just past the staging area.
+const TRAMPOLINE_ADDR: usize = STAGING_ADDR + MAX_IMAGE_SIZE;
+
+/// The file fetched when none is named.
+pub const DEFAULT_FILE: &str = "kernel8.img";
+
+/// The `boot.cfg` and kernel command line key naming the TFTP server.
+const BOOT_KEY: &str = "tftp";
+
+/// The TFTP server set by `configure()`.
+static SERVER: Mutex<Option<Ipv4Addr>> = Mutex::new(None);
+
+extern "C" {
+    /// The bounds of the copy loop in `init.S`.
+    static _trampoline_start: u8;
+    static _trampoline_end: u8;
+}
+
+/// Sets the TFTP server given as `tftp=<address>`: `setting`, the `tftp`
+/// value in `boot.cfg`, or else the one on the kernel command line, which
+/// the firmware only passes when it boots with ATAGs.
+pub fn configure(setting: Option<&str>) {
+    *SERVER.lock() = setting.or_else(|| info::cmdline_arg(BOOT_KEY))
+        .and_then(|arg| arg.parse().ok());
+}
+
+/// Returns the TFTP server set by `configure()`, or else the DHCP server that
+/// leased the interface its address.
+pub fn default_server() -> Option<Ipv4Addr> {
+    if let Some(server) = *SERVER.lock() {
+        return Some(server);
+    }
+
+    match net::origin() {
+        Origin::Dhcp(lease) => Some(lease.server),
+        _ => None
+    }
+}
+
+/// Downloads `filename` from the TFTP server at `server` into the staging
+/// area, calling `progress` with the number of bytes received so far after
+/// each block, and returns the image's length.
+pub fn fetch<F: FnMut(usize)>(server: Ipv4Addr,
+                              filename: &str,
+                              progress: F) -> Result<usize, TftpError> {
+    let staging = unsafe { slice::from_raw_parts_mut(STAGING_ADDR as *mut u8, MAX_IMAGE_SIZE) };
+    tftp::fetch(server, filename, staging, progress)
+}
+
+/// Replaces the running kernel with the `len` byte image `fetch()` left in
+/// the staging area and enters it at `LOAD_ADDR`, at EL1 with every
+/// interrupt masked and disabled as at reset.
+pub fn boot(len: usize) -> ! {
+    enter(STAGING_ADDR, len)
+}
+
+/// Hands the board back to the bootloader that booted the running kernel,
+/// so a new kernel can be sent without a power cycle. The bootloader's copy
+/// of itself is entered at `LOAD_ADDR` as `boot()` enters images. If there
+/// is no intact copy, the board is reset instead, which boots the bootloader
+/// when it's installed as the firmware's kernel.
+pub fn reenter_bootloader() -> ! {
+    match resident::image() {
+        Some(image) => enter(image.as_ptr() as usize, image.len()),
+        None => {
+            spin_sleep_ms(10);
+            power::reboot()
+        }
+    }
+}
+
+/// Replaces the running kernel with the `len` byte image at `source`, which
+/// must be clear of where it ends up, and enters it at `LOAD_ADDR`.
+///
+/// The copy can't run from the kernel it overwrites, so the trampoline in
+/// `init.S` is copied past the staging area and does it from there.
+fn enter(source: usize, len: usize) -> ! {
+    // Give the UART time to send what's been printed.
+    spin_sleep_ms(10);
+
+    traps::disable_irqs();
+    traps::disable_fiqs();
+    Controller::new().disable_all();
+
+    unsafe {
+        let start = &_trampoline_start as *const u8;
+        let size = &_trampoline_end as *const u8 as usize - start as usize;
+        ptr::copy_nonoverlapping(start, TRAMPOLINE_ADDR as *mut u8, size);
+
+        let trampoline: extern "C" fn(usize, usize, usize, usize) -> ! =
+            mem::transmute(TRAMPOLINE_ADDR);
+        trampoline(LOAD_ADDR, source, (len + 7) & !7, LOAD_ADDR)
+    }
+}
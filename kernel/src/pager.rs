@@ -0,0 +1,239 @@
+use std::io::{self, Write};
+
+use editor::{COLS, ROWS};
+
+/// The rows of text shown above the status line.
+const TEXT_ROWS: usize = ROWS - 1;
+
+/// Keys the pager handles.
+mod key {
+    pub const CTRL_C: u8 = 0x03;
+    pub const ESC: u8 = 0x1B;
+}
+
+/// How far through an ANSI escape sequence the input is.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+enum Escape {
+    None,
+    /// After `ESC`.
+    Start,
+    /// After `ESC [` or `ESC O`, with the numeric parameter so far.
+    Csi(usize)
+}
+
+/// A pager for a `ROWS` by `COLS` ANSI terminal, showing text held in a
+/// buffer it's given a screen at a time.
+///
+/// Space, `f` and Page Down move forward a screen, `b` and Page Up back
+/// one; Enter, `j` and Down move forward a row, `k` and Up back one; `g`
+/// and Home go to the start, `G` and End to the end; `q` and Ctrl-C quit.
+/// Lines wider than the terminal wrap. Tabs show as a space, and bytes
+/// outside printable ASCII as `?`.
+pub struct Pager<'a> {
+    text: &'a [u8],
+    /// The offset of the first row shown.
+    top: usize,
+    escape: Escape,
+    /// Whether the text shown must be redrawn.
+    stale: bool,
+}
+
+impl<'a> Pager<'a> {
+    /// Returns a pager for `text`, showing its start.
+    pub fn new(text: &'a [u8]) -> Pager<'a> {
+        Pager { text, top: 0, escape: Escape::None, stale: true }
+    }
+
+    /// Returns `true` if all of the text fits on one screen.
+    pub fn fits(&self) -> bool {
+        self.row_after(0, TEXT_ROWS).is_none()
+    }
+
+    /// Returns the offset of the start of the line `pos` is on.
+    fn line_start(&self, pos: usize) -> usize {
+        self.text[..pos].iter().rposition(|&byte| byte == b'\n').map_or(0, |nl| nl + 1)
+    }
+
+    /// Returns the offset of the end of the line `pos` is on, before its NL.
+    fn line_end(&self, pos: usize) -> usize {
+        self.text[pos..].iter().position(|&byte| byte == b'\n')
+            .map_or(self.text.len(), |nl| pos + nl)
+    }
+
+    /// Returns the offset of the row after the one starting at `pos`, or
+    /// `None` if that's the last. A NL ending the text doesn't start a row.
+    fn next_row(&self, pos: usize) -> Option<usize> {
+        let end = self.line_end(pos);
+        if end - pos > COLS {
+            Some(pos + COLS)
+        } else if end + 1 < self.text.len() {
+            Some(end + 1)
+        } else {
+            None
+        }
+    }
+
+    /// Returns the offset of the row before the one starting at `pos`, or
+    /// `None` if that's the first.
+    fn previous_row(&self, pos: usize) -> Option<usize> {
+        if pos == 0 {
+            return None;
+        }
+
+        let start = self.line_start(pos - 1);
+        let width = if self.text[pos - 1] == b'\n' { pos - 1 - start } else { pos - start };
+        Some(start + width.saturating_sub(1) / COLS * COLS)
+    }
+
+    /// Returns the offset of the row `rows` after the one starting at `pos`,
+    /// or `None` if there are fewer rows after it.
+    fn row_after(&self, pos: usize, rows: usize) -> Option<usize> {
+        let mut row = pos;
+        for _ in 0..rows {
+            row = self.next_row(row)?;
+        }
+
+        Some(row)
+    }
+
+    /// Returns `true` if the last row is shown.
+    fn at_end(&self) -> bool {
+        self.row_after(self.top, TEXT_ROWS).is_none()
+    }
+
+    /// Scrolls forward `rows` rows, stopping once the last is shown.
+    fn forward(&mut self, rows: usize) {
+        for _ in 0..rows {
+            if self.at_end() {
+                break;
+            }
+
+            self.top = self.next_row(self.top).unwrap_or(self.top);
+            self.stale = true;
+        }
+    }
+
+    /// Scrolls back `rows` rows, stopping at the first.
+    fn back(&mut self, rows: usize) {
+        for _ in 0..rows {
+            match self.previous_row(self.top) {
+                Some(top) => self.top = top,
+                None => break
+            }
+
+            self.stale = true;
+        }
+    }
+
+    /// Handles the escape sequence ending in `control` with the numeric
+    /// parameter `param`.
+    fn control(&mut self, control: u8, param: usize) {
+        match (control, param) {
+            (b'A', _) => self.back(1),
+            (b'B', _) => self.forward(1),
+            (b'H', _) | (b'~', 1) | (b'~', 7) => self.back(usize::max_value()),
+            (b'F', _) | (b'~', 4) | (b'~', 8) => self.forward(usize::max_value()),
+            (b'~', 5) => self.back(TEXT_ROWS),
+            (b'~', 6) => self.forward(TEXT_ROWS),
+            _ => {  }
+        }
+    }
+
+    /// Handles the byte `input` typed on the terminal. Returns `true` if it
+    /// asked to quit.
+    pub fn input(&mut self, input: u8) -> bool {
+        match self.escape {
+            Escape::None => {  }
+            Escape::Start => {
+                self.escape = match input {
+                    b'[' | b'O' => Escape::Csi(0),
+                    _ => Escape::None
+                };
+                return false;
+            }
+            Escape::Csi(param) => {
+                self.escape = match input {
+                    b'0'...b'9' => {
+                        let digit = (input - b'0') as usize;
+                        Escape::Csi(param.saturating_mul(10).saturating_add(digit))
+                    }
+                    b';' => Escape::Csi(param),
+                    _ => {
+                        self.control(input, param);
+                        Escape::None
+                    }
+                };
+                return false;
+            }
+        }
+
+        match input {
+            b'q' | b'Q' | key::CTRL_C => return true,
+            key::ESC => self.escape = Escape::Start,
+            b' ' | b'f' => self.forward(TEXT_ROWS),
+            b'b' => self.back(TEXT_ROWS),
+            b'\r' | b'\n' | b'j' => self.forward(1),
+            b'k' => self.back(1),
+            b'g' => self.back(usize::max_value()),
+            b'G' => self.forward(usize::max_value()),
+            _ => {  }
+        }
+
+        false
+    }
+
+    /// Writes the row starting at `start`.
+    fn draw_row<W: Write>(&self, out: &mut W, start: usize) -> io::Result<()> {
+        let end = ::std::cmp::min(self.line_end(start), start + COLS);
+        let mut row = [0u8; COLS];
+        for (byte, &shown) in row.iter_mut().zip(&self.text[start..end]) {
+            *byte = match shown {
+                b'\t' => b' ',
+                b' '...b'~' => shown,
+                _ => b'?'
+            };
+        }
+
+        out.write_all(&row[..end - start])?;
+        out.write_all(b"\x1b[K")
+    }
+
+    /// Draws the rows in view, if they've changed, and a status line naming
+    /// what's shown `name` and how far through it they are.
+    pub fn draw<W: Write>(&mut self, out: &mut W, name: &str) -> io::Result<()> {
+        if self.stale {
+            out.write_all(b"\x1b[H")?;
+            let mut row = Some(self.top);
+            for _ in 0..TEXT_ROWS {
+                if let Some(start) = row {
+                    self.draw_row(out, start)?;
+                    row = self.next_row(start);
+                } else {
+                    out.write_all(b"\x1b[K")?;
+                }
+
+                out.write_all(b"\r\n")?;
+            }
+
+            self.stale = false;
+        }
+
+        // The status line stops short of the last column, so the terminal
+        // doesn't wrap and scroll.
+        let mut status = [b' '; COLS - 1];
+        {
+            let mut cursor = io::Cursor::new(&mut status[..]);
+            let _ = write!(cursor, " {}  ", name);
+            let _ = match self.row_after(self.top, TEXT_ROWS) {
+                Some(bottom) => write!(cursor, "{}%", bottom * 100 / self.text.len()),
+                None => write!(cursor, "(END)")
+            };
+            let _ = write!(cursor, "  space forward  b back  q quit  ");
+        }
+
+        write!(out, "\x1b[{};1H\x1b[7m", ROWS)?;
+        out.write_all(&status)?;
+        out.write_all(b"\x1b[0m")?;
+        out.flush()
+    }
+}
@@ -0,0 +1,159 @@
+use std::cmp;
+use std::sync::atomic::{AtomicUsize, Ordering};
+
+use mutex::Mutex;
+
+/// The most bytes a pipe holds between two commands.
+pub const PIPE_SIZE: usize = 64 * 1024;
+
+/// The most pipelines running at once: the one typed, and one for each
+/// script line run by a command in a pipeline already running.
+pub const MAX_DEPTH: usize = 4;
+
+/// The index of no pipe.
+const NONE: usize = !0;
+
+/// A ring buffer of bytes one command writes and the next reads.
+pub struct Pipe {
+    bytes: [u8; PIPE_SIZE],
+    head: usize,
+    len: usize,
+    /// The number of bytes dropped because the pipe was full.
+    dropped: usize,
+}
+
+impl Pipe {
+    pub const fn new() -> Pipe {
+        Pipe { bytes: [0; PIPE_SIZE], head: 0, len: 0, dropped: 0 }
+    }
+
+    /// Empties the pipe.
+    pub fn clear(&mut self) {
+        self.head = 0;
+        self.len = 0;
+        self.dropped = 0;
+    }
+
+    /// Appends `bytes`, dropping whatever doesn't fit.
+    pub fn write(&mut self, bytes: &[u8]) {
+        for &byte in bytes {
+            if self.len == PIPE_SIZE {
+                self.dropped += 1;
+                continue;
+            }
+
+            self.bytes[(self.head + self.len) % PIPE_SIZE] = byte;
+            self.len += 1;
+        }
+    }
+
+    /// Takes up to `buf.len()` bytes from the front of the pipe into `buf`,
+    /// and returns how many. Returns 0 once the pipe is empty.
+    pub fn read(&mut self, buf: &mut [u8]) -> usize {
+        let len = cmp::min(buf.len(), self.len);
+        for byte in buf[..len].iter_mut() {
+            *byte = self.bytes[self.head];
+            self.head = (self.head + 1) % PIPE_SIZE;
+        }
+
+        self.len -= len;
+        len
+    }
+
+    /// Returns the number of bytes dropped because the pipe was full.
+    pub fn dropped(&self) -> usize {
+        self.dropped
+    }
+}
+
+/// Two pipes for each pipeline that may be running. Commands in a pipeline
+/// run one at a time: each reads from one of its pipes what the command
+/// before it wrote, and writes to the other for the command after it.
+static PIPES: [Mutex<Pipe>; 2 * MAX_DEPTH] = [
+    Mutex::new(Pipe::new()), Mutex::new(Pipe::new()),
+    Mutex::new(Pipe::new()), Mutex::new(Pipe::new()),
+    Mutex::new(Pipe::new()), Mutex::new(Pipe::new()),
+    Mutex::new(Pipe::new()), Mutex::new(Pipe::new()),
+];
+
+/// The number of pipelines running.
+static DEPTH: AtomicUsize = AtomicUsize::new(0);
+
+/// The index in `PIPES` of the pipe the command running reads, or `NONE`.
+static INPUT: AtomicUsize = AtomicUsize::new(NONE);
+
+/// A pipeline running, holding its pair of pipes until it's dropped.
+pub struct Pipeline {
+    level: usize,
+    /// The input of the command that started this pipeline, given back to it
+    /// when the pipeline ends.
+    input: usize,
+}
+
+impl Pipeline {
+    /// Starts a pipeline inside those already running. Returns `None` if
+    /// `MAX_DEPTH` are.
+    pub fn enter() -> Option<Pipeline> {
+        let level = DEPTH.load(Ordering::Relaxed);
+        if level == MAX_DEPTH {
+            return None;
+        }
+
+        DEPTH.store(level + 1, Ordering::Relaxed);
+        Some(Pipeline { level, input: INPUT.load(Ordering::Relaxed) })
+    }
+
+    /// Returns the index in `PIPES` of the pipe the command at `stage`,
+    /// counted from 0, writes to.
+    fn output(&self, stage: usize) -> usize {
+        2 * self.level + stage % 2
+    }
+
+    /// Prepares the pipes for the command at `stage`: it reads what the
+    /// command before it wrote, if there was one, and writes to an emptied
+    /// pipe. Returns the pipe to give `write()`.
+    pub fn start(&self, stage: usize) -> usize {
+        let input = if stage > 0 { self.output(stage - 1) } else { NONE };
+        INPUT.store(input, Ordering::Relaxed);
+        PIPES[self.output(stage)].lock().clear();
+        self.output(stage)
+    }
+
+    /// Takes what the command at `stage` wrote into `buf`, and returns how
+    /// many bytes were taken: 0 once it's all taken.
+    pub fn read_output(&self, stage: usize, buf: &mut [u8]) -> usize {
+        PIPES[self.output(stage)].lock().read(buf)
+    }
+
+    /// Returns the number of bytes the command at `stage` couldn't write
+    /// because its pipe was full.
+    pub fn dropped(&self, stage: usize) -> usize {
+        PIPES[self.output(stage)].lock().dropped()
+    }
+}
+
+impl Drop for Pipeline {
+    fn drop(&mut self) {
+        INPUT.store(self.input, Ordering::Relaxed);
+        DEPTH.store(self.level, Ordering::Relaxed);
+    }
+}
+
+/// Writes `bytes` to the pipe `pipe`, as returned by `Pipeline::start()`.
+pub fn write(pipe: usize, bytes: &[u8]) {
+    PIPES[pipe].lock().write(bytes);
+}
+
+/// Returns `true` if the command running has piped input.
+pub fn is_piped() -> bool {
+    INPUT.load(Ordering::Relaxed) != NONE
+}
+
+/// Reads the piped input of the command running into `buf`, and returns
+/// how many bytes were read: 0 once it's all read, or if there is none.
+pub fn read(buf: &mut [u8]) -> usize {
+    match INPUT.load(Ordering::Relaxed) {
+        NONE => 0,
+        input => PIPES[input].lock().read(buf)
+    }
+}
@@ -0,0 +1,31 @@
+use pi::rng::Rng;
+
+use mutex::Mutex;
+
+/// The hardware generator, enabled on first use.
+static RNG: Mutex<Option<Rng>> = Mutex::new(None);
+
+/// Calls `f` with the hardware generator, enabling it first if needed.
+fn with_rng<F: FnOnce(&mut Rng) -> R, R>(f: F) -> R {
+    let mut rng = RNG.lock();
+    if rng.is_none() {
+        *rng = Some(Rng::new());
+    }
+
+    f(rng.as_mut().unwrap())
+}
+
+/// Returns a random `u32`.
+pub fn next_u32() -> u32 {
+    with_rng(|rng| rng.next_u32())
+}
+
+/// Returns a random `u64`.
+pub fn next_u64() -> u64 {
+    with_rng(|rng| rng.next_u64())
+}
+
+/// Fills `buffer` with random bytes.
+pub fn fill_bytes(buffer: &mut [u8]) {
+    with_rng(|rng| rng.fill_bytes(buffer))
+}
@@ -1,11 +1,80 @@
-use std::fmt::Write;
+use std::cell::Cell;
+use std::io::{self, Write};
+use std::str;
+use std::sync::atomic::{AtomicBool, AtomicUsize, Ordering};
+use core::time::Duration;
 
-use console::{kprint, kprintln, CONSOLE};
+use pi::{gpio, ir, power, sysinfo, uart};
+use pi::audio::{Audio, Wav};
+use pi::emmc::SECTOR_SIZE;
+use pi::fat::{Entry, FatError};
+use pi::gpio::{Gpio, Pull};
+use pi::i2c::{I2c, I2cError};
+use pi::info::BoardInfo;
+use pi::mcp2515::{Id, Mode};
+use pi::rtc::DateTime;
+use pi::spi::{ChipSelect, Spi};
+use pi::timer::{spin_sleep_ms, Timer};
+use pi::ymodem::{self, Header, Ymodem};
+
+use can;
+use console::{self, print, println, Color, Sink, CONSOLE};
+use editor::{Action, Editor};
+use fs::{self, Path};
+use mutex::Mutex;
+use net::{self, Origin};
+use netboot;
+use pager::Pager;
+use pipe::{self, Pipeline};
 use stack_vec::StackVec;
+use telnet;
+use time;
+use timers;
+use xmodem;
 
 const MAX_CMDLEN : usize = 512;
 const MAX_ARGLEN : usize = 64;
 
+/// The most commands `history` keeps.
+const HISTORY_SIZE: usize = 16;
+
+/// The most variables the environment holds, and the longest name and
+/// value each has.
+const MAX_VARS: usize = 32;
+const MAX_VAR_NAME: usize = 32;
+const MAX_VAR_VALUE: usize = 128;
+
+/// Exit statuses `$?` expands to: a command succeeded, a command failed, a
+/// line couldn't be parsed or its output redirected, and a command doesn't
+/// exist.
+mod status {
+    pub const SUCCESS: usize = 0;
+    pub const FAILURE: usize = 1;
+    pub const SYNTAX: usize = 2;
+    pub const NOT_FOUND: usize = 127;
+}
+
+/// What a builtin returns: `Err` with its exit status if it failed.
+type Status = Result<(), usize>;
+
+/// Like `println!`, then returns `status::FAILURE` from the builtin calling
+/// it.
+macro fail($($arg:tt)*) {
+    {
+        println!($($arg)*);
+        return Err(status::FAILURE);
+    }
+}
+
+/// The largest script `sh` runs.
+const MAX_SCRIPT_SIZE: usize = 8 * 1024;
+
+/// The script the shell runs when it starts, if the card has one.
+const RC_SCRIPT: &str = "/rc.sh";
+
+/// The bytes downloaded per progress mark `netboot` prints.
+const NETBOOT_PROGRESS_BYTES: usize = 64 * 1024;
+
 /// Error type for `Command` parse failures.
 #[derive(Debug)]
 enum Error {
@@ -15,7 +84,9 @@ enum Error {
 
 #[derive(Debug)]
 enum HandleError {
-    NoSuchCommand
+    NoSuchCommand,
+    /// The command ran and exited with this status.
+    Failed(usize)
 }
 
 /// A structure representing a single shell command.
@@ -51,74 +122,2680 @@ impl<'a> Command<'a> {
     }
 
     fn handle(&self) -> Result<(), HandleError> {
-        if self.path() == "echo" {
-            use std::ops::Deref;
-            let mut first = true;
-            for arg in &self.args[1..] {
-                if !first {
-                    kprint!(" ");
-                }
-                kprint!("{}", arg);
-                first = false;
-            }
-            kprintln!();
-            Ok(())
-        } else {
-            Err(HandleError::NoSuchCommand)
+        use std::ops::Deref;
+        run(self.args.deref())
+    }
+}
+
+/// The builtins `run()` knows, for Tab completion.
+const BUILTINS: &[&str] = &[
+    "echo", "watch", "info", "temp", "ifconfig", "netboot", "date", "sleep", "uptime", "gpio",
+    "i2cdetect", "spi", "ir", "candump", "cd", "pwd", "export", "env", "alias", "unalias", "color",
+    "ls", "cat", "grep", "mkdir", "rm", "cp", "edit", "less", "play", "xxd", "peek", "poke",
+    "history", "sh", "exit", "send", "rx", "reboot", "halt", "bootloader"
+];
+
+/// Runs the command whose path and arguments are `args`.
+fn run(args: &[&str]) -> Result<(), HandleError> {
+    let result = match args[0] {
+        "echo" => echo(&args[1..]),
+        "watch" => watch(&args[1..]),
+        "info" => info(),
+        "temp" => temp(),
+        "ifconfig" => ifconfig(),
+        "netboot" => netboot(&args[1..]),
+        "date" => date(&args[1..]),
+        "sleep" => sleep(&args[1..]),
+        "uptime" => uptime(),
+        "gpio" => gpio(&args[1..]),
+        "i2cdetect" => i2cdetect(),
+        "spi" => spi(&args[1..]),
+        "ir" => ir(&args[1..]),
+        "candump" => candump(&args[1..]),
+        "cd" => cd(&args[1..]),
+        "pwd" => pwd(),
+        "export" => export(&args[1..]),
+        "env" => env(),
+        "alias" => alias(&args[1..]),
+        "unalias" => unalias(&args[1..]),
+        "color" => color(&args[1..]),
+        "ls" => ls(&args[1..]),
+        "cat" => cat(&args[1..]),
+        "grep" => grep(&args[1..]),
+        "mkdir" => mkdir(&args[1..]),
+        "rm" => rm(&args[1..]),
+        "cp" => cp(&args[1..]),
+        "edit" => edit(&args[1..]),
+        "less" => less(&args[1..]),
+        "play" => play(&args[1..]),
+        "xxd" => xxd(&args[1..]),
+        "peek" => peek(&args[1..]),
+        "poke" => poke(&args[1..]),
+        "history" => history(),
+        "send" => send(&args[1..]),
+        "rx" => rx(&args[1..]),
+        "reboot" => reboot(),
+        "halt" => halt(),
+        "sh" => sh(&args[1..]),
+        "exit" => exit(&args[1..]),
+        "bootloader" => bootloader(),
+        path if is_script(path) => sh(args),
+        _ => return Err(HandleError::NoSuchCommand)
+    };
+
+    result.map_err(HandleError::Failed)
+}
+
+/// Prints `args` separated by spaces.
+fn echo(args: &[&str]) -> Status {
+    let mut first = true;
+    for arg in args {
+        if !first {
+            print!(" ");
         }
+        print!("{}", arg);
+        first = false;
     }
+    println!();
+    Ok(())
 }
 
-/// Starts a shell using `prefix` as the prefix for each line. This function
-/// never returns: it is perpetually in a shell loop.
-pub fn shell(prefix: &str) -> ! {
-    let mut input_buf = [0; MAX_CMDLEN];
-    let mut input_vec = StackVec::new(&mut input_buf);
+/// Prints the board's model, serial number, memory split and MAC address.
+fn info() -> Status {
+    let info = match BoardInfo::query() {
+        Ok(info) => info,
+        Err(_) => fail!("info: firmware query failed")
+    };
+
+    println!("model:    {} (revision {:06x}, {:?})",
+             info.model(), info.revision, info.processor());
+    println!("serial:   {:016x}", info.serial);
+    println!("memory:   ARM {}MiB at {:#010x}, VideoCore {}MiB at {:#010x}",
+             info.arm_memory.size >> 20, info.arm_memory.base,
+             info.vc_memory.size >> 20, info.vc_memory.base);
+
+    let mac = info.mac_address;
+    println!("mac:      {:02x}:{:02x}:{:02x}:{:02x}:{:02x}:{:02x}",
+             mac[0], mac[1], mac[2], mac[3], mac[4], mac[5]);
+    Ok(())
+}
+
+/// Prints the SoC temperature, the throttling threshold, and the core
+/// voltage.
+fn temp() -> Status {
+    let (current, max, voltage) = match (sysinfo::temperature(),
+                                         sysinfo::max_temperature(),
+                                         sysinfo::core_voltage()) {
+        (Ok(current), Ok(max), Ok(voltage)) => (current, max, voltage),
+        _ => fail!("temp: firmware query failed")
+    };
+
+    println!("{}.{}'C (throttles at {}.{}'C), core {}.{:04}V",
+             current / 1000, current % 1000 / 100,
+             max / 1000, max % 1000 / 100,
+             voltage / 1_000_000, voltage % 1_000_000 / 100);
+    Ok(())
+}
+
+/// Prints the wall clock time, or with a date and time of day as
+/// `YYYY-MM-DD HH:MM:SS`, sets it and the RTC.
+fn date(args: &[&str]) -> Status {
+    if args.is_empty() {
+        let known = if time::is_set() { "" } else { " (not set)" };
+        println!("{} UTC{}", time::now(), known);
+        return Ok(());
+    }
+
+    let now = match (args.len(), DateTime::parse(args[0], args.get(1).unwrap_or(&""))) {
+        (2, Some(now)) => now,
+        _ => fail!("usage: date [YYYY-MM-DD HH:MM:SS]")
+    };
+
+    match time::set(now) {
+        Ok(()) if time::has_rtc() => println!("{} UTC", now),
+        Ok(()) => println!("{} UTC (no RTC: lost at reset)", now),
+        Err(error) => fail!("date: RTC not set: {:?}", error)
+    }
+
+    Ok(())
+}
+
+/// Set by the `sleep` timer when the sleep is over.
+static SLEEP_DONE: AtomicBool = AtomicBool::new(false);
+
+/// Waits `args[0]` milliseconds, rounded up to a whole tick: `sleep <ms>`.
+/// A key press cuts the wait short, and fails, so a script's delays can be
+/// skipped.
+fn sleep(args: &[&str]) -> Status {
+    let ms = match args.first().and_then(|s| s.parse::<u64>().ok()) {
+        Some(ms) if args.len() == 1 => ms,
+        _ => fail!("usage: sleep <ms>")
+    };
+
+    SLEEP_DONE.store(false, Ordering::Relaxed);
+    let id = match timers::after(Duration::from_millis(ms),
+                                 || SLEEP_DONE.store(true, Ordering::Relaxed)) {
+        Some(id) => id,
+        None => fail!("sleep: too many timers")
+    };
+
+    while !SLEEP_DONE.load(Ordering::Relaxed) {
+        if running_on().try_read_byte().is_some() {
+            timers::cancel(id);
+            fail!("sleep: interrupted");
+        }
+    }
+
+    Ok(())
+}
+
+/// Prints the time since the board was powered up, from the system timer.
+fn uptime() -> Status {
+    let secs = Timer::new().read() / 1_000_000;
+    println!("up {}d {:02}:{:02}:{:02}",
+             secs / 86400, secs % 86400 / 3600, secs % 3600 / 60, secs % 60);
+    Ok(())
+}
+
+/// Resets the board, which then boots normally.
+fn reboot() -> Status {
+    println!("rebooting");
+    spin_sleep_ms(10);
+    power::reboot()
+}
+
+/// Halts the board until it's power cycled: the firmware stops at the next
+/// boot rather than loading a kernel.
+fn halt() -> Status {
+    println!("halting: power cycle the board to start it again");
+    spin_sleep_ms(10);
+    power::power_off()
+}
+
+/// Hands the board back to the UART bootloader, so a new kernel can be sent
+/// without a power cycle.
+fn bootloader() -> Status {
+    println!("entering the bootloader");
+    netboot::reenter_bootloader()
+}
+
+/// Prints the interface's MAC address, IPv4 addresses, and where they came
+/// from, with the state of the DHCP lease if there is one.
+fn ifconfig() -> Status {
+    let origin = net::origin();
+    if origin == Origin::None {
+        fail!("ifconfig: no network device");
+    }
+
+    let config = net::config();
+    println!("ether:    {}", net::mac_address());
+    println!("inet:     {} netmask {}", config.address, config.netmask);
+    println!("gateway:  {}", config.gateway);
+
+    match origin {
+        Origin::Dhcp(lease) => {
+            let duration = lease.duration.as_secs();
+            match lease.remaining() {
+                Some(remaining) => println!("lease:    from {} for {}s, {}s left",
+                                            lease.server, duration, remaining.as_secs()),
+                None => println!("lease:    from {} for {}s, expired",
+                                 lease.server, duration)
+            }
+        }
+        Origin::Static => println!("lease:    none (static, from boot.cfg or cmdline.txt)"),
+        Origin::Default => println!("lease:    none (static, default)"),
+        Origin::None => {  }
+    }
+
+    Ok(())
+}
+
+/// Downloads a kernel image over TFTP and boots it in place of this one:
+/// `netboot [server] [file]`. The server defaults to the one configured in
+/// `boot.cfg` or `cmdline.txt`, or else the DHCP server, and the file to `kernel8.img`.
+/// Returns only if the download fails.
+fn netboot(args: &[&str]) -> Status {
+    let server = match args.first() {
+        Some(arg) => match arg.parse() {
+            Ok(server) => server,
+            Err(_) => fail!("usage: netboot [server] [file]")
+        },
+        None => match netboot::default_server() {
+            Some(server) => server,
+            None => fail!("netboot: no server given or configured")
+        }
+    };
+
+    let file = args.get(1).cloned().unwrap_or(netboot::DEFAULT_FILE);
+    println!("netboot: fetching {} from {}", file, server);
+
+    let mut marks = 0;
+    let result = netboot::fetch(server, file, |received| {
+        while marks < received / NETBOOT_PROGRESS_BYTES {
+            print!("#");
+            marks += 1;
+        }
+    });
 
+    println!();
+    match result {
+        Ok(len) => {
+            println!("netboot: booting {} bytes", len);
+            netboot::boot(len)
+        }
+        Err(error) => fail!("netboot: failed: {:?}", error)
+    }
+}
+
+/// Set by the `watch` timer each time the watched command is due.
+static WATCH_DUE: AtomicBool = AtomicBool::new(false);
+
+/// Runs the command in `args[1..]` every `args[0]` seconds until a key is
+/// pressed.
+fn watch(args: &[&str]) -> Status {
+    let seconds = match args.first().and_then(|s| s.parse::<u64>().ok()) {
+        Some(seconds) if args.len() > 1 => seconds,
+        _ => fail!("usage: watch <seconds> <command> [args...]")
+    };
+
+    let id = match timers::every(Duration::from_secs(seconds),
+                                 || WATCH_DUE.store(true, Ordering::Relaxed)) {
+        Some(id) => id,
+        None => fail!("watch: too many timers")
+    };
+
+    // Run once straight away rather than after the first period.
+    WATCH_DUE.store(true, Ordering::Relaxed);
+    let mut result = Ok(());
     loop {
-        {
-            let mut console = CONSOLE.lock();
-            console.write_str(prefix).expect("failed to write prefix");
+        if WATCH_DUE.swap(false, Ordering::Relaxed) {
+            if let Err(HandleError::NoSuchCommand) = run(&args[1..]) {
+                println!("unknown command: {}", args[1]);
+                result = Err(status::NOT_FOUND);
+                break;
+            }
+        }
 
-            loop {
-                let input = console.read_byte();
-
-                if input == b'\n' || input == b'\r' { // newline
-                    console.write_byte(b'\r');
-                    console.write_byte(b'\n');
-                    break
-                } else if input == b'\x7f' { // delete / backspace
-                    if let Some(_) = input_vec.pop() {
-                        console.write_byte(b'\x08');
-                        console.write_byte(b' ');
-                        console.write_byte(b'\x08');
-                    }
-                } else if input < 32 { // unprintable uninterpreted
-                    console.write_byte(b'\x07');
-                } else { // regular character
-                    if let Ok(_) = input_vec.push(input) {
-                        console.write_byte(input);
-                    }
-                }
+        if running_on().try_read_byte().is_some() {
+            break;
+        }
+    }
+
+    timers::cancel(id);
+    result
+}
+
+/// Configures, drives or reads a GPIO pin:
+/// `gpio <pin> out|in|set|clear|read|pull-up|pull-down`. `set` and `clear`
+/// make the pin an output, and `read` prints its level whatever its
+/// function. Pins claimed by a driver, like the UART's, are left be; with
+/// no arguments, those are listed.
+fn gpio(args: &[&str]) -> Status {
+    const USAGE: &str = "usage: gpio <pin> out|in|set|clear|read|pull-up|pull-down";
+    if args.is_empty() {
+        for pin in 0..54 {
+            if let Some(owner) = gpio::owner(pin) {
+                println!("{:2}  {}", pin, owner);
             }
         }
 
-        {
-            let input_str = std::str::from_utf8(input_vec.as_slice())
-                                    .expect("failed to decode utf8");
-            let mut input_args = [input_str; MAX_ARGLEN];
+        return Ok(());
+    }
+
+    let pin = match args[0].parse::<u8>() {
+        Ok(pin) if pin < 54 && args.len() == 2 => pin,
+        _ => fail!("{}", USAGE)
+    };
+
+    if let Some(owner) = gpio::owner(pin) {
+        fail!("gpio: pin {} is in use by {}", pin, owner);
+    }
+
+    // The level is latched before the pin becomes an output, so it doesn't
+    // glitch to the old one.
+    match args[1] {
+        "out" => { Gpio::new(pin).into_output(); }
+        "in" => { Gpio::new(pin).into_input(); }
+        "set" => {
+            gpio::set_mask(1 << pin);
+            Gpio::new(pin).into_output();
+        }
+        "clear" => {
+            gpio::clear_mask(1 << pin);
+            Gpio::new(pin).into_output();
+        }
+        "read" => println!("{}", gpio::levels() >> pin & 1),
+        "pull-up" => Gpio::new(pin).set_pull(Pull::Up),
+        "pull-down" => Gpio::new(pin).set_pull(Pull::Down),
+        _ => fail!("{}", USAGE)
+    }
+
+    Ok(())
+}
+
+/// Prints the NEC remote codes received on pin `args[0]` until a key is
+/// pressed.
+fn ir(args: &[&str]) -> Status {
+    let pin = match args.first().and_then(|s| s.parse::<u8>().ok()) {
+        Some(pin) if pin < 54 && args.len() == 1 => pin,
+        _ => fail!("usage: ir <pin>")
+    };
+
+    while ir::poll().is_some() {  }
+    ir::start(pin);
+    println!("ir: listening on pin {}, press a key to stop", pin);
+
+    while running_on().try_read_byte().is_none() {
+        if let Some(event) = ir::poll() {
+            println!("address {:#06x} command {:#04x}{}",
+                     event.address, event.command, if event.repeat { " (repeat)" } else { "" });
+        }
+    }
+
+    ir::stop();
+    Ok(())
+}
+
+/// Prints a grid of the I2C addresses 0x03-0x77 on `BSC1`, with the address
+/// of each slave acknowledging a 1 byte read, `--` where none does, and
+/// `??` where the bus failed some other way.
+fn i2cdetect() -> Status {
+    let mut i2c = I2c::new();
+    let mut byte = [0u8; 1];
+    println!("     0  1  2  3  4  5  6  7  8  9  a  b  c  d  e  f");
+    for row in 0..8u8 {
+        print!("{:02x}:", row * 16);
+        for addr in row * 16..row * 16 + 16 {
+            if addr < 0x03 || addr > 0x77 {
+                print!("   ");
+                continue;
+            }
+
+            match i2c.read(addr, &mut byte) {
+                Ok(()) => print!(" {:02x}", addr),
+                Err(I2cError::Nack) => print!(" --"),
+                Err(_) => print!(" ??")
+            }
+        }
+
+        println!();
+    }
+
+    Ok(())
+}
+
+/// The most bytes `spi xfer` transfers.
+const SPI_MAX_XFER: usize = 64;
+
+/// Sends bytes written in hex on `SPI0` at 1MHz in mode 0 and prints those
+/// received in their place: `spi xfer [ce0|ce1] <hexbytes>...`, with each
+/// argument one or more bytes of two hex digits.
+fn spi(args: &[&str]) -> Status {
+    const USAGE: &str = "usage: spi xfer [ce0|ce1] <hexbytes>...";
+    let (cs, hex) = match args.get(1) {
+        _ if args.first() != Some(&"xfer") => fail!("{}", USAGE),
+        Some(&"ce0") => (ChipSelect::Ce0, &args[2..]),
+        Some(&"ce1") => (ChipSelect::Ce1, &args[2..]),
+        _ => (ChipSelect::Ce0, &args[1..])
+    };
+
+    let mut buffer = [0u8; SPI_MAX_XFER];
+    let mut len = 0;
+    for arg in hex {
+        if arg.len() % 2 != 0 || !arg.chars().all(|c| c.is_digit(16)) {
+            fail!("spi: {}: not hex bytes", arg);
+        }
+
+        if len + arg.len() / 2 > SPI_MAX_XFER {
+            fail!("spi: more than {} bytes", SPI_MAX_XFER);
+        }
+
+        for i in 0..arg.len() / 2 {
+            buffer[len] = u8::from_str_radix(&arg[2 * i..2 * i + 2], 16).unwrap_or(0);
+            len += 1;
+        }
+    }
+
+    if len == 0 {
+        fail!("{}", USAGE);
+    }
+
+    let mut spi = Spi::new();
+    spi.select(cs);
+    spi.transfer(&mut buffer[..len]);
+    for byte in &buffer[..len] {
+        print!("{:02x} ", byte);
+    }
+
+    println!();
+    Ok(())
+}
+
+/// Returns the CAN identifier written in hex as `s`: extended if it's longer
+/// than 3 digits, as `candump` writes them, or doesn't fit in 11 bits.
+fn parse_can_id(s: &str) -> Option<Id> {
+    let id = u32::from_str_radix(s, 16).ok()?;
+    match id {
+        0...0x7FF if s.len() <= 3 => Some(Id::Standard(id as u16)),
+        0...0x1FFF_FFFF => Some(Id::Extended(id)),
+        _ => None
+    }
+}
 
-            match Command::parse(&input_str, &mut input_args[..]) {
-                Err(Error::Empty) => {},
-                Err(Error::TooManyArgs) => kprintln!("too many arguments"),
-                Ok(command) => match command.handle() {
-                    Ok(_) => { },
-                    Err(HandleError::NoSuchCommand) =>
-                        kprintln!("unknown command: {}", command.path())
+/// Prints the CAN frames received at `args[0]` bits per second until a key
+/// is pressed, only those matching `args[1]`, `<id>:<mask>` in hex, if it's
+/// given.
+fn candump(args: &[&str]) -> Status {
+    const USAGE: &str = "usage: candump <bitrate> [<id>:<mask>]";
+    let bitrate = match args.first().and_then(|s| s.parse::<u32>().ok()) {
+        Some(bitrate) if args.len() <= 2 => bitrate,
+        _ => fail!("{}", USAGE)
+    };
+
+    let filter = match args.get(1) {
+        Some(arg) => {
+            let mut parts = arg.splitn(2, ':');
+            match (parts.next().and_then(parse_can_id), parts.next().and_then(parse_can_id)) {
+                (Some(id), Some(mask)) => Some((id, mask)),
+                _ => fail!("{}", USAGE)
+            }
+        }
+        None => None
+    };
+
+    if let Err(error) = can::start(bitrate, Mode::Normal) {
+        fail!("candump: failed to start: {:?}", error);
+    }
+
+    if let Some((id, mask)) = filter {
+        if let Err(error) = can::set_filter(id, mask) {
+            can::stop();
+            fail!("candump: failed to set filter: {:?}", error);
+        }
+    }
+
+    println!("candump: listening at {} bit/s, press a key to stop", bitrate);
+    while running_on().try_read_byte().is_none() {
+        let frame = match can::recv() {
+            Some(frame) => frame,
+            None => continue
+        };
+
+        match frame.id {
+            Id::Standard(id) => print!("  {:03X}", id),
+            Id::Extended(id) => print!("  {:08X}", id),
+        }
+
+        print!("   [{}] ", frame.len);
+        if frame.remote {
+            print!(" remote request");
+        }
+
+        for byte in frame.data() {
+            print!(" {:02X}", byte);
+        }
+
+        println!();
+    }
+
+    let dropped = can::dropped();
+    if dropped > 0 {
+        println!("candump: {} frames dropped", dropped);
+    }
+
+    can::stop();
+    Ok(())
+}
+
+/// The largest file `send` transmits, `rx` receives, `cp` copies and `edit`
+/// and `less` load.
+const FILE_MAX_SIZE: usize = 1024 * 1024;
+
+/// Holds the file `send` is transmitting, `rx` is receiving, `cp` is
+/// copying, `edit` is editing, `less` is showing or `play` is playing.
+static FILE_BUFFER: Mutex<[u8; FILE_MAX_SIZE]> = Mutex::new([0; FILE_MAX_SIZE]);
+
+/// Sends a file from the SD card's FAT partition to the host over XMODEM on
+/// the serial console, for `ttywrite --recv` to receive: `send <file>`.
+fn send(args: &[&str]) -> Status {
+    if args.len() != 1 {
+        fail!("usage: send <file>");
+    }
+
+    if running_on() != Terminal::Console {
+        fail!("send: only over the serial console");
+    }
+
+    let path = match resolve(args[0]) {
+        Some(path) => path,
+        None => fail!("send: {}: path too long", args[0])
+    };
+
+    let mut buffer = FILE_BUFFER.lock();
+    let len = match fs::load(&path, &mut buffer[..]) {
+        Ok(len) => len,
+        Err(error) => fail!("send: {}: {:?}", args[0], error)
+    };
+
+    println!("send: {} bytes, waiting for the receiver", len);
+    let result = console::binary_transfer(|console| {
+        console.set_read_timeout(Duration::from_secs(1));
+        xmodem::send(console, &buffer[..len])
+    });
+
+    match result {
+        Ok(()) => println!("send: sent {}", args[0]),
+        Err(error) => fail!("send: failed: {:?}", error)
+    }
+
+    Ok(())
+}
+
+/// How long `rx` waits for the sender to start, in seconds.
+const RX_WAIT_SECS: usize = 30;
+
+/// Receives one file from a YMODEM sender on `port` into `buffer`, waiting
+/// up to `RX_WAIT_SECS` for it to start, and returns its header and length.
+fn receive_one<T: io::Read + io::Write>(port: T, buffer: &mut [u8])
+                                        -> io::Result<(Header, usize)> {
+    let mut ymodem = Ymodem::new(port);
+    let mut waited = 0;
+    let header = loop {
+        match ymodem.next_file() {
+            Ok(Some(header)) => break header,
+            Ok(None) => return Err(io::Error::new(io::ErrorKind::UnexpectedEof, "No file sent.")),
+            Err(ref e) if e.kind() == io::ErrorKind::TimedOut && waited < RX_WAIT_SECS => {
+                waited += 1;
+            }
+            Err(e) => return Err(e)
+        }
+    };
+
+    let len = ymodem.receive_file(buffer)?;
+    match ymodem.next_file()? {
+        None => Ok((header, len)),
+        Some(_) => {
+            ymodem.cancel();
+            Err(io::Error::new(io::ErrorKind::Other, "More than one file sent."))
+        }
+    }
+}
+
+/// Receives a file from the host over YMODEM or XMODEM on the serial
+/// console, as `sb` or `ttywrite` sends it, and saves it to the SD card's
+/// FAT partition: `rx <file>`. A YMODEM header gives the file's exact size,
+/// so the bytes padding the last packet are never saved. A file sent over
+/// XMODEM has no size, and is refused if it ends in what may be padding,
+/// rather than saved wrong.
+fn rx(args: &[&str]) -> Status {
+    if args.len() != 1 {
+        fail!("usage: rx <file>");
+    }
+
+    if running_on() != Terminal::Console {
+        fail!("rx: only over the serial console");
+    }
+
+    let path = match resolve(args[0]) {
+        Some(path) => path,
+        None => fail!("rx: {}: path too long", args[0])
+    };
+
+    println!("rx: waiting for the sender");
+    let mut buffer = FILE_BUFFER.lock();
+    let result = console::binary_transfer(|console| {
+        console.set_read_timeout(Duration::from_secs(1));
+        receive_one(console, &mut buffer[..])
+    });
+
+    let (header, len) = match result {
+        Ok(received) => received,
+        Err(error) => fail!("rx: failed: {:?}", error)
+    };
+
+    if header.size.is_none() && len > 0 && buffer[len - 1] == ymodem::PAD {
+        fail!("rx: {}: sent without its size and ends in padding; not saved", header.name());
+    }
+
+    if let Err(error) = fs::save(&path, &buffer[..len]) {
+        fail!("rx: {}: {:?}", args[0], error);
+    }
+
+    println!("rx: received {} ({} bytes) into {}", header.name(), len, args[0]);
+    Ok(())
+}
+
+/// The directory relative paths are resolved from, shared by every
+/// terminal.
+static CWD: Mutex<Path> = Mutex::new(Path::root());
+
+/// Returns `path` resolved against the working directory, or `None` if it's
+/// too long. Every builtin taking a path resolves it through here.
+fn resolve(path: &str) -> Option<Path> {
+    CWD.lock().join(path)
+}
+
+/// Changes the working directory: `cd [dir]`, to the root if none is given.
+fn cd(args: &[&str]) -> Status {
+    if args.len() > 1 {
+        fail!("usage: cd [dir]");
+    }
+
+    let arg = args.first().cloned().unwrap_or("/");
+    let path = match resolve(arg) {
+        Some(path) => path,
+        None => fail!("cd: {}: path too long", arg)
+    };
+
+    match fs::stat(&path) {
+        Ok(ref entry) if entry.is_dir() => *CWD.lock() = path,
+        Ok(_) => fail!("cd: {}: {:?}", arg, FatError::NotADirectory),
+        Err(error) => fail!("cd: {}: {:?}", arg, error)
+    }
+
+    Ok(())
+}
+
+/// Prints the working directory.
+fn pwd() -> Status {
+    println!("{}", CWD.lock().as_str());
+    Ok(())
+}
+
+/// Prints `entry`'s name, marking directories with a trailing `/` and, if
+/// coloring is on, in blue. If `long` is `true`, its size and when it was
+/// last written come first.
+fn print_entry(entry: &Entry, long: bool) {
+    let suffix = if entry.is_dir() { "/" } else { "" };
+    if long {
+        let time = entry.modified();
+        print!("{:>10} {:04}-{:02}-{:02} {:02}:{:02} ",
+               entry.size, time.year, time.month, time.day, time.hour, time.minute);
+    }
+
+    let color = entry.is_dir() && console::shell_color();
+    println!("{}{}", Color::Blue.paint(entry.name(), color), suffix);
+}
+
+/// Lists a directory, or names a file: `ls [-a] [-l] [path]`, the working
+/// directory if no path is given. `-a` includes hidden entries and those
+/// starting with `.`; `-l` adds sizes and when each was last written.
+fn ls(args: &[&str]) -> Status {
+    let (mut all, mut long, mut target) = (false, false, None);
+    for &arg in args {
+        match arg {
+            "-a" => all = true,
+            "-l" => long = true,
+            "-al" | "-la" => {
+                all = true;
+                long = true;
+            }
+            _ if target.is_none() && !arg.starts_with('-') => target = Some(arg),
+            _ => fail!("usage: ls [-a] [-l] [path]")
+        }
+    }
+
+    let arg = target.unwrap_or(".");
+    let path = match resolve(arg) {
+        Some(path) => path,
+        None => fail!("ls: {}: path too long", arg)
+    };
+
+    let result = fs::stat(&path).and_then(|entry| {
+        if !entry.is_dir() {
+            print_entry(&entry, long);
+            return Ok(());
+        }
+
+        fs::read_dir(&path, |entry| {
+            if all || !(entry.is_hidden() || entry.name().starts_with('.')) {
+                print_entry(entry, long);
+            }
+        })
+    });
+
+    if let Err(error) = result {
+        fail!("ls: {}: {:?}", arg, error);
+    }
+
+    Ok(())
+}
+
+/// Prints `bytes` as text, with `?` for each byte that isn't valid UTF-8,
+/// and returns how many bytes at the end were held back as the start of a
+/// character that may yet be completed.
+fn print_text(bytes: &[u8]) -> usize {
+    let mut bytes = bytes;
+    loop {
+        let error = match str::from_utf8(bytes) {
+            Ok(text) => {
+                print!("{}", text);
+                return 0;
+            }
+            Err(error) => error
+        };
+
+        let (valid, rest) = bytes.split_at(error.valid_up_to());
+        print!("{}", str::from_utf8(valid).unwrap_or(""));
+        match error.error_len() {
+            Some(len) => {
+                print!("?");
+                bytes = &rest[len..];
+            }
+            None => return rest.len()
+        }
+    }
+}
+
+/// Prints text fed to it in chunks of up to a sector, holding back a
+/// character split across two chunks until the rest of it comes.
+struct TextPrinter {
+    pending: [u8; SECTOR_SIZE + 3],
+    held: usize,
+}
+
+impl TextPrinter {
+    fn new() -> TextPrinter {
+        TextPrinter { pending: [0; SECTOR_SIZE + 3], held: 0 }
+    }
+
+    /// Prints `chunk`, after what was held back from the last one.
+    fn feed(&mut self, chunk: &[u8]) {
+        let end = self.held + chunk.len();
+        self.pending[self.held..end].copy_from_slice(chunk);
+        self.held = print_text(&self.pending[..end]);
+        for i in 0..self.held {
+            self.pending[i] = self.pending[end - self.held + i];
+        }
+    }
+
+    /// Prints a `?` for each byte still held back, as the text has ended.
+    fn finish(&mut self) {
+        for _ in 0..self.held {
+            print!("?");
+        }
+
+        self.held = 0;
+    }
+}
+
+/// Prints the contents of each file given, in turn, or the piped input if
+/// none is: `cat [file...]`.
+fn cat(args: &[&str]) -> Status {
+    if args.is_empty() && !pipe::is_piped() {
+        fail!("usage: cat <file>...");
+    }
+
+    let mut printer = TextPrinter::new();
+    if args.is_empty() {
+        let mut chunk = [0u8; SECTOR_SIZE];
+        loop {
+            match pipe::read(&mut chunk) {
+                0 => break,
+                len => printer.feed(&chunk[..len])
+            }
+        }
+
+        printer.finish();
+    }
+
+    let mut result = Ok(());
+    for &arg in args {
+        let path = match resolve(arg) {
+            Some(path) => path,
+            None => {
+                println!("cat: {}: path too long", arg);
+                result = Err(status::FAILURE);
+                continue;
+            }
+        };
+
+        let streamed = fs::stream(&path, |chunk| printer.feed(chunk));
+        printer.finish();
+        if let Err(error) = streamed {
+            println!("cat: {}: {:?}", arg, error);
+            result = Err(status::FAILURE);
+        }
+    }
+
+    result
+}
+
+/// The longest line `grep` matches whole; longer lines are split.
+const MAX_LINE_LEN: usize = 512;
+
+/// Splits text fed to it in chunks into lines.
+struct Lines {
+    line: [u8; MAX_LINE_LEN],
+    len: usize,
+}
+
+impl Lines {
+    fn new() -> Lines {
+        Lines { line: [0; MAX_LINE_LEN], len: 0 }
+    }
+
+    /// Calls `f` with each line `chunk` completes, without its NL.
+    fn feed<F: FnMut(&[u8])>(&mut self, chunk: &[u8], f: &mut F) {
+        for &byte in chunk {
+            if byte == b'\n' || self.len == MAX_LINE_LEN {
+                f(&self.line[..self.len]);
+                self.len = 0;
+                if byte == b'\n' {
+                    continue;
                 }
             }
+
+            self.line[self.len] = byte;
+            self.len += 1;
         }
+    }
 
-        input_vec.truncate(0);
+    /// Calls `f` with the last line, if the text didn't end with a NL.
+    fn finish<F: FnMut(&[u8])>(&mut self, f: &mut F) {
+        if self.len > 0 {
+            f(&self.line[..self.len]);
+            self.len = 0;
+        }
+    }
+}
+
+/// Prints the lines containing `pattern` from each file given, or from the
+/// piped input if none is: `grep <pattern> [file...]`. Lines from files
+/// are prefixed with the file's name if there's more than one. Fails if no
+/// line matches.
+fn grep(args: &[&str]) -> Status {
+    if args.is_empty() || (args.len() == 1 && !pipe::is_piped()) {
+        fail!("usage: grep <pattern> [file...]");
+    }
+
+    let (pattern, files) = (args[0].as_bytes(), &args[1..]);
+    let mut lines = Lines::new();
+    let matched = Cell::new(false);
+    let mut show = |name: Option<&str>, line: &[u8]| {
+        if pattern.is_empty() || line.windows(pattern.len()).any(|window| window == pattern) {
+            matched.set(true);
+            if let Some(name) = name {
+                print!("{}:", name);
+            }
+
+            let held = print_text(line);
+            for _ in 0..held {
+                print!("?");
+            }
+
+            println!();
+        }
+    };
+
+    if files.is_empty() {
+        let mut chunk = [0u8; SECTOR_SIZE];
+        let mut show = |line: &[u8]| show(None, line);
+        loop {
+            match pipe::read(&mut chunk) {
+                0 => break,
+                len => lines.feed(&chunk[..len], &mut show)
+            }
+        }
+
+        lines.finish(&mut show);
+    }
+
+    let mut failed = false;
+    for &arg in files {
+        let path = match resolve(arg) {
+            Some(path) => path,
+            None => {
+                println!("grep: {}: path too long", arg);
+                failed = true;
+                continue;
+            }
+        };
+
+        let name = if files.len() > 1 { Some(arg) } else { None };
+        let mut show = |line: &[u8]| show(name, line);
+        let result = fs::stream(&path, |chunk| lines.feed(chunk, &mut show));
+        lines.finish(&mut show);
+        if let Err(error) = result {
+            println!("grep: {}: {:?}", arg, error);
+            failed = true;
+        }
+    }
+
+    if failed || !matched.get() {
+        return Err(status::FAILURE);
+    }
+
+    Ok(())
+}
+
+/// Creates a directory: `mkdir <dir>`.
+fn mkdir(args: &[&str]) -> Status {
+    if args.len() != 1 {
+        fail!("usage: mkdir <dir>");
+    }
+
+    match resolve(args[0]) {
+        Some(path) => if let Err(error) = fs::mkdir(&path) {
+            fail!("mkdir: {}: {:?}", args[0], error);
+        },
+        None => fail!("mkdir: {}: path too long", args[0])
+    }
+
+    Ok(())
+}
+
+/// Removes files and empty directories: `rm <path>...`.
+fn rm(args: &[&str]) -> Status {
+    if args.is_empty() {
+        fail!("usage: rm <path>...");
+    }
+
+    let mut result = Ok(());
+    for &arg in args {
+        match resolve(arg) {
+            Some(path) => if let Err(error) = fs::remove(&path) {
+                println!("rm: {}: {:?}", arg, error);
+                result = Err(status::FAILURE);
+            },
+            None => {
+                println!("rm: {}: path too long", arg);
+                result = Err(status::FAILURE);
+            }
+        }
+    }
+
+    result
+}
+
+/// Copies a file: `cp <src> <dst>`, into `dst` under the same name if it's
+/// a directory.
+fn cp(args: &[&str]) -> Status {
+    if args.len() != 2 {
+        fail!("usage: cp <src> <dst>");
+    }
+
+    let (source, destination) = match (resolve(args[0]), resolve(args[1])) {
+        (Some(source), Some(destination)) => (source, destination),
+        _ => fail!("cp: path too long")
+    };
+
+    let entry = match fs::stat(&source) {
+        Ok(ref entry) if entry.is_dir() => {
+            fail!("cp: {}: {:?}", args[0], FatError::IsADirectory)
+        }
+        Ok(entry) => entry,
+        Err(error) => fail!("cp: {}: {:?}", args[0], error)
+    };
+
+    let destination = match fs::stat(&destination) {
+        Ok(ref dir) if dir.is_dir() => match destination.join(entry.name()) {
+            Some(path) => path,
+            None => fail!("cp: {}: path too long", args[1])
+        },
+        _ => destination
+    };
+
+    let mut buffer = FILE_BUFFER.lock();
+    let len = match fs::load(&source, &mut buffer[..]) {
+        Ok(len) => len,
+        Err(error) => fail!("cp: {}: {:?}", args[0], error)
+    };
+
+    if let Err(error) = fs::save(&destination, &buffer[..len]) {
+        fail!("cp: {}: {:?}", args[1], error);
+    }
+
+    Ok(())
+}
+
+/// Edits a file on the terminal with a full-screen editor, creating it on
+/// the first save if there's none: `edit <file>`. See `Editor` for the
+/// keys.
+fn edit(args: &[&str]) -> Status {
+    if args.len() != 1 {
+        fail!("usage: edit <file>");
+    }
+
+    let path = match resolve(args[0]) {
+        Some(path) => path,
+        None => fail!("edit: {}: path too long", args[0])
+    };
+
+    let mut buffer = FILE_BUFFER.lock();
+    let len = match fs::load(&path, &mut buffer[..]) {
+        Ok(len) => len,
+        Err(FatError::NotFound) => 0,
+        Err(error) => fail!("edit: {}: {:?}", args[0], error)
+    };
+
+    let mut terminal = running_on();
+    let mut editor = Editor::new(&mut buffer[..], len);
+    terminal.write_bytes(b"\x1b[2J");
+    let _ = editor.draw(&mut terminal, args[0]);
+    loop {
+        let input = match terminal.try_read_byte() {
+            Some(input) => input,
+            None => continue
+        };
+
+        match editor.input(input) {
+            Action::None => {  }
+            Action::Save => {
+                let result = fs::save(&path, editor.text());
+                match result {
+                    Ok(()) => editor.saved(),
+                    Err(error) => editor.set_message(format_args!("not saved: {:?}", error))
+                }
+            }
+            Action::Quit => break
+        }
+
+        let _ = editor.draw(&mut terminal, args[0]);
+    }
+
+    terminal.write_bytes(b"\x1b[2J\x1b[H");
+    Ok(())
+}
+
+/// Commands whose output is shown through the pager when typed on their own
+/// or at the end of a pipeline, as if followed by `| less`. Only as much of
+/// it as fits in a pipe is kept.
+const PAGED_COMMANDS: &[&str] = &["cat", "xxd"];
+
+/// Shows `text` on the terminal a screen at a time, with `name` on the
+/// status line, until `q` is pressed; see `Pager` for the keys. Prints it
+/// as it is instead if the output is piped, or if `fit` is `true` and it
+/// fits on one screen.
+fn page(text: &[u8], name: &str, fit: bool) {
+    let mut pager = Pager::new(text);
+    let piped = match console::shell_sink() {
+        Sink::Pipe(_) => true,
+        Sink::Console | Sink::Terminal(_) => false
+    };
+
+    if piped || fit && pager.fits() {
+        if print_text(text) > 0 {
+            print!("?");
+        }
+
+        return;
+    }
+
+    let mut terminal = running_on();
+    terminal.write_bytes(b"\x1b[2J");
+    let _ = pager.draw(&mut terminal, name);
+    loop {
+        let input = match terminal.try_read_byte() {
+            Some(input) => input,
+            None => continue
+        };
+
+        if pager.input(input) {
+            break;
+        }
+
+        let _ = pager.draw(&mut terminal, name);
+    }
+
+    terminal.write_bytes(b"\x1b[2J\x1b[H");
+}
+
+/// Pages the piped input, named `name` on the status line, as `page()`
+/// does. Input past `FILE_MAX_SIZE` bytes is left out.
+fn page_input(name: &str, fit: bool) {
+    let mut buffer = FILE_BUFFER.lock();
+    let mut len = 0;
+    while len < buffer.len() {
+        match pipe::read(&mut buffer[len..]) {
+            0 => break,
+            read => len += read
+        }
+    }
+
+    page(&buffer[..len], name, fit);
+}
+
+/// Shows a file, or the piped input if none is given, a screen at a time:
+/// `less [file]`. Space and Page Down go forward, `b` and Page Up back, and
+/// `q` quits.
+fn less(args: &[&str]) -> Status {
+    if args.is_empty() && pipe::is_piped() {
+        page_input("", false);
+        return Ok(());
+    }
+
+    if args.len() != 1 {
+        fail!("usage: less <file>");
+    }
+
+    let path = match resolve(args[0]) {
+        Some(path) => path,
+        None => fail!("less: {}: path too long", args[0])
+    };
+
+    let mut buffer = FILE_BUFFER.lock();
+    let len = match fs::load(&path, &mut buffer[..]) {
+        Ok(len) => len,
+        Err(error) => fail!("less: {}: {:?}", args[0], error)
+    };
+
+    page(&buffer[..len], args[0], false);
+    Ok(())
+}
+
+/// Plays a WAV file on the headphone jack: `play <file>`. The file must be
+/// 8 or 16-bit PCM, mono or stereo, and is played in mono.
+fn play(args: &[&str]) -> Status {
+    if args.len() != 1 {
+        fail!("usage: play <file>");
+    }
+
+    let path = match resolve(args[0]) {
+        Some(path) => path,
+        None => fail!("play: {}: path too long", args[0])
+    };
+
+    let mut buffer = FILE_BUFFER.lock();
+    let len = match fs::load(&path, &mut buffer[..]) {
+        Ok(len) => len,
+        Err(error) => fail!("play: {}: {:?}", args[0], error)
+    };
+
+    let wav = match Wav::parse(&buffer[..len]) {
+        Ok(wav) => wav,
+        Err(error) => fail!("play: {}: {:?}", args[0], error)
+    };
+
+    let mut audio = match Audio::try_new() {
+        Some(audio) => audio,
+        None => fail!("play: no free DMA channel")
+    };
+
+    println!("play: {}: {}Hz, {}-bit, {} channel(s), {}.{:03}s", args[0], wav.sample_rate,
+             wav.bits_per_sample, wav.channels, wav.duration_ms() / 1000, wav.duration_ms() % 1000);
+    audio.play_wav(&wav);
+    Ok(())
+}
+
+/// Parses `s` as a number: hexadecimal if it starts with `0x`, decimal
+/// otherwise.
+fn parse_number(s: &str) -> Option<usize> {
+    if s.starts_with("0x") || s.starts_with("0X") {
+        usize::from_str_radix(&s[2..], 16).ok()
+    } else {
+        s.parse().ok()
+    }
+}
+
+/// The bytes `xxd` prints per line.
+const XXD_LINE_LEN: usize = 16;
+
+/// Prints `bytes`, found at `offset`, as an `xxd` line: the offset, the
+/// bytes in hex in pairs, and the bytes as ASCII with `.` for anything
+/// unprintable.
+fn print_hex_line(offset: usize, bytes: &[u8]) {
+    print!("{:08x}:", offset);
+    for i in 0..XXD_LINE_LEN {
+        if i % 2 == 0 {
+            print!(" ");
+        }
+
+        match bytes.get(i) {
+            Some(byte) => print!("{:02x}", byte),
+            None => print!("  ")
+        }
+    }
+
+    print!("  ");
+    for &byte in bytes {
+        print!("{}", if byte >= b' ' && byte < 0x7F { byte as char } else { '.' });
+    }
+
+    println!();
+}
+
+/// Dumps a file, or `len` bytes of memory from `addr`, in hex and ASCII
+/// until a key is pressed: `xxd <file>` or `xxd <addr> <len>`. Numbers are
+/// hex with a `0x` prefix, decimal otherwise. Dumping memory that isn't
+/// mapped faults like any other access to it.
+fn xxd(args: &[&str]) -> Status {
+    match args.len() {
+        1 => xxd_file(args[0]),
+        2 => match (parse_number(args[0]), parse_number(args[1])) {
+            (Some(addr), Some(len)) if addr.checked_add(len).is_some() => xxd_memory(addr, len),
+            _ => fail!("xxd: bad address or length")
+        },
+        _ => fail!("usage: xxd <file> | xxd <addr> <len>")
+    }
+}
+
+/// Dumps the file at `arg` for `xxd`.
+fn xxd_file(arg: &str) -> Status {
+    let path = match resolve(arg) {
+        Some(path) => path,
+        None => fail!("xxd: {}: path too long", arg)
+    };
+
+    // Every chunk but the last is a whole sector, so lines never straddle
+    // two chunks. The rest of the file is still read after a key press,
+    // but not printed.
+    let (mut offset, mut stopped) = (0, false);
+    let result = fs::stream(&path, |chunk| {
+        for line in chunk.chunks(XXD_LINE_LEN) {
+            stopped = stopped || running_on().try_read_byte().is_some();
+            if stopped {
+                return;
+            }
+
+            print_hex_line(offset, line);
+            offset += line.len();
+        }
+    });
+
+    if let Err(error) = result {
+        fail!("xxd: {}: {:?}", arg, error);
+    }
+
+    Ok(())
+}
+
+/// Dumps `len` bytes of memory from `addr` for `xxd`.
+fn xxd_memory(addr: usize, len: usize) -> Status {
+    let mut line = [0u8; XXD_LINE_LEN];
+    let mut start = addr;
+    while start < addr + len && running_on().try_read_byte().is_none() {
+        let end = ::std::cmp::min(start + XXD_LINE_LEN, addr + len);
+        for (i, byte) in line[..end - start].iter_mut().enumerate() {
+            *byte = unsafe { ::std::ptr::read_volatile((start + i) as *const u8) };
+        }
+
+        print_hex_line(start, &line[..end - start]);
+        start = end;
+    }
+
+    Ok(())
+}
+
+/// The width of a `peek` or `poke`.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+enum Width {
+    Word,
+    Half,
+    Byte,
+}
+
+impl Width {
+    /// Parses `w`, `h` or `b`; the width defaults to a word if `arg` is
+    /// `None`.
+    fn parse(arg: Option<&&str>) -> Option<Width> {
+        match arg.map(|arg| *arg) {
+            None | Some("w") => Some(Width::Word),
+            Some("h") => Some(Width::Half),
+            Some("b") => Some(Width::Byte),
+            _ => None
+        }
+    }
+
+    /// Returns the width in bytes.
+    fn bytes(self) -> usize {
+        match self {
+            Width::Word => 4,
+            Width::Half => 2,
+            Width::Byte => 1,
+        }
+    }
+}
+
+/// Parses the address of a `peek` or `poke`, which must be aligned to
+/// `width`, printing why it's not valid if it isn't.
+fn parse_address(command: &str, arg: &str, width: Width) -> Option<usize> {
+    match parse_number(arg) {
+        Some(addr) if addr % width.bytes() == 0 => Some(addr),
+        Some(_) => {
+            println!("{}: {}: not aligned to {} bytes", command, arg, width.bytes());
+            None
+        }
+        None => {
+            println!("{}: {}: bad address", command, arg);
+            None
+        }
+    }
+}
+
+/// Reads and prints the word, half-word or byte at `addr` with a volatile
+/// access, like a peripheral register: `peek <addr> [w|h|b]`.
+fn peek(args: &[&str]) -> Status {
+    let width = match Width::parse(args.get(1)) {
+        Some(width) if args.len() == 1 || args.len() == 2 => width,
+        _ => fail!("usage: peek <addr> [w|h|b]")
+    };
+
+    let addr = match parse_address("peek", args[0], width) {
+        Some(addr) => addr,
+        None => return Err(status::FAILURE)
+    };
+
+    let value = unsafe {
+        match width {
+            Width::Word => ::std::ptr::read_volatile(addr as *const u32),
+            Width::Half => ::std::ptr::read_volatile(addr as *const u16) as u32,
+            Width::Byte => ::std::ptr::read_volatile(addr as *const u8) as u32,
+        }
+    };
+
+    println!("{:#010x}: {:#0width$x}", addr, value, width = 2 + 2 * width.bytes());
+    Ok(())
+}
+
+/// Writes `value` to the word, half-word or byte at `addr` with a volatile
+/// access: `poke <addr> <value> [w|h|b]`.
+fn poke(args: &[&str]) -> Status {
+    let width = match Width::parse(args.get(2)) {
+        Some(width) if args.len() == 2 || args.len() == 3 => width,
+        _ => fail!("usage: poke <addr> <value> [w|h|b]")
+    };
+
+    let addr = match parse_address("poke", args[0], width) {
+        Some(addr) => addr,
+        None => return Err(status::FAILURE)
+    };
+
+    let value = match parse_number(args[1]) {
+        Some(value) if value >> (8 * width.bytes()) == 0 => value,
+        _ => fail!("poke: {}: not a {}-byte value", args[1], width.bytes())
+    };
+
+    unsafe {
+        match width {
+            Width::Word => ::std::ptr::write_volatile(addr as *mut u32, value as u32),
+            Width::Half => ::std::ptr::write_volatile(addr as *mut u16, value as u16),
+            Width::Byte => ::std::ptr::write_volatile(addr as *mut u8, value as u8),
+        }
+    }
+
+    Ok(())
+}
+
+/// The commands run most recently on any terminal, oldest first.
+struct History {
+    lines: [[u8; MAX_CMDLEN]; HISTORY_SIZE],
+    lens: [usize; HISTORY_SIZE],
+    /// The slot of the oldest command.
+    start: usize,
+    len: usize
+}
+
+impl History {
+    const fn new() -> History {
+        History {
+            lines: [[0; MAX_CMDLEN]; HISTORY_SIZE],
+            lens: [0; HISTORY_SIZE],
+            start: 0,
+            len: 0
+        }
+    }
+
+    /// Returns the number of commands kept.
+    fn len(&self) -> usize {
+        self.len
+    }
+
+    /// Returns the `i`th command kept, counting from the oldest.
+    fn get(&self, i: usize) -> Option<&[u8]> {
+        if i >= self.len {
+            return None;
+        }
+
+        let slot = (self.start + i) % HISTORY_SIZE;
+        Some(&self.lines[slot][..self.lens[slot]])
+    }
+
+    /// Adds `line` as the newest command, dropping the oldest if full.
+    /// Blank lines and repeats of the newest command aren't kept.
+    fn push(&mut self, line: &[u8]) {
+        let repeat = self.len.checked_sub(1).and_then(|newest| self.get(newest)) == Some(line);
+        if repeat || line.iter().all(|&byte| byte == b' ') {
+            return;
+        }
+
+        let slot = (self.start + self.len) % HISTORY_SIZE;
+        self.lines[slot][..line.len()].copy_from_slice(line);
+        self.lens[slot] = line.len();
+        if self.len == HISTORY_SIZE {
+            self.start = (self.start + 1) % HISTORY_SIZE;
+        } else {
+            self.len += 1;
+        }
+    }
+}
+
+static HISTORY: Mutex<History> = Mutex::new(History::new());
+
+/// Lists the commands kept in the history, oldest first.
+fn history() -> Status {
+    let history = HISTORY.lock();
+    for i in 0..history.len() {
+        let line = history.get(i).unwrap_or(&[]);
+        println!("{:4}  {}", i + 1, std::str::from_utf8(line).unwrap_or("?"));
+    }
+
+    Ok(())
+}
+
+/// An error setting an environment variable.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+enum EnvError {
+    /// The name isn't letters, digits and `_`, starting with a non-digit.
+    InvalidName,
+    /// The name or value is longer than `MAX_VAR_NAME` or `MAX_VAR_VALUE`.
+    TooLong,
+    /// `MAX_VARS` variables are already set.
+    Full,
+}
+
+/// Names and their values, in the order they were first set: the shell's
+/// environment variables, or its aliases.
+struct Env {
+    names: [[u8; MAX_VAR_NAME]; MAX_VARS],
+    name_lens: [usize; MAX_VARS],
+    values: [[u8; MAX_VAR_VALUE]; MAX_VARS],
+    value_lens: [usize; MAX_VARS],
+    len: usize
+}
+
+impl Env {
+    const fn new() -> Env {
+        Env {
+            names: [[0; MAX_VAR_NAME]; MAX_VARS],
+            name_lens: [0; MAX_VARS],
+            values: [[0; MAX_VAR_VALUE]; MAX_VARS],
+            value_lens: [0; MAX_VARS],
+            len: 0
+        }
+    }
+
+    /// Returns the number of variables set.
+    fn len(&self) -> usize {
+        self.len
+    }
+
+    /// Returns the name and value of the `i`th variable set.
+    fn entry(&self, i: usize) -> Option<(&[u8], &[u8])> {
+        if i >= self.len {
+            return None;
+        }
+
+        Some((&self.names[i][..self.name_lens[i]], &self.values[i][..self.value_lens[i]]))
+    }
+
+    /// Returns the value of the variable `name`, if it's set.
+    fn get(&self, name: &[u8]) -> Option<&[u8]> {
+        (0..self.len).filter_map(|i| self.entry(i))
+            .find(|&(var, _)| var == name)
+            .map(|(_, value)| value)
+    }
+
+    /// Sets the variable `name` to `value`.
+    fn set(&mut self, name: &[u8], value: &[u8]) -> Result<(), EnvError> {
+        if name.is_empty() || variable_name_len(name) != name.len() {
+            return Err(EnvError::InvalidName);
+        }
+
+        if name.len() > MAX_VAR_NAME || value.len() > MAX_VAR_VALUE {
+            return Err(EnvError::TooLong);
+        }
+
+        let i = match (0..self.len).find(|&i| &self.names[i][..self.name_lens[i]] == name) {
+            Some(i) => i,
+            None if self.len < MAX_VARS => {
+                self.len += 1;
+                self.len - 1
+            }
+            None => return Err(EnvError::Full)
+        };
+
+        self.names[i][..name.len()].copy_from_slice(name);
+        self.name_lens[i] = name.len();
+        self.values[i][..value.len()].copy_from_slice(value);
+        self.value_lens[i] = value.len();
+        Ok(())
+    }
+
+    /// Removes `name`, keeping the order of the rest. Returns `false` if it
+    /// wasn't set.
+    fn remove(&mut self, name: &[u8]) -> bool {
+        let i = match (0..self.len).find(|&i| &self.names[i][..self.name_lens[i]] == name) {
+            Some(i) => i,
+            None => return false
+        };
+
+        for j in i..self.len - 1 {
+            self.names[j] = self.names[j + 1];
+            self.name_lens[j] = self.name_lens[j + 1];
+            self.values[j] = self.values[j + 1];
+            self.value_lens[j] = self.value_lens[j + 1];
+        }
+
+        self.len -= 1;
+        true
+    }
+}
+
+/// The environment variables, shared by every terminal.
+static ENV: Mutex<Env> = Mutex::new(Env::new());
+
+/// The aliases `alias` defines, shared by every terminal.
+static ALIASES: Mutex<Env> = Mutex::new(Env::new());
+
+/// The exit status of the last command run, which `$?` expands to.
+static STATUS: AtomicUsize = AtomicUsize::new(status::SUCCESS);
+
+/// Returns the length of the variable name at the start of `bytes`:
+/// letters, digits and `_`, not starting with a digit.
+fn variable_name_len(bytes: &[u8]) -> usize {
+    if bytes.first().map_or(false, |&byte| byte >= b'0' && byte <= b'9') {
+        return 0;
+    }
+
+    bytes.iter()
+        .take_while(|&&byte| match byte {
+            b'a'...b'z' | b'A'...b'Z' | b'0'...b'9' | b'_' => true,
+            _ => false
+        })
+        .count()
+}
+
+/// Copies `line` into `buffer` with each `$NAME` replaced by the value of
+/// the variable `NAME`, or nothing if it's not set, and `$?` by the last
+/// command's exit status. Returns the result, or `None` if it doesn't fit.
+fn expand<'a>(line: &str, buffer: &'a mut [u8]) -> Option<&'a str> {
+    let mut status = [0u8; 20];
+    let status_len = {
+        let mut cursor = io::Cursor::new(&mut status[..]);
+        let _ = write!(cursor, "{}", STATUS.load(Ordering::Relaxed));
+        cursor.position() as usize
+    };
+
+    let env = ENV.lock();
+    let (bytes, mut len, mut i) = (line.as_bytes(), 0, 0);
+    while i < bytes.len() {
+        let dollar = bytes[i] == b'$';
+        let name_len = if dollar { variable_name_len(&bytes[i + 1..]) } else { 0 };
+        let (piece, used) = if dollar && bytes.get(i + 1) == Some(&b'?') {
+            (&status[..status_len], 2)
+        } else if name_len > 0 {
+            (env.get(&bytes[i + 1..i + 1 + name_len]).unwrap_or(&[]), 1 + name_len)
+        } else {
+            (&bytes[i..i + 1], 1)
+        };
+
+        buffer.get_mut(len..len + piece.len())?.copy_from_slice(piece);
+        len += piece.len();
+        i += used;
+    }
+
+    let buffer: &'a [u8] = buffer;
+    str::from_utf8(&buffer[..len]).ok()
+}
+
+/// Turns coloring of the kernel log and the shell's output on or off:
+/// `color [on|off]`. Dumb terminals show the ANSI escapes as they are, so
+/// need it off. Prints whether it's on if neither is given.
+fn color(args: &[&str]) -> Status {
+    match args.first().map(|&arg| arg) {
+        None => println!("color: {}", if console::color() { "on" } else { "off" }),
+        Some("on") if args.len() == 1 => console::set_color(true),
+        Some("off") if args.len() == 1 => console::set_color(false),
+        _ => fail!("usage: color [on|off]")
+    }
+
+    Ok(())
+}
+
+/// Sets environment variables: `export NAME=value...`. Lists them, like
+/// `env`, if none are given.
+fn export(args: &[&str]) -> Status {
+    if args.is_empty() {
+        return env();
+    }
+
+    let mut result = Ok(());
+    for &arg in args {
+        let mut parts = arg.splitn(2, '=');
+        let (name, value) = match (parts.next(), parts.next()) {
+            (Some(name), Some(value)) => (name, value),
+            _ => {
+                println!("export: {}: expected NAME=value", arg);
+                result = Err(status::FAILURE);
+                continue;
+            }
+        };
+
+        if let Err(error) = ENV.lock().set(name.as_bytes(), value.as_bytes()) {
+            println!("export: {}: {:?}", name, error);
+            result = Err(status::FAILURE);
+        }
+    }
+
+    result
+}
+
+/// Lists the environment variables as `NAME=value`.
+fn env() -> Status {
+    let env = ENV.lock();
+    for i in 0..env.len() {
+        if let Some((name, value)) = env.entry(i) {
+            let name = str::from_utf8(name).unwrap_or("?");
+            println!("{}={}", name, str::from_utf8(value).unwrap_or("?"));
+        }
+    }
+
+    Ok(())
+}
+
+/// Copies `command` into `buffer` with its first word replaced by the
+/// alias it names, if it names one, then the first word of that, and so
+/// on. An alias isn't expanded again within its own expansion, so
+/// `alias ls='ls -a'` works. Returns the result, or `None` if it doesn't
+/// fit.
+fn expand_aliases<'a>(command: &str, buffer: &'a mut [u8]) -> Option<&'a str> {
+    let aliases = ALIASES.lock();
+    let mut expanded = [false; MAX_VARS];
+    buffer.get_mut(..command.len())?.copy_from_slice(command.as_bytes());
+    let mut len = command.len();
+    loop {
+        let (start, end, i) = {
+            let text = &buffer[..len];
+            let start = text.iter().position(|&byte| byte != b' ').unwrap_or(len);
+            let end = text[start..].iter().position(|&byte| byte == b' ')
+                .map_or(len, |space| start + space);
+            let word = &text[start..end];
+            match (0..aliases.len()).find(|&i| {
+                !expanded[i] && aliases.entry(i).map_or(false, |(name, _)| name == word)
+            }) {
+                Some(i) => (start, end, i),
+                None => break
+            }
+        };
+
+        expanded[i] = true;
+        let value = aliases.entry(i).map_or(&[][..], |(_, value)| value);
+        let new_len = len - (end - start) + value.len();
+        if new_len > buffer.len() {
+            return None;
+        }
+
+        let mut rest = [0u8; MAX_CMDLEN];
+        rest[..len - end].copy_from_slice(&buffer[end..len]);
+        buffer[start..start + value.len()].copy_from_slice(value);
+        buffer[start + value.len()..new_len].copy_from_slice(&rest[..len - end]);
+        len = new_len;
+    }
+
+    let buffer: &'a [u8] = buffer;
+    str::from_utf8(&buffer[..len]).ok()
+}
+
+/// Defines an alias: `alias NAME=command [args...]`. A command whose first
+/// word is `NAME` runs `command` in its place, with `args` before its own.
+/// The definition may be quoted, as in `alias ll='ls -l'`. Aliases can't
+/// hold `|`, `;`, `&&`, `||` or `>`, as lines are split at those before
+/// aliases are expanded. With just a name, prints that alias; with nothing,
+/// all of them. Aliases defined in `/rc.sh` are there from the start.
+fn alias(args: &[&str]) -> Status {
+    if args.is_empty() {
+        let aliases = ALIASES.lock();
+        for i in 0..aliases.len() {
+            if let Some((name, value)) = aliases.entry(i) {
+                let name = str::from_utf8(name).unwrap_or("?");
+                println!("alias {}='{}'", name, str::from_utf8(value).unwrap_or("?"));
+            }
+        }
+
+        return Ok(());
+    }
+
+    // The line was split at spaces, so the definition is joined back up.
+    let mut definition = [0u8; MAX_CMDLEN];
+    let mut len = 0;
+    for (i, arg) in args.iter().enumerate() {
+        if i > 0 {
+            definition[len] = b' ';
+            len += 1;
+        }
+
+        definition[len..len + arg.len()].copy_from_slice(arg.as_bytes());
+        len += arg.len();
+    }
+
+    let definition = str::from_utf8(&definition[..len]).unwrap_or("");
+    let mut parts = definition.splitn(2, '=');
+    let (name, value) = match (parts.next(), parts.next()) {
+        (Some(name), Some(value)) => (name, value),
+        (Some(name), None) if args.len() == 1 => {
+            match ALIASES.lock().get(name.as_bytes()) {
+                Some(value) => {
+                    println!("alias {}='{}'", name, str::from_utf8(value).unwrap_or("?"))
+                }
+                None => fail!("alias: {}: not found", name)
+            }
+
+            return Ok(());
+        }
+        _ => fail!("usage: alias [NAME[=command [args...]]]")
+    };
+
+    let quoted = value.len() >= 2 && (value.starts_with('\'') && value.ends_with('\'')
+                                      || value.starts_with('"') && value.ends_with('"'));
+    let value = if quoted { &value[1..value.len() - 1] } else { value };
+    if let Err(error) = ALIASES.lock().set(name.as_bytes(), value.as_bytes()) {
+        fail!("alias: {}: {:?}", name, error);
+    }
+
+    Ok(())
+}
+
+/// Removes aliases: `unalias NAME...`.
+fn unalias(args: &[&str]) -> Status {
+    if args.is_empty() {
+        fail!("usage: unalias NAME...");
+    }
+
+    let mut result = Ok(());
+    for &name in args {
+        if !ALIASES.lock().remove(name.as_bytes()) {
+            println!("unalias: {}: not found", name);
+            result = Err(status::FAILURE);
+        }
+    }
+
+    result
+}
+
+/// Where a shell session reads input and writes output.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+enum Terminal {
+    /// The console device.
+    Console,
+    /// The client connected to the telnet port.
+    Telnet
+}
+
+impl Terminal {
+    /// Returns the next byte typed on the terminal, if there is one.
+    fn try_read_byte(self) -> Option<u8> {
+        match self {
+            Terminal::Console => CONSOLE.lock().try_read_byte(),
+            Terminal::Telnet => telnet::try_read_byte()
+        }
+    }
+
+    /// Writes `bytes` to the terminal.
+    fn write_bytes(self, bytes: &[u8]) {
+        match self {
+            Terminal::Console => {
+                let mut console = CONSOLE.lock();
+                for &byte in bytes {
+                    console.write_byte(byte);
+                }
+                console.flush();
+            }
+            Terminal::Telnet => telnet::write_bytes(bytes)
+        }
+    }
+}
+
+impl io::Write for Terminal {
+    fn write(&mut self, buf: &[u8]) -> io::Result<usize> {
+        self.write_bytes(buf);
+        Ok(buf.len())
+    }
+
+    fn flush(&mut self) -> io::Result<()> {
+        Ok(())
+    }
+}
+
+/// Whether the command running was typed on the telnet terminal.
+static RUNNING_REMOTE: AtomicBool = AtomicBool::new(false);
+
+/// Returns the terminal the command running was typed on.
+fn running_on() -> Terminal {
+    if RUNNING_REMOTE.load(Ordering::Relaxed) {
+        Terminal::Telnet
+    } else {
+        Terminal::Console
+    }
+}
+
+/// The most names Tab completion offers for a word.
+const MAX_COMPLETIONS: usize = 64;
+
+/// The longest name Tab completion offers: an 8.3 file name.
+const MAX_COMPLETION_LEN: usize = 12;
+
+/// The builtins or directory entries a word typed so far could be.
+struct Completions {
+    names: [[u8; MAX_COMPLETION_LEN]; MAX_COMPLETIONS],
+    lens: [usize; MAX_COMPLETIONS],
+    /// Whether each name is a directory, completed with `/` rather than a
+    /// space.
+    dirs: [bool; MAX_COMPLETIONS],
+    count: usize
+}
+
+impl Completions {
+    fn new() -> Completions {
+        Completions {
+            names: [[0; MAX_COMPLETION_LEN]; MAX_COMPLETIONS],
+            lens: [0; MAX_COMPLETIONS],
+            dirs: [false; MAX_COMPLETIONS],
+            count: 0
+        }
+    }
+
+    /// Adds `name` if it starts with `stem`, ignoring case as FAT does.
+    fn add(&mut self, name: &str, dir: bool, stem: &[u8]) {
+        let name = name.as_bytes();
+        if self.count == MAX_COMPLETIONS || name.len() > MAX_COMPLETION_LEN
+            || name.len() < stem.len() || !name[..stem.len()].eq_ignore_ascii_case(stem) {
+            return;
+        }
+
+        self.names[self.count][..name.len()].copy_from_slice(name);
+        self.lens[self.count] = name.len();
+        self.dirs[self.count] = dir;
+        self.count += 1;
+    }
+
+    /// Returns the `i`th name added.
+    fn name(&self, i: usize) -> &[u8] {
+        &self.names[i][..self.lens[i]]
+    }
+
+    /// Returns the length of the longest prefix every name shares.
+    fn common_len(&self) -> usize {
+        let first = self.name(0);
+        (1..self.count).fold(first.len(), |common, i| {
+            let name = self.name(i);
+            (0..common).find(|&j| j >= name.len() || !first[j].eq_ignore_ascii_case(&name[j]))
+                .unwrap_or(common)
+        })
+    }
+}
+
+/// How far through an ANSI escape sequence a session's input is.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+enum Escape {
+    None,
+    /// After `ESC`.
+    Start,
+    /// After `ESC [` or `ESC O`, with the numeric parameter so far.
+    Csi(usize)
+}
+
+/// A shell on one terminal: the line being typed there, and where in it the
+/// cursor is.
+struct Session<'a> {
+    terminal: Terminal,
+    line: StackVec<'a, u8>,
+    cursor: usize,
+    escape: Escape,
+    /// The history entry the line was recalled from, if it was.
+    recalled: Option<usize>,
+    /// Whether the last input was a Tab that couldn't complete anything.
+    tabbed: bool
+}
+
+impl<'a> Session<'a> {
+    /// Returns a session on `terminal` keeping its line in `buf`.
+    fn new(terminal: Terminal, buf: &'a mut [u8]) -> Session<'a> {
+        Session {
+            terminal,
+            line: StackVec::new(buf),
+            cursor: 0,
+            escape: Escape::None,
+            recalled: None,
+            tabbed: false
+        }
+    }
+
+    /// Writes the prompt: the variable `PS1` if it's set, `prefix` if not.
+    /// In `PS1`, `\w` stands for the working directory, `\?` for the last
+    /// command's exit status, `\e` for ESC, to start a color, `\s` for a
+    /// space, which a variable can't otherwise hold, and `\\` for `\`.
+    fn write_prompt(&self, prefix: &str) {
+        let mut ps1 = [0u8; MAX_VAR_VALUE];
+        let len = match ENV.lock().get(b"PS1") {
+            Some(value) => {
+                ps1[..value.len()].copy_from_slice(value);
+                value.len()
+            }
+            None => return self.terminal.write_bytes(prefix.as_bytes())
+        };
+
+        let mut rest = &ps1[..len];
+        while let Some(backslash) = rest.iter().position(|&byte| byte == b'\\') {
+            self.terminal.write_bytes(&rest[..backslash]);
+            let used = match rest.get(backslash + 1) {
+                Some(&b'w') => {
+                    self.terminal.write_bytes(CWD.lock().as_str().as_bytes());
+                    2
+                }
+                Some(&b'?') => {
+                    let mut status = [0u8; 20];
+                    let len = {
+                        let mut cursor = io::Cursor::new(&mut status[..]);
+                        let _ = write!(cursor, "{}", STATUS.load(Ordering::Relaxed));
+                        cursor.position() as usize
+                    };
+                    self.terminal.write_bytes(&status[..len]);
+                    2
+                }
+                Some(&b'e') => {
+                    self.terminal.write_bytes(b"\x1b");
+                    2
+                }
+                Some(&b's') => {
+                    self.terminal.write_bytes(b" ");
+                    2
+                }
+                Some(&b'\\') => {
+                    self.terminal.write_bytes(b"\\");
+                    2
+                }
+                _ => {
+                    self.terminal.write_bytes(b"\\");
+                    1
+                }
+            };
+
+            rest = &rest[backslash + used..];
+        }
+
+        self.terminal.write_bytes(rest);
+    }
+
+    /// Discards the line typed so far and writes the prompt, `prefix` unless
+    /// `PS1` is set.
+    fn prompt(&mut self, prefix: &str) {
+        self.line.truncate(0);
+        self.cursor = 0;
+        self.recalled = None;
+        self.write_prompt(prefix);
+    }
+
+    /// Rewrites the prompt and the line over what the terminal shows, and
+    /// puts the terminal's cursor back at the line's.
+    fn redraw(&self, prefix: &str) {
+        self.terminal.write_bytes(b"\r");
+        self.write_prompt(prefix);
+        self.terminal.write_bytes(self.line.as_slice());
+        self.terminal.write_bytes(b"\x1b[K");
+
+        let behind = self.line.len() - self.cursor;
+        if behind > 0 {
+            let mut move_left = [0u8; 16];
+            let len = {
+                let mut cursor = io::Cursor::new(&mut move_left[..]);
+                let _ = write!(cursor, "\x1b[{}D", behind);
+                cursor.position() as usize
+            };
+            self.terminal.write_bytes(&move_left[..len]);
+        }
+    }
+
+    /// Inserts `byte` at the cursor and moves the cursor past it. Returns
+    /// `false` if the line is full.
+    fn insert(&mut self, byte: u8) -> bool {
+        if self.line.push(byte).is_err() {
+            return false;
+        }
+
+        let line = self.line.as_mut_slice();
+        for i in (self.cursor + 1..line.len()).rev() {
+            line[i] = line[i - 1];
+        }
+
+        line[self.cursor] = byte;
+        self.cursor += 1;
+        true
+    }
+
+    /// Removes the byte at `at`, before the end of the line, moving the
+    /// cursor back if it was past it.
+    fn remove(&mut self, at: usize) {
+        {
+            let line = self.line.as_mut_slice();
+            for i in at..line.len() - 1 {
+                line[i] = line[i + 1];
+            }
+        }
+
+        self.line.pop();
+        if self.cursor > at {
+            self.cursor -= 1;
+        }
+    }
+
+    /// Replaces the line with the history entry `recalled`, or with nothing
+    /// if it's `None`, and redraws it after the prompt `prefix` with the
+    /// cursor at its end.
+    fn recall(&mut self, recalled: Option<usize>, prefix: &str) {
+        self.line.truncate(0);
+        if let Some(i) = recalled {
+            let history = HISTORY.lock();
+            for &byte in history.get(i).unwrap_or(&[]) {
+                let _ = self.line.push(byte);
+            }
+        }
+
+        self.cursor = self.line.len();
+        self.recalled = recalled;
+        self.redraw(prefix);
+    }
+
+    /// Completes the word before the cursor: the command against the
+    /// builtins, and arguments against the entries of the directory they
+    /// name. Ambiguous words are completed as far as every candidate agrees,
+    /// and the candidates listed if that's no further and `again` is set.
+    fn complete(&mut self, again: bool, prefix: &str) {
+        let separator = |byte: &u8| b"|;&".contains(byte);
+        let start = self.line[..self.cursor].iter()
+            .rposition(|byte| *byte == b' ' || separator(byte))
+            .map_or(0, |separator| separator + 1);
+        let stage = self.line[..start].iter().rposition(|byte| separator(byte))
+            .map_or(0, |separator| separator + 1);
+        let command = self.line[stage..start].iter().all(|&byte| byte == b' ');
+
+        let mut word = [0u8; MAX_CMDLEN];
+        let word_len = self.cursor - start;
+        word[..word_len].copy_from_slice(&self.line[start..self.cursor]);
+        let word = &word[..word_len];
+
+        // The part of the word being completed: after the directory, if any.
+        let mut completions = Completions::new();
+        let stem_start = if command {
+            for name in BUILTINS {
+                completions.add(name, false, word);
+            }
+
+            0
+        } else {
+            let stem_start = word.iter().rposition(|&byte| byte == b'/')
+                .map_or(0, |slash| slash + 1);
+            let (dir, stem) = word.split_at(stem_start);
+            let dir = match std::str::from_utf8(dir).ok().and_then(resolve) {
+                Some(dir) => dir,
+                None => return self.terminal.write_bytes(b"\x07")
+            };
+
+            let _ = fs::read_dir(&dir, |entry| {
+                if entry.name() != "." && entry.name() != ".." {
+                    completions.add(entry.name(), entry.is_dir(), stem);
+                }
+            });
+
+            stem_start
+        };
+
+        let stem_len = word_len - stem_start;
+        let replacement_len = match completions.count {
+            0 => return self.terminal.write_bytes(b"\x07"),
+            1 => completions.lens[0],
+            _ => completions.common_len()
+        };
+
+        if completions.count > 1 && replacement_len == stem_len {
+            if !again {
+                self.tabbed = true;
+                return self.terminal.write_bytes(b"\x07");
+            }
+
+            self.terminal.write_bytes(b"\r\n");
+            for i in 0..completions.count {
+                self.terminal.write_bytes(completions.name(i));
+                self.terminal.write_bytes(if completions.dirs[i] { b"/  " } else { b"  " });
+            }
+
+            self.terminal.write_bytes(b"\r\n");
+            return self.redraw(prefix);
+        }
+
+        // The stem is replaced so it takes the candidate's case.
+        for _ in 0..stem_len {
+            let cursor = self.cursor;
+            self.remove(cursor - 1);
+        }
+
+        for &byte in &completions.name(0)[..replacement_len] {
+            self.insert(byte);
+        }
+
+        if completions.count == 1 {
+            self.insert(if completions.dirs[0] { b'/' } else { b' ' });
+        }
+
+        self.redraw(prefix);
+    }
+
+    /// Handles the escape sequence ending in `control` with the numeric
+    /// parameter `param`: Up and Down step back and forth through the
+    /// history, Left, Right, Home and End move the cursor, and Delete
+    /// removes the byte under it.
+    fn control(&mut self, control: u8, param: usize, prefix: &str) {
+        let len = HISTORY.lock().len();
+        match (control, param, self.recalled) {
+            (b'A', _, None) if len > 0 => self.recall(Some(len - 1), prefix),
+            (b'A', _, Some(i)) if i > 0 => self.recall(Some(i - 1), prefix),
+            (b'B', _, Some(i)) if i + 1 < len => self.recall(Some(i + 1), prefix),
+            (b'B', _, Some(_)) => self.recall(None, prefix),
+            (b'C', _, _) if self.cursor < self.line.len() => {
+                self.terminal.write_bytes(&self.line[self.cursor..self.cursor + 1]);
+                self.cursor += 1;
+            }
+            (b'D', _, _) if self.cursor > 0 => {
+                self.terminal.write_bytes(b"\x08");
+                self.cursor -= 1;
+            }
+            (b'H', _, _) | (b'~', 1, _) | (b'~', 7, _) => {
+                self.cursor = 0;
+                self.redraw(prefix);
+            }
+            (b'F', _, _) | (b'~', 4, _) | (b'~', 8, _) => {
+                self.cursor = self.line.len();
+                self.redraw(prefix);
+            }
+            (b'~', 3, _) if self.cursor < self.line.len() => {
+                let cursor = self.cursor;
+                self.remove(cursor);
+                self.redraw(prefix);
+            }
+            _ => self.terminal.write_bytes(b"\x07")
+        }
+    }
+
+    /// Handles the byte `input` typed on the terminal: edits the line at
+    /// the cursor and redraws it, and runs the line and prompts with `prefix`
+    /// again at a newline. Up and Down recall commands from the history.
+    fn input(&mut self, input: u8, prefix: &str) {
+        let tabbed = self.tabbed;
+        self.tabbed = false;
+        match self.escape {
+            Escape::None => {  }
+            Escape::Start => {
+                self.escape = match input {
+                    b'[' | b'O' => Escape::Csi(0),
+                    _ => Escape::None
+                };
+                return;
+            }
+            Escape::Csi(param) => {
+                self.escape = match input {
+                    b'0'...b'9' => {
+                        let digit = (input - b'0') as usize;
+                        Escape::Csi(param.saturating_mul(10).saturating_add(digit))
+                    }
+                    b';' => Escape::Csi(param),
+                    _ => {
+                        self.control(input, param, prefix);
+                        Escape::None
+                    }
+                };
+                return;
+            }
+        }
+
+        if input == b'\n' || input == b'\r' { // newline
+            self.terminal.write_bytes(b"\r\n");
+            HISTORY.lock().push(self.line.as_slice());
+            self.execute();
+            self.prompt(prefix);
+        } else if input == b'\x1b' { // escape sequence
+            self.escape = Escape::Start;
+        } else if input == b'\x7f' { // delete / backspace
+            if self.cursor > 0 {
+                let cursor = self.cursor;
+                self.remove(cursor - 1);
+                self.redraw(prefix);
+            }
+        } else if input == b'\t' { // completion
+            self.complete(tabbed, prefix);
+        } else if input < 32 || input > 126 { // unprintable uninterpreted
+            self.terminal.write_bytes(b"\x07");
+        } else { // regular character
+            if !self.insert(input) {
+                self.terminal.write_bytes(b"\x07");
+            } else if self.cursor == self.line.len() {
+                self.terminal.write_bytes(&[input]);
+            } else {
+                self.redraw(prefix);
+            }
+        }
+    }
+
+    /// Runs the commands on the line, with their output going to the
+    /// session's terminal.
+    fn execute(&self) {
+        let remote = self.terminal == Terminal::Telnet;
+        let terminal = if remote { Sink::Terminal(telnet::write_bytes) } else { Sink::Console };
+        RUNNING_REMOTE.store(remote, Ordering::Relaxed);
+
+        let line = std::str::from_utf8(self.line.as_slice()).expect("failed to decode utf8");
+        run_line(line, terminal);
+
+        RUNNING_REMOTE.store(false, Ordering::Relaxed);
+        console::redirect_shell(Sink::Console);
+    }
+}
+
+/// A file a pipeline's output is redirected to.
+struct Redirection<'a> {
+    path: &'a str,
+    /// Whether the output is added to the end of the file (`>>`) rather
+    /// than replacing what it held (`>`).
+    append: bool,
+}
+
+/// Splits `line` into a pipeline and the file its output is redirected to
+/// with `> file` or `>> file` at the end, if it is. Returns `None` if a
+/// redirection isn't followed by exactly one file name.
+fn parse_redirection(line: &str) -> Option<(&str, Option<Redirection>)> {
+    let at = match line.find('>') {
+        Some(at) => at,
+        None => return Some((line, None))
+    };
+
+    let append = line[at + 1..].starts_with('>');
+    let rest = &line[at + if append { 2 } else { 1 }..];
+
+    let mut words = rest.split(' ').filter(|word| !word.is_empty());
+    match (words.next(), words.next()) {
+        (Some(path), None) if !path.contains('>') && !path.contains('|') => {
+            Some((&line[..at], Some(Redirection { path, append })))
+        }
+        _ => None
+    }
+}
+
+/// Creates or empties the file `redirection` names, or creates it if it's
+/// appended to and there's none, and returns its path.
+fn open_redirection(redirection: &Redirection) -> Result<Path, ()> {
+    let path = match resolve(redirection.path) {
+        Some(path) => path,
+        None => {
+            println!("{}: path too long", redirection.path);
+            return Err(());
+        }
+    };
+
+    let result = if redirection.append {
+        fs::append(&path, &[])
+    } else {
+        fs::save(&path, &[])
+    };
+
+    result.map(|_| path).map_err(|error| println!("{}: {:?}", redirection.path, error))
+}
+
+/// Runs the commands on `line`, separated by `|`, one after another: each
+/// one's output is piped to the next one's input, and the last one's goes
+/// to `terminal`, or to the file named after a `>` or `>>` ending the
+/// line. A command that can't run ends the pipeline, with the error going
+/// to `terminal`. `$?` is left as the exit status of the last command run.
+fn run_pipeline(line: &str, terminal: Sink) {
+    console::redirect_shell(terminal);
+    let mut expanded = [0u8; MAX_CMDLEN];
+    let line = match expand(line, &mut expanded) {
+        Some(line) => line,
+        None => {
+            STATUS.store(status::SYNTAX, Ordering::Relaxed);
+            return println!("line too long once variables are expanded");
+        }
+    };
+
+    let (line, redirection) = match parse_redirection(line) {
+        Some(parsed) => parsed,
+        None => {
+            STATUS.store(status::SYNTAX, Ordering::Relaxed);
+            return println!("syntax error: expected `> <file>` or `>> <file>` at the end");
+        }
+    };
+
+    // The file's path, and its name as it was given.
+    let file = match redirection {
+        Some(ref redirection) => match open_redirection(redirection) {
+            Ok(path) => Some((path, redirection.path)),
+            Err(()) => return STATUS.store(status::FAILURE, Ordering::Relaxed)
+        },
+        None => None
+    };
+
+    let pipeline = match Pipeline::enter() {
+        Some(pipeline) => pipeline,
+        None => {
+            STATUS.store(status::FAILURE, Ordering::Relaxed);
+            return println!("pipelines nested too deeply");
+        }
+    };
+
+    let stages = line.split('|').count();
+    for (stage, command) in line.split('|').enumerate() {
+        let last = stage + 1 == stages;
+        let program = command.split(' ').find(|word| !word.is_empty()).unwrap_or("");
+        let paged = last && file.is_none() && SCRIPTS.load(Ordering::Relaxed) == 0
+            && PAGED_COMMANDS.contains(&program);
+        let piped = !last || file.is_some() || paged;
+        let output = pipeline.start(stage);
+
+        // `Ok` with the command's exit status if it ran, `Err` if it couldn't.
+        let ran = {
+            let mut aliased = [0u8; MAX_CMDLEN];
+            let command = match expand_aliases(command, &mut aliased) {
+                Some(command) => command,
+                None => {
+                    println!("command too long once aliases are expanded");
+                    STATUS.store(status::SYNTAX, Ordering::Relaxed);
+                    break;
+                }
+            };
+
+            let mut args = [command; MAX_ARGLEN];
+            match Command::parse(command, &mut args[..]) {
+                Ok(command) => {
+                    console::redirect_shell(if piped { Sink::Pipe(output) } else { terminal });
+                    let result = command.handle();
+                    console::redirect_shell(terminal);
+                    match result {
+                        Ok(()) => Ok(status::SUCCESS),
+                        Err(HandleError::Failed(code)) => Ok(code),
+                        Err(HandleError::NoSuchCommand) => {
+                            println!("unknown command: {}", command.path());
+                            Err(status::NOT_FOUND)
+                        }
+                    }
+                }
+                Err(Error::Empty) if stages == 1 => break,
+                Err(Error::Empty) => {
+                    println!("empty command in pipeline");
+                    Err(status::SYNTAX)
+                }
+                Err(Error::TooManyArgs) => {
+                    println!("too many arguments");
+                    Err(status::SYNTAX)
+                }
+            }
+        };
+
+        match ran {
+            Ok(code) => STATUS.store(code, Ordering::Relaxed),
+            Err(code) => {
+                STATUS.store(code, Ordering::Relaxed);
+                break;
+            }
+        }
+
+        if paged {
+            pipeline.start(stage + 1);
+            page_input(command.trim(), true);
+        }
+
+        let dropped = pipeline.dropped(stage);
+        if piped && dropped > 0 {
+            println!("pipe full: {} bytes of output dropped", dropped);
+        }
+
+        if let (true, Some((ref path, name))) = (last, file) {
+            let mut chunk = [0u8; 4096];
+            loop {
+                let len = pipeline.read_output(stage, &mut chunk);
+                if len == 0 {
+                    break;
+                }
+
+                if let Err(error) = fs::append(path, &chunk[..len]) {
+                    println!("{}: {:?}", name, error);
+                    STATUS.store(status::FAILURE, Ordering::Relaxed);
+                    break;
+                }
+            }
+        }
+    }
+}
+
+/// How a pipeline in a list is joined to the one after it.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+enum Operator {
+    /// `;`: the next one runs regardless.
+    Then,
+    /// `&&`: the next one runs only if this one succeeded.
+    And,
+    /// `||`: the next one runs only if this one failed.
+    Or,
+}
+
+impl Operator {
+    fn symbol(self) -> &'static str {
+        match self {
+            Operator::Then => ";",
+            Operator::And => "&&",
+            Operator::Or => "||",
+        }
+    }
+}
+
+/// Splits `line` at its first `;`, `&&` or `||` into the pipeline before
+/// it, the operator, and the rest of the line. Returns the whole line and
+/// no operator if there's none.
+fn split_list(line: &str) -> (&str, Option<Operator>, &str) {
+    let bytes = line.as_bytes();
+    for i in 0..bytes.len() {
+        let (operator, len) = match (bytes[i], bytes.get(i + 1)) {
+            (b';', _) => (Operator::Then, 1),
+            (b'&', Some(&b'&')) => (Operator::And, 2),
+            (b'|', Some(&b'|')) => (Operator::Or, 2),
+            _ => continue
+        };
+
+        return (&line[..i], Some(operator), &line[i + len..]);
+    }
+
+    (line, None, "")
+}
+
+/// Runs the pipelines on `line`, separated by `;`, `&&` and `||`, left to
+/// right with their output going to `terminal`. A pipeline after `&&` runs
+/// only if the last one run succeeded, and one after `||` only if it
+/// failed; those skipped leave `$?` as it was. Nothing runs if a pipeline
+/// is missing either side of an operator, though a line may end with `;`.
+fn run_line(line: &str, terminal: Sink) {
+    let (mut rest, mut previous) = (line, None);
+    loop {
+        let (pipeline, operator, next) = split_list(rest);
+        let dangling = previous.map_or(false, |previous| previous != Operator::Then);
+        if pipeline.trim().is_empty() && (operator.is_some() || dangling) {
+            let symbol = operator.or(previous).map_or("", Operator::symbol);
+            console::redirect_shell(terminal);
+            println!("syntax error: expected a command around `{}`", symbol);
+            return STATUS.store(status::SYNTAX, Ordering::Relaxed);
+        }
+
+        match operator {
+            Some(_) => rest = next,
+            None => break
+        }
+
+        previous = operator;
+    }
+
+    let (mut rest, mut skip) = (line, false);
+    loop {
+        let (pipeline, operator, next) = split_list(rest);
+        if !skip {
+            run_pipeline(pipeline, terminal);
+            if EXIT.lock().is_some() {
+                break;
+            }
+        }
+
+        let failed = STATUS.load(Ordering::Relaxed) != status::SUCCESS;
+        skip = match operator {
+            Some(Operator::Then) => false,
+            Some(Operator::And) => failed,
+            Some(Operator::Or) => !failed,
+            None => break
+        };
+
+        rest = next;
+    }
+}
+
+/// The number of scripts running, one inside another.
+static SCRIPTS: AtomicUsize = AtomicUsize::new(0);
+
+/// The status `exit` asked the innermost script running to end with.
+static EXIT: Mutex<Option<usize>> = Mutex::new(None);
+
+/// Returns `line` up to the first `#` starting a word.
+fn strip_comment(line: &str) -> &str {
+    let bytes = line.as_bytes();
+    match (0..bytes.len()).find(|&i| bytes[i] == b'#' && (i == 0 || bytes[i - 1] == b' ')) {
+        Some(i) => &line[..i],
+        None => line
+    }
+}
+
+/// Runs the script at `path`, named `name`, a line at a time through the
+/// same parser as typed lines, with its output going to `terminal`, until
+/// it ends or runs `exit`. Everything from a `#` starting a word is a
+/// comment, so a `#!` line is skipped too, as are blank lines. The script's
+/// exit status is that of the last command it ran, or the one given to
+/// `exit`.
+fn run_script(path: &Path, name: &str, terminal: Sink) -> Status {
+    let mut script = [0u8; MAX_SCRIPT_SIZE];
+    let len = match fs::load(path, &mut script) {
+        Ok(len) => len,
+        Err(error) => fail!("sh: {}: {:?}", name, error)
+    };
+
+    let text = match str::from_utf8(&script[..len]) {
+        Ok(text) => text,
+        Err(_) => fail!("sh: {}: not UTF-8 text", name)
+    };
+
+    SCRIPTS.fetch_add(1, Ordering::Relaxed);
+    STATUS.store(status::SUCCESS, Ordering::Relaxed);
+    for line in text.lines() {
+        let line = strip_comment(line).trim();
+        if line.is_empty() {
+            continue;
+        }
+
+        run_line(line, terminal);
+        if let Some(code) = EXIT.lock().take() {
+            STATUS.store(code, Ordering::Relaxed);
+            break;
+        }
+    }
+
+    SCRIPTS.fetch_sub(1, Ordering::Relaxed);
+    match STATUS.load(Ordering::Relaxed) {
+        status::SUCCESS => Ok(()),
+        code => Err(code)
+    }
+}
+
+/// Returns `true` if `arg` names a file starting with `#!`, which runs as a
+/// script when given as a command. Only names with a `/`, like `./boot.sh`,
+/// are looked up, so mistyped builtins don't wait on the card.
+fn is_script(arg: &str) -> bool {
+    if !arg.contains('/') {
+        return false;
+    }
+
+    let path = match resolve(arg) {
+        Some(path) => path,
+        None => return false
+    };
+
+    let (mut magic, mut len) = ([0u8; 2], None);
+    let _ = fs::stream(&path, |chunk| {
+        if len.is_none() {
+            let end = ::std::cmp::min(magic.len(), chunk.len());
+            magic[..end].copy_from_slice(&chunk[..end]);
+            len = Some(end);
+        }
+    });
+
+    len.map_or(false, |len| &magic[..len] == b"#!")
+}
+
+/// Runs a script from the SD card: `sh <file>`. See `run_script()`.
+fn sh(args: &[&str]) -> Status {
+    if args.len() != 1 {
+        fail!("usage: sh <file>");
+    }
+
+    match resolve(args[0]) {
+        Some(path) => run_script(&path, args[0], console::shell_sink()),
+        None => fail!("sh: {}: path too long", args[0])
+    }
+}
+
+/// Ends the script running: `exit [status]`, with status 0 if none is
+/// given.
+fn exit(args: &[&str]) -> Status {
+    let code = match args.first().map(|arg| arg.parse()) {
+        None => status::SUCCESS,
+        Some(Ok(code)) if args.len() == 1 => code,
+        _ => fail!("usage: exit [status]")
+    };
+
+    if SCRIPTS.load(Ordering::Relaxed) == 0 {
+        fail!("exit: not running a script");
+    }
+
+    *EXIT.lock() = Some(code);
+    Ok(())
+}
+
+/// Starts a shell using `prefix` as the prefix for each line, served both
+/// on the console and to the client connected to the telnet port, if any.
+/// Each has its own line; commands from either run one at a time. This
+/// function never returns: it is perpetually in a shell loop.
+pub fn shell(prefix: &str) -> ! {
+    let mut console_buf = [0; MAX_CMDLEN];
+    let mut telnet_buf = [0; MAX_CMDLEN];
+    let mut local = Session::new(Terminal::Console, &mut console_buf);
+    let mut remote = Session::new(Terminal::Telnet, &mut telnet_buf);
+
+    // The boot script runs before the first prompt.
+    if let Some(rc) = Path::root().join(RC_SCRIPT) {
+        if fs::stat(&rc).is_ok() {
+            let _ = run_script(&rc, RC_SCRIPT, Sink::Console);
+        }
+    }
+
+    local.prompt(prefix);
+    loop {
+        // The host's request for the bootloader is seen in the FIQ handler,
+        // but acted on here, where no lock can be held by what was
+        // interrupted.
+        if uart::reenter_requested() {
+            let _ = bootloader();
+        }
+
+        if let Some(input) = Terminal::Console.try_read_byte() {
+            local.input(input, prefix);
+        }
+
+        if telnet::accept() {
+            remote.prompt(prefix);
+        }
+
+        if let Some(input) = Terminal::Telnet.try_read_byte() {
+            remote.input(input, prefix);
+        }
     }
 }
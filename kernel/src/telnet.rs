@@ -0,0 +1,169 @@
+use console::kprintln;
+use mutex::Mutex;
+use net::{self, TcpListener, TcpStream};
+
+/// The port the remote shell is served on.
+pub const PORT: u16 = 23;
+
+/// Telnet commands.
+mod command {
+    pub const SE: u8 = 240;
+    pub const SB: u8 = 250;
+    pub const WILL: u8 = 251;
+    pub const DONT: u8 = 254;
+    pub const IAC: u8 = 255;
+}
+
+/// Telnet options.
+mod option {
+    pub const ECHO: u8 = 1;
+    pub const SUPPRESS_GO_AHEAD: u8 = 3;
+}
+
+/// Sent to each new client: the shell echoes what's typed and never sends go
+/// aheads, which puts clients in character at a time mode.
+const NEGOTIATION: [u8; 6] = [
+    command::IAC, command::WILL, option::ECHO,
+    command::IAC, command::WILL, option::SUPPRESS_GO_AHEAD,
+];
+
+/// Where the client's input is in the telnet protocol.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+enum Input {
+    /// Plain data.
+    Data,
+    /// Just after a CR, which clients follow with a LF or NUL.
+    Cr,
+    /// Just after an IAC.
+    Command,
+    /// Expecting the option of a WILL, WONT, DO or DONT.
+    Option,
+    /// Within a subnegotiation, and just after an IAC in one.
+    Subnegotiation,
+    SubnegotiationIac,
+}
+
+/// The listener and the connected client, if any.
+struct Server {
+    listener: Option<TcpListener>,
+    client: Option<TcpStream>,
+    input: Input,
+}
+
+static SERVER: Mutex<Server> = Mutex::new(Server {
+    listener: None,
+    client: None,
+    input: Input::Data,
+});
+
+/// Starts accepting remote shell clients on `PORT`. The network stack must
+/// already be up.
+pub fn listen() -> Result<(), net::Error> {
+    let mut server = SERVER.lock();
+    if server.listener.is_none() {
+        server.listener = Some(TcpListener::bind(PORT)?);
+    }
+
+    Ok(())
+}
+
+/// Accepts a waiting client if none is connected, and starts negotiating
+/// with it. Returns whether one was accepted. Clients connecting while
+/// another is served wait until it leaves.
+pub fn accept() -> bool {
+    let mut server = SERVER.lock();
+    if server.client.is_some() {
+        return false;
+    }
+
+    let client = match server.listener.as_ref().and_then(|listener| listener.accept()) {
+        Some(client) => client,
+        None => return false
+    };
+
+    let (address, port) = client.peer_addr();
+    kprintln!("telnet: {}:{} connected", address, port);
+
+    server.input = Input::Data;
+    server.client = Some(client);
+    send(&mut server, &NEGOTIATION);
+    server.client.is_some()
+}
+
+/// Returns the next byte typed by the client, or `None` if there isn't one
+/// or no client is connected. Telnet commands are dropped, as is the LF or
+/// NUL after a CR. Disconnects the client once it has closed the connection.
+pub fn try_read_byte() -> Option<u8> {
+    let mut server = SERVER.lock();
+    loop {
+        let mut byte = [0];
+        let result = match server.client {
+            Some(ref client) => client.recv(&mut byte),
+            None => return None
+        };
+
+        match result {
+            Ok(Some(_)) => {  }
+            Ok(None) => return None,
+            Err(_) => {
+                disconnect(&mut server);
+                return None;
+            }
+        }
+
+        let (input, typed) = parse(server.input, byte[0]);
+        server.input = input;
+        if typed.is_some() {
+            return typed;
+        }
+    }
+}
+
+/// Sends `bytes` to the client, if one is connected. Disconnects the client
+/// if it has closed the connection or stopped reading.
+pub fn write_bytes(bytes: &[u8]) {
+    send(&mut SERVER.lock(), bytes);
+}
+
+/// Sends `bytes` to `server`'s client, disconnecting it on failure.
+fn send(server: &mut Server, bytes: &[u8]) {
+    let result = match server.client {
+        Some(ref client) => client.send_all(bytes),
+        None => return
+    };
+
+    if result.is_err() {
+        disconnect(server);
+    }
+}
+
+/// Drops `server`'s client, which closes the connection once queued output
+/// is sent.
+fn disconnect(server: &mut Server) {
+    if let Some(client) = server.client.take() {
+        let (address, port) = client.peer_addr();
+        kprintln!("telnet: {}:{} disconnected", address, port);
+    }
+}
+
+/// Advances the parser in state `input` past `byte`, returning the next
+/// state and the byte typed, if `byte` was one.
+fn parse(input: Input, byte: u8) -> (Input, Option<u8>) {
+    match (input, byte) {
+        (Input::Data, command::IAC) | (Input::Cr, command::IAC) => (Input::Command, None),
+        (Input::Data, b'\r') | (Input::Cr, b'\r') => (Input::Cr, Some(b'\r')),
+        (Input::Cr, b'\n') | (Input::Cr, 0) => (Input::Data, None),
+        (Input::Data, byte) | (Input::Cr, byte) => (Input::Data, Some(byte)),
+        // An escaped 0xFF is data, though not any the shell takes.
+        (Input::Command, command::IAC) => (Input::Data, Some(command::IAC)),
+        (Input::Command, command::SB) => (Input::Subnegotiation, None),
+        (Input::Command, byte) if byte >= command::WILL && byte <= command::DONT => {
+            (Input::Option, None)
+        }
+        (Input::Command, _) | (Input::Option, _) => (Input::Data, None),
+        (Input::Subnegotiation, command::IAC) => (Input::SubnegotiationIac, None),
+        (Input::Subnegotiation, _) => (Input::Subnegotiation, None),
+        (Input::SubnegotiationIac, command::SE) => (Input::Data, None),
+        (Input::SubnegotiationIac, _) => (Input::Subnegotiation, None),
+    }
+}
@@ -0,0 +1,84 @@
+use core::sync::atomic::{AtomicUsize, Ordering};
+use core::time::Duration;
+
+use pi::generic_timer;
+use pi::timer::{Timer, Compare};
+
+use irq;
+use timers;
+
+/// The timers that can drive the scheduler tick.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum Source {
+    /// Compare channel `C3` of the shared system timer.
+    SystemTimer,
+    /// The calling core's ARM generic timer.
+    GenericTimer
+}
+
+/// The timer driving the scheduler tick.
+const SOURCE: Source = Source::SystemTimer;
+
+/// The interval between scheduler ticks, in microseconds.
+pub const TICK_US: u32 = 10_000;
+
+/// The number of ticks taken since `start()`.
+static TICKS: AtomicUsize = AtomicUsize::new(0);
+
+/// The system timer counter value the next tick is due at.
+static mut DEADLINE: u32 = 0;
+
+/// Starts the scheduler tick on `SOURCE`.
+pub fn start() {
+    match SOURCE {
+        Source::SystemTimer => {
+            irq::register(Compare::C3.interrupt(), handle_system_timer);
+            unsafe { DEADLINE = Timer::new().arm_in(Compare::C3, TICK_US); }
+        }
+        Source::GenericTimer => {
+            generic_timer::periodic(Duration::from_micros(TICK_US as u64));
+        }
+    }
+}
+
+/// Returns the number of ticks taken since `start()`.
+pub fn ticks() -> usize {
+    TICKS.load(Ordering::Relaxed)
+}
+
+/// Counts a tick and services the timers registered with `timers`.
+fn on_tick() {
+    TICKS.fetch_add(1, Ordering::Relaxed);
+    timers::tick();
+}
+
+/// Handles the generic timer's interrupt, which doesn't go through the
+/// interrupt controller, if it is the tick source and is pending.
+pub fn handle_local_irq() {
+    if SOURCE == Source::GenericTimer && generic_timer::handle_irq() {
+        on_tick();
+    }
+}
+
+/// Acknowledges the system timer tick, if it is pending, arms the next one,
+/// and takes the tick. Deadlines advance by exactly `TICK_US` so ticks don't
+/// drift; ticks missed while IRQs were masked are skipped rather than taken
+/// back to back.
+fn handle_system_timer() {
+    let mut timer = Timer::new();
+    if !timer.acknowledge(Compare::C3) {
+        return;
+    }
+
+    unsafe {
+        let now = timer.counter_low();
+        DEADLINE = DEADLINE.wrapping_add(TICK_US);
+        while DEADLINE.wrapping_sub(now) as i32 <= 0 {
+            DEADLINE = DEADLINE.wrapping_add(TICK_US);
+        }
+
+        timer.arm_at(Compare::C3, DEADLINE);
+    }
+
+    on_tick();
+}
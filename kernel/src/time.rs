@@ -0,0 +1,95 @@
+use pi::i2c::{I2c, I2cError};
+use pi::rtc::{Chip, DateTime, Rtc, RtcError};
+use pi::timer::{Instant, Timer};
+
+use mutex::Mutex;
+
+/// The RTC chip fitted. Both supported chips answer at the same address, so
+/// it can't be probed for.
+const RTC_CHIP: Chip = Chip::Ds3231;
+
+/// How often the RTC is read again, correcting the system timer's drift.
+const RESYNC_SECS: u64 = 3600;
+
+/// The wall clock: the RTC, and the time it last read.
+struct Clock {
+    rtc: Option<Rtc>,
+    /// Seconds since the Unix epoch at the instant the time was last read or
+    /// set, or `None` if it never has been.
+    synced: Option<(u64, Instant)>,
+}
+
+impl Clock {
+    /// Returns seconds since the Unix epoch, reading the RTC again if it's
+    /// been `RESYNC_SECS` since it was last read.
+    fn now(&mut self) -> Option<u64> {
+        let (base, at) = self.synced?;
+        let elapsed = at.elapsed().as_secs();
+        if elapsed >= RESYNC_SECS {
+            if let Some(time) = self.rtc.as_mut().and_then(|rtc| rtc.read().ok()) {
+                self.synced = Some((time.to_unix(), Instant::now()));
+                return Some(time.to_unix());
+            }
+        }
+
+        Some(base + elapsed)
+    }
+}
+
+static CLOCK: Mutex<Clock> = Mutex::new(Clock { rtc: None, synced: None });
+
+/// Reads the wall clock time from the RTC and returns it.
+///
+/// # Errors
+///
+/// Returns `RtcError::I2c(I2cError::Nack)` if there's no RTC, in which case
+/// the time is unknown until `set()`. Returns `RtcError::Stopped` if the
+/// RTC's battery ran out; it's kept, so that `set()` can start it again.
+pub fn init() -> Result<DateTime, RtcError> {
+    let mut clock = CLOCK.lock();
+    let mut rtc = Rtc::new(I2c::new(), RTC_CHIP);
+
+    let result = rtc.read();
+    match result {
+        Ok(time) => clock.synced = Some((time.to_unix(), Instant::now())),
+        Err(RtcError::I2c(I2cError::Nack)) => return result,
+        Err(_) => {  }
+    }
+
+    clock.rtc = Some(rtc);
+    result
+}
+
+/// Returns whether the wall clock time is known, from the RTC or `set()`.
+pub fn is_set() -> bool {
+    CLOCK.lock().synced.is_some()
+}
+
+/// Returns the current wall clock time in UTC. While it isn't known, the
+/// time since boot is returned as a time since the Unix epoch.
+pub fn now() -> DateTime {
+    match CLOCK.lock().now() {
+        Some(seconds) => DateTime::from_unix(seconds),
+        None => DateTime::from_unix(Timer::new().read() / 1_000_000)
+    }
+}
+
+/// Sets the wall clock time to `time`, in UTC, and the RTC too if there is
+/// one. The wall clock is set even if setting the RTC fails.
+pub fn set(time: DateTime) -> Result<(), RtcError> {
+    if !time.is_valid() {
+        return Err(RtcError::Invalid);
+    }
+
+    let mut clock = CLOCK.lock();
+    clock.synced = Some((time.to_unix(), Instant::now()));
+    match clock.rtc {
+        Some(ref mut rtc) => rtc.set(&time),
+        None => Ok(())
+    }
+}
+
+/// Returns whether the wall clock has an RTC to keep time while powered off.
+pub fn has_rtc() -> bool {
+    CLOCK.lock().rtc.is_some()
+}
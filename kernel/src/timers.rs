@@ -0,0 +1,192 @@
+use core::time::Duration;
+
+use tick::TICK_US;
+use traps::without_irqs;
+
+/// The largest number of timers that can be registered at once.
+pub const MAX_TIMERS: usize = 16;
+
+/// The number of slots in the wheel, one per tick. Timers due further away
+/// than this wait out whole turns of the wheel in their slot.
+const WHEEL_SLOTS: usize = 64;
+
+/// A function called from the tick interrupt when a timer is due. Callbacks
+/// run with IRQs masked, so they must be short and must not take locks such
+/// as the console's.
+pub type Callback = fn();
+
+/// Identifies a registered timer for `cancel()`.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct TimerId {
+    index: usize,
+    generation: usize
+}
+
+#[derive(Clone, Copy)]
+struct Entry {
+    callback: Callback,
+    /// Ticks between runs, or 0 for a one-shot timer.
+    period: usize,
+    /// The slot the entry is linked into.
+    slot: usize,
+    /// Whole turns of the wheel left before the timer is due.
+    rounds: usize,
+    /// The next entry in the same slot.
+    next: Option<usize>,
+    /// Distinguishes this registration from earlier ones in the same entry.
+    generation: usize
+}
+
+struct Wheel {
+    entries: [Option<Entry>; MAX_TIMERS],
+    /// The first entry linked into each slot.
+    slots: [Option<usize>; WHEEL_SLOTS],
+    /// The slot serviced by the next tick.
+    current: usize,
+    /// The generation given to the next registration.
+    generation: usize
+}
+
+/// Only accessed with IRQs masked: by the tick handler, or in `without_irqs`.
+static mut WHEEL: Wheel = Wheel {
+    entries: [None; MAX_TIMERS],
+    slots: [None; WHEEL_SLOTS],
+    current: 0,
+    generation: 0
+};
+
+/// Returns `duration` in ticks, rounded up, and at least 1.
+fn duration_to_ticks(duration: Duration) -> usize {
+    let us = duration.as_secs()
+        .saturating_mul(1_000_000)
+        .saturating_add(duration.subsec_micros() as u64);
+    let ticks = (us + TICK_US as u64 - 1) / TICK_US as u64;
+    ::core::cmp::max(ticks, 1) as usize
+}
+
+impl Wheel {
+    /// Links entry `index` into the slot serviced `ticks` ticks from now.
+    fn link(&mut self, index: usize, ticks: usize) {
+        let slot = (self.current + ticks - 1) % WHEEL_SLOTS;
+        let head = self.slots[slot];
+
+        if let Some(ref mut entry) = self.entries[index] {
+            entry.slot = slot;
+            entry.rounds = (ticks - 1) / WHEEL_SLOTS;
+            entry.next = head;
+        }
+
+        self.slots[slot] = Some(index);
+    }
+
+    /// Removes entry `index` from its slot's list. The entry itself is kept.
+    fn unlink(&mut self, index: usize) {
+        let (slot, next) = match self.entries[index] {
+            Some(entry) => (entry.slot, entry.next),
+            None => return
+        };
+
+        if self.slots[slot] == Some(index) {
+            self.slots[slot] = next;
+            return;
+        }
+
+        let mut cursor = self.slots[slot];
+        while let Some(i) = cursor {
+            let entry = self.entries[i].as_mut().expect("unlinked timer in slot");
+            if entry.next == Some(index) {
+                entry.next = next;
+                return;
+            }
+
+            cursor = entry.next;
+        }
+    }
+
+    fn register(&mut self, ticks: usize, period: usize, callback: Callback) -> Option<TimerId> {
+        let index = self.entries.iter().position(|e| e.is_none())?;
+        let generation = self.generation;
+        self.generation = self.generation.wrapping_add(1);
+
+        self.entries[index] = Some(Entry {
+            callback, period, generation, slot: 0, rounds: 0, next: None
+        });
+        self.link(index, ticks);
+
+        Some(TimerId { index, generation })
+    }
+}
+
+/// Calls `callback` once, `delay` from now, rounded up to a whole tick.
+/// Returns `None` if `MAX_TIMERS` timers are already registered.
+pub fn after(delay: Duration, callback: Callback) -> Option<TimerId> {
+    let ticks = duration_to_ticks(delay);
+    without_irqs(|| unsafe { WHEEL.register(ticks, 0, callback) })
+}
+
+/// Calls `callback` every `period`, rounded up to a whole tick, until the
+/// timer is cancelled. Returns `None` if `MAX_TIMERS` timers are already
+/// registered.
+pub fn every(period: Duration, callback: Callback) -> Option<TimerId> {
+    let ticks = duration_to_ticks(period);
+    without_irqs(|| unsafe { WHEEL.register(ticks, ticks, callback) })
+}
+
+/// Cancels the timer `id`. Returns `false` if it had already fired (for a
+/// one-shot timer) or been cancelled.
+pub fn cancel(id: TimerId) -> bool {
+    without_irqs(|| unsafe {
+        match WHEEL.entries[id.index] {
+            Some(entry) if entry.generation == id.generation => {
+                WHEEL.unlink(id.index);
+                WHEEL.entries[id.index] = None;
+                true
+            }
+            _ => false
+        }
+    })
+}
+
+/// Advances the wheel by one tick and runs the callbacks that are due. Must be
+/// called from the tick interrupt.
+pub fn tick() {
+    let mut due: [Option<Callback>; MAX_TIMERS] = [None; MAX_TIMERS];
+
+    unsafe {
+        let wheel = &mut WHEEL;
+        let slot = wheel.current;
+        wheel.current = (wheel.current + 1) % WHEEL_SLOTS;
+
+        // Detach the slot's list and relink what isn't due yet, or is
+        // periodic, relative to the next slot.
+        let mut cursor = wheel.slots[slot].take();
+        let mut count = 0;
+        while let Some(index) = cursor {
+            let entry = wheel.entries[index].expect("unlinked timer in slot");
+            cursor = entry.next;
+
+            if entry.rounds > 0 {
+                if let Some(ref mut e) = wheel.entries[index] {
+                    e.rounds -= 1;
+                    e.next = wheel.slots[slot];
+                }
+                wheel.slots[slot] = Some(index);
+                continue;
+            }
+
+            due[count] = Some(entry.callback);
+            count += 1;
+
+            match entry.period {
+                0 => wheel.entries[index] = None,
+                period => wheel.link(index, period)
+            }
+        }
+    }
+
+    // Callbacks may register or cancel timers, so run them once the wheel is
+    // consistent.
+    for callback in due.iter().filter_map(|c| *c) {
+        callback();
+    }
+}
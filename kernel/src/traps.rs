@@ -0,0 +1,113 @@
+use irq;
+use tick;
+
+/// The type of exception that was taken.
+#[repr(u16)]
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum Kind {
+    Synchronous = 0,
+    Irq = 1,
+    Fiq = 2,
+    SError = 3,
+}
+
+/// The state the processor was in when the exception was taken.
+#[repr(u16)]
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum Source {
+    CurrentSpEl0 = 0,
+    CurrentSpElx = 1,
+    LowerAArch64 = 2,
+    LowerAArch32 = 3,
+}
+
+/// Information about an exception, as packed into `x0` by the `HANDLER` macro
+/// in `init.S`.
+#[repr(C)]
+#[derive(Debug, Clone, Copy)]
+pub struct Info {
+    pub source: Source,
+    pub kind: Kind,
+}
+
+/// Unmasks IRQs on the current core.
+pub fn enable_irqs() {
+    unsafe { asm!("msr DAIFClr, #2" :::: "volatile"); }
+}
+
+/// Masks IRQs on the current core.
+pub fn disable_irqs() {
+    unsafe { asm!("msr DAIFSet, #2" :::: "volatile"); }
+}
+
+/// Unmasks FIQs on the current core.
+pub fn enable_fiqs() {
+    unsafe { asm!("msr DAIFClr, #1" :::: "volatile"); }
+}
+
+/// Masks FIQs on the current core.
+pub fn disable_fiqs() {
+    unsafe { asm!("msr DAIFSet, #1" :::: "volatile"); }
+}
+
+/// Runs `f` with IRQs masked on the current core, then restores the previous
+/// mask. Used to update state shared with IRQ handlers. FIQs are unaffected.
+pub fn without_irqs<F: FnOnce() -> R, R>(f: F) -> R {
+    let daif: u64;
+    unsafe {
+        asm!("mrs $0, DAIF" : "=r"(daif) ::: "volatile");
+        asm!("msr DAIFSet, #2" :::: "volatile");
+    }
+
+    let result = f();
+    unsafe { asm!("msr DAIF, $0" :: "r"(daif) :: "volatile"); }
+    result
+}
+
+/// Runs `f` with IRQs and FIQs masked on the current core, then restores the
+/// previous mask. Used to update state shared with the FIQ handler.
+pub fn without_fiqs<F: FnOnce() -> R, R>(f: F) -> R {
+    let daif: u64;
+    unsafe {
+        asm!("mrs $0, DAIF" : "=r"(daif) ::: "volatile");
+        asm!("msr DAIFSet, #3" :::: "volatile");
+    }
+
+    let result = f();
+    unsafe { asm!("msr DAIF, $0" :: "r"(daif) :: "volatile"); }
+    result
+}
+
+/// Handles an IRQ by dispatching the pending GPU interrupts to the handlers
+/// registered with `irq`. The generic timer is local to the core and isn't
+/// routed through the interrupt controller, so it's checked separately.
+fn handle_irq() {
+    irq::dispatch();
+    tick::handle_local_irq();
+}
+
+/// This function is called when an exception occurs. The `info` parameter
+/// specifies the source and kind of exception that has occurred. The `esr` is
+/// the value of the exception syndrome register.
+///
+/// Exceptions other than IRQs and FIQs are not yet handled: the core is
+/// parked. The console is deliberately not used here since the interrupted
+/// code may be holding its lock.
+#[no_mangle]
+pub extern fn handle_exception(info: Info, esr: u32) {
+    let _ = esr;
+
+    match info.kind {
+        Kind::Irq => {
+            // `context_save` has saved ELR and SPSR, so a FIQ may preempt the
+            // IRQ handlers.
+            enable_fiqs();
+            handle_irq();
+            disable_fiqs();
+        }
+        Kind::Fiq => irq::dispatch_fiq(),
+        _ => loop {
+            unsafe { asm!("wfe" :::: "volatile"); }
+        }
+    }
+}
@@ -0,0 +1,79 @@
+use pi::ring_buffer::RingBuffer;
+use pi::usb::{hub, Host, UsbError};
+use pi::usb::keyboard::Keyboard;
+use pi::usb::smsc95xx::Smsc95xx;
+
+use mutex::Mutex;
+
+/// The USB host controller, once `init()` has brought it up.
+pub static HOST: Mutex<Option<Host>> = Mutex::new(None);
+
+/// The first keyboard found by `init()`, if any.
+static KEYBOARD: Mutex<Option<Keyboard>> = Mutex::new(None);
+
+/// The onboard Ethernet controller, if `init()` found one.
+pub static ETHERNET: Mutex<Option<Smsc95xx>> = Mutex::new(None);
+
+/// Bytes typed on the keyboard that haven't been read yet.
+static KEYS: RingBuffer = RingBuffer::new();
+
+/// Brings up the USB host controller, enumerates every attached device, and
+/// attaches drivers to the ones the kernel knows. Does nothing if USB is
+/// already up.
+///
+/// Enumeration takes about a second, mostly spent waiting out port resets.
+pub fn init() -> Result<(), UsbError> {
+    if HOST.lock().is_some() {
+        return Ok(());
+    }
+
+    let mut host = Host::new()?;
+    hub::enumerate_all(&mut host, |host, device| {
+        let mut keyboard = KEYBOARD.lock();
+        if keyboard.is_none() {
+            *keyboard = Keyboard::new(host, device).ok();
+        }
+
+        let mut ethernet = ETHERNET.lock();
+        if ethernet.is_none() {
+            *ethernet = Smsc95xx::new(host, device).ok();
+        }
+    })?;
+
+    *HOST.lock() = Some(host);
+    Ok(())
+}
+
+/// Returns `true` if a keyboard was found by `init()`.
+pub fn has_keyboard() -> bool {
+    KEYBOARD.lock().is_some()
+}
+
+/// Polls the keyboard, if there is one, queueing the bytes typed since the
+/// last poll. Bytes typed while the queue is full are dropped.
+fn poll_keyboard() {
+    let mut keyboard = KEYBOARD.lock();
+    let mut host = HOST.lock();
+    if let (Some(keyboard), Some(host)) = (keyboard.as_mut(), host.as_mut()) {
+        let _ = keyboard.poll(host, |byte| { let _ = KEYS.push(byte); });
+    }
+}
+
+/// Returns `true` if a byte typed on the keyboard is waiting to be read.
+pub fn has_key() -> bool {
+    if KEYS.is_empty() {
+        poll_keyboard();
+    }
+
+    !KEYS.is_empty()
+}
+
+/// Returns the oldest byte typed on the keyboard that hasn't been read yet,
+/// if there is one.
+pub fn read_key() -> Option<u8> {
+    if KEYS.is_empty() {
+        poll_keyboard();
+    }
+
+    KEYS.pop()
+}
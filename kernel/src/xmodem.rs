@@ -0,0 +1,122 @@
+use std::io;
+
+use pi::crc::crc16;
+
+/// Protocol bytes.
+mod byte {
+    pub const SOH: u8 = 0x01;
+    pub const STX: u8 = 0x02;
+    pub const EOT: u8 = 0x04;
+    pub const ACK: u8 = 0x06;
+    pub const NAK: u8 = 0x15;
+    pub const CAN: u8 = 0x18;
+    /// Sent by the receiver in place of `NAK` to start a transfer with CRC-16
+    /// packets.
+    pub const CRC: u8 = b'C';
+    /// Pads the last packet.
+    pub const SUB: u8 = 0x1A;
+}
+
+/// The payload sizes of `SOH` and `STX` packets.
+const PACKET_SIZE: usize = 128;
+const LONG_PACKET_SIZE: usize = 1024;
+
+/// How many times the receiver may fail to start, or reject or miss a
+/// packet in a row, before the transfer is given up.
+const MAX_RETRIES: usize = 10;
+
+fn read_byte<T: io::Read>(port: &mut T) -> io::Result<u8> {
+    let mut byte = [0];
+    port.read_exact(&mut byte)?;
+    Ok(byte[0])
+}
+
+fn cancelled() -> io::Error {
+    io::Error::new(io::ErrorKind::ConnectionAborted, "Transfer cancelled by receiver.")
+}
+
+fn too_many_retries() -> io::Error {
+    io::Error::new(io::ErrorKind::TimedOut, "Too many retries.")
+}
+
+/// Waits for the receiver to start the transfer and returns whether it
+/// asked for CRC-16 packets rather than checksummed ones.
+fn wait_for_start<T: io::Read>(port: &mut T) -> io::Result<bool> {
+    for _ in 0..MAX_RETRIES {
+        match read_byte(port) {
+            Ok(byte::CRC) => return Ok(true),
+            Ok(byte::NAK) => return Ok(false),
+            Ok(byte::CAN) => return Err(cancelled()),
+            Ok(_) => {  }
+            Err(ref e) if e.kind() == io::ErrorKind::TimedOut => {  }
+            Err(e) => return Err(e)
+        }
+    }
+
+    Err(too_many_retries())
+}
+
+/// Sends `message`, a packet or `EOT`, until the receiver acknowledges it.
+fn send_until_acked<T: io::Read + io::Write>(port: &mut T, message: &[u8]) -> io::Result<()> {
+    for _ in 0..MAX_RETRIES {
+        port.write_all(message)?;
+        port.flush()?;
+        match read_byte(port) {
+            Ok(byte::ACK) => return Ok(()),
+            Ok(byte::CAN) => return Err(cancelled()),
+            Ok(_) => {  }
+            Err(ref e) if e.kind() == io::ErrorKind::TimedOut => {  }
+            Err(e) => return Err(e)
+        }
+    }
+
+    Err(too_many_retries())
+}
+
+/// Sends `data` to an XMODEM receiver on `port`, with the CRC-16 or checksum
+/// the receiver asks for. A receiver asking for CRC-16 packets is taken to
+/// understand XMODEM-1K, and gets 1024 byte packets until what's left fits
+/// in a 128 byte one; one asking for checksums gets only 128 byte packets.
+/// The last packet is padded with `SUB` bytes. `port`'s reads should time
+/// out after about a second so that lost replies are retried.
+pub fn send<T: io::Read + io::Write>(port: &mut T, data: &[u8]) -> io::Result<()> {
+    let crc = wait_for_start(port)?;
+
+    let mut packet = [0u8; LONG_PACKET_SIZE + 5];
+    let (mut sent, mut seq) = (0, 1u8);
+    while sent < data.len() {
+        let rest = &data[sent..];
+        let (start, size) = if crc && rest.len() > PACKET_SIZE {
+            (byte::STX, LONG_PACKET_SIZE)
+        } else {
+            (byte::SOH, PACKET_SIZE)
+        };
+
+        let chunk = &rest[..::std::cmp::min(rest.len(), size)];
+        packet[..3].copy_from_slice(&[start, seq, !seq]);
+        {
+            let payload = &mut packet[3..3 + size];
+            payload[..chunk.len()].copy_from_slice(chunk);
+            for pad in payload[chunk.len()..].iter_mut() {
+                *pad = byte::SUB;
+            }
+        }
+
+        let len = if crc {
+            let check = crc16(&packet[3..3 + size]);
+            packet[3 + size] = (check >> 8) as u8;
+            packet[4 + size] = check as u8;
+            size + 5
+        } else {
+            let sum = packet[3..3 + size].iter().fold(0u8, |s, &b| s.wrapping_add(b));
+            packet[3 + size] = sum;
+            size + 4
+        };
+
+        send_until_acked(port, &packet[..len])?;
+        sent += chunk.len();
+        seq = seq.wrapping_add(1);
+    }
+
+    send_until_acked(port, &[byte::EOT])
+}
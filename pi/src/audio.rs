@@ -0,0 +1,285 @@
+use dma::{self, ControlBlock};
+use gpio::{self, Gpio, pin, signal};
+use pwm::{self, Pwm, Mode};
+
+/// The PWM clock while audio plays, in Hz: the oscillator divided by 2. At
+/// 44.1kHz this leaves a range of 217 ticks, a little under 8 bits per sample.
+const PWM_CLOCK_HZ: u32 = 9_600_000;
+
+/// The number of stereo frames converted and queued per DMA transfer.
+const CHUNK_FRAMES: usize = 1024;
+
+/// A buffer of PWM FIFO words, alternating left and right.
+#[repr(C, align(32))]
+struct Buffer([u32; CHUNK_FRAMES * 2]);
+
+/// The two buffers `Audio::play_with` alternates between: one is converted
+/// while the DMA engine drains the other.
+static mut BUFFERS: [Buffer; 2] = [Buffer([0; CHUNK_FRAMES * 2]), Buffer([0; CHUNK_FRAMES * 2])];
+
+/// The control block describing each buffer's transfer.
+static mut BLOCKS: [ControlBlock; 2] = [ControlBlock::new(), ControlBlock::new()];
+
+/// The headphone jack of the Pi 3: `PWM0` on GPIO 40 drives the left channel
+/// and `PWM1` on GPIO 45 the right, fed from the PWM FIFO by DMA.
+///
+/// Only one `Audio` should exist at a time; it owns the PWM controller and the
+/// statics holding samples in flight.
+pub struct Audio {
+    left: Pwm,
+    right: Pwm,
+    dma: dma::Channel,
+}
+
+impl Audio {
+    /// Routes GPIO 40 and 45 to the PWM controller and claims a DMA channel.
+    ///
+    /// # Panics
+    ///
+    /// Panics if every full DMA channel is claimed.
+    pub fn new() -> Audio {
+        match Audio::try_new() {
+            Some(audio) => audio,
+            None => panic!("audio::Audio::new(): no free DMA channel")
+        }
+    }
+
+    /// Like `new()`, but returns `None` if every full DMA channel is claimed.
+    pub fn try_new() -> Option<Audio> {
+        let dma = dma::Channel::claim_any()?;
+
+        Gpio::with_signal(pin::P40, signal::Pwm0);
+        Gpio::with_signal(pin::P45, signal::Pwm1);
+        gpio::claim(&[40, 45], "audio");
+
+        pwm::set_clock(PWM_CLOCK_HZ);
+        let mut left = Pwm::new(pwm::Channel::Pwm0, Mode::Balanced, 2);
+        let mut right = Pwm::new(pwm::Channel::Pwm1, Mode::Balanced, 2);
+        left.set_fifo(true);
+        right.set_fifo(true);
+        pwm::clear_fifo();
+        pwm::enable_dma();
+
+        Some(Audio { left, right, dma })
+    }
+
+    /// Plays the signed 16-bit mono samples in `samples` at `sample_rate` Hz on
+    /// both channels, blocking until the last one is queued.
+    ///
+    /// # Panics
+    ///
+    /// Panics if `sample_rate` is 0 or above 2.4MHz.
+    pub fn play(&mut self, samples: &[i16], sample_rate: u32) {
+        let range = self.set_sample_rate(sample_rate);
+        self.play_with(samples.len(), |i| {
+            ((samples[i] as i32 + 0x8000) as u32 * range) >> 16
+        });
+    }
+
+    /// Plays the unsigned 8-bit mono samples in `samples` at `sample_rate` Hz,
+    /// as found in 8-bit WAV files, on both channels.
+    ///
+    /// # Panics
+    ///
+    /// Panics if `sample_rate` is 0 or above 2.4MHz.
+    pub fn play_u8(&mut self, samples: &[u8], sample_rate: u32) {
+        let range = self.set_sample_rate(sample_rate);
+        self.play_with(samples.len(), |i| (samples[i] as u32 * range) >> 8);
+    }
+
+    /// Plays the samples in `wav` on both channels, mixing stereo down to
+    /// mono, blocking until the last one is queued.
+    pub fn play_wav(&mut self, wav: &Wav) {
+        let range = self.set_sample_rate(wav.sample_rate);
+        let channels = wav.channels as usize;
+        let width = wav.bits_per_sample as usize / 8;
+        let data = wav.data;
+        self.play_with(wav.frames(), |i| {
+            let frame = &data[i * channels * width..(i + 1) * channels * width];
+            let sum: u32 = frame.chunks(width).map(|sample| match width {
+                1 => (sample[0] as u32) << 8,
+                _ => (sample[0] as u32 | (sample[1] as u32) << 8) ^ 0x8000
+            }).sum();
+
+            (sum / channels as u32 * range) >> 16
+        });
+    }
+
+    /// Sets both channels' range so that each PWM period lasts one sample.
+    /// Returns the range.
+    fn set_sample_rate(&mut self, sample_rate: u32) -> u32 {
+        let range = if sample_rate == 0 { 0 } else { PWM_CLOCK_HZ / sample_rate };
+        if range < 4 {
+            panic!("audio::Audio::play(): sample rate of {}Hz is out of range", sample_rate);
+        }
+
+        self.left.set_range(range);
+        self.right.set_range(range);
+        range
+    }
+
+    /// Plays `len` frames, where `sample(i)` returns the PWM data for frame
+    /// `i`. Each chunk is converted into one buffer while the other plays.
+    fn play_with<F: Fn(usize) -> u32>(&mut self, len: usize, sample: F) {
+        let mut next = 0;
+        let mut current = 0;
+        while next < len {
+            let frames = ::core::cmp::min(CHUNK_FRAMES, len - next);
+
+            // The transfer that last read this buffer finished before the
+            // other buffer's transfer was started.
+            let buffer = unsafe { &mut BUFFERS[current].0 };
+            for i in 0..frames {
+                let data = sample(next + i);
+                buffer[2 * i] = data;
+                buffer[2 * i + 1] = data;
+            }
+
+            self.dma.wait();
+            unsafe {
+                BLOCKS[current] = ControlBlock::builder()
+                    .source(buffer.as_ptr() as usize)
+                    .destination_peripheral(pwm::fifo_address(), dma::dreq::PWM)
+                    .length(frames * 2 * 4)
+                    .build();
+
+                // The buffer and block are statics that aren't touched again
+                // until this transfer is done.
+                self.dma.start(&BLOCKS[current]);
+            }
+
+            next += frames;
+            current ^= 1;
+        }
+
+        self.dma.wait();
+    }
+}
+
+impl Drop for Audio {
+    fn drop(&mut self) {
+        self.dma.wait();
+        pwm::disable_dma();
+        self.left.set_fifo(false);
+        self.right.set_fifo(false);
+        self.left.disable();
+        self.right.disable();
+        gpio::release_owned(&[40, 45], "audio");
+    }
+}
+
+/// Why a file couldn't be read as a WAV file by `Wav::parse()`.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum WavError {
+    /// It isn't a RIFF `WAVE` file with `fmt ` and `data` chunks.
+    Malformed,
+    /// Its samples aren't 8 or 16-bit PCM in one or two channels, at a rate
+    /// `Audio` can play.
+    Unsupported,
+}
+
+/// The format and samples of a PCM WAV file.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct Wav<'a> {
+    pub sample_rate: u32,
+    pub channels: u16,
+    pub bits_per_sample: u16,
+    /// The samples, interleaved by channel: unsigned if 8-bit, signed and
+    /// little-endian if 16-bit.
+    pub data: &'a [u8],
+}
+
+/// Returns the little-endian `u16` at the start of `bytes`.
+fn le16(bytes: &[u8]) -> u16 {
+    bytes[0] as u16 | (bytes[1] as u16) << 8
+}
+
+/// Returns the little-endian `u32` at the start of `bytes`.
+fn le32(bytes: &[u8]) -> u32 {
+    le16(bytes) as u32 | (le16(&bytes[2..]) as u32) << 16
+}
+
+impl<'a> Wav<'a> {
+    /// Parses the contents of a WAV file. A `data` chunk cut short by the
+    /// end of the file is taken as it is.
+    pub fn parse(bytes: &'a [u8]) -> Result<Wav<'a>, WavError> {
+        if bytes.len() < 12 || &bytes[..4] != b"RIFF" || &bytes[8..12] != b"WAVE" {
+            return Err(WavError::Malformed);
+        }
+
+        let (mut format, mut data) = (None, None);
+        let mut rest = &bytes[12..];
+        while rest.len() >= 8 {
+            let len = le32(&rest[4..]) as usize;
+            let body = &rest[8..];
+            let chunk = &body[..::core::cmp::min(len, body.len())];
+            match &rest[..4] {
+                b"fmt " => format = Some(chunk),
+                b"data" => data = Some(chunk),
+                _ => {  }
+            }
+
+            // Chunks are padded to an even length.
+            let padded = len.saturating_add(len & 1);
+            if padded >= body.len() {
+                break;
+            }
+
+            rest = &body[padded..];
+        }
+
+        let (format, data) = match (format, data) {
+            (Some(format), Some(data)) if format.len() >= 16 => (format, data),
+            _ => return Err(WavError::Malformed)
+        };
+
+        let wav = Wav {
+            sample_rate: le32(&format[4..]),
+            channels: le16(&format[2..]),
+            bits_per_sample: le16(&format[14..]),
+            data
+        };
+
+        // Format tag 1 is uncompressed PCM.
+        let pcm = le16(format) == 1;
+        let rate = wav.sample_rate > 0 && PWM_CLOCK_HZ / wav.sample_rate >= 4;
+        let channels = wav.channels == 1 || wav.channels == 2;
+        let bits = wav.bits_per_sample == 8 || wav.bits_per_sample == 16;
+        if !(pcm && rate && channels && bits) {
+            return Err(WavError::Unsupported);
+        }
+
+        Ok(wav)
+    }
+
+    /// Returns the number of whole frames, one sample for each channel.
+    pub fn frames(&self) -> usize {
+        self.data.len() / (self.channels as usize * self.bits_per_sample as usize / 8)
+    }
+
+    /// Returns how long the samples play for, in milliseconds.
+    pub fn duration_ms(&self) -> u64 {
+        self.frames() as u64 * 1000 / self.sample_rate as u64
+    }
+}
+
+/// Plays the signed 16-bit mono samples in `samples` at `sample_rate` Hz on
+/// the headphone jack, blocking until they've all been queued.
+///
+/// # Panics
+///
+/// Panics if `sample_rate` is 0 or above 2.4MHz, or if no DMA channel is
+/// free.
+pub fn play(samples: &[i16], sample_rate: u32) {
+    Audio::new().play(samples, sample_rate)
+}
+
+/// Plays `wav` on the headphone jack as `Audio::play_wav()` does, blocking
+/// until it's all been queued.
+///
+/// # Panics
+///
+/// Panics if no DMA channel is free.
+pub fn play_wav(wav: &Wav) {
+    Audio::new().play_wav(wav)
+}
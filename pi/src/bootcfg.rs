@@ -0,0 +1,136 @@
+use core::str;
+
+use emmc::Emmc;
+use fat::{self, FatError};
+
+/// The configuration file read from the boot partition.
+pub const FILE_NAME: &str = "boot.cfg";
+
+/// The largest configuration file read.
+pub const MAX_SIZE: usize = 4096;
+
+/// Where the kernel's console goes.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum Console {
+    /// The mini UART.
+    Uart,
+    /// The HDMI framebuffer, with a USB keyboard.
+    Hdmi,
+}
+
+/// How much the kernel logs while it starts.
+#[derive(Debug, Clone, Copy, PartialEq, Eq, PartialOrd, Ord)]
+pub enum LogLevel {
+    /// Only failures.
+    Error,
+    /// Failures and what was brought up.
+    Info,
+    /// Everything, including details only useful when debugging.
+    Debug,
+}
+
+/// The settings in `boot.cfg`: one `key=value` per line, with `#` starting a
+/// comment. Unknown keys and bad values are ignored, leaving the defaults.
+///
+/// | key            | value                                   | default       |
+/// |----------------|-----------------------------------------|---------------|
+/// | `console`      | `uart` or `hdmi`                        | `uart`        |
+/// | `baud`         | the bootloader's BAUD rate              | `115200`      |
+/// | `log`          | `error`, `info` or `debug`              | `info`        |
+/// | `kernel`       | the image the bootloader boots          | `kernel8.img` |
+/// | `initrd`       | a file the bootloader loads after it    | none          |
+/// | `timeout`      | seconds to wait for a host, then boot   | `10`          |
+/// | `panic_reboot` | seconds after a kernel panic to reboot  | never         |
+/// | `ip`           | the kernel's static address, if no DHCP | none          |
+/// | `tftp`         | the server `netboot` fetches from       | none          |
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct BootConfig<'a> {
+    pub console: Console,
+    pub baud: u32,
+    pub log_level: LogLevel,
+    pub kernel: &'a str,
+    pub initrd: Option<&'a str>,
+    pub timeout_secs: u64,
+    pub panic_reboot_secs: Option<u64>,
+    pub ip: Option<&'a str>,
+    pub tftp: Option<&'a str>,
+}
+
+impl<'a> Default for BootConfig<'a> {
+    fn default() -> BootConfig<'a> {
+        BootConfig {
+            console: Console::Uart,
+            baud: 115200,
+            log_level: LogLevel::Info,
+            kernel: "kernel8.img",
+            initrd: None,
+            timeout_secs: 10,
+            panic_reboot_secs: None,
+            ip: None,
+            tftp: None,
+        }
+    }
+}
+
+impl<'a> BootConfig<'a> {
+    /// Parses the contents of `boot.cfg`.
+    pub fn parse(text: &'a str) -> BootConfig<'a> {
+        let mut config = BootConfig::default();
+        for line in text.lines() {
+            let line = line.split('#').next().unwrap_or("");
+            let mut parts = line.splitn(2, '=');
+            let (key, value) = match (parts.next(), parts.next()) {
+                (Some(key), Some(value)) => (key.trim(), value.trim()),
+                _ => continue
+            };
+
+            match key {
+                "console" => match value {
+                    "uart" => config.console = Console::Uart,
+                    "hdmi" => config.console = Console::Hdmi,
+                    _ => {  }
+                },
+                "baud" => if let Ok(baud) = value.parse() {
+                    config.baud = baud;
+                },
+                "log" => match value {
+                    "error" => config.log_level = LogLevel::Error,
+                    "info" => config.log_level = LogLevel::Info,
+                    "debug" => config.log_level = LogLevel::Debug,
+                    _ => {  }
+                },
+                "kernel" if !value.is_empty() => config.kernel = value,
+                "initrd" if !value.is_empty() => config.initrd = Some(value),
+                "timeout" => if let Ok(secs) = value.parse() {
+                    config.timeout_secs = secs;
+                },
+                "panic_reboot" => if let Ok(secs) = value.parse() {
+                    config.panic_reboot_secs = Some(secs);
+                },
+                "ip" if !value.is_empty() => config.ip = Some(value),
+                "tftp" if !value.is_empty() => config.tftp = Some(value),
+                _ => {  }
+            }
+        }
+
+        config
+    }
+
+    /// Reads `boot.cfg` from the card's FAT partition into `buffer` and
+    /// parses it. Returns the defaults if the card has no such file.
+    ///
+    /// # Errors
+    ///
+    /// Returns the error reading the card, other than the file being
+    /// missing or not UTF-8.
+    pub fn read(emmc: &mut Emmc, buffer: &'a mut [u8]) -> Result<BootConfig<'a>, FatError> {
+        let len = match fat::load(emmc, FILE_NAME, buffer) {
+            Ok(len) => len,
+            Err(FatError::NotFound) => return Ok(BootConfig::default()),
+            Err(error) => return Err(error)
+        };
+
+        let buffer: &'a [u8] = buffer;
+        Ok(str::from_utf8(&buffer[..len]).map(BootConfig::parse).unwrap_or_default())
+    }
+}
@@ -0,0 +1,103 @@
+use core::time::Duration;
+
+use timer::Instant;
+use gpio::{self, Gpio, Input};
+
+/// A change in a button's debounced state.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum ButtonEvent {
+    Pressed,
+    Released
+}
+
+/// A push button connecting a GPIO pin to ground, read through the pin's
+/// pull-up resistor.
+///
+/// A change in the pin's level is only accepted once the level has been stable
+/// for the debounce interval. Buttons are sampled whenever one of their
+/// methods is called, so `poll()` should be called regularly to see every
+/// press.
+pub struct Button {
+    pin: Gpio<Input>,
+    debounce: Duration,
+    /// Whether the pin was low when last sampled.
+    raw_pressed: bool,
+    /// The time `raw_pressed` last changed.
+    changed_at: Instant,
+    /// The debounced state.
+    pressed: bool
+}
+
+impl Button {
+    /// Returns a `Button` on pin number `pin`, which is switched to an input
+    /// with its pull-up enabled, accepting changes after `debounce`.
+    ///
+    /// # Panics
+    ///
+    /// Panics if `pin` > `53`.
+    pub fn new(pin: u8, debounce: Duration) -> Button {
+        let mut pin = Gpio::new(pin).into_input_pullup();
+        gpio::claim(&[pin.number()], "button");
+        let pressed = !pin.level();
+
+        Button {
+            pin,
+            debounce,
+            raw_pressed: pressed,
+            changed_at: Instant::now(),
+            pressed
+        }
+    }
+
+    /// Sets the time the pin's level must be stable before a change is
+    /// accepted.
+    pub fn set_debounce(&mut self, debounce: Duration) {
+        self.debounce = debounce;
+    }
+
+    /// Samples the pin and returns the change in the debounced state, if
+    /// there was one. This method does not block.
+    pub fn poll(&mut self) -> Option<ButtonEvent> {
+        let now = Instant::now();
+        let raw_pressed = !self.pin.level();
+
+        if raw_pressed != self.raw_pressed {
+            self.raw_pressed = raw_pressed;
+            self.changed_at = now;
+            return None;
+        }
+
+        if raw_pressed == self.pressed
+            || now.duration_since(self.changed_at) < self.debounce {
+            return None;
+        }
+
+        self.pressed = raw_pressed;
+        Some(if raw_pressed { ButtonEvent::Pressed } else { ButtonEvent::Released })
+    }
+
+    /// Returns `true` if the button is pressed, after debouncing.
+    pub fn is_pressed(&mut self) -> bool {
+        self.poll();
+        self.pressed
+    }
+
+    /// Blocks until the button is next pressed. If it is already held down,
+    /// it must be released first.
+    pub fn wait_for_press(&mut self) {
+        while self.poll() != Some(ButtonEvent::Pressed) {  }
+    }
+
+    /// Blocks until the button is released. Returns immediately if it is not
+    /// held down.
+    pub fn wait_for_release(&mut self) {
+        while self.is_pressed() {  }
+    }
+}
+
+impl Drop for Button {
+    /// Withdraws the claim on the button's pin.
+    fn drop(&mut self) {
+        gpio::release_owned(&[self.pin.number()], "button");
+    }
+}
@@ -0,0 +1,96 @@
+use mailbox::{self, tag, MailboxError};
+
+/// The core clock rate assumed when the firmware won't report it, in Hz: the
+/// Pi 3 default.
+const DEFAULT_CORE_RATE: u32 = 250_000_000;
+
+/// The UART reference clock rate assumed when the firmware won't report it,
+/// in Hz: the Pi 3 default, changed with `init_uart_clock` in `config.txt`.
+const DEFAULT_UART_RATE: u32 = 48_000_000;
+
+/// A clock managed by the VideoCore firmware, numbered as in its property
+/// interface.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum Clock {
+    /// The EMMC controller's base clock.
+    Emmc = 1,
+    /// The PL011 UART's reference clock.
+    Uart = 2,
+    /// The ARM cores.
+    Arm = 3,
+    /// The VPU core clock, which also clocks the mini UART, SPI and I2C.
+    Core = 4,
+    V3d = 5,
+    H264 = 6,
+    Isp = 7,
+    Sdram = 8,
+    Pixel = 9,
+    Pwm = 10,
+}
+
+/// Sends the clock rate tag `id` for `clock` and returns the rate in Hz.
+fn query(id: u32, clock: Clock) -> Result<u32, MailboxError> {
+    let mut values = [clock as u32, 0];
+    mailbox::call_property(id, &mut values)?;
+    Ok(values[1])
+}
+
+/// Returns the current rate of `clock` in Hz. A rate of 0 means the clock
+/// doesn't exist.
+pub fn rate(clock: Clock) -> Result<u32, MailboxError> {
+    query(tag::GET_CLOCK_RATE, clock)
+}
+
+/// Returns the highest rate `clock` may be set to, in Hz.
+pub fn max_rate(clock: Clock) -> Result<u32, MailboxError> {
+    query(tag::GET_MAX_CLOCK_RATE, clock)
+}
+
+/// Returns the lowest rate `clock` may be set to, in Hz.
+pub fn min_rate(clock: Clock) -> Result<u32, MailboxError> {
+    query(tag::GET_MIN_CLOCK_RATE, clock)
+}
+
+/// Sets `clock` to `rate` Hz, or the nearest rate the firmware allows, and
+/// returns the rate it was set to. The firmware's turbo settings are left
+/// unchanged.
+///
+/// Peripherals derive their timing from the core and UART clocks when they
+/// are initialized; reinitialize them after changing either.
+pub fn set_rate(clock: Clock, rate: u32) -> Result<u32, MailboxError> {
+    let mut values = [clock as u32, rate, 1];
+    mailbox::call_property(tag::SET_CLOCK_RATE, &mut values)?;
+    Ok(values[1])
+}
+
+/// Returns the core clock rate in Hz, or the 250MHz default if the firmware
+/// doesn't answer.
+pub fn core_rate() -> u32 {
+    match rate(Clock::Core) {
+        Ok(rate) if rate != 0 => rate,
+        _ => DEFAULT_CORE_RATE
+    }
+}
+
+/// Returns the UART reference clock rate in Hz, or the 48MHz default if the
+/// firmware doesn't answer.
+pub fn uart_rate() -> u32 {
+    match rate(Clock::Uart) {
+        Ok(rate) if rate != 0 => rate,
+        _ => DEFAULT_UART_RATE
+    }
+}
+
+/// Returns the even divisor of a `clock` Hz core clock that yields the
+/// fastest rate no faster than `frequency`, as the SPI and BSC masters take
+/// it: at least 2, and `max`, their largest, or more written as 0. Returns
+/// `None` if `frequency` is 0.
+pub(crate) fn even_divisor(clock: u32, frequency: u32, max: u32) -> Option<u32> {
+    if frequency == 0 {
+        return None;
+    }
+
+    let divisor = (clock as u64 + frequency as u64 - 1) / frequency as u64;
+    let divisor = ::core::cmp::max(divisor + (divisor & 1), 2);
+    Some(if divisor >= max as u64 { 0 } else { divisor as u32 })
+}
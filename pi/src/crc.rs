@@ -0,0 +1,31 @@
+/// The CRC-32 of each nibble value, for the reflected polynomial 0xEDB88320.
+const CRC32_TABLE: [u32; 16] = [
+    0x00000000, 0x1DB71064, 0x3B6E20C8, 0x26D930AC,
+    0x76DC4190, 0x6B6B51F4, 0x4DB26158, 0x5005713C,
+    0xEDB88320, 0xF00F9344, 0xD6D6A3E8, 0xCB61B38C,
+    0x9B64C2B0, 0x86D3D2D4, 0xA00AE278, 0xBDBDF21C,
+];
+
+/// Returns the CRC-32 (IEEE 802.3) of `data`.
+pub fn crc32(data: &[u8]) -> u32 {
+    let mut crc = !0u32;
+    for &byte in data {
+        crc = crc >> 4 ^ CRC32_TABLE[((crc ^ byte as u32) & 0xF) as usize];
+        crc = crc >> 4 ^ CRC32_TABLE[((crc ^ (byte >> 4) as u32) & 0xF) as usize];
+    }
+
+    !crc
+}
+
+/// Returns the CRC-16/XMODEM of `data`: polynomial 0x1021, initial value 0.
+pub fn crc16(data: &[u8]) -> u16 {
+    let mut crc = 0u16;
+    for &byte in data {
+        crc ^= (byte as u16) << 8;
+        for _ in 0..8 {
+            crc = if crc & 0x8000 != 0 { crc << 1 ^ 0x1021 } else { crc << 1 };
+        }
+    }
+
+    crc
+}
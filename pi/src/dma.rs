@@ -0,0 +1,442 @@
+use core::mem::size_of;
+use core::sync::atomic::{AtomicUsize, Ordering};
+
+use common::IO_BASE;
+use interrupt::{Controller, Interrupt};
+use volatile::prelude::*;
+use volatile::{Volatile, ReadVolatile};
+
+/// The base address of the DMA controller's channel 0 registers. Each channel
+/// occupies `0x100` bytes.
+const DMA_REG_BASE: usize = IO_BASE + 0x7000;
+
+/// The `DMA_ENABLE` register: one enable bit per channel.
+const DMA_ENABLE: *mut Volatile<u32> = (IO_BASE + 0x7FF0) as *mut Volatile<u32>;
+
+/// The highest channel number covered by this driver. Channel 15 lives in a
+/// separate register block.
+pub const MAX_CHANNEL: u8 = 14;
+
+/// The highest full channel. Channels 7 and up are "lite" channels: they don't
+/// support 2D mode and transfer at most 64KiB per control block.
+pub const MAX_FULL_CHANNEL: u8 = 6;
+
+/// The full channels the firmware leaves to the ARM, searched by
+/// `Channel::claim_any()`. Channels 1, 3 and 6 are used by the VideoCore.
+const FREE_FULL_CHANNELS: usize = 0b0011_0101;
+
+/// One bit per channel currently owned by a `Channel`.
+static CLAIMED: AtomicUsize = AtomicUsize::new(0);
+
+/// ARM physical addresses of peripherals start here...
+const ARM_IO_BASE: usize = IO_BASE;
+/// ...and they appear at this address on the VideoCore bus.
+const BUS_IO_BASE: u32 = 0x7E000000;
+/// SDRAM seen through the bus alias that bypasses the VideoCore L2 cache.
+const BUS_SDRAM_UNCACHED: u32 = 0xC0000000;
+
+/// Enum representing bit fields of a channel's `CS` register.
+#[repr(u32)]
+enum CsFlags {
+    Active = 1,
+    End = 1 << 1,
+    Int = 1 << 2,
+    Error = 1 << 8,
+    Reset = 1 << 31,
+}
+
+/// Bit fields of a control block's transfer information (`TI`) word.
+pub mod ti {
+    /// Raise an interrupt when the transfer completes.
+    pub const INTEN: u32 = 1;
+    /// Interpret `TXFR_LEN` as rows and columns and apply `STRIDE` after each
+    /// row. Full channels only.
+    pub const TDMODE: u32 = 1 << 1;
+    /// Wait for a write response before starting the next write.
+    pub const WAIT_RESP: u32 = 1 << 3;
+    /// Increment the destination address after each write.
+    pub const DEST_INC: u32 = 1 << 4;
+    /// Pace writes using the peripheral's DREQ signal.
+    pub const DEST_DREQ: u32 = 1 << 6;
+    /// Increment the source address after each read.
+    pub const SRC_INC: u32 = 1 << 8;
+    /// Pace reads using the peripheral's DREQ signal.
+    pub const SRC_DREQ: u32 = 1 << 10;
+
+    /// Returns the `PERMAP` field selecting which peripheral DREQ paces the
+    /// transfer.
+    pub const fn permap(dreq: u32) -> u32 {
+        (dreq & 0x1f) << 16
+    }
+}
+
+/// Peripheral DREQ numbers for `ti::permap`.
+pub mod dreq {
+    pub const PWM: u32 = 5;
+    pub const UART_TX: u32 = 12;
+    pub const UART_RX: u32 = 14;
+}
+
+#[repr(C)]
+#[allow(non_snake_case)]
+struct Registers {
+    CS: Volatile<u32>,
+    CONBLK_AD: Volatile<u32>,
+    TI: ReadVolatile<u32>,
+    SOURCE_AD: ReadVolatile<u32>,
+    DEST_AD: ReadVolatile<u32>,
+    TXFR_LEN: ReadVolatile<u32>,
+    STRIDE: ReadVolatile<u32>,
+    NEXTCONBK: ReadVolatile<u32>,
+    DEBUG: Volatile<u32>,
+}
+
+/// A DMA control block: a single transfer description read by the DMA engine
+/// from memory. Control blocks must be 32-byte aligned.
+#[repr(C, align(32))]
+#[derive(Debug, Default, Clone, Copy)]
+pub struct ControlBlock {
+    pub transfer_info: u32,
+    pub source: u32,
+    pub destination: u32,
+    pub length: u32,
+    pub stride: u32,
+    pub next: u32,
+    __r0: [u32; 2],
+}
+
+impl ControlBlock {
+    /// Returns a control block with all fields zeroed.
+    pub const fn new() -> ControlBlock {
+        ControlBlock {
+            transfer_info: 0,
+            source: 0,
+            destination: 0,
+            length: 0,
+            stride: 0,
+            next: 0,
+            __r0: [0; 2],
+        }
+    }
+
+    /// Returns a builder for a control block transferring between two
+    /// regions of memory.
+    pub fn builder() -> ControlBlockBuilder {
+        ControlBlockBuilder { block: ControlBlock::new() }
+    }
+}
+
+/// Builds a `ControlBlock` from ARM physical addresses.
+///
+/// ```rust,ignore
+/// let block = ControlBlock::builder()
+///     .source(src.as_ptr() as usize)
+///     .destination(dst.as_mut_ptr() as usize)
+///     .length(dst.len())
+///     .interrupt()
+///     .build();
+/// ```
+#[derive(Debug, Clone, Copy)]
+pub struct ControlBlockBuilder {
+    block: ControlBlock,
+}
+
+impl ControlBlockBuilder {
+    /// Reads from memory starting at `addr`, incrementing after each read.
+    pub fn source(mut self, addr: usize) -> ControlBlockBuilder {
+        self.block.source = bus_address(addr);
+        self.block.transfer_info |= ti::SRC_INC;
+        self
+    }
+
+    /// Reads repeatedly from the peripheral register at `addr`, paced by the
+    /// peripheral's DREQ signal `dreq`.
+    pub fn source_peripheral(mut self, addr: usize, dreq: u32) -> ControlBlockBuilder {
+        self.block.source = bus_address(addr);
+        self.block.transfer_info &= !ti::SRC_INC;
+        self.block.transfer_info |= ti::SRC_DREQ | ti::permap(dreq);
+        self
+    }
+
+    /// Writes to memory starting at `addr`, incrementing after each write.
+    pub fn destination(mut self, addr: usize) -> ControlBlockBuilder {
+        self.block.destination = bus_address(addr);
+        self.block.transfer_info |= ti::DEST_INC;
+        self
+    }
+
+    /// Writes repeatedly to the peripheral register at `addr`, paced by the
+    /// peripheral's DREQ signal `dreq`, waiting for each write to land.
+    pub fn destination_peripheral(mut self, addr: usize, dreq: u32) -> ControlBlockBuilder {
+        self.block.destination = bus_address(addr);
+        self.block.transfer_info &= !ti::DEST_INC;
+        self.block.transfer_info |= ti::DEST_DREQ | ti::WAIT_RESP | ti::permap(dreq);
+        self
+    }
+
+    /// Transfers `len` bytes in one run.
+    pub fn length(mut self, len: usize) -> ControlBlockBuilder {
+        self.block.transfer_info &= !ti::TDMODE;
+        self.block.length = len as u32;
+        self.block.stride = 0;
+        self
+    }
+
+    /// Transfers `rows` runs of `row_len` bytes, skipping `source_stride`
+    /// bytes after each row read and `destination_stride` bytes after each
+    /// row written. For a rectangle of a framebuffer, the stride is the pitch
+    /// less the row length. Only full channels honour 2D mode.
+    ///
+    /// # Panics
+    ///
+    /// Panics if `rows` is 0 or greater than 16384.
+    pub fn two_d(mut self,
+                 row_len: u16,
+                 rows: u16,
+                 source_stride: i16,
+                 destination_stride: i16) -> ControlBlockBuilder {
+        if rows == 0 || rows > 0x4000 {
+            panic!("dma::ControlBlockBuilder::two_d(): {} rows is out of range", rows);
+        }
+
+        // The engine performs YLENGTH + 1 rows.
+        self.block.transfer_info |= ti::TDMODE;
+        self.block.length = (rows as u32 - 1) << 16 | row_len as u32;
+        self.block.stride = (destination_stride as u16 as u32) << 16 | source_stride as u16 as u32;
+        self
+    }
+
+    /// Raises the channel's interrupt once this block completes.
+    pub fn interrupt(mut self) -> ControlBlockBuilder {
+        self.block.transfer_info |= ti::INTEN;
+        self
+    }
+
+    /// Continues with the control block `next` once this one completes.
+    ///
+    /// `next` must stay at the same address for as long as this block may be
+    /// executed.
+    pub fn then(mut self, next: &ControlBlock) -> ControlBlockBuilder {
+        self.block.next = bus_address(next as *const ControlBlock as usize);
+        self
+    }
+
+    /// Returns the control block.
+    pub fn build(self) -> ControlBlock {
+        self.block
+    }
+}
+
+/// Returns the VideoCore bus address the DMA engine must use to reach the ARM
+/// physical address `addr`. Peripheral addresses are remapped into the
+/// `0x7E000000` window; SDRAM addresses use the uncached alias since the ARM
+/// side doesn't keep the VideoCore L2 coherent.
+pub fn bus_address(addr: usize) -> u32 {
+    if addr >= ARM_IO_BASE {
+        (addr - ARM_IO_BASE) as u32 + BUS_IO_BASE
+    } else {
+        addr as u32 | BUS_SDRAM_UNCACHED
+    }
+}
+
+/// Returns the ARM physical address of the VideoCore bus address `addr`, as
+/// handed out by the firmware. Inverse of `bus_address()`.
+pub fn arm_address(addr: u32) -> usize {
+    if addr >= BUS_IO_BASE && addr < BUS_SDRAM_UNCACHED {
+        (addr - BUS_IO_BASE) as usize + ARM_IO_BASE
+    } else {
+        // Strip the cache alias bits from an SDRAM address.
+        (addr & 0x3FFF_FFFF) as usize
+    }
+}
+
+/// Returns the registers of channel `number`.
+fn registers(number: u8) -> &'static mut Registers {
+    unsafe { &mut *((DMA_REG_BASE + number as usize * 0x100) as *mut Registers) }
+}
+
+/// Returns `true` if channel `number` has raised its interrupt since the last
+/// call, clearing it. For use in interrupt handlers, which don't own the
+/// `Channel`.
+pub fn acknowledge(number: u8) -> bool {
+    let registers = registers(number);
+    if registers.CS.has_mask(CsFlags::Int as u32) {
+        // Writing a 1 clears the flag; ACTIVE is left as it was.
+        registers.CS.write(CsFlags::Int as u32 | (registers.CS.read() & CsFlags::Active as u32));
+        true
+    } else {
+        false
+    }
+}
+
+/// A single DMA channel, claimed for as long as it's alive. Dropping the
+/// channel releases it.
+pub struct Channel {
+    number: u8,
+    registers: &'static mut Registers,
+}
+
+impl Channel {
+    /// Returns the DMA channel `number`, enabled and reset.
+    ///
+    /// # Panics
+    ///
+    /// Panics if `number` > `MAX_CHANNEL` or the channel is already claimed.
+    pub fn new(number: u8) -> Channel {
+        if number > MAX_CHANNEL {
+            panic!("dma::Channel::new(): channel {} exceeds maximum of {}", number, MAX_CHANNEL);
+        }
+
+        match Channel::claim(number) {
+            Some(channel) => channel,
+            None => panic!("dma::Channel::new(): channel {} is already claimed", number)
+        }
+    }
+
+    /// Returns the DMA channel `number`, enabled and reset, unless it is
+    /// already claimed or doesn't exist.
+    pub fn claim(number: u8) -> Option<Channel> {
+        if number > MAX_CHANNEL {
+            return None;
+        }
+
+        let mask = 1 << number;
+        if CLAIMED.fetch_or(mask, Ordering::AcqRel) & mask != 0 {
+            return None;
+        }
+
+        Some(Channel::enable(number))
+    }
+
+    /// Returns a free full channel, enabled and reset, if there is one.
+    pub fn claim_any() -> Option<Channel> {
+        (0..MAX_FULL_CHANNEL + 1)
+            .filter(|number| FREE_FULL_CHANNELS & (1 << *number) != 0)
+            .filter_map(Channel::claim)
+            .next()
+    }
+
+    /// Releases the channel so it can be claimed again. Equivalent to
+    /// dropping it.
+    pub fn release(self) {  }
+
+    /// Enables and resets channel `number`, which the caller has claimed.
+    fn enable(number: u8) -> Channel {
+        unsafe { (*DMA_ENABLE).or_mask(1 << number); }
+        let registers = registers(number);
+
+        registers.CS.write(CsFlags::Reset as u32);
+
+        Channel { number, registers }
+    }
+
+    /// Returns this channel's number.
+    pub fn number(&self) -> u8 {
+        self.number
+    }
+
+    /// Returns this channel's interrupt, or `None` for lite channels, which
+    /// aren't covered by `Interrupt`.
+    pub fn interrupt(&self) -> Option<Interrupt> {
+        match self.number {
+            0 => Some(Interrupt::Dma0),
+            1 => Some(Interrupt::Dma1),
+            2 => Some(Interrupt::Dma2),
+            3 => Some(Interrupt::Dma3),
+            4 => Some(Interrupt::Dma4),
+            5 => Some(Interrupt::Dma5),
+            6 => Some(Interrupt::Dma6),
+            _ => None
+        }
+    }
+
+    /// Enables this channel's interrupt, raised when a control block with
+    /// `ti::INTEN` set completes. The handler must call `acknowledge()`.
+    ///
+    /// # Panics
+    ///
+    /// Panics if this is a lite channel.
+    pub fn enable_interrupt(&mut self) {
+        match self.interrupt() {
+            Some(interrupt) => Controller::new().enable(interrupt),
+            None => panic!("dma::Channel::enable_interrupt(): channel {} has no interrupt",
+                           self.number)
+        }
+    }
+
+    /// Disables this channel's interrupt.
+    pub fn disable_interrupt(&mut self) {
+        if let Some(interrupt) = self.interrupt() {
+            Controller::new().disable(interrupt);
+        }
+    }
+
+    /// Starts executing the chain of control blocks beginning at `block` and
+    /// returns immediately.
+    ///
+    /// # Safety
+    ///
+    /// `block`, every block chained from it, and the memory they transfer
+    /// from and to must remain valid and unmodified by the CPU until
+    /// `is_done()` returns `true`.
+    pub unsafe fn start(&mut self, block: &ControlBlock) {
+        // Clear the END and INT flags left over from the last transfer.
+        self.registers.CS.write(CsFlags::End as u32 | CsFlags::Int as u32);
+        self.registers.CONBLK_AD.write(bus_address(block as *const ControlBlock as usize));
+        self.registers.CS.write(CsFlags::Active as u32);
+    }
+
+    /// Returns `true` if the channel is not currently executing a transfer.
+    pub fn is_done(&self) -> bool {
+        self.registers.CS.read() & CsFlags::Active as u32 == 0
+    }
+
+    /// Returns `true` if the channel has flagged an error (e.g. a read from an
+    /// invalid address or a FIFO error).
+    pub fn has_error(&self) -> bool {
+        self.registers.CS.read() & CsFlags::Error as u32 != 0
+    }
+
+    /// Blocks until the current transfer, if any, completes.
+    pub fn wait(&self) {
+        while !self.is_done() {
+            // Spin while the transfer is active.
+        }
+    }
+}
+
+impl Drop for Channel {
+    fn drop(&mut self) {
+        self.disable_interrupt();
+        CLAIMED.fetch_and(!(1 << self.number), Ordering::AcqRel);
+    }
+}
+
+/// Copies `src` into `dst` with the DMA engine, blocking until the copy
+/// completes. Falls back to a CPU copy when every full channel is claimed.
+///
+/// # Panics
+///
+/// Panics if `dst` and `src` have different lengths.
+pub fn dma_copy<T: Copy>(dst: &mut [T], src: &[T]) {
+    if dst.len() != src.len() {
+        panic!("dma::dma_copy(): destination length {} doesn't match source length {}",
+               dst.len(), src.len());
+    }
+
+    let mut channel = match Channel::claim_any() {
+        Some(channel) => channel,
+        None => return dst.copy_from_slice(src)
+    };
+
+    let block = ControlBlock::builder()
+        .source(src.as_ptr() as usize)
+        .destination(dst.as_mut_ptr() as usize)
+        .length(dst.len() * size_of::<T>())
+        .build();
+
+    // Both slices are borrowed, and `block` lives on the stack, until the
+    // transfer is done.
+    unsafe { channel.start(&block); }
+    channel.wait();
+}
@@ -0,0 +1,500 @@
+use core::time::Duration;
+
+use common::IO_BASE;
+use gpio::{self, Gpio, Pull, pin, signal};
+use clock::{self, Clock};
+use timer::{Instant, spin_sleep_ms};
+use volatile::prelude::*;
+use volatile::{Volatile, ReadVolatile, Reserved};
+
+/// The base address of the Arasan EMMC controller's registers.
+const EMMC_REG_BASE: usize = IO_BASE + 0x300000;
+
+/// The size of a sector, in bytes.
+pub const SECTOR_SIZE: usize = 512;
+
+/// The most sectors a single transfer can move.
+pub const MAX_SECTORS: usize = 0xFFFF;
+
+/// The EMMC base clock assumed when the firmware won't report it, in Hz.
+const DEFAULT_BASE_CLOCK: u32 = 41_666_666;
+
+/// The SD clock during identification, and once the card is selected, in Hz.
+const IDENTIFICATION_CLOCK: u32 = 400_000;
+const TRANSFER_CLOCK: u32 = 25_000_000;
+
+/// How long a command, or a sector's data, may take, in milliseconds.
+const COMMAND_TIMEOUT_MS: u64 = 500;
+
+/// How long the card may stay busy powering up in response to `ACMD41`, in
+/// milliseconds.
+const POWER_UP_TIMEOUT_MS: u64 = 1000;
+
+/// The `SEND_IF_COND` argument: 2.7-3.6V and a check pattern echoed back.
+const IF_COND_ARG: u32 = 0x1AA;
+
+/// The `SD_SEND_OP_COND` argument: high capacity support and 3.2-3.4V.
+const OP_COND_ARG: u32 = 1 << 30 | 0x30 << 15;
+
+/// Bits of the `SD_SEND_OP_COND` response (`OCR`).
+const OCR_POWERED_UP: u32 = 1 << 31;
+const OCR_HIGH_CAPACITY: u32 = 1 << 30;
+
+/// The `SET_BUS_WIDTH` argument selecting a 4-bit bus.
+const BUS_WIDTH_4: u32 = 2;
+
+/// Enum representing bit fields of the `CONTROL0` register.
+#[repr(u32)]
+enum Control0 {
+    DataWidth4 = 1 << 1,
+}
+
+/// Enum representing bit fields of the `CONTROL1` register.
+#[repr(u32)]
+enum Control1 {
+    ClockInternalEnable = 1,
+    ClockStable = 1 << 1,
+    ClockEnable = 1 << 2,
+    /// The largest data timeout, `TMCLK * 2^27`.
+    DataTimeoutMax = 0xE << 16,
+    ResetHost = 1 << 24,
+    ResetCommand = 1 << 25,
+}
+
+/// The `CONTROL1` fields holding the clock divisor.
+const CONTROL1_DIVISOR_MASK: u32 = 0xFFC0;
+
+/// Enum representing bit fields of the `STATUS` register.
+#[repr(u32)]
+enum Status {
+    CommandInhibit = 1,
+    DataInhibit = 1 << 1,
+}
+
+/// Enum representing bit fields of the `INTERRUPT` register.
+#[repr(u32)]
+enum Irpt {
+    CommandDone = 1,
+    DataDone = 1 << 1,
+    WriteReady = 1 << 4,
+    ReadReady = 1 << 5,
+    Error = 1 << 15,
+    CommandTimeout = 1 << 16,
+}
+
+/// The `INTERRUPT` bits reporting errors.
+const IRPT_ERROR_MASK: u32 = 0xFFFF_0000;
+
+/// Fields of the `CMDTM` register and the commands built from them. Each
+/// command constant is the complete `CMDTM` value that issues it.
+mod cmd {
+    const fn index(n: u32) -> u32 { n << 24 }
+
+    const RESPONSE_NONE: u32 = 0;
+    const RESPONSE_136: u32 = 1 << 16;
+    const RESPONSE_48: u32 = 2 << 16;
+    const RESPONSE_48_BUSY: u32 = 3 << 16;
+    const CRC_CHECK: u32 = 1 << 19;
+    const INDEX_CHECK: u32 = 1 << 20;
+    const IS_DATA: u32 = 1 << 21;
+    const BLOCK_COUNT: u32 = 1 << 1;
+    /// Sends `STOP_TRANSMISSION` once the block count is reached.
+    const AUTO_STOP: u32 = 1 << 2;
+    const READ: u32 = 1 << 4;
+    const MULTI_BLOCK: u32 = 1 << 5;
+
+    /// An `R1` response, checked.
+    const R1: u32 = RESPONSE_48 | CRC_CHECK | INDEX_CHECK;
+
+    pub const GO_IDLE_STATE: u32 = index(0) | RESPONSE_NONE;
+    pub const ALL_SEND_CID: u32 = index(2) | RESPONSE_136 | CRC_CHECK;
+    pub const SEND_RELATIVE_ADDR: u32 = index(3) | R1;
+    pub const SELECT_CARD: u32 = index(7) | RESPONSE_48_BUSY | CRC_CHECK | INDEX_CHECK;
+    pub const SEND_IF_COND: u32 = index(8) | R1;
+    pub const STOP_TRANSMISSION: u32 = index(12) | RESPONSE_48_BUSY | CRC_CHECK | INDEX_CHECK;
+    pub const SET_BLOCKLEN: u32 = index(16) | R1;
+    pub const READ_SINGLE_BLOCK: u32 = index(17) | R1 | IS_DATA | READ;
+    pub const READ_MULTIPLE_BLOCK: u32 =
+        index(18) | R1 | IS_DATA | READ | MULTI_BLOCK | BLOCK_COUNT | AUTO_STOP;
+    pub const WRITE_SINGLE_BLOCK: u32 = index(24) | R1 | IS_DATA;
+    pub const WRITE_MULTIPLE_BLOCK: u32 =
+        index(25) | R1 | IS_DATA | MULTI_BLOCK | BLOCK_COUNT | AUTO_STOP;
+    pub const APP_CMD: u32 = index(55) | R1;
+
+    /// Application commands, sent after `APP_CMD`.
+    pub const SET_BUS_WIDTH: u32 = index(6) | R1;
+    /// `R3` carries no CRC or index.
+    pub const SD_SEND_OP_COND: u32 = index(41) | RESPONSE_48;
+}
+
+#[repr(C)]
+#[allow(non_snake_case)]
+struct Registers {
+    ARG2: Volatile<u32>,
+    BLKSIZECNT: Volatile<u32>,
+    ARG1: Volatile<u32>,
+    CMDTM: Volatile<u32>,
+    RESP: [ReadVolatile<u32>; 4],
+    DATA: Volatile<u32>,
+    STATUS: ReadVolatile<u32>,
+    CONTROL0: Volatile<u32>,
+    CONTROL1: Volatile<u32>,
+    INTERRUPT: Volatile<u32>,
+    IRPT_MASK: Volatile<u32>,
+    IRPT_EN: Volatile<u32>,
+    CONTROL2: Volatile<u32>,
+    __r0: [Reserved<u32>; 47],
+    SLOTISR_VER: ReadVolatile<u32>,
+}
+
+/// An error from the EMMC controller or the card.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum EmmcError {
+    /// The controller or card didn't finish in time. There may be no card.
+    TimedOut,
+    /// The controller flagged an error; these are the error bits of its
+    /// `INTERRUPT` register.
+    Controller(u32),
+    /// The card is a version 1 card or doesn't accept the host's voltage.
+    UnsupportedCard,
+}
+
+/// Returns the `CONTROL1` divisor fields that bring `base` Hz down to at most
+/// `frequency` Hz. The SD clock is `base / (2 * n)` for the 10-bit `n`, with 0
+/// meaning the undivided base clock.
+fn clock_divisor(base: u32, frequency: u32) -> u32 {
+    let n = if frequency >= base { 0 } else { (base + 2 * frequency - 1) / (2 * frequency) };
+    let n = ::core::cmp::min(n, 0x3FF);
+    (n & 0xFF) << 8 | (n >> 8) << 6
+}
+
+/// An SD card on the EMMC controller, initialized and selected for transfers.
+///
+/// Only SDHC/SDXC (and version 2 standard capacity) cards are supported.
+pub struct Emmc {
+    registers: &'static mut Registers,
+    /// The card's relative address, already shifted into the upper half as
+    /// command arguments expect it.
+    rca: u32,
+    /// High capacity cards are addressed in sectors rather than bytes.
+    high_capacity: bool,
+    base_clock: u32,
+}
+
+impl Emmc {
+    /// Routes the SD card pins (GPIO 48-53) to the EMMC controller, resets it,
+    /// and takes the card through identification into the transfer state
+    /// with a 4-bit bus at 25MHz.
+    pub fn new() -> Result<Emmc, EmmcError> {
+        Gpio::with_signal(pin::P48, signal::SdClk);
+        Gpio::with_signal(pin::P49, signal::SdCmd).set_pull(Pull::Up);
+        Gpio::with_signal(pin::P50, signal::SdDat0).set_pull(Pull::Up);
+        Gpio::with_signal(pin::P51, signal::SdDat1).set_pull(Pull::Up);
+        Gpio::with_signal(pin::P52, signal::SdDat2).set_pull(Pull::Up);
+        Gpio::with_signal(pin::P53, signal::SdDat3).set_pull(Pull::Up);
+        gpio::claim(&[48, 49, 50, 51, 52, 53], "emmc");
+
+        let base_clock = match clock::rate(Clock::Emmc) {
+            Ok(rate) if rate != 0 => rate,
+            _ => DEFAULT_BASE_CLOCK
+        };
+
+        let registers = unsafe { &mut *(EMMC_REG_BASE as *mut Registers) };
+        let mut emmc = Emmc { registers, rca: 0, high_capacity: false, base_clock };
+        emmc.reset()?;
+        emmc.identify()?;
+        Ok(emmc)
+    }
+
+    /// Resets the controller and starts the identification clock.
+    fn reset(&mut self) -> Result<(), EmmcError> {
+        self.registers.CONTROL0.write(0);
+        self.registers.CONTROL1.write(Control1::ResetHost as u32);
+        let deadline = Instant::now() + Duration::from_millis(COMMAND_TIMEOUT_MS);
+        while self.registers.CONTROL1.has_mask(Control1::ResetHost as u32) {
+            if Instant::now() > deadline {
+                return Err(EmmcError::TimedOut);
+            }
+        }
+
+        self.registers.CONTROL1.write(Control1::ClockInternalEnable as u32
+                                      | Control1::DataTimeoutMax as u32);
+        self.set_clock(IDENTIFICATION_CLOCK)?;
+
+        // Latch every status flag, but don't raise the interrupt line.
+        self.registers.IRPT_EN.write(0);
+        self.registers.IRPT_MASK.write(0xFFFF_FFFF);
+        self.registers.INTERRUPT.write(0xFFFF_FFFF);
+        Ok(())
+    }
+
+    /// Switches the SD clock to the fastest rate no faster than `frequency`.
+    fn set_clock(&mut self, frequency: u32) -> Result<(), EmmcError> {
+        let deadline = Instant::now() + Duration::from_millis(COMMAND_TIMEOUT_MS);
+        while self.registers.STATUS.read()
+                & (Status::CommandInhibit as u32 | Status::DataInhibit as u32) != 0 {
+            if Instant::now() > deadline {
+                return Err(EmmcError::TimedOut);
+            }
+        }
+
+        self.registers.CONTROL1.and_mask(!(Control1::ClockEnable as u32));
+        spin_sleep_ms(1);
+
+        let control = self.registers.CONTROL1.read() & !CONTROL1_DIVISOR_MASK;
+        self.registers.CONTROL1.write(control | clock_divisor(self.base_clock, frequency));
+        spin_sleep_ms(1);
+
+        while !self.registers.CONTROL1.has_mask(Control1::ClockStable as u32) {
+            if Instant::now() > deadline {
+                return Err(EmmcError::TimedOut);
+            }
+        }
+
+        self.registers.CONTROL1.or_mask(Control1::ClockEnable as u32);
+        spin_sleep_ms(1);
+        Ok(())
+    }
+
+    /// Takes the card from idle to the transfer state.
+    fn identify(&mut self) -> Result<(), EmmcError> {
+        self.command(cmd::GO_IDLE_STATE, 0)?;
+
+        // Version 1 cards don't answer, so a timeout is taken to mean one;
+        // they're not supported.
+        match self.command(cmd::SEND_IF_COND, IF_COND_ARG) {
+            Ok(response) if response & 0xFFF == IF_COND_ARG => {  }
+            Ok(_) | Err(EmmcError::Controller(_)) | Err(EmmcError::TimedOut) => {
+                return Err(EmmcError::UnsupportedCard)
+            }
+            Err(e) => return Err(e)
+        }
+
+        let deadline = Instant::now() + Duration::from_millis(POWER_UP_TIMEOUT_MS);
+        let ocr = loop {
+            let ocr = self.app_command(cmd::SD_SEND_OP_COND, OP_COND_ARG)?;
+            if ocr & OCR_POWERED_UP != 0 {
+                break ocr;
+            }
+
+            if Instant::now() > deadline {
+                return Err(EmmcError::UnsupportedCard);
+            }
+
+            spin_sleep_ms(10);
+        };
+
+        self.high_capacity = ocr & OCR_HIGH_CAPACITY != 0;
+
+        self.command(cmd::ALL_SEND_CID, 0)?;
+        self.rca = self.command(cmd::SEND_RELATIVE_ADDR, 0)? & 0xFFFF_0000;
+        self.command(cmd::SELECT_CARD, self.rca)?;
+        self.set_clock(TRANSFER_CLOCK)?;
+
+        if !self.high_capacity {
+            self.command(cmd::SET_BLOCKLEN, SECTOR_SIZE as u32)?;
+        }
+
+        self.app_command(cmd::SET_BUS_WIDTH, BUS_WIDTH_4)?;
+        self.registers.CONTROL0.or_mask(Control0::DataWidth4 as u32);
+        Ok(())
+    }
+
+    /// Waits until any bit in `mask` is set in `INTERRUPT`, then clears it.
+    /// Any error flagged first is cleared and returned instead.
+    fn wait_for(&mut self, mask: u32) -> Result<(), EmmcError> {
+        let deadline = Instant::now() + Duration::from_millis(COMMAND_TIMEOUT_MS);
+        loop {
+            let interrupt = self.registers.INTERRUPT.read();
+            if interrupt & (Irpt::Error as u32 | IRPT_ERROR_MASK) != 0 {
+                self.registers.INTERRUPT.write(interrupt);
+                return Err(if interrupt & Irpt::CommandTimeout as u32 != 0 {
+                    EmmcError::TimedOut
+                } else {
+                    EmmcError::Controller(interrupt & IRPT_ERROR_MASK)
+                });
+            }
+
+            if interrupt & mask != 0 {
+                self.registers.INTERRUPT.write(interrupt & mask);
+                return Ok(());
+            }
+
+            if Instant::now() > deadline {
+                return Err(EmmcError::TimedOut);
+            }
+        }
+    }
+
+    /// Issues the command `cmdtm` with argument `arg` and returns the first
+    /// word of its response. If the command fails, the command circuit is
+    /// reset so the next one can be issued.
+    fn command(&mut self, cmdtm: u32, arg: u32) -> Result<u32, EmmcError> {
+        let deadline = Instant::now() + Duration::from_millis(COMMAND_TIMEOUT_MS);
+        while self.registers.STATUS.has_mask(Status::CommandInhibit as u32) {
+            if Instant::now() > deadline {
+                self.reset_command();
+                return Err(EmmcError::TimedOut);
+            }
+        }
+
+        self.registers.INTERRUPT.write(self.registers.INTERRUPT.read());
+        self.registers.ARG1.write(arg);
+        self.registers.CMDTM.write(cmdtm);
+        if let Err(e) = self.wait_for(Irpt::CommandDone as u32) {
+            self.reset_command();
+            return Err(e);
+        }
+
+        Ok(self.registers.RESP[0].read())
+    }
+
+    /// Resets the command circuit, which stays inhibited after a command
+    /// times out or fails. If it doesn't come out of reset, the next command
+    /// times out.
+    fn reset_command(&mut self) {
+        self.registers.CONTROL1.or_mask(Control1::ResetCommand as u32);
+        let deadline = Instant::now() + Duration::from_millis(COMMAND_TIMEOUT_MS);
+        while self.registers.CONTROL1.has_mask(Control1::ResetCommand as u32) {
+            if Instant::now() > deadline {
+                return;
+            }
+        }
+    }
+
+    /// Issues the application command `cmdtm`, preceded by `APP_CMD`.
+    fn app_command(&mut self, cmdtm: u32, arg: u32) -> Result<u32, EmmcError> {
+        let rca = self.rca;
+        self.command(cmd::APP_CMD, rca)?;
+        self.command(cmdtm, arg)
+    }
+
+    /// Returns the command argument addressing sector `lba`.
+    fn address(&self, lba: u32) -> u32 {
+        if self.high_capacity { lba } else { lba * SECTOR_SIZE as u32 }
+    }
+
+    /// Waits for the data lines, sets up a transfer of `count` sectors and
+    /// issues `cmdtm` to start it at sector `lba`.
+    fn start_transfer(&mut self, cmdtm: u32, lba: u32, count: usize) -> Result<(), EmmcError> {
+        let deadline = Instant::now() + Duration::from_millis(COMMAND_TIMEOUT_MS);
+        while self.registers.STATUS.has_mask(Status::DataInhibit as u32) {
+            if Instant::now() > deadline {
+                return Err(EmmcError::TimedOut);
+            }
+        }
+
+        self.registers.BLKSIZECNT.write((count as u32) << 16 | SECTOR_SIZE as u32);
+        let address = self.address(lba);
+        self.command(cmdtm, address).map(|_| ())
+    }
+
+    /// Ends a multiple block transfer that failed before the automatic
+    /// `STOP_TRANSMISSION`. Errors are ignored: the transfer has already
+    /// failed.
+    fn stop_transmission(&mut self) {
+        let _ = self.command(cmd::STOP_TRANSMISSION, 0);
+    }
+
+    /// Panics unless `buf` holds exactly `count` sectors, with `count` no
+    /// more than `MAX_SECTORS`.
+    fn check_buffer(function: &str, count: usize, buf: &[u8]) {
+        if count > MAX_SECTORS {
+            panic!("Emmc::{}(): {} sectors exceeds maximum of {}", function, count, MAX_SECTORS);
+        }
+
+        if buf.len() != count * SECTOR_SIZE {
+            panic!("Emmc::{}(): buffer of {} bytes doesn't hold {} sectors",
+                   function, buf.len(), count);
+        }
+    }
+
+    /// Reads sector `lba` into `buf`.
+    ///
+    /// # Panics
+    ///
+    /// Panics if `buf` isn't `SECTOR_SIZE` bytes long.
+    pub fn read_sector(&mut self, lba: u32, buf: &mut [u8]) -> Result<(), EmmcError> {
+        Emmc::check_buffer("read_sector", 1, buf);
+        self.read_sectors(lba, 1, buf)
+    }
+
+    /// Reads the `count` sectors starting at `lba` into `buf` with a single
+    /// command.
+    ///
+    /// # Panics
+    ///
+    /// Panics if `buf` isn't `count * SECTOR_SIZE` bytes long or `count`
+    /// exceeds `MAX_SECTORS`.
+    pub fn read_sectors(&mut self, lba: u32, count: usize, buf: &mut [u8]) -> Result<(), EmmcError> {
+        Emmc::check_buffer("read_sectors", count, buf);
+        if count == 0 {
+            return Ok(());
+        }
+
+        let cmdtm = if count == 1 { cmd::READ_SINGLE_BLOCK } else { cmd::READ_MULTIPLE_BLOCK };
+        let result = self.start_transfer(cmdtm, lba, count).and_then(|_| {
+            for sector in buf.chunks_mut(SECTOR_SIZE) {
+                self.wait_for(Irpt::ReadReady as u32)?;
+                for chunk in sector.chunks_mut(4) {
+                    let word = self.registers.DATA.read();
+                    for (i, byte) in chunk.iter_mut().enumerate() {
+                        *byte = (word >> (8 * i)) as u8;
+                    }
+                }
+            }
+
+            self.wait_for(Irpt::DataDone as u32)
+        });
+
+        if result.is_err() && count > 1 {
+            self.stop_transmission();
+        }
+
+        result
+    }
+
+    /// Writes `buf` to sector `lba`.
+    ///
+    /// # Panics
+    ///
+    /// Panics if `buf` isn't `SECTOR_SIZE` bytes long.
+    pub fn write_sector(&mut self, lba: u32, buf: &[u8]) -> Result<(), EmmcError> {
+        Emmc::check_buffer("write_sector", 1, buf);
+        self.write_sectors(lba, 1, buf)
+    }
+
+    /// Writes `buf` to the `count` sectors starting at `lba` with a single
+    /// command.
+    ///
+    /// # Panics
+    ///
+    /// Panics if `buf` isn't `count * SECTOR_SIZE` bytes long or `count`
+    /// exceeds `MAX_SECTORS`.
+    pub fn write_sectors(&mut self, lba: u32, count: usize, buf: &[u8]) -> Result<(), EmmcError> {
+        Emmc::check_buffer("write_sectors", count, buf);
+        if count == 0 {
+            return Ok(());
+        }
+
+        let cmdtm = if count == 1 { cmd::WRITE_SINGLE_BLOCK } else { cmd::WRITE_MULTIPLE_BLOCK };
+        let result = self.start_transfer(cmdtm, lba, count).and_then(|_| {
+            for sector in buf.chunks(SECTOR_SIZE) {
+                self.wait_for(Irpt::WriteReady as u32)?;
+                for chunk in sector.chunks(4) {
+                    let word = chunk.iter().enumerate()
+                        .fold(0, |word, (i, &byte)| word | (byte as u32) << (8 * i));
+                    self.registers.DATA.write(word);
+                }
+            }
+
+            self.wait_for(Irpt::DataDone as u32)
+        });
+
+        if result.is_err() && count > 1 {
+            self.stop_transmission();
+        }
+
+        result
+    }
+}
@@ -0,0 +1,186 @@
+use core::sync::atomic::{AtomicIsize, Ordering, ATOMIC_ISIZE_INIT};
+use core::time::Duration;
+
+use button::{Button, ButtonEvent};
+use gpio::{self, Edge, Gpio, Input};
+use timer::Instant;
+
+/// The number of encoders that can be in use at once.
+pub const MAX_ENCODERS: usize = 4;
+
+/// The debounce interval of encoder push buttons.
+const BUTTON_DEBOUNCE_MS: u64 = 10;
+
+/// The change in count for each transition of the A and B levels, indexed
+/// by `previous << 2 | current` with A in bit 1 and B in bit 0. Transitions
+/// skipping a state, from a missed edge, count as 0.
+const TRANSITIONS: [isize; 16] = [
+     0, -1,  1,  0,
+     1,  0,  0, -1,
+    -1,  0,  0,  1,
+     0,  1, -1,  0,
+];
+
+/// An encoder's pins and the last state seen, used by the edge handler.
+struct Quadrature {
+    a: Gpio<Input>,
+    b: Gpio<Input>,
+    pins: (u8, u8),
+    state: usize,
+}
+
+impl Quadrature {
+    /// Returns the current levels of A and B as a state.
+    fn read(&mut self) -> usize {
+        (self.a.level() as usize) << 1 | self.b.level() as usize
+    }
+}
+
+/// The encoders in use, indexed by slot. Only the edge handler touches one
+/// once it's set up.
+static mut ENCODERS: [Option<Quadrature>; MAX_ENCODERS] = [None, None, None, None];
+
+/// The transitions counted for each slot's encoder, clockwise positive.
+static COUNTS: [AtomicIsize; MAX_ENCODERS] = [
+    ATOMIC_ISIZE_INIT, ATOMIC_ISIZE_INIT, ATOMIC_ISIZE_INIT, ATOMIC_ISIZE_INIT,
+];
+
+/// Counts the transition of the encoder with a pin `pin`.
+fn on_edge(pin: u8) {
+    for slot in 0..MAX_ENCODERS {
+        let encoder = match unsafe { ENCODERS[slot].as_mut() } {
+            Some(encoder) if encoder.pins.0 == pin || encoder.pins.1 == pin => encoder,
+            _ => continue
+        };
+
+        let state = encoder.read();
+        let change = TRANSITIONS[encoder.state << 2 | state];
+        encoder.state = state;
+        COUNTS[slot].fetch_add(change, Ordering::Relaxed);
+        return;
+    }
+}
+
+/// A quadrature rotary encoder with its common pin grounded and A and B on
+/// GPIO pins, read through their pull-ups, optionally with a push button.
+///
+/// Every edge on A or B is decoded from the GPIO interrupt, so
+/// `gpio::handle_irq()` must be called for `Interrupt::Gpio3`.
+pub struct Encoder {
+    slot: usize,
+    steps_per_detent: isize,
+    button: Option<Button>,
+    /// The count and time at the last `velocity()` call.
+    sampled: (isize, Instant),
+}
+
+impl Encoder {
+    /// Returns the encoder with A on pin `a` and B on pin `b`, counting
+    /// `steps_per_detent` transitions as one step: 4 for most encoders, or 2
+    /// or 1 for those with detents at every half or quarter cycle. Returns
+    /// `None` if `MAX_ENCODERS` are already in use.
+    ///
+    /// # Panics
+    ///
+    /// Panics if `a` or `b` > `53`, or `steps_per_detent` is 0.
+    pub fn new(a: u8, b: u8, steps_per_detent: u8) -> Option<Encoder> {
+        if steps_per_detent == 0 {
+            panic!("Encoder: steps_per_detent is 0");
+        }
+
+        let slot = unsafe { ENCODERS.iter().position(|encoder| encoder.is_none())? };
+
+        let mut quadrature = Quadrature {
+            a: Gpio::new(a).into_input_pullup(),
+            b: Gpio::new(b).into_input_pullup(),
+            pins: (a, b),
+            state: 0,
+        };
+        gpio::claim(&[a, b], "encoder");
+
+        quadrature.state = quadrature.read();
+        quadrature.a.enable_edge_detect(Edge::Both);
+        quadrature.b.enable_edge_detect(Edge::Both);
+
+        COUNTS[slot].store(0, Ordering::Relaxed);
+        unsafe { ENCODERS[slot] = Some(quadrature); }
+        gpio::register_edge_handler(a, on_edge);
+        gpio::register_edge_handler(b, on_edge);
+
+        Some(Encoder {
+            slot,
+            steps_per_detent: steps_per_detent as isize,
+            button: None,
+            sampled: (0, Instant::now()),
+        })
+    }
+
+    /// Adds the encoder's push button, connecting pin `pin` to ground.
+    ///
+    /// # Panics
+    ///
+    /// Panics if `pin` > `53`.
+    pub fn with_button(mut self, pin: u8) -> Encoder {
+        self.button = Some(Button::new(pin, Duration::from_millis(BUTTON_DEBOUNCE_MS)));
+        self
+    }
+
+    /// Returns the transitions counted since the encoder was created or last
+    /// reset.
+    fn count(&self) -> isize {
+        COUNTS[self.slot].load(Ordering::Relaxed)
+    }
+
+    /// Returns the steps turned since the encoder was created or last reset,
+    /// clockwise positive. Partly turned steps aren't counted.
+    pub fn position(&self) -> i32 {
+        (self.count() / self.steps_per_detent) as i32
+    }
+
+    /// Makes the current position 0.
+    pub fn reset(&mut self) {
+        COUNTS[self.slot].store(0, Ordering::Relaxed);
+        self.sampled = (0, Instant::now());
+    }
+
+    /// Returns the average speed since the last call, or since the encoder
+    /// was created or reset, in steps per second, clockwise positive.
+    pub fn velocity(&mut self) -> i32 {
+        let (count, now) = (self.count(), Instant::now());
+        let (last_count, last) = self.sampled;
+        self.sampled = (count, now);
+
+        let elapsed = now.duration_since(last);
+        let micros = elapsed.as_secs() * 1_000_000 + elapsed.subsec_micros() as u64;
+        if micros == 0 {
+            return 0;
+        }
+
+        let steps = (count - last_count) as i64 * 1_000_000 / self.steps_per_detent as i64;
+        (steps / micros as i64) as i32
+    }
+
+    /// Returns the change in the push button's debounced state, if there is
+    /// a button and it changed. Must be called regularly to see every press.
+    pub fn poll_button(&mut self) -> Option<ButtonEvent> {
+        self.button.as_mut().and_then(|button| button.poll())
+    }
+
+    /// Returns `true` if there is a push button and it's pressed.
+    pub fn is_pressed(&mut self) -> bool {
+        self.button.as_mut().map_or(false, |button| button.is_pressed())
+    }
+}
+
+impl Drop for Encoder {
+    /// Stops decoding, freeing the encoder's slot.
+    fn drop(&mut self) {
+        if let Some(mut quadrature) = unsafe { ENCODERS[self.slot].take() } {
+            gpio::unregister_edge_handler(quadrature.pins.0);
+            gpio::unregister_edge_handler(quadrature.pins.1);
+            quadrature.a.disable_edge_detect();
+            quadrature.b.disable_edge_detect();
+            gpio::release_owned(&[quadrature.pins.0, quadrature.pins.1], "encoder");
+        }
+    }
+}
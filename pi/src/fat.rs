@@ -0,0 +1,1062 @@
+use core::cmp;
+
+use emmc::{Emmc, EmmcError, SECTOR_SIZE};
+use rtc::DateTime;
+
+/// MBR partition types of FAT16 and FAT32 file systems.
+const FAT_PARTITION_TYPES: [u8; 5] = [0x04, 0x06, 0x0B, 0x0C, 0x0E];
+
+/// The offset of the MBR's first partition entry, and the signature ending
+/// both the MBR and boot sectors.
+const PARTITION_TABLE: usize = 446;
+const SIGNATURE: [u8; 2] = [0x55, 0xAA];
+
+/// Directory entry attributes and layout.
+const ENTRY_SIZE: usize = 32;
+const ATTR_HIDDEN: u8 = 0x02;
+const ATTR_ARCHIVE: u8 = 0x20;
+const ATTR_VOLUME_ID: u8 = 0x08;
+const ATTR_DIRECTORY: u8 = 0x10;
+/// The attributes marking a long file name entry.
+const ATTR_LONG_NAME: u8 = 0x0F;
+const DELETED: u8 = 0xE5;
+
+/// The flags in byte 12 of an entry marking its base name and extension as
+/// lower case.
+const LOWER_BASE: u8 = 0x08;
+const LOWER_EXTENSION: u8 = 0x10;
+
+/// The signature opening FAT32's FSInfo sector, and the offsets of its free
+/// cluster count and next free cluster hints.
+const FS_INFO_SIGNATURE: u32 = 0x4161_5252;
+const FS_INFO_FREE_COUNT: usize = 488;
+const FS_INFO_NEXT_FREE: usize = 492;
+
+/// The characters 8.3 names can't hold, besides spaces and control
+/// characters.
+const INVALID_NAME_CHARS: &[u8] = b"\"*+,/:;<=>?[\\]|";
+
+/// The fewest clusters a FAT16 and a FAT32 file system have.
+const MIN_FAT16_CLUSTERS: u32 = 4085;
+const MIN_FAT32_CLUSTERS: u32 = 65525;
+
+/// An error reading or writing a file or directory.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum FatError {
+    Emmc(EmmcError),
+    /// The card has no FAT16 or FAT32 file system, or it's corrupt.
+    NoFileSystem,
+    /// There is no file or directory at that path.
+    NotFound,
+    /// A path names a file where a directory is needed.
+    NotADirectory,
+    /// A path names a directory where a file is needed.
+    IsADirectory,
+    /// The file doesn't fit in the buffer.
+    TooLarge,
+    /// A name to create or remove isn't a valid 8.3 name, or is `.` or `..`.
+    InvalidName,
+    /// Something already exists at the path to create.
+    AlreadyExists,
+    /// A directory to remove isn't empty, or is the root.
+    DirectoryNotEmpty,
+    /// The file system has no free clusters left, or FAT16's fixed root
+    /// directory has no free entries left.
+    NoSpace,
+}
+
+impl From<EmmcError> for FatError {
+    fn from(error: EmmcError) -> FatError {
+        FatError::Emmc(error)
+    }
+}
+
+fn read_u16(bytes: &[u8], offset: usize) -> u16 {
+    bytes[offset] as u16 | (bytes[offset + 1] as u16) << 8
+}
+
+fn read_u32(bytes: &[u8], offset: usize) -> u32 {
+    read_u16(bytes, offset) as u32 | (read_u16(bytes, offset + 2) as u32) << 16
+}
+
+fn write_u16(bytes: &mut [u8], offset: usize, value: u16) {
+    bytes[offset] = value as u8;
+    bytes[offset + 1] = (value >> 8) as u8;
+}
+
+fn write_u32(bytes: &mut [u8], offset: usize, value: u32) {
+    write_u16(bytes, offset, value as u16);
+    write_u16(bytes, offset + 2, (value >> 16) as u16);
+}
+
+/// Returns `time` packed as a directory entry's date and time.
+fn pack_time(time: &DateTime) -> (u16, u16) {
+    let date = (time.year.saturating_sub(1980) as u16) << 9 | (time.month as u16) << 5
+        | time.day as u16;
+    let time = (time.hour as u16) << 11 | (time.minute as u16) << 5 | (time.second / 2) as u16;
+    (date, time)
+}
+
+/// Where an entry is stored: the sector holding it and its offset in there.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+struct Location {
+    lba: u32,
+    offset: usize,
+}
+
+/// Where a directory's entries are.
+#[derive(Debug, Clone, Copy)]
+enum Dir {
+    /// FAT16's fixed root directory area of `sectors` sectors from `start`.
+    Fixed { start: u32, sectors: u32 },
+    /// The cluster chain from cluster `cluster`: FAT32's root directory, and
+    /// every other directory.
+    Chain(u32),
+}
+
+/// An entry in a directory: a file or a subdirectory.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct Entry {
+    name: [u8; 12],
+    name_len: usize,
+    attributes: u8,
+    /// The first cluster, or 0 for empty files and the root directory.
+    cluster: u32,
+    /// The file's size in bytes; 0 for directories.
+    pub size: u32,
+    /// The date and time last written, packed as on disk.
+    date: u16,
+    time: u16,
+    /// Where the entry is stored, or `None` for the root directory.
+    location: Option<Location>,
+}
+
+impl Entry {
+    /// The root directory, which has no entry of its own.
+    fn root() -> Entry {
+        let mut name = [0; 12];
+        name[0] = b'/';
+        Entry {
+            name,
+            name_len: 1,
+            attributes: ATTR_DIRECTORY,
+            cluster: 0,
+            size: 0,
+            date: 0,
+            time: 0,
+            location: None,
+        }
+    }
+
+    /// Parses the 32 byte directory entry `raw`, stored at `location`.
+    fn parse(raw: &[u8], location: Location) -> Entry {
+        let mut entry = Entry {
+            name: [0; 12],
+            name_len: 0,
+            attributes: raw[11],
+            cluster: (read_u16(raw, 20) as u32) << 16 | read_u16(raw, 26) as u32,
+            size: read_u32(raw, 28),
+            date: read_u16(raw, 24),
+            time: read_u16(raw, 22),
+            location: Some(location),
+        };
+
+        // The space padding is dropped, and the name shown in the case the
+        // flags record.
+        let parts = [(&raw[..8], raw[12] & LOWER_BASE != 0),
+                     (&raw[8..11], raw[12] & LOWER_EXTENSION != 0)];
+        for (i, &(part, lower)) in parts.iter().enumerate() {
+            let len = part.iter().rposition(|&byte| byte != b' ').map_or(0, |end| end + 1);
+            if i == 1 && len > 0 {
+                entry.name[entry.name_len] = b'.';
+                entry.name_len += 1;
+            }
+
+            for &byte in &part[..len] {
+                entry.name[entry.name_len] = if lower { byte.to_ascii_lowercase() } else { byte };
+                entry.name_len += 1;
+            }
+        }
+
+        entry
+    }
+
+    /// Returns the entry's name, like `KERNEL8.IMG`, or `"?"` if it isn't
+    /// valid UTF-8.
+    pub fn name(&self) -> &str {
+        ::core::str::from_utf8(&self.name[..self.name_len]).unwrap_or("?")
+    }
+
+    /// Returns `true` if the entry is a directory.
+    pub fn is_dir(&self) -> bool {
+        self.attributes & ATTR_DIRECTORY != 0
+    }
+
+    /// Returns `true` if the entry is marked hidden.
+    pub fn is_hidden(&self) -> bool {
+        self.attributes & ATTR_HIDDEN != 0
+    }
+
+    /// Returns when the entry was last written, to the 2 seconds FAT keeps.
+    pub fn modified(&self) -> DateTime {
+        DateTime {
+            year: 1980 + (self.date >> 9),
+            month: (self.date >> 5 & 0xF) as u8,
+            day: (self.date & 0x1F) as u8,
+            hour: (self.time >> 11) as u8,
+            minute: (self.time >> 5 & 0x3F) as u8,
+            second: (self.time & 0x1F) as u8 * 2,
+        }
+    }
+}
+
+/// A FAT16 or FAT32 file system's layout.
+struct Volume {
+    fat_start: u32,
+    fat_size: u32,
+    fats: u32,
+    sectors_per_cluster: u32,
+    data_start: u32,
+    /// The number of data clusters, numbered from 2.
+    clusters: u32,
+    root: Dir,
+    fat32: bool,
+    /// FAT32's FSInfo sector, if it has one whose hints are still to be
+    /// invalidated before the first change to the FAT.
+    fs_info: Option<u32>,
+    /// Where the search for a free cluster starts.
+    next_free: u32,
+}
+
+impl Volume {
+    /// Reads the layout of the file system on the card's first FAT
+    /// partition, or on the whole card if it has no partition table.
+    fn open(emmc: &mut Emmc, sector: &mut [u8]) -> Result<Volume, FatError> {
+        emmc.read_sector(0, sector)?;
+        if sector[510..] != SIGNATURE {
+            return Err(FatError::NoFileSystem);
+        }
+
+        let start = match sector[PARTITION_TABLE + 4] {
+            kind if FAT_PARTITION_TYPES.contains(&kind) => read_u32(sector, PARTITION_TABLE + 8),
+            _ => 0
+        };
+
+        if start != 0 {
+            emmc.read_sector(start, sector)?;
+        }
+
+        let sectors_per_cluster = sector[13] as u32;
+        if read_u16(sector, 11) as usize != SECTOR_SIZE || sectors_per_cluster == 0
+            || sector[510..] != SIGNATURE {
+            return Err(FatError::NoFileSystem);
+        }
+
+        let reserved = read_u16(sector, 14) as u32;
+        let fats = sector[16] as u32;
+        let root_entries = read_u16(sector, 17) as u32;
+        let total = match read_u16(sector, 19) {
+            0 => read_u32(sector, 32),
+            total => total as u32
+        };
+
+        let fat_size = match read_u16(sector, 22) {
+            0 => read_u32(sector, 36),
+            size => size as u32
+        };
+
+        let fat_start = start + reserved;
+        let root_sectors = (root_entries * ENTRY_SIZE as u32 + SECTOR_SIZE as u32 - 1)
+            / SECTOR_SIZE as u32;
+        let root_start = fat_start + fats * fat_size;
+        let data_start = root_start + root_sectors;
+
+        let clusters = total.saturating_sub(data_start - start) / sectors_per_cluster;
+        let (fat32, root) = if clusters >= MIN_FAT32_CLUSTERS {
+            (true, Dir::Chain(read_u32(sector, 44)))
+        } else if clusters >= MIN_FAT16_CLUSTERS {
+            (false, Dir::Fixed { start: root_start, sectors: root_sectors })
+        } else {
+            // FAT12 is only found on tiny volumes.
+            return Err(FatError::NoFileSystem);
+        };
+
+        let fs_info = match read_u16(sector, 48) as u32 {
+            0 | 0xFFFF => None,
+            fs_info if fat32 => Some(start + fs_info),
+            _ => None
+        };
+
+        Ok(Volume {
+            fat_start,
+            fat_size,
+            fats,
+            sectors_per_cluster,
+            data_start,
+            clusters,
+            root,
+            fat32,
+            fs_info,
+            next_free: 2,
+        })
+    }
+
+    /// Returns the first sector of cluster `cluster`.
+    fn cluster_start(&self, cluster: u32) -> u32 {
+        self.data_start + (cluster - 2) * self.sectors_per_cluster
+    }
+
+    /// Returns the size of a cluster in bytes.
+    fn cluster_size(&self) -> usize {
+        self.sectors_per_cluster as usize * SECTOR_SIZE
+    }
+
+    /// Returns the sector of the first FAT holding `cluster`'s entry,
+    /// counted from the FAT's start, and the entry's offset in there.
+    fn fat_entry(&self, cluster: u32) -> (u32, usize) {
+        let offset = cluster as usize * if self.fat32 { 4 } else { 2 };
+        ((offset / SECTOR_SIZE) as u32, offset % SECTOR_SIZE)
+    }
+
+    /// Returns the FAT entry at `offset` in the FAT sector `sector`.
+    fn fat_value(&self, sector: &[u8], offset: usize) -> u32 {
+        if self.fat32 {
+            read_u32(sector, offset) & 0x0FFF_FFFF
+        } else {
+            read_u16(sector, offset) as u32
+        }
+    }
+
+    /// Returns the FAT entry marking the end of a chain.
+    fn end_of_chain(&self) -> u32 {
+        if self.fat32 { 0x0FFF_FFFF } else { 0xFFFF }
+    }
+
+    /// Returns the cluster after `cluster` in its chain, or `None` at the
+    /// chain's end.
+    fn next_cluster(&self,
+                    emmc: &mut Emmc,
+                    sector: &mut [u8],
+                    cluster: u32) -> Result<Option<u32>, FatError> {
+        let (index, offset) = self.fat_entry(cluster);
+        emmc.read_sector(self.fat_start + index, sector)?;
+
+        let next = self.fat_value(sector, offset);
+        let end = self.end_of_chain() & !7;
+        match next {
+            _ if next >= end => Ok(None),
+            0 | 1 => Err(FatError::NoFileSystem),
+            _ if next >= end - 1 => Err(FatError::NoFileSystem),
+            next => Ok(Some(next))
+        }
+    }
+
+    /// Returns where the entries of the directory `entry` are.
+    fn dir(&self, entry: &Entry) -> Dir {
+        match entry.cluster {
+            // `..` entries in the root's subdirectories point at cluster 0.
+            0 => self.root,
+            cluster => Dir::Chain(cluster)
+        }
+    }
+
+    /// Calls `f` with each sector of `dir` and its address in turn until it
+    /// returns `Some`, and returns what that holds: `f` returns `Some(None)`
+    /// once it sees the directory's end.
+    fn find_in_dir<T, F>(&self,
+                         emmc: &mut Emmc,
+                         sector: &mut [u8],
+                         dir: Dir,
+                         mut f: F) -> Result<Option<T>, FatError>
+        where F: FnMut(u32, &[u8]) -> Option<Option<T>>
+    {
+        match dir {
+            Dir::Fixed { start, sectors } => {
+                for lba in start..start + sectors {
+                    emmc.read_sector(lba, sector)?;
+                    if let Some(found) = f(lba, sector) {
+                        return Ok(found);
+                    }
+                }
+            }
+            Dir::Chain(first) => {
+                let mut cluster = Some(first);
+                while let Some(current) = cluster {
+                    let start = self.cluster_start(current);
+                    for lba in start..start + self.sectors_per_cluster {
+                        emmc.read_sector(lba, sector)?;
+                        if let Some(found) = f(lba, sector) {
+                            return Ok(found);
+                        }
+                    }
+
+                    cluster = self.next_cluster(emmc, sector, current)?;
+                }
+            }
+        }
+
+        Ok(None)
+    }
+
+    /// Calls `f` with each file and subdirectory in `dir`, including `.`
+    /// and `..`, until it returns `Some`, and returns what that holds.
+    fn find_entry<T, F>(&self,
+                        emmc: &mut Emmc,
+                        sector: &mut [u8],
+                        dir: Dir,
+                        mut f: F) -> Result<Option<T>, FatError>
+        where F: FnMut(&[u8], Location) -> Option<T>
+    {
+        self.find_in_dir(emmc, sector, dir, |lba, sector| {
+            for (i, raw) in sector.chunks(ENTRY_SIZE).enumerate() {
+                match raw[0] {
+                    0 => return Some(None),
+                    DELETED => continue,
+                    _ if raw[11] & ATTR_LONG_NAME == ATTR_LONG_NAME => continue,
+                    _ if raw[11] & ATTR_VOLUME_ID != 0 => continue,
+                    _ => if let Some(found) = f(raw, Location { lba, offset: i * ENTRY_SIZE }) {
+                        return Some(Some(found));
+                    }
+                }
+            }
+
+            None
+        })
+    }
+
+    /// Returns the entry at `path`, whose components are separated by `/`
+    /// and are `.`, `..` or 8.3 names, from the root directory.
+    fn lookup(&self, emmc: &mut Emmc, sector: &mut [u8], path: &str) -> Result<Entry, FatError> {
+        let mut entry = Entry::root();
+        for component in path.split('/').filter(|component| !component.is_empty()) {
+            if !entry.is_dir() {
+                return Err(FatError::NotADirectory);
+            }
+
+            // The root has no `.` or `..` of its own.
+            if entry.cluster == 0 && (component == "." || component == "..") {
+                continue;
+            }
+
+            let short = short_name(component).ok_or(FatError::NotFound)?;
+            let dir = self.dir(&entry);
+            let found = self.find_entry(emmc, sector, dir, |raw, location| {
+                if raw[..11] == short { Some(Entry::parse(raw, location)) } else { None }
+            })?;
+
+            entry = found.ok_or(FatError::NotFound)?;
+            if entry.cluster == 0 && entry.is_dir() {
+                entry = Entry::root();
+            }
+        }
+
+        Ok(entry)
+    }
+
+    /// Returns the directory holding `path`'s last component, and that
+    /// component.
+    fn parent<'a>(&self,
+                  emmc: &mut Emmc,
+                  sector: &mut [u8],
+                  path: &'a str) -> Result<(Entry, &'a str), FatError> {
+        let mut parts = path.trim_right_matches('/').rsplitn(2, '/');
+        let name = parts.next().unwrap_or("");
+        let parent = self.lookup(emmc, sector, parts.next().unwrap_or(""))?;
+        if !parent.is_dir() {
+            return Err(FatError::NotADirectory);
+        }
+
+        Ok((parent, name))
+    }
+
+    /// Marks FAT32's FSInfo free cluster hints unknown, if that's still to
+    /// be done, so they needn't be kept up to date as clusters are
+    /// allocated and freed.
+    fn invalidate_fs_info(&mut self, emmc: &mut Emmc, sector: &mut [u8]) -> Result<(), FatError> {
+        if let Some(lba) = self.fs_info.take() {
+            emmc.read_sector(lba, sector)?;
+            if read_u32(sector, 0) == FS_INFO_SIGNATURE {
+                write_u32(sector, FS_INFO_FREE_COUNT, 0xFFFF_FFFF);
+                write_u32(sector, FS_INFO_NEXT_FREE, 0xFFFF_FFFF);
+                emmc.write_sector(lba, sector)?;
+            }
+        }
+
+        Ok(())
+    }
+
+    /// Sets `cluster`'s entry in every copy of the FAT to `value`.
+    fn set_fat(&mut self,
+               emmc: &mut Emmc,
+               sector: &mut [u8],
+               cluster: u32,
+               value: u32) -> Result<(), FatError> {
+        self.invalidate_fs_info(emmc, sector)?;
+
+        let (index, offset) = self.fat_entry(cluster);
+        for copy in 0..self.fats {
+            let lba = self.fat_start + copy * self.fat_size + index;
+            emmc.read_sector(lba, sector)?;
+            if self.fat32 {
+                // The top 4 bits are reserved, and kept as they are.
+                let value = read_u32(sector, offset) & 0xF000_0000 | value & 0x0FFF_FFFF;
+                write_u32(sector, offset, value);
+            } else {
+                write_u16(sector, offset, value as u16);
+            }
+
+            emmc.write_sector(lba, sector)?;
+        }
+
+        Ok(())
+    }
+
+    /// Allocates a free cluster as the end of the chain ending at `last`,
+    /// or of a new chain if `last` is 0, and returns it.
+    fn alloc_cluster(&mut self,
+                     emmc: &mut Emmc,
+                     sector: &mut [u8],
+                     last: u32) -> Result<u32, FatError> {
+        let end = self.clusters + 2;
+        let start = cmp::min(self.next_free, end);
+        let mut loaded = None;
+        let mut free = None;
+        for cluster in (start..end).chain(2..start) {
+            let (index, offset) = self.fat_entry(cluster);
+            if loaded != Some(index) {
+                emmc.read_sector(self.fat_start + index, sector)?;
+                loaded = Some(index);
+            }
+
+            if self.fat_value(sector, offset) == 0 {
+                free = Some(cluster);
+                break;
+            }
+        }
+
+        let free = free.ok_or(FatError::NoSpace)?;
+        let end_of_chain = self.end_of_chain();
+        self.set_fat(emmc, sector, free, end_of_chain)?;
+        if last != 0 {
+            self.set_fat(emmc, sector, last, free)?;
+        }
+
+        self.next_free = free + 1;
+        Ok(free)
+    }
+
+    /// Returns the cluster after `cluster` in its chain, allocating one if
+    /// the chain ends there.
+    fn next_or_alloc(&mut self,
+                     emmc: &mut Emmc,
+                     sector: &mut [u8],
+                     cluster: u32) -> Result<u32, FatError> {
+        match self.next_cluster(emmc, sector, cluster)? {
+            Some(next) => Ok(next),
+            None => self.alloc_cluster(emmc, sector, cluster)
+        }
+    }
+
+    /// Frees the chain of clusters from `first`, if that's not 0.
+    fn free_chain(&mut self,
+                  emmc: &mut Emmc,
+                  sector: &mut [u8],
+                  first: u32) -> Result<(), FatError> {
+        let mut cluster = if first == 0 { None } else { Some(first) };
+        while let Some(current) = cluster {
+            cluster = self.next_cluster(emmc, sector, current)?;
+            self.set_fat(emmc, sector, current, 0)?;
+            self.next_free = cmp::min(self.next_free, current);
+        }
+
+        Ok(())
+    }
+
+    /// Fills cluster `cluster` with zeroes.
+    fn zero_cluster(&self,
+                    emmc: &mut Emmc,
+                    sector: &mut [u8],
+                    cluster: u32) -> Result<(), FatError> {
+        for byte in sector.iter_mut() {
+            *byte = 0;
+        }
+
+        let start = self.cluster_start(cluster);
+        for lba in start..start + self.sectors_per_cluster {
+            emmc.write_sector(lba, sector)?;
+        }
+
+        Ok(())
+    }
+
+    /// Writes `data` at byte `offset` of the chain from `first`, or of a new
+    /// chain if that's 0, growing the chain as needed, and returns the
+    /// chain's first cluster. The chain must already reach `offset`.
+    fn write_data(&mut self,
+                  emmc: &mut Emmc,
+                  sector: &mut [u8],
+                  first: u32,
+                  offset: usize,
+                  data: &[u8]) -> Result<u32, FatError> {
+        if data.is_empty() {
+            return Ok(first);
+        }
+
+        let first = match first {
+            0 => self.alloc_cluster(emmc, sector, 0)?,
+            first => first
+        };
+
+        let cluster_size = self.cluster_size();
+        let mut cluster = first;
+        for _ in 0..offset / cluster_size {
+            cluster = self.next_or_alloc(emmc, sector, cluster)?;
+        }
+
+        let (mut position, mut written) = (offset % cluster_size, 0);
+        loop {
+            let lba = self.cluster_start(cluster) + (position / SECTOR_SIZE) as u32;
+            let start = position % SECTOR_SIZE;
+            let left = data.len() - written;
+
+            // Whole sectors go straight from `data` in one transfer; a sector
+            // written in part is read first to keep the rest of it.
+            let whole = (cluster_size - position) / SECTOR_SIZE;
+            let len = if start == 0 && left >= SECTOR_SIZE {
+                let len = cmp::min(whole, left / SECTOR_SIZE) * SECTOR_SIZE;
+                emmc.write_sectors(lba, len / SECTOR_SIZE, &data[written..written + len])?;
+                len
+            } else {
+                let len = cmp::min(SECTOR_SIZE - start, left);
+                emmc.read_sector(lba, sector)?;
+                sector[start..start + len].copy_from_slice(&data[written..written + len]);
+                emmc.write_sector(lba, sector)?;
+                len
+            };
+
+            written += len;
+            position += len;
+            if written == data.len() {
+                return Ok(first);
+            }
+
+            if position == cluster_size {
+                cluster = self.next_or_alloc(emmc, sector, cluster)?;
+                position = 0;
+            }
+        }
+    }
+
+    /// Calls `f` with the 32 bytes of the entry at `location`, and writes
+    /// them back.
+    fn write_entry<F>(&self,
+                      emmc: &mut Emmc,
+                      sector: &mut [u8],
+                      location: Location,
+                      f: F) -> Result<(), FatError>
+        where F: FnOnce(&mut [u8])
+    {
+        emmc.read_sector(location.lba, sector)?;
+        f(&mut sector[location.offset..location.offset + ENTRY_SIZE]);
+        emmc.write_sector(location.lba, sector)?;
+        Ok(())
+    }
+
+    /// Writes `entry`'s first cluster and size back to its directory, and
+    /// records it as last written at `modified`.
+    fn update(&self,
+              emmc: &mut Emmc,
+              sector: &mut [u8],
+              entry: &Entry,
+              modified: &DateTime) -> Result<(), FatError> {
+        let location = match entry.location {
+            Some(location) => location,
+            None => return Ok(())
+        };
+
+        let (date, time) = pack_time(modified);
+        self.write_entry(emmc, sector, location, |raw| {
+            write_u16(raw, 20, (entry.cluster >> 16) as u16);
+            write_u16(raw, 26, entry.cluster as u16);
+            write_u32(raw, 28, entry.size);
+            write_u16(raw, 22, time);
+            write_u16(raw, 24, date);
+            raw[11] |= if entry.is_dir() { 0 } else { ATTR_ARCHIVE };
+        })
+    }
+
+    /// Stores the 32 byte entry `raw` in the first free slot of `dir`,
+    /// growing the directory by a cluster if it has none, and returns where.
+    fn add_entry(&mut self,
+                 emmc: &mut Emmc,
+                 sector: &mut [u8],
+                 dir: Dir,
+                 raw: &[u8]) -> Result<Location, FatError> {
+        let free = self.find_in_dir(emmc, sector, dir, |lba, sector| {
+            sector.chunks(ENTRY_SIZE)
+                .position(|raw| raw[0] == 0 || raw[0] == DELETED)
+                .map(|i| Some(Location { lba, offset: i * ENTRY_SIZE }))
+        })?;
+
+        let location = match (free, dir) {
+            (Some(location), _) => location,
+            (None, Dir::Fixed { .. }) => return Err(FatError::NoSpace),
+            (None, Dir::Chain(first)) => {
+                let mut last = first;
+                while let Some(next) = self.next_cluster(emmc, sector, last)? {
+                    last = next;
+                }
+
+                let cluster = self.alloc_cluster(emmc, sector, last)?;
+                self.zero_cluster(emmc, sector, cluster)?;
+                Location { lba: self.cluster_start(cluster), offset: 0 }
+            }
+        };
+
+        self.write_entry(emmc, sector, location, |entry| entry.copy_from_slice(raw))?;
+        Ok(location)
+    }
+
+    /// Creates an entry named `name` in the directory `parent`, with
+    /// `attributes` and first cluster `cluster`, and returns it.
+    fn create(&mut self,
+              emmc: &mut Emmc,
+              sector: &mut [u8],
+              parent: &Entry,
+              name: &str,
+              attributes: u8,
+              modified: &DateTime) -> Result<Entry, FatError> {
+        let short = match short_name(name) {
+            Some(short) if name != "." && name != ".." => short,
+            _ => return Err(FatError::InvalidName)
+        };
+
+        let dir = self.dir(parent);
+        let exists = self.find_entry(emmc, sector, dir, |raw, _| {
+            if raw[..11] == short { Some(()) } else { None }
+        })?;
+
+        if exists.is_some() {
+            return Err(FatError::AlreadyExists);
+        }
+
+        let raw = new_entry(&short, case_flags(name), attributes, 0, modified);
+        let location = self.add_entry(emmc, sector, dir, &raw)?;
+        Ok(Entry::parse(&raw, location))
+    }
+
+    /// Returns the file at `path`, creating it empty if there's none.
+    fn open_file(&mut self,
+                 emmc: &mut Emmc,
+                 sector: &mut [u8],
+                 path: &str,
+                 modified: &DateTime) -> Result<Entry, FatError> {
+        let entry = match self.lookup(emmc, sector, path) {
+            Err(FatError::NotFound) => {
+                let (parent, name) = self.parent(emmc, sector, path)?;
+                self.create(emmc, sector, &parent, name, ATTR_ARCHIVE, modified)?
+            }
+            entry => entry?
+        };
+
+        if entry.is_dir() {
+            return Err(FatError::IsADirectory);
+        }
+
+        Ok(entry)
+    }
+
+    /// Marks the entry at `location` deleted, along with the long file name
+    /// entries before it in the same sector. Any in the sector before are
+    /// left as orphans for a disk check to clear.
+    fn delete_entry(&self,
+                    emmc: &mut Emmc,
+                    sector: &mut [u8],
+                    location: Location) -> Result<(), FatError> {
+        emmc.read_sector(location.lba, sector)?;
+        sector[location.offset] = DELETED;
+
+        let mut offset = location.offset;
+        while offset >= ENTRY_SIZE {
+            offset -= ENTRY_SIZE;
+            let raw = &mut sector[offset..offset + ENTRY_SIZE];
+            if raw[0] == DELETED || raw[11] & ATTR_LONG_NAME != ATTR_LONG_NAME {
+                break;
+            }
+
+            raw[0] = DELETED;
+        }
+
+        emmc.write_sector(location.lba, sector)?;
+        Ok(())
+    }
+}
+
+/// Returns `name`, like `kernel8.img`, `.` or `..`, as a directory entry's
+/// space-padded upper case 8.3 name, or `None` if it isn't a valid short
+/// name.
+fn short_name(name: &str) -> Option<[u8; 11]> {
+    if name == "." || name == ".." {
+        let mut short = [b' '; 11];
+        short[..name.len()].copy_from_slice(name.as_bytes());
+        return Some(short);
+    }
+
+    let mut parts = name.splitn(2, '.');
+    let (base, extension) = (parts.next()?, parts.next().unwrap_or(""));
+    if base.is_empty() || base.len() > 8 || extension.len() > 3 || extension.contains('.') {
+        return None;
+    }
+
+    if name.bytes().any(|byte| byte <= b' ' || byte >= 0x7F || INVALID_NAME_CHARS.contains(&byte)) {
+        return None;
+    }
+
+    let mut short = [b' '; 11];
+    for (i, byte) in base.bytes().enumerate() {
+        short[i] = byte.to_ascii_uppercase();
+    }
+
+    for (i, byte) in extension.bytes().enumerate() {
+        short[8 + i] = byte.to_ascii_uppercase();
+    }
+
+    Some(short)
+}
+
+/// Returns the flags recording that `name`'s base name or extension is all
+/// lower case, so it's listed as it was given.
+fn case_flags(name: &str) -> u8 {
+    fn is_lower(part: &str) -> bool {
+        part.bytes().any(|byte| byte >= b'a' && byte <= b'z')
+            && !part.bytes().any(|byte| byte >= b'A' && byte <= b'Z')
+    }
+
+    let mut parts = name.splitn(2, '.');
+    let base = if parts.next().map_or(false, is_lower) { LOWER_BASE } else { 0 };
+    let extension = if parts.next().map_or(false, is_lower) { LOWER_EXTENSION } else { 0 };
+    base | extension
+}
+
+/// Returns a new directory entry with the 8.3 name `short`, created and
+/// last written at `modified`.
+fn new_entry(short: &[u8; 11],
+             case: u8,
+             attributes: u8,
+             cluster: u32,
+             modified: &DateTime) -> [u8; ENTRY_SIZE] {
+    let mut raw = [0; ENTRY_SIZE];
+    raw[..11].copy_from_slice(short);
+    raw[11] = attributes;
+    raw[12] = case;
+
+    let (date, time) = pack_time(modified);
+    for &(offset, value) in &[(14, time), (16, date), (18, date), (22, time), (24, date)] {
+        write_u16(&mut raw, offset, value);
+    }
+
+    write_u16(&mut raw, 20, (cluster >> 16) as u16);
+    write_u16(&mut raw, 26, cluster as u16);
+    raw
+}
+
+/// Returns the entry of the file or directory at `path`: `/` separated 8.3
+/// names, from the root directory of the card's FAT16 or FAT32 file system.
+pub fn stat(emmc: &mut Emmc, path: &str) -> Result<Entry, FatError> {
+    let mut sector = [0u8; SECTOR_SIZE];
+    let volume = Volume::open(emmc, &mut sector)?;
+    volume.lookup(emmc, &mut sector, path)
+}
+
+/// Calls `f` with each file and subdirectory, including `.` and `..`, in the
+/// directory at `path` (see `stat()`).
+pub fn read_dir<F: FnMut(&Entry)>(emmc: &mut Emmc, path: &str, mut f: F) -> Result<(), FatError> {
+    let mut sector = [0u8; SECTOR_SIZE];
+    let volume = Volume::open(emmc, &mut sector)?;
+    let dir = volume.lookup(emmc, &mut sector, path)?;
+    if !dir.is_dir() {
+        return Err(FatError::NotADirectory);
+    }
+
+    let dir = volume.dir(&dir);
+    volume.find_entry(emmc, &mut sector, dir, |raw, location| -> Option<()> {
+        f(&Entry::parse(raw, location));
+        None
+    })?;
+
+    Ok(())
+}
+
+/// Reads the file at `path` (see `stat()`), like `kernel8.img` or
+/// `logs/boot.txt`, from the card's FAT16 or FAT32 file system into
+/// `output`, and returns its length.
+pub fn load(emmc: &mut Emmc, path: &str, output: &mut [u8]) -> Result<usize, FatError> {
+    let mut sector = [0u8; SECTOR_SIZE];
+    let volume = Volume::open(emmc, &mut sector)?;
+    let entry = volume.lookup(emmc, &mut sector, path)?;
+    if entry.is_dir() {
+        return Err(FatError::IsADirectory);
+    }
+
+    let (first, size) = (entry.cluster, entry.size as usize);
+    if size > output.len() {
+        return Err(FatError::TooLarge);
+    }
+
+    let sectors_per_cluster = volume.sectors_per_cluster as usize;
+    let mut cluster = if size == 0 { None } else { Some(first) };
+    let mut offset = 0;
+    while let Some(current) = cluster {
+        // Whole sectors go straight to `output` in one transfer.
+        let start = volume.cluster_start(current);
+        let whole = cmp::min(sectors_per_cluster, (size - offset) / SECTOR_SIZE);
+        if whole > 0 {
+            emmc.read_sectors(start, whole, &mut output[offset..offset + whole * SECTOR_SIZE])?;
+            offset += whole * SECTOR_SIZE;
+        }
+
+        if offset == size {
+            break;
+        }
+
+        if whole < sectors_per_cluster {
+            // The file ends part way through the next sector.
+            emmc.read_sector(start + whole as u32, &mut sector)?;
+            output[offset..size].copy_from_slice(&sector[..size - offset]);
+            offset = size;
+            break;
+        }
+
+        cluster = volume.next_cluster(emmc, &mut sector, current)?;
+    }
+
+    if offset < size {
+        return Err(FatError::NoFileSystem);
+    }
+
+    Ok(size)
+}
+
+/// Calls `f` with the contents of the file at `path` (see `stat()`) a sector
+/// at a time, and returns its length.
+pub fn stream<F: FnMut(&[u8])>(emmc: &mut Emmc, path: &str, mut f: F) -> Result<usize, FatError> {
+    let mut sector = [0u8; SECTOR_SIZE];
+    let volume = Volume::open(emmc, &mut sector)?;
+    let entry = volume.lookup(emmc, &mut sector, path)?;
+    if entry.is_dir() {
+        return Err(FatError::IsADirectory);
+    }
+
+    let size = entry.size as usize;
+    let mut cluster = if size == 0 { None } else { Some(entry.cluster) };
+    let mut offset = 0;
+    'chain: while let Some(current) = cluster {
+        let start = volume.cluster_start(current);
+        for lba in start..start + volume.sectors_per_cluster {
+            let len = cmp::min(SECTOR_SIZE, size - offset);
+            emmc.read_sector(lba, &mut sector)?;
+            f(&sector[..len]);
+            offset += len;
+            if offset == size {
+                break 'chain;
+            }
+        }
+
+        cluster = volume.next_cluster(emmc, &mut sector, current)?;
+    }
+
+    if offset < size {
+        return Err(FatError::NoFileSystem);
+    }
+
+    Ok(size)
+}
+
+/// Writes `data` to the file at `path` (see `stat()`), replacing what it
+/// held, or creating it if there's none in the directory named.
+/// `modified` is recorded as when it was last written.
+pub fn save(emmc: &mut Emmc, path: &str, data: &[u8], modified: &DateTime) -> Result<(), FatError> {
+    let mut sector = [0u8; SECTOR_SIZE];
+    let mut volume = Volume::open(emmc, &mut sector)?;
+    let mut entry = volume.open_file(emmc, &mut sector, path, modified)?;
+
+    volume.free_chain(emmc, &mut sector, entry.cluster)?;
+    entry.cluster = volume.write_data(emmc, &mut sector, 0, 0, data)?;
+    entry.size = data.len() as u32;
+    volume.update(emmc, &mut sector, &entry, modified)
+}
+
+/// Writes `data` to the end of the file at `path`, creating it like `save()`
+/// if there's none.
+pub fn append(emmc: &mut Emmc,
+              path: &str,
+              data: &[u8],
+              modified: &DateTime) -> Result<(), FatError> {
+    let mut sector = [0u8; SECTOR_SIZE];
+    let mut volume = Volume::open(emmc, &mut sector)?;
+    let mut entry = volume.open_file(emmc, &mut sector, path, modified)?;
+    if entry.size as usize + data.len() > u32::max_value() as usize {
+        return Err(FatError::TooLarge);
+    }
+
+    let (first, size) = (entry.cluster, entry.size as usize);
+    entry.cluster = volume.write_data(emmc, &mut sector, first, size, data)?;
+    entry.size += data.len() as u32;
+    volume.update(emmc, &mut sector, &entry, modified)
+}
+
+/// Creates an empty directory at `path` (see `stat()`), in a directory that
+/// exists.
+pub fn mkdir(emmc: &mut Emmc, path: &str, modified: &DateTime) -> Result<(), FatError> {
+    let mut sector = [0u8; SECTOR_SIZE];
+    let mut volume = Volume::open(emmc, &mut sector)?;
+    let (parent, name) = volume.parent(emmc, &mut sector, path)?;
+    let mut entry = volume.create(emmc, &mut sector, &parent, name, ATTR_DIRECTORY, modified)?;
+
+    let cluster = match volume.alloc_cluster(emmc, &mut sector, 0) {
+        Ok(cluster) => cluster,
+        Err(error) => {
+            volume.delete_entry(emmc, &mut sector, entry.location.unwrap())?;
+            return Err(error);
+        }
+    };
+
+    // Every directory but the root opens with `.` and `..`, the latter
+    // pointing at cluster 0 for the root.
+    volume.zero_cluster(emmc, &mut sector, cluster)?;
+    let (dot, dot_dot) = (short_name(".").unwrap(), short_name("..").unwrap());
+    let dot = new_entry(&dot, 0, ATTR_DIRECTORY, cluster, modified);
+    let dot_dot = new_entry(&dot_dot, 0, ATTR_DIRECTORY, parent.cluster, modified);
+    sector[..ENTRY_SIZE].copy_from_slice(&dot);
+    sector[ENTRY_SIZE..2 * ENTRY_SIZE].copy_from_slice(&dot_dot);
+    emmc.write_sector(volume.cluster_start(cluster), &sector)?;
+
+    entry.cluster = cluster;
+    volume.update(emmc, &mut sector, &entry, modified)
+}
+
+/// Removes the file or empty directory at `path` (see `stat()`).
+pub fn remove(emmc: &mut Emmc, path: &str) -> Result<(), FatError> {
+    let mut sector = [0u8; SECTOR_SIZE];
+    let mut volume = Volume::open(emmc, &mut sector)?;
+    let entry = volume.lookup(emmc, &mut sector, path)?;
+    if entry.name() == "." || entry.name() == ".." {
+        return Err(FatError::InvalidName);
+    }
+
+    let location = entry.location.ok_or(FatError::DirectoryNotEmpty)?;
+
+    if entry.is_dir() {
+        let dir = volume.dir(&entry);
+        let child = volume.find_entry(emmc, &mut sector, dir, |raw, _| {
+            if raw[0] == b'.' { None } else { Some(()) }
+        })?;
+
+        if child.is_some() {
+            return Err(FatError::DirectoryNotEmpty);
+        }
+    }
+
+    volume.delete_entry(emmc, &mut sector, location)?;
+    volume.free_chain(emmc, &mut sector, entry.cluster)
+}
@@ -0,0 +1,53 @@
+/// The magic number opening a PSF1 font.
+const PSF1_MAGIC: [u8; 2] = [0x36, 0x04];
+
+/// The PSF1 mode bit marking a 512-glyph font.
+const PSF1_MODE_512: u8 = 0x01;
+
+/// The size of a PSF1 header in bytes.
+const PSF1_HEADER_LEN: usize = 4;
+
+/// PSF1 glyphs are always 8 pixels wide, one byte per row.
+pub const GLYPH_WIDTH: usize = 8;
+
+/// A PSF1 bitmap font.
+#[derive(Debug, Clone, Copy)]
+pub struct Font {
+    glyphs: &'static [u8],
+    height: usize,
+    count: usize,
+}
+
+impl Font {
+    /// Parses the PSF1 font in `data`.
+    ///
+    /// # Panics
+    ///
+    /// Panics if `data` isn't a complete PSF1 font.
+    pub fn psf1(data: &'static [u8]) -> Font {
+        if data.len() < PSF1_HEADER_LEN || data[..2] != PSF1_MAGIC[..] {
+            panic!("Font: font isn't in PSF1 format");
+        }
+
+        let count = if data[2] & PSF1_MODE_512 != 0 { 512 } else { 256 };
+        let height = data[3] as usize;
+        let end = PSF1_HEADER_LEN + count * height;
+        if data.len() < end {
+            panic!("Font: font is truncated");
+        }
+
+        Font { glyphs: &data[PSF1_HEADER_LEN..end], height, count }
+    }
+
+    /// Returns the height of every glyph in pixels.
+    pub fn height(&self) -> usize {
+        self.height
+    }
+
+    /// Returns the rows of the glyph for `byte`, most significant bit
+    /// leftmost.
+    pub fn glyph(&self, byte: u8) -> &'static [u8] {
+        let index = byte as usize % self.count;
+        &self.glyphs[index * self.height..(index + 1) * self.height]
+    }
+}
@@ -0,0 +1,139 @@
+use core::slice;
+
+use dma::arm_address;
+use mailbox::{tag, Message, MailboxError};
+
+/// The number of bits in each pixel.
+const DEPTH: u32 = 32;
+
+/// The alignment requested for the framebuffer, in bytes.
+const ALIGNMENT: u32 = 16;
+
+/// An error setting up the framebuffer.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum FramebufferError {
+    /// The property request failed.
+    Mailbox(MailboxError),
+    /// The firmware didn't allocate a buffer, or didn't honour the requested
+    /// size or depth.
+    Unsupported,
+}
+
+impl From<MailboxError> for FramebufferError {
+    fn from(error: MailboxError) -> FramebufferError {
+        FramebufferError::Mailbox(error)
+    }
+}
+
+/// The requested size of a framebuffer, in pixels.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct FramebufferConfig {
+    pub width: u32,
+    pub height: u32,
+}
+
+impl Default for FramebufferConfig {
+    /// Returns the 1920x1080 configuration.
+    fn default() -> FramebufferConfig {
+        FramebufferConfig { width: 1920, height: 1080 }
+    }
+}
+
+/// A 32-bit framebuffer allocated by the VideoCore and scanned out over HDMI.
+///
+/// Each pixel is a `u32` of the form `0xAARRGGBB`. Rows are `pitch()` bytes
+/// apart, which may be more than `width() * 4`.
+pub struct Framebuffer {
+    buffer: &'static mut [u32],
+    width: u32,
+    height: u32,
+    pitch: u32,
+}
+
+impl Framebuffer {
+    /// Allocates a framebuffer of the default size, 1920x1080.
+    pub fn new() -> Result<Framebuffer, FramebufferError> {
+        Framebuffer::with_config(FramebufferConfig::default())
+    }
+
+    /// Allocates a framebuffer of the size in `config`. Any framebuffer
+    /// allocated before is replaced.
+    pub fn with_config(config: FramebufferConfig) -> Result<Framebuffer, FramebufferError> {
+        let size = [config.width, config.height];
+
+        let mut message = Message::new();
+        let physical = message.push(tag::SET_PHYSICAL_SIZE, &size, 2)?;
+        message.push(tag::SET_VIRTUAL_SIZE, &size, 2)?;
+        message.push(tag::SET_VIRTUAL_OFFSET, &[0, 0], 2)?;
+        let depth = message.push(tag::SET_DEPTH, &[DEPTH], 1)?;
+        message.push(tag::SET_PIXEL_ORDER, &[1], 1)?;
+        let allocation = message.push(tag::ALLOCATE_BUFFER, &[ALIGNMENT], 2)?;
+        let pitch = message.push(tag::GET_PITCH, &[], 1)?;
+        message.send()?;
+
+        if message.response(physical)? != &size[..] || message.response(depth)? != &[DEPTH][..] {
+            return Err(FramebufferError::Unsupported);
+        }
+
+        let (base, len) = {
+            let allocation = message.response(allocation)?;
+            if allocation.len() != 2 || allocation[0] == 0 || allocation[1] == 0 {
+                return Err(FramebufferError::Unsupported);
+            }
+            (allocation[0], allocation[1])
+        };
+
+        let pitch = match message.response(pitch)?.first() {
+            Some(&pitch) => pitch,
+            None => return Err(FramebufferError::Unsupported)
+        };
+
+        let buffer = unsafe {
+            slice::from_raw_parts_mut(arm_address(base) as *mut u32, len as usize / 4)
+        };
+
+        Ok(Framebuffer { buffer, width: config.width, height: config.height, pitch })
+    }
+
+    /// Returns the width in pixels.
+    pub fn width(&self) -> u32 {
+        self.width
+    }
+
+    /// Returns the height in pixels.
+    pub fn height(&self) -> u32 {
+        self.height
+    }
+
+    /// Returns the distance between the starts of consecutive rows, in bytes.
+    pub fn pitch(&self) -> u32 {
+        self.pitch
+    }
+
+    /// Returns the size of the buffer in bytes.
+    pub fn size(&self) -> usize {
+        self.buffer.len() * 4
+    }
+
+    /// Returns the pixels, row by row. Pixel (`x`, `y`) is at index
+    /// `y * pitch() / 4 + x`.
+    pub fn pixels(&mut self) -> &mut [u32] {
+        self.buffer
+    }
+
+    /// Sets pixel (`x`, `y`) to `color`. Pixels outside the framebuffer are
+    /// ignored.
+    pub fn set_pixel(&mut self, x: u32, y: u32, color: u32) {
+        if x < self.width && y < self.height {
+            let index = (y * self.pitch / 4 + x) as usize;
+            self.buffer[index] = color;
+        }
+    }
+
+    /// Sets every pixel to `color`.
+    pub fn fill(&mut self, color: u32) {
+        for pixel in self.buffer.iter_mut() {
+            *pixel = color;
+        }
+    }
+}
@@ -0,0 +1,113 @@
+use core::time::Duration;
+
+use local_interrupt::{Controller, CoreTimer, LocalInterrupt, NUM_CORES, core_id};
+use timer::duration_to_us;
+
+/// Enum representing bit fields of `CNTP_CTL_EL0`.
+#[repr(u64)]
+enum CtlFlags {
+    Enable = 1,
+    IMask = 1 << 1,
+    IStatus = 1 << 2,
+}
+
+/// The period of each core's timer in counter ticks, or 0 in one-shot mode.
+static mut PERIODS: [u64; NUM_CORES] = [0; NUM_CORES];
+
+/// Returns the frequency of the generic timer's counter in Hz, as set in
+/// `CNTFRQ_EL0` by the firmware.
+pub fn frequency() -> u64 {
+    let frequency: u64;
+    unsafe { asm!("mrs $0, cntfrq_el0" : "=r"(frequency)); }
+    frequency
+}
+
+/// Returns the value of the generic timer's counter, which is shared by all
+/// cores.
+pub fn counter() -> u64 {
+    let count: u64;
+    unsafe { asm!("mrs $0, cntpct_el0" : "=r"(count)); }
+    count
+}
+
+/// Returns `duration` in counter ticks, at least 1.
+fn duration_to_ticks(duration: Duration) -> u64 {
+    let ticks = duration_to_us(duration).saturating_mul(frequency()) / 1_000_000;
+    ::core::cmp::max(ticks, 1)
+}
+
+fn write_ctl(value: u64) {
+    unsafe { asm!("msr cntp_ctl_el0, $0" :: "r"(value) :: "volatile"); }
+}
+
+fn read_ctl() -> u64 {
+    let ctl: u64;
+    unsafe { asm!("mrs $0, cntp_ctl_el0" : "=r"(ctl) ::: "volatile"); }
+    ctl
+}
+
+/// Fires the calling core's timer `ticks` counter ticks from now.
+fn arm(ticks: u64) {
+    let ticks = ::core::cmp::min(ticks, i32::max_value() as u64);
+    unsafe { asm!("msr cntp_tval_el0, $0" :: "r"(ticks) :: "volatile"); }
+    write_ctl(CtlFlags::Enable as u64);
+}
+
+/// Routes the calling core's timer interrupt to the core as an IRQ.
+fn route() {
+    Controller::new().enable_timer(core_id(), CoreTimer::NonSecurePhysical);
+}
+
+/// Raises a timer interrupt on the calling core once, `after` from now,
+/// replacing any previous setting. `handle_irq()` then stops the timer.
+pub fn one_shot(after: Duration) {
+    unsafe { PERIODS[core_id()] = 0; }
+    route();
+    arm(duration_to_ticks(after));
+}
+
+/// Raises a timer interrupt on the calling core every `period`, replacing any
+/// previous setting. `handle_irq()` arms the next period.
+pub fn periodic(period: Duration) {
+    let ticks = duration_to_ticks(period);
+    unsafe { PERIODS[core_id()] = ticks; }
+    route();
+    arm(ticks);
+}
+
+/// Stops the calling core's timer and its interrupt.
+pub fn stop() {
+    write_ctl(CtlFlags::IMask as u64);
+    Controller::new().disable_timer(core_id(), CoreTimer::NonSecurePhysical);
+}
+
+/// Returns `true` if the calling core's timer has fired and not been handled.
+pub fn is_pending() -> bool {
+    let ctl = read_ctl();
+    ctl & CtlFlags::Enable as u64 != 0 && ctl & CtlFlags::IStatus as u64 != 0
+}
+
+/// Handles the calling core's timer interrupt if it is pending, arming the
+/// next period in periodic mode and stopping the timer otherwise. Returns
+/// `true` if the timer had fired. Must be called from the IRQ handler once
+/// `one_shot()` or `periodic()` has been called.
+pub fn handle_irq() -> bool {
+    let core = core_id();
+    if !Controller::new().is_pending(core, LocalInterrupt::CntPns) || !is_pending() {
+        return false;
+    }
+
+    match unsafe { PERIODS[core] } {
+        // Disabling the timer deasserts its interrupt.
+        0 => write_ctl(CtlFlags::IMask as u64),
+        // Advance the compare value rather than rearming from now so the
+        // period doesn't drift.
+        period => unsafe {
+            let cval: u64;
+            asm!("mrs $0, cntp_cval_el0" : "=r"(cval) ::: "volatile");
+            asm!("msr cntp_cval_el0, $0" :: "r"(cval.wrapping_add(period)) :: "volatile");
+        }
+    }
+
+    true
+}
@@ -1,6 +1,7 @@
 use core::marker::PhantomData;
 
 use common::{IO_BASE, states};
+use interrupt::{Controller, Interrupt};
 use volatile::prelude::*;
 use volatile::{Volatile, WriteVolatile, ReadVolatile, Reserved};
 
@@ -17,6 +18,42 @@ pub enum Function {
     Alt5 = 0b010
 }
 
+/// The pull-up/pull-down resistor configuration of a GPIO pin.
+#[repr(u8)]
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum Pull {
+    None = 0b00,
+    Down = 0b01,
+    Up = 0b10
+}
+
+/// The edges of a pin's input signal that generate events.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum Edge {
+    Rising,
+    Falling,
+    Both
+}
+
+/// A function called from `handle_irq()` with the number of a pin on which an
+/// edge was detected.
+pub type EdgeHandler = fn(u8);
+
+/// The number of GPIO pins.
+pub(crate) const NUM_PINS: usize = 54;
+
+/// Handlers registered with `register_edge_handler()`, indexed by pin.
+static mut EDGE_HANDLERS: [Option<EdgeHandler>; NUM_PINS] = [None; NUM_PINS];
+
+/// The names of the drivers that have claimed each pin with `claim()`.
+static mut OWNERS: [Option<&'static str>; NUM_PINS] = [None; NUM_PINS];
+
+/// How many claims each pin's owner has made that it hasn't withdrawn.
+static mut CLAIMS: [usize; NUM_PINS] = [0; NUM_PINS];
+
+/// The number of cycles the pull control signals must be held stable for.
+const PULL_SETUP_CYCLES: usize = 150;
+
 #[repr(C)]
 #[allow(non_snake_case)]
 struct Registers {
@@ -48,7 +85,7 @@ struct Registers {
 
 /// Possible states for a GPIO pin.
 states! {
-    Uninitialized, Input, Output, Alt
+    Uninitialized, Input, Output, Alt, OpenDrain
 }
 
 /// A GPIP pin in state `State`.
@@ -56,8 +93,9 @@ states! {
 /// The `State` generic always corresponds to an uninstantiatable type that is
 /// use solely to mark and track the state of a given GPIO pin. A `Gpio`
 /// structure starts in the `Uninitialized` state and must be transitions into
-/// one of `Input`, `Output`, or `Alt` via the `into_input`, `into_output`, and
-/// `into_alt` methods before it can be used.
+/// one of `Input`, `Output`, `Alt`, or `OpenDrain` via the `into_input`,
+/// `into_output`, `into_alt`, and `into_open_drain` methods before it can be
+/// used.
 pub struct Gpio<State> {
     pin: u8,
     registers: &'static mut Registers,
@@ -68,6 +106,11 @@ pub struct Gpio<State> {
 const GPIO_BASE: usize = IO_BASE + 0x200000;
 
 impl<T> Gpio<T> {
+    /// Returns the pin's number.
+    pub fn number(&self) -> u8 {
+        self.pin
+    }
+
     /// Transitions `self` to state `S`, consuming `self` and returning a new
     /// `Gpio` instance in state `S`. This method should _never_ be exposed to
     /// the public!
@@ -79,6 +122,58 @@ impl<T> Gpio<T> {
             _state: PhantomData
         }
     }
+
+    /// Reads the pin's level from `GPLEV`.
+    fn read_level(&self) -> bool {
+        // Thirty two pins to a GPIO reg.
+        let pin_no = self.pin % 32;
+
+        self.registers.LEV[self.pin as usize / 32].read()
+            & (1 << pin_no) != 0
+    }
+
+    /// Selects `function` for the pin in `GPFSEL`.
+    fn select(&mut self, function: Function) {
+        // Ten pins to a GPIO reg.
+        let pin_no = self.pin % 10;
+        // Each pin has a 3 bit function select.
+        let pin_bits = pin_no * 3;
+
+        let reg = &mut self.registers.FSEL[self.pin as usize / 10];
+
+        let mut selection = reg.read();
+        // First zero out the current selection.
+        selection &= !(0b111 << pin_bits);
+        // Set new function.
+        selection |= (function as u32) << pin_bits;
+
+        reg.write(selection);
+    }
+
+    /// Configures the pin's pull-up/pull-down resistor as `pull`. The setting
+    /// is retained regardless of the pin's function.
+    pub fn set_pull(&mut self, pull: Pull) {
+        let bank = self.pin as usize / 32;
+        let pin_no = self.pin % 32;
+
+        // Select the control signal, then clock it into just this pin.
+        self.registers.PUD.write(pull as u32);
+        wait_cycles(PULL_SETUP_CYCLES);
+        self.registers.PUDCLK[bank].write(1 << pin_no);
+        wait_cycles(PULL_SETUP_CYCLES);
+
+        // Remove the control signal and the clock.
+        self.registers.PUD.write(0);
+        self.registers.PUDCLK[bank].write(0);
+    }
+}
+
+/// Spins for at least `cycles` CPU cycles.
+#[inline(never)]
+fn wait_cycles(cycles: usize) {
+    for _ in 0..cycles {
+        unsafe { asm!("nop" :::: "volatile"); }
+    }
 }
 
 impl Gpio<Uninitialized> {
@@ -99,26 +194,24 @@ impl Gpio<Uninitialized> {
         }
     }
 
-    /// Enables the alternative function `function` for `self`. Consumes self
-    /// and returns a `Gpio` structure in the `Alt` state.
-    pub fn into_alt(self, function: Function) -> Gpio<Alt> {
-        // Ten pins to a GPIO reg.
-        let pin_no = self.pin % 10;
-        // Each pin has a 3 bit function select.
-        let pin_bits = pin_no * 3;
-
-        {
-            let reg = &mut self.registers.FSEL[self.pin as usize / 10];
-
-            let mut selection = reg.read();
-            // First zero out the current selection.
-            selection &= !(0b111 << pin_bits);
-            // Set new function.
-            selection |= (function as u32) << pin_bits;
+    /// Returns a new `GPIO` structure for the pin `P`. Unlike `new()`, the pin
+    /// number is checked at compile time: only valid pins have a type in
+    /// `pin`.
+    pub fn from_pin<P: Pin>(_pin: P) -> Gpio<Uninitialized> {
+        Gpio::new(P::NUMBER)
+    }
 
-            reg.write(selection);
-        }
+    /// Returns the pin `P` switched to the alternative function that carries
+    /// the peripheral signal `S`. Pins that cannot carry `S` are rejected at
+    /// compile time.
+    pub fn with_signal<P: HasFunction<S>, S>(pin: P, _signal: S) -> Gpio<Alt> {
+        Gpio::from_pin(pin).into_alt(P::FUNCTION)
+    }
 
+    /// Enables the alternative function `function` for `self`. Consumes self
+    /// and returns a `Gpio` structure in the `Alt` state.
+    pub fn into_alt(mut self, function: Function) -> Gpio<Alt> {
+        self.select(function);
         self.transition()
     }
 
@@ -133,6 +226,26 @@ impl Gpio<Uninitialized> {
     pub fn into_input(self) -> Gpio<Input> {
         self.into_alt(Function::Input).transition()
     }
+
+    /// Sets this pin to be an _input_ pin with its pull-up resistor enabled.
+    /// Consumes self and returns a `Gpio` structure in the `Input` state.
+    pub fn into_input_pullup(mut self) -> Gpio<Input> {
+        self.set_pull(Pull::Up);
+        self.into_input()
+    }
+
+    /// Sets this pin up as an open drain line, as 1-Wire uses: it is either
+    /// driven low or released and pulled high, by the pin's pull-up and any
+    /// external one. The pin starts released. Consumes self and returns a
+    /// `Gpio` structure in the `OpenDrain` state.
+    pub fn into_open_drain(mut self) -> Gpio<OpenDrain> {
+        self.set_pull(Pull::Up);
+
+        // The output latch stays low, so selecting the output function is
+        // all it takes to drive the line.
+        self.registers.CLR[self.pin as usize / 32].write(1 << (self.pin % 32));
+        self.into_input().transition()
+    }
 }
 
 impl Gpio<Output> {
@@ -151,16 +264,442 @@ impl Gpio<Output> {
 
         self.registers.CLR[self.pin as usize / 32].write(1 << pin_no);
     }
+
+    /// Reads the pin's value back. Returns `true` if the level is high and
+    /// `false` if the level is low. This reflects the actual pin level, which
+    /// may differ from the driven one if the pin is shorted or overloaded.
+    pub fn level(&mut self) -> bool {
+        self.read_level()
+    }
+
+    /// Inverts the pin's current level.
+    pub fn toggle(&mut self) {
+        if self.read_level() {
+            self.clear();
+        } else {
+            self.set();
+        }
+    }
+}
+
+impl Gpio<OpenDrain> {
+    /// Drives the line low.
+    pub fn drive_low(&mut self) {
+        self.select(Function::Output);
+    }
+
+    /// Stops driving the line, letting it be pulled high unless another
+    /// device holds it low.
+    pub fn release(&mut self) {
+        self.select(Function::Input);
+    }
+
+    /// Reads the line's level. Returns `true` if the level is high and
+    /// `false` if the level is low.
+    pub fn level(&mut self) -> bool {
+        self.read_level()
+    }
 }
 
 impl Gpio<Input> {
     /// Reads the pin's value. Returns `true` if the level is high and `false`
     /// if the level is low.
     pub fn level(&mut self) -> bool {
-        // Ten pins to a GPIO reg.
-        let pin_no = self.pin % 32;
+        self.read_level()
+    }
 
-        self.registers.LEV[self.pin as usize / 32].read()
-            & (1 << pin_no) != 0
+    /// Starts recording an event each time `edge` is seen on this pin,
+    /// replacing any previous edge selection.
+    pub fn enable_edge_detect(&mut self, edge: Edge) {
+        let bank = self.pin as usize / 32;
+        let mask = 1 << (self.pin % 32);
+
+        let (rising, falling) = match edge {
+            Edge::Rising => (true, false),
+            Edge::Falling => (false, true),
+            Edge::Both => (true, true)
+        };
+
+        if rising {
+            self.registers.REN[bank].or_mask(mask);
+        } else {
+            self.registers.REN[bank].and_mask(!mask);
+        }
+
+        if falling {
+            self.registers.FEN[bank].or_mask(mask);
+        } else {
+            self.registers.FEN[bank].and_mask(!mask);
+        }
+    }
+
+    /// Stops recording edge events on this pin and discards a pending one.
+    pub fn disable_edge_detect(&mut self) {
+        let bank = self.pin as usize / 32;
+        let mask = 1 << (self.pin % 32);
+
+        self.registers.REN[bank].and_mask(!mask);
+        self.registers.FEN[bank].and_mask(!mask);
+        self.registers.EDS[bank].write(mask);
+    }
+
+    /// Returns `true` if an edge selected with `enable_edge_detect()` has been
+    /// seen since the last call, clearing the event.
+    ///
+    /// Once GPIO interrupts are enabled by `register_edge_handler()`,
+    /// `handle_irq()` consumes every event, so this only reports events that
+    /// have not been handled yet.
+    pub fn poll_event(&mut self) -> bool {
+        let bank = self.pin as usize / 32;
+        let mask = 1 << (self.pin % 32);
+
+        if self.registers.EDS[bank].has_mask(mask) {
+            self.registers.EDS[bank].write(mask);
+            true
+        } else {
+            false
+        }
+    }
+}
+
+/// Sets (turns on) every pin whose bit is set in `mask`, where bit `n`
+/// corresponds to pin `n`. Pins 0-31 change together in a single register
+/// write, as do pins 32-53. Pins that are not outputs are unaffected.
+pub fn set_mask(mask: u64) {
+    let registers = unsafe { &mut *(GPIO_BASE as *mut Registers) };
+    write_banks(&mut registers.SET, mask);
+}
+
+/// Clears (turns off) every pin whose bit is set in `mask`, where bit `n`
+/// corresponds to pin `n`. Pins 0-31 change together in a single register
+/// write, as do pins 32-53. Pins that are not outputs are unaffected.
+pub fn clear_mask(mask: u64) {
+    let registers = unsafe { &mut *(GPIO_BASE as *mut Registers) };
+    write_banks(&mut registers.CLR, mask);
+}
+
+/// Returns the level of every pin, where bit `n` corresponds to pin `n`,
+/// whatever function the pins have.
+pub fn levels() -> u64 {
+    let registers = unsafe { &mut *(GPIO_BASE as *mut Registers) };
+    registers.LEV[0].read() as u64 | (registers.LEV[1].read() as u64) << 32
+}
+
+/// Records that the driver named `owner` is using `pins`, replacing any
+/// earlier claim by another driver. Claims don't stop anything touching the
+/// pins; they tell code driving pins on a user's behalf, like a shell, which
+/// to leave be.
+///
+/// Claims by the same driver are counted, so that a short-lived instance,
+/// like an `I2c` made for one command, can withdraw its claim with
+/// `release_owned()` without withdrawing a long-lived instance's.
+///
+/// # Panics
+///
+/// Panics if a pin > `53`.
+pub fn claim(pins: &[u8], owner: &'static str) {
+    for &pin in pins {
+        if pin as usize >= NUM_PINS {
+            panic!("claim(): pin {} exceeds maximum of 53", pin);
+        }
+
+        unsafe {
+            if OWNERS[pin as usize] == Some(owner) {
+                CLAIMS[pin as usize] += 1;
+            } else {
+                OWNERS[pin as usize] = Some(owner);
+                CLAIMS[pin as usize] = 1;
+            }
+        }
+    }
+}
+
+/// Withdraws every claim on `pins`.
+pub fn release(pins: &[u8]) {
+    for &pin in pins {
+        if (pin as usize) < NUM_PINS {
+            unsafe {
+                OWNERS[pin as usize] = None;
+                CLAIMS[pin as usize] = 0;
+            }
+        }
+    }
+}
+
+/// Withdraws one of `owner`'s claims on each of `pins`, leaving any another
+/// driver has made since. A pin is released once its owner has withdrawn
+/// every claim it made. Drivers call this as they stop using their pins.
+pub fn release_owned(pins: &[u8], owner: &str) {
+    for &pin in pins {
+        if self::owner(pin) == Some(owner) {
+            unsafe { CLAIMS[pin as usize] -= 1; }
+            if unsafe { CLAIMS[pin as usize] } == 0 {
+                release(&[pin]);
+            }
+        }
+    }
+}
+
+/// Withdraws all of `owner`'s claims on `pins`, however many it made,
+/// leaving any another driver has made since.
+pub fn release_all_owned(pins: &[u8], owner: &str) {
+    for &pin in pins {
+        if self::owner(pin) == Some(owner) {
+            release(&[pin]);
+        }
+    }
+}
+
+/// Returns the name of the driver that claimed `pin`, if one has.
+pub fn owner(pin: u8) -> Option<&'static str> {
+    if pin as usize >= NUM_PINS {
+        return None;
+    }
+
+    unsafe { OWNERS[pin as usize] }
+}
+
+/// Writes the low and high halves of `mask` to the two registers in `regs`,
+/// skipping a register whose half is zero.
+fn write_banks(regs: &mut [WriteVolatile<u32>; 2], mask: u64) {
+    let low = mask as u32;
+    let high = (mask >> 32) as u32;
+
+    if low != 0 {
+        regs[0].write(low);
+    }
+
+    if high != 0 {
+        regs[1].write(high);
+    }
+}
+
+/// Registers `handler` to be called from `handle_irq()` when an edge event is
+/// detected on `pin`, replacing any previous handler, and enables the GPIO
+/// interrupts. Edges to detect are selected with `enable_edge_detect()`.
+///
+/// # Panics
+///
+/// Panics if `pin` > `53`.
+pub fn register_edge_handler(pin: u8, handler: EdgeHandler) {
+    if pin as usize >= NUM_PINS {
+        panic!("register_edge_handler(): pin {} exceeds maximum of 53", pin);
+    }
+
+    unsafe { EDGE_HANDLERS[pin as usize] = Some(handler); }
+    Controller::new().enable(Interrupt::Gpio3);
+}
+
+/// Removes the handler registered for `pin`, if any. Events on the pin are
+/// still cleared by `handle_irq()`.
+pub fn unregister_edge_handler(pin: u8) {
+    if (pin as usize) < NUM_PINS {
+        unsafe { EDGE_HANDLERS[pin as usize] = None; }
+    }
+}
+
+/// Clears every pending edge event and calls the handler registered for each
+/// pin that had one. Must be called from the IRQ handler when
+/// `Interrupt::Gpio3` is pending once `register_edge_handler()` has been
+/// called.
+pub fn handle_irq() {
+    let registers = unsafe { &mut *(GPIO_BASE as *mut Registers) };
+
+    for bank in 0..2 {
+        let events = registers.EDS[bank].read();
+        if events == 0 {
+            continue;
+        }
+
+        // Writing a 1 clears the event.
+        registers.EDS[bank].write(events);
+
+        for bit in 0..32 {
+            let pin = bank * 32 + bit;
+            if events & (1 << bit) == 0 || pin >= NUM_PINS {
+                continue;
+            }
+
+            if let Some(handler) = unsafe { EDGE_HANDLERS[pin] } {
+                handler(pin as u8);
+            }
+        }
+    }
+}
+
+/// A GPIO pin whose number is known at compile time.
+pub trait Pin {
+    /// The pin's number.
+    const NUMBER: u8;
+}
+
+/// Implemented by pins that can carry signal `S` and the alternative function
+/// that selects it.
+pub trait HasFunction<S>: Pin {
+    /// The function that routes `S` to this pin.
+    const FUNCTION: Function;
+}
+
+/// Generates a zero-sized `pub struct` implementing `Pin` for each pin.
+macro_rules! pins {
+    ($($name:ident = $number:expr),*) => {$(
+        pub struct $name;
+
+        impl Pin for $name {
+            const NUMBER: u8 = $number;
+        }
+    )*}
+}
+
+/// Generates a zero-sized `pub struct` for each peripheral signal.
+macro_rules! signals {
+    ($($name:ident),*) => {$(pub struct $name;)*}
+}
+
+/// Implements `HasFunction` for each `signal => function` listed for a pin.
+macro_rules! functions {
+    ($($pin:ident: $($signal:ident => $function:ident),*;)*) => {$($(
+        impl HasFunction<signal::$signal> for pin::$pin {
+            const FUNCTION: Function = Function::$function;
+        }
+    )*)*}
+}
+
+/// Types for each valid GPIO pin, for use with `Gpio::from_pin()` and
+/// `Gpio::with_signal()`.
+pub mod pin {
+    use super::Pin;
+
+    pins! {
+        P0 = 0, P1 = 1, P2 = 2, P3 = 3, P4 = 4, P5 = 5, P6 = 6, P7 = 7,
+        P8 = 8, P9 = 9, P10 = 10, P11 = 11, P12 = 12, P13 = 13, P14 = 14,
+        P15 = 15, P16 = 16, P17 = 17, P18 = 18, P19 = 19, P20 = 20, P21 = 21,
+        P22 = 22, P23 = 23, P24 = 24, P25 = 25, P26 = 26, P27 = 27, P28 = 28,
+        P29 = 29, P30 = 30, P31 = 31, P32 = 32, P33 = 33, P34 = 34, P35 = 35,
+        P36 = 36, P37 = 37, P38 = 38, P39 = 39, P40 = 40, P41 = 41, P42 = 42,
+        P43 = 43, P44 = 44, P45 = 45, P46 = 46, P47 = 47, P48 = 48, P49 = 49,
+        P50 = 50, P51 = 51, P52 = 52, P53 = 53
+    }
+}
+
+/// Peripheral signals that can be routed to a pin via an alternative
+/// function.
+pub mod signal {
+    signals! {
+        // UART0 (PL011) and UART1 (mini UART).
+        Txd0, Rxd0, Cts0, Rts0, Txd1, Rxd1, Cts1, Rts1,
+        // BSC0 and BSC1 (I2C).
+        Sda0, Scl0, Sda1, Scl1,
+        // SPI0 and SPI1.
+        Spi0Ce0, Spi0Ce1, Spi0Miso, Spi0Mosi, Spi0Sclk,
+        Spi1Ce0, Spi1Ce1, Spi1Ce2, Spi1Miso, Spi1Mosi, Spi1Sclk,
+        // PWM channels and general purpose clocks.
+        Pwm0, Pwm1, GpClk0, GpClk1, GpClk2,
+        // PCM (I2S).
+        PcmClk, PcmFs, PcmDin, PcmDout,
+        // The EMMC controller's SD bus.
+        SdClk, SdCmd, SdDat0, SdDat1, SdDat2, SdDat3
+    }
+}
+
+// The pins each signal can be routed to, from the BCM2837 alternative function
+// assignment table.
+functions! {
+    P0: Sda0 => Alt0;
+    P1: Scl0 => Alt0;
+    P2: Sda1 => Alt0;
+    P3: Scl1 => Alt0;
+    P4: GpClk0 => Alt0;
+    P5: GpClk1 => Alt0;
+    P6: GpClk2 => Alt0;
+    P7: Spi0Ce1 => Alt0;
+    P8: Spi0Ce0 => Alt0;
+    P9: Spi0Miso => Alt0;
+    P10: Spi0Mosi => Alt0;
+    P11: Spi0Sclk => Alt0;
+    P12: Pwm0 => Alt0;
+    P13: Pwm1 => Alt0;
+    P14: Txd0 => Alt0, Txd1 => Alt5;
+    P15: Rxd0 => Alt0, Rxd1 => Alt5;
+    P16: Cts0 => Alt3, Spi1Ce2 => Alt4, Cts1 => Alt5;
+    P17: Rts0 => Alt3, Spi1Ce1 => Alt4, Rts1 => Alt5;
+    P18: PcmClk => Alt0, Spi1Ce0 => Alt4, Pwm0 => Alt5;
+    P19: PcmFs => Alt0, Spi1Miso => Alt4, Pwm1 => Alt5;
+    P20: PcmDin => Alt0, Spi1Mosi => Alt4, GpClk0 => Alt5;
+    P21: PcmDout => Alt0, Spi1Sclk => Alt4, GpClk1 => Alt5;
+    P28: Sda0 => Alt0, PcmClk => Alt2;
+    P29: Scl0 => Alt0, PcmFs => Alt2;
+    P30: PcmDin => Alt2, Cts0 => Alt3, Cts1 => Alt5;
+    P31: PcmDout => Alt2, Rts0 => Alt3, Rts1 => Alt5;
+    P32: GpClk0 => Alt0, Txd0 => Alt3, Txd1 => Alt5;
+    P33: Rxd0 => Alt3, Rxd1 => Alt5;
+    P34: GpClk0 => Alt0;
+    P35: Spi0Ce1 => Alt0;
+    P36: Spi0Ce0 => Alt0, Txd0 => Alt2;
+    P37: Spi0Miso => Alt0, Rxd0 => Alt2;
+    P38: Spi0Mosi => Alt0, Rts0 => Alt2;
+    P39: Spi0Sclk => Alt0, Cts0 => Alt2;
+    P40: Pwm0 => Alt0, Txd1 => Alt5;
+    P41: Pwm1 => Alt0, Rxd1 => Alt5;
+    P42: GpClk1 => Alt0, Rts1 => Alt5;
+    P43: GpClk2 => Alt0, Cts1 => Alt5;
+    P44: GpClk1 => Alt0, Sda0 => Alt1, Sda1 => Alt2;
+    P45: Pwm1 => Alt0, Scl0 => Alt1, Scl1 => Alt2;
+    P48: SdClk => Alt3;
+    P49: SdCmd => Alt3;
+    P50: SdDat0 => Alt3;
+    P51: SdDat1 => Alt3;
+    P52: SdDat2 => Alt3;
+    P53: SdDat3 => Alt3;
+}
+
+#[cfg(feature = "hal")]
+mod gpio_hal {
+    use embedded_hal::digital::v2::{OutputPin, InputPin, StatefulOutputPin, ToggleableOutputPin};
+    use super::{Gpio, Output, Input};
+
+    impl OutputPin for Gpio<Output> {
+        type Error = !;
+
+        fn set_low(&mut self) -> Result<(), !> {
+            self.clear();
+            Ok(())
+        }
+
+        fn set_high(&mut self) -> Result<(), !> {
+            self.set();
+            Ok(())
+        }
+    }
+
+    impl StatefulOutputPin for Gpio<Output> {
+        fn is_set_high(&self) -> Result<bool, !> {
+            Ok(self.read_level())
+        }
+
+        fn is_set_low(&self) -> Result<bool, !> {
+            Ok(!self.read_level())
+        }
+    }
+
+    impl ToggleableOutputPin for Gpio<Output> {
+        type Error = !;
+
+        fn toggle(&mut self) -> Result<(), !> {
+            Gpio::<Output>::toggle(self);
+            Ok(())
+        }
+    }
+
+    impl InputPin for Gpio<Input> {
+        type Error = !;
+
+        fn is_high(&self) -> Result<bool, !> {
+            Ok(self.read_level())
+        }
+
+        fn is_low(&self) -> Result<bool, !> {
+            Ok(!self.read_level())
+        }
     }
 }
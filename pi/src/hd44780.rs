@@ -0,0 +1,367 @@
+use core::fmt;
+
+use gpio::{self, Gpio, Output};
+use i2c::{I2c, I2cError};
+use timer::{spin_sleep_ms, spin_sleep_us};
+
+/// Instructions, with the flags that go with each.
+mod instruction {
+    pub const CLEAR: u8 = 0x01;
+    pub const HOME: u8 = 0x02;
+    pub const ENTRY_MODE: u8 = 0x04;
+    pub const DISPLAY_CONTROL: u8 = 0x08;
+    pub const FUNCTION_SET: u8 = 0x20;
+    pub const SET_DDRAM_ADDRESS: u8 = 0x80;
+
+    /// `ENTRY_MODE`: move the cursor right after each character.
+    pub const INCREMENT: u8 = 1 << 1;
+
+    /// `DISPLAY_CONTROL`: show the display, the cursor, and make it blink.
+    pub const DISPLAY_ON: u8 = 1 << 2;
+    pub const CURSOR_ON: u8 = 1 << 1;
+    pub const BLINK_ON: u8 = 1;
+
+    /// `FUNCTION_SET`: two lines, rather than one.
+    pub const TWO_LINES: u8 = 1 << 3;
+}
+
+/// How long instructions take to execute, in microseconds. The busy flag
+/// can't be read with R/W tied low, so the driver waits this long instead.
+mod delay {
+    /// Every instruction but `CLEAR` and `HOME`, and data writes.
+    pub const SHORT: u64 = 50;
+    /// `CLEAR` and `HOME`.
+    pub const LONG: u64 = 2000;
+    /// After power on, before the controller takes instructions.
+    pub const POWER_ON_MS: u64 = 50;
+    /// After the first reset nibble.
+    pub const RESET: u64 = 4500;
+}
+
+/// The wiring between the Pi and the controller's 4-bit interface.
+pub trait Interface {
+    type Error;
+
+    /// Puts the low 4 bits of `nibble` on D4-D7 with RS set if `data` and
+    /// pulses E, latching them.
+    fn write_nibble(&mut self, nibble: u8, data: bool) -> Result<(), Self::Error>;
+
+    /// Turns the backlight on or off, if the interface controls it.
+    fn set_backlight(&mut self, _on: bool) -> Result<(), Self::Error> {
+        Ok(())
+    }
+}
+
+/// A controller wired directly to GPIO pins in 4-bit mode, with R/W tied
+/// low.
+pub struct FourBitGpio {
+    rs: Gpio<Output>,
+    enable: Gpio<Output>,
+    data: [Gpio<Output>; 4],
+}
+
+impl FourBitGpio {
+    /// Returns the interface with RS on pin `rs`, E on pin `enable` and
+    /// D4-D7 on pins `data`, in that order. Every pin is set as an output.
+    ///
+    /// # Panics
+    ///
+    /// Panics if any pin number is > `53`.
+    pub fn new(rs: u8, enable: u8, data: [u8; 4]) -> FourBitGpio {
+        let mut enable_pin = Gpio::new(enable).into_output();
+        enable_pin.clear();
+
+        let interface = FourBitGpio {
+            rs: Gpio::new(rs).into_output(),
+            enable: enable_pin,
+            data: [
+                Gpio::new(data[0]).into_output(),
+                Gpio::new(data[1]).into_output(),
+                Gpio::new(data[2]).into_output(),
+                Gpio::new(data[3]).into_output(),
+            ],
+        };
+        gpio::claim(&[rs, enable, data[0], data[1], data[2], data[3]], "hd44780");
+        interface
+    }
+}
+
+impl Drop for FourBitGpio {
+    /// Withdraws the claims on the interface's pins.
+    fn drop(&mut self) {
+        let data = &self.data;
+        let pins = [self.rs.number(), self.enable.number(),
+                    data[0].number(), data[1].number(), data[2].number(), data[3].number()];
+        gpio::release_owned(&pins, "hd44780");
+    }
+}
+
+impl Interface for FourBitGpio {
+    type Error = !;
+
+    fn write_nibble(&mut self, nibble: u8, data: bool) -> Result<(), !> {
+        if data { self.rs.set() } else { self.rs.clear() }
+        for (i, pin) in self.data.iter_mut().enumerate() {
+            if nibble & (1 << i) != 0 { pin.set() } else { pin.clear() }
+        }
+
+        self.enable.set();
+        spin_sleep_us(1);
+        self.enable.clear();
+        Ok(())
+    }
+}
+
+/// The PCF8574 port expander bits on the common I2C backpacks.
+mod backpack {
+    pub const RS: u8 = 1;
+    pub const ENABLE: u8 = 1 << 2;
+    pub const BACKLIGHT: u8 = 1 << 3;
+    /// D4-D7 are on the high 4 bits.
+    pub const DATA_SHIFT: u8 = 4;
+}
+
+/// A controller behind a PCF8574 I2C port expander, as on the common
+/// backpack boards.
+pub struct Pcf8574 {
+    i2c: I2c,
+    addr: u8,
+    backlight: u8,
+}
+
+impl Pcf8574 {
+    /// The backpack's address with A0-A2 unjumpered. Boards with the
+    /// PCF8574A instead of the PCF8574 answer at `0x3F`.
+    pub const DEFAULT_ADDRESS: u8 = 0x27;
+
+    /// Returns the interface through the backpack at `addr` on `i2c`. The
+    /// backlight starts on.
+    pub fn new(i2c: I2c, addr: u8) -> Pcf8574 {
+        Pcf8574 { i2c, addr, backlight: backpack::BACKLIGHT }
+    }
+
+    /// Returns the I2C bus, giving up the interface.
+    pub fn release(self) -> I2c {
+        self.i2c
+    }
+}
+
+impl Interface for Pcf8574 {
+    type Error = I2cError;
+
+    fn write_nibble(&mut self, nibble: u8, data: bool) -> Result<(), I2cError> {
+        let mut bits = (nibble & 0xF) << backpack::DATA_SHIFT | self.backlight;
+        if data {
+            bits |= backpack::RS;
+        }
+
+        // Each byte takes 90us at 100kHz, far longer than E's pulse needs.
+        self.i2c.write(self.addr, &[bits | backpack::ENABLE, bits])
+    }
+
+    fn set_backlight(&mut self, on: bool) -> Result<(), I2cError> {
+        self.backlight = if on { backpack::BACKLIGHT } else { 0 };
+        self.i2c.write(self.addr, &[self.backlight])
+    }
+}
+
+/// An HD44780 character LCD, or a compatible, driven in 4-bit mode.
+///
+/// Writing with `write!` prints at the cursor, wrapping at the end of each
+/// row; `\n` moves to the start of the next row, and the last row wraps to
+/// the first. Characters the controller's ROM lacks are printed as `?`.
+pub struct Hd44780<I: Interface> {
+    interface: I,
+    columns: u8,
+    rows: u8,
+    /// The cursor's column and row.
+    column: u8,
+    row: u8,
+    /// The `DISPLAY_CONTROL` flags in effect.
+    control: u8,
+}
+
+impl<I: Interface> Hd44780<I> {
+    /// Initializes the `columns` by `rows` display on `interface`, then
+    /// clears it with the cursor hidden.
+    ///
+    /// The controller is reset by instruction, so this works whatever state
+    /// it was left in, but it must have been powered for 40ms: the first call
+    /// after boot may wait for that.
+    ///
+    /// # Panics
+    ///
+    /// Panics if `rows` isn't 1 to 4, or `columns` isn't 1 to 40.
+    pub fn new(interface: I, columns: u8, rows: u8) -> Result<Hd44780<I>, I::Error> {
+        if rows == 0 || rows > 4 || columns == 0 || columns > 40 {
+            panic!("Hd44780: unsupported {}x{} display", columns, rows);
+        }
+
+        let mut lcd = Hd44780 {
+            interface,
+            columns,
+            rows,
+            column: 0,
+            row: 0,
+            control: instruction::DISPLAY_ON,
+        };
+
+        spin_sleep_ms(delay::POWER_ON_MS);
+
+        // Three 8-bit function sets put the controller in 8-bit mode, from
+        // either mode and halfway through a 4-bit byte; a 4-bit function set
+        // then switches it. Only the high nibble is seen until then.
+        lcd.interface.write_nibble(0x3, false)?;
+        spin_sleep_us(delay::RESET);
+        lcd.interface.write_nibble(0x3, false)?;
+        spin_sleep_us(delay::SHORT * 3);
+        lcd.interface.write_nibble(0x3, false)?;
+        spin_sleep_us(delay::SHORT);
+        lcd.interface.write_nibble(0x2, false)?;
+        spin_sleep_us(delay::SHORT);
+
+        let lines = if rows > 1 { instruction::TWO_LINES } else { 0 };
+        lcd.command(instruction::FUNCTION_SET | lines)?;
+        let control = lcd.control;
+        lcd.command(instruction::DISPLAY_CONTROL | control)?;
+        lcd.command(instruction::ENTRY_MODE | instruction::INCREMENT)?;
+        lcd.clear()?;
+        Ok(lcd)
+    }
+
+    /// Returns the interface, giving up the display.
+    pub fn release(self) -> I {
+        self.interface
+    }
+
+    /// Returns the number of columns and rows.
+    pub fn size(&self) -> (u8, u8) {
+        (self.columns, self.rows)
+    }
+
+    /// Sends `byte` as an instruction if `data` is `false`, or as a
+    /// character otherwise, and waits for it to execute.
+    fn write(&mut self, byte: u8, data: bool) -> Result<(), I::Error> {
+        self.interface.write_nibble(byte >> 4, data)?;
+        self.interface.write_nibble(byte & 0xF, data)?;
+
+        let slow = !data && (byte == instruction::CLEAR || byte == instruction::HOME);
+        spin_sleep_us(if slow { delay::LONG } else { delay::SHORT });
+        Ok(())
+    }
+
+    /// Sends the instruction `byte`.
+    fn command(&mut self, byte: u8) -> Result<(), I::Error> {
+        self.write(byte, false)
+    }
+
+    /// Blanks the display and moves the cursor to the top left.
+    pub fn clear(&mut self) -> Result<(), I::Error> {
+        self.command(instruction::CLEAR)?;
+        self.column = 0;
+        self.row = 0;
+        Ok(())
+    }
+
+    /// Moves the cursor to the top left.
+    pub fn home(&mut self) -> Result<(), I::Error> {
+        self.command(instruction::HOME)?;
+        self.column = 0;
+        self.row = 0;
+        Ok(())
+    }
+
+    /// Moves the cursor to `column` in `row`, both from 0. Out of range
+    /// positions are clamped to the last column or row.
+    pub fn set_cursor(&mut self, column: u8, row: u8) -> Result<(), I::Error> {
+        self.column = ::core::cmp::min(column, self.columns - 1);
+        self.row = ::core::cmp::min(row, self.rows - 1);
+
+        // Rows 2 and 3 continue rows 0 and 1 in display memory.
+        let start = match self.row {
+            0 => 0,
+            1 => 0x40,
+            2 => self.columns,
+            _ => 0x40 + self.columns,
+        };
+
+        let address = start + self.column;
+        self.command(instruction::SET_DDRAM_ADDRESS | address)
+    }
+
+    /// Returns the cursor's column and row.
+    pub fn cursor(&self) -> (u8, u8) {
+        (self.column, self.row)
+    }
+
+    /// Sets `flag` in the display control flags if `on`, clears it otherwise.
+    fn set_control(&mut self, flag: u8, on: bool) -> Result<(), I::Error> {
+        if on {
+            self.control |= flag;
+        } else {
+            self.control &= !flag;
+        }
+
+        let control = self.control;
+        self.command(instruction::DISPLAY_CONTROL | control)
+    }
+
+    /// Shows or hides the whole display. Its contents are kept while hidden.
+    pub fn set_display(&mut self, on: bool) -> Result<(), I::Error> {
+        self.set_control(instruction::DISPLAY_ON, on)
+    }
+
+    /// Shows or hides the underline cursor.
+    pub fn show_cursor(&mut self, on: bool) -> Result<(), I::Error> {
+        self.set_control(instruction::CURSOR_ON, on)
+    }
+
+    /// Turns blinking of the cursor's cell on or off.
+    pub fn blink(&mut self, on: bool) -> Result<(), I::Error> {
+        self.set_control(instruction::BLINK_ON, on)
+    }
+
+    /// Turns the backlight on or off, if the interface controls it.
+    pub fn set_backlight(&mut self, on: bool) -> Result<(), I::Error> {
+        self.interface.set_backlight(on)
+    }
+
+    /// Writes the character with code `byte` in the controller's ROM at the
+    /// cursor and moves the cursor on, wrapping to the next row at the end of
+    /// this one.
+    pub fn write_byte(&mut self, byte: u8) -> Result<(), I::Error> {
+        self.write(byte, true)?;
+
+        self.column += 1;
+        if self.column == self.columns {
+            let row = (self.row + 1) % self.rows;
+            self.set_cursor(0, row)?;
+        }
+
+        Ok(())
+    }
+}
+
+impl<I: Interface> fmt::Write for Hd44780<I> {
+    fn write_str(&mut self, s: &str) -> fmt::Result {
+        for c in s.chars() {
+            let result = match c {
+                '\n' => {
+                    let row = (self.row + 1) % self.rows;
+                    self.set_cursor(0, row)
+                }
+                '\r' => {
+                    let row = self.row;
+                    self.set_cursor(0, row)
+                }
+                // The ROM matches ASCII here, but for `\` and `~`.
+                ' '...'}' if c != '\\' => self.write_byte(c as u8),
+                _ => self.write_byte(b'?'),
+            };
+
+            result.map_err(|_| fmt::Error)?;
+        }
+
+        Ok(())
+    }
+}
@@ -0,0 +1,330 @@
+use core::time::Duration;
+
+use clock;
+use common::IO_BASE;
+use gpio::{self, Gpio, pin, signal};
+use timer::Instant;
+use volatile::prelude::*;
+use volatile::Volatile;
+
+/// The base address of the `BSC1` registers.
+const BSC1_REG_BASE: usize = IO_BASE + 0x804000;
+
+/// The depth of the controller's FIFO.
+const FIFO_DEPTH: usize = 16;
+
+/// Enum representing bit fields of the `C` register.
+#[repr(u32)]
+enum ControlFlags {
+    Read = 1,
+    Clear = 0b11 << 4,
+    Start = 1 << 7,
+    Enable = 1 << 15,
+}
+
+/// Enum representing bit fields of the `S` register.
+#[repr(u32)]
+enum Status {
+    TransferActive = 1,
+    Done = 1 << 1,
+    TxAccepting = 1 << 4,
+    RxData = 1 << 5,
+    AckError = 1 << 8,
+    ClockStretchTimeout = 1 << 9,
+}
+
+#[repr(C)]
+#[allow(non_snake_case)]
+struct Registers {
+    C: Volatile<u32>,
+    S: Volatile<u32>,
+    DLEN: Volatile<u32>,
+    A: Volatile<u32>,
+    FIFO: Volatile<u32>,
+    DIV: Volatile<u32>,
+    DEL: Volatile<u32>,
+    CLKT: Volatile<u32>,
+}
+
+/// An error that ended an I2C transaction early.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum I2cError {
+    /// The slave didn't acknowledge its address or a byte written to it.
+    Nack,
+    /// The slave stretched the clock for longer than the configured limit.
+    ClockStretchTimeout,
+    /// The transaction didn't complete within the configured timeout.
+    TimedOut,
+}
+
+/// Bus settings for an I2C master.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct I2cConfig {
+    /// The upper bound on the SCL frequency, in Hz.
+    pub frequency: u32,
+    /// How many SCL cycles a slave may stretch the clock for before the
+    /// transaction fails with `ClockStretchTimeout`. 0 waits indefinitely.
+    pub clock_stretch_limit: u16,
+    /// The longest a transaction may take before it fails with `TimedOut`.
+    pub timeout: Duration,
+}
+
+impl Default for I2cConfig {
+    /// Returns the 100kHz configuration with generous clock stretching and a
+    /// 100ms timeout.
+    fn default() -> I2cConfig {
+        I2cConfig {
+            frequency: 100_000,
+            clock_stretch_limit: 0xFFFF,
+            timeout: Duration::from_millis(100)
+        }
+    }
+}
+
+/// Returns the value of the `DIV` register that yields the fastest SCL no
+/// faster than `frequency` given a core clock of `clock` Hz. SCL is
+/// `clock / divisor` for an even divisor, with 0 meaning 32768.
+///
+/// # Panics
+///
+/// Panics if `frequency` is 0.
+fn clock_divisor(clock: u32, frequency: u32) -> u32 {
+    match clock::even_divisor(clock, frequency, 0x8000) {
+        Some(divisor) => divisor,
+        None => panic!("I2c: SCL frequency of 0Hz is unreachable")
+    }
+}
+
+/// The `BSC1` I2C master, on GPIO 2 (SDA1) and 3 (SCL1). Slaves are addressed
+/// with 7-bit addresses.
+pub struct I2c {
+    registers: &'static mut Registers,
+    timeout: Duration
+}
+
+impl I2c {
+    /// Initializes `BSC1` with the default configuration: 100kHz with a
+    /// 100ms timeout.
+    pub fn new() -> I2c {
+        I2c::with_config(I2cConfig::default())
+    }
+
+    /// Initializes `BSC1` with the settings in `config`. GPIO pins 2 and 3
+    /// are set to alternative function 0.
+    ///
+    /// # Panics
+    ///
+    /// Panics if `config.frequency` is 0.
+    pub fn with_config(config: I2cConfig) -> I2c {
+        Gpio::with_signal(pin::P2, signal::Sda1);
+        Gpio::with_signal(pin::P3, signal::Scl1);
+        gpio::claim(&[2, 3], "i2c");
+
+        let registers = unsafe { &mut *(BSC1_REG_BASE as *mut Registers) };
+        registers.DIV.write(clock_divisor(clock::core_rate(), config.frequency));
+        registers.CLKT.write(config.clock_stretch_limit as u32);
+        registers.C.write(ControlFlags::Enable as u32 | ControlFlags::Clear as u32);
+
+        I2c { registers, timeout: config.timeout }
+    }
+
+    /// Clears the FIFO and status flags and starts a transfer of `len` bytes
+    /// to or from `addr`.
+    fn start(&mut self, addr: u8, len: usize, read: bool) {
+        self.registers.A.write(addr as u32 & 0x7F);
+        self.registers.DLEN.write(len as u32);
+        self.registers.S.write(Status::Done as u32
+                               | Status::AckError as u32
+                               | Status::ClockStretchTimeout as u32);
+
+        let mut control = ControlFlags::Enable as u32 | ControlFlags::Start as u32;
+        if read {
+            control |= ControlFlags::Read as u32;
+        }
+        self.registers.C.write(control);
+    }
+
+    /// Returns the error flagged in `S`, if any.
+    fn check(&self, deadline: Instant) -> Result<(), I2cError> {
+        let status = self.registers.S.read();
+        if status & Status::AckError as u32 != 0 {
+            Err(I2cError::Nack)
+        } else if status & Status::ClockStretchTimeout as u32 != 0 {
+            Err(I2cError::ClockStretchTimeout)
+        } else if Instant::now() > deadline {
+            Err(I2cError::TimedOut)
+        } else {
+            Ok(())
+        }
+    }
+
+    /// Waits for the current transfer to finish, then clears its status. On
+    /// error, the FIFO is cleared and the controller is left idle.
+    fn finish(&mut self, deadline: Instant, result: Result<(), I2cError>) -> Result<(), I2cError> {
+        let result = result.and_then(|_| {
+            while !self.registers.S.has_mask(Status::Done as u32) {
+                self.check(deadline)?;
+            }
+
+            self.check(deadline)
+        });
+
+        if result.is_err() {
+            self.registers.C.write(ControlFlags::Enable as u32 | ControlFlags::Clear as u32);
+        }
+
+        self.registers.S.write(Status::Done as u32
+                               | Status::AckError as u32
+                               | Status::ClockStretchTimeout as u32);
+        result
+    }
+
+    /// Moves `bytes` into the FIFO as it drains.
+    fn fill(&mut self, bytes: &[u8], deadline: Instant) -> Result<(), I2cError> {
+        for &byte in bytes {
+            while !self.registers.S.has_mask(Status::TxAccepting as u32) {
+                self.check(deadline)?;
+            }
+
+            self.registers.FIFO.write(byte as u32);
+        }
+
+        Ok(())
+    }
+
+    /// Moves bytes out of the FIFO into `buffer` until it is full.
+    fn drain(&mut self, buffer: &mut [u8], deadline: Instant) -> Result<(), I2cError> {
+        for byte in buffer.iter_mut() {
+            while !self.registers.S.has_mask(Status::RxData as u32) {
+                self.check(deadline)?;
+            }
+
+            *byte = self.registers.FIFO.read() as u8;
+        }
+
+        Ok(())
+    }
+
+    /// Returns the time the transaction starting now must finish by.
+    fn deadline(&self) -> Instant {
+        Instant::now() + self.timeout
+    }
+
+    /// Writes `bytes` to the slave at `addr`.
+    pub fn write(&mut self, addr: u8, bytes: &[u8]) -> Result<(), I2cError> {
+        let deadline = self.deadline();
+        self.registers.C.or_mask(ControlFlags::Clear as u32);
+        self.start(addr, bytes.len(), false);
+
+        let result = self.fill(bytes, deadline);
+        self.finish(deadline, result)
+    }
+
+    /// Reads `buffer.len()` bytes from the slave at `addr` into `buffer`.
+    pub fn read(&mut self, addr: u8, buffer: &mut [u8]) -> Result<(), I2cError> {
+        let deadline = self.deadline();
+        self.registers.C.or_mask(ControlFlags::Clear as u32);
+        self.start(addr, buffer.len(), true);
+
+        let result = self.drain(buffer, deadline);
+        self.finish(deadline, result)
+    }
+
+    /// Writes `bytes` to the slave at `addr`, then reads `buffer.len()` bytes
+    /// from it into `buffer`, typically to read a register.
+    ///
+    /// When `bytes` fits in the FIFO (16 bytes), the read follows a repeated
+    /// start without releasing the bus, unless the write finishes before the
+    /// read can be queued. Otherwise a stop is sent in between.
+    pub fn write_read(&mut self, addr: u8, bytes: &[u8], buffer: &mut [u8]) -> Result<(), I2cError> {
+        if bytes.len() > FIFO_DEPTH || bytes.is_empty() {
+            self.write(addr, bytes)?;
+            return self.read(addr, buffer);
+        }
+
+        let deadline = self.deadline();
+        self.registers.C.or_mask(ControlFlags::Clear as u32);
+        self.registers.A.write(addr as u32 & 0x7F);
+        self.registers.DLEN.write(bytes.len() as u32);
+        for &byte in bytes {
+            self.registers.FIFO.write(byte as u32);
+        }
+        self.registers.S.write(Status::Done as u32
+                               | Status::AckError as u32
+                               | Status::ClockStretchTimeout as u32);
+        self.registers.C.write(ControlFlags::Enable as u32 | ControlFlags::Start as u32);
+
+        // Once the write is under way, queueing a read makes the controller
+        // follow it with a repeated start instead of a stop.
+        let mut result = Ok(());
+        let mut stopped = false;
+        loop {
+            if let Err(e) = self.check(deadline) {
+                result = Err(e);
+                break;
+            }
+
+            let status = self.registers.S.read();
+            if status & Status::TransferActive as u32 != 0 {
+                break;
+            } else if status & Status::Done as u32 != 0 {
+                stopped = true;
+                break;
+            }
+        }
+
+        if stopped {
+            // The write finished before it was seen under way, so it ended
+            // with a stop; the read follows as a transfer of its own.
+            self.finish(deadline, Ok(()))?;
+            return self.read(addr, buffer);
+        }
+
+        if result.is_ok() {
+            self.registers.DLEN.write(buffer.len() as u32);
+            self.registers.C.write(ControlFlags::Enable as u32
+                                   | ControlFlags::Start as u32
+                                   | ControlFlags::Read as u32);
+            result = self.drain(buffer, deadline);
+        }
+
+        self.finish(deadline, result)
+    }
+}
+
+impl Drop for I2c {
+    /// Withdraws the claim on GPIO pins 2 and 3.
+    fn drop(&mut self) {
+        gpio::release_owned(&[2, 3], "i2c");
+    }
+}
+
+#[cfg(feature = "hal")]
+mod i2c_hal {
+    use embedded_hal::blocking::i2c;
+    use super::{I2c, I2cError};
+
+    impl i2c::Write for I2c {
+        type Error = I2cError;
+
+        fn write(&mut self, addr: u8, bytes: &[u8]) -> Result<(), I2cError> {
+            I2c::write(self, addr, bytes)
+        }
+    }
+
+    impl i2c::Read for I2c {
+        type Error = I2cError;
+
+        fn read(&mut self, addr: u8, buffer: &mut [u8]) -> Result<(), I2cError> {
+            I2c::read(self, addr, buffer)
+        }
+    }
+
+    impl i2c::WriteRead for I2c {
+        type Error = I2cError;
+
+        fn write_read(&mut self, addr: u8, bytes: &[u8], buffer: &mut [u8]) -> Result<(), I2cError> {
+            I2c::write_read(self, addr, bytes, buffer)
+        }
+    }
+}
@@ -0,0 +1,225 @@
+use mailbox::{tag, Message, MailboxError};
+
+/// The address the firmware leaves the ATAG list at.
+const ATAG_BASE: usize = 0x100;
+
+/// ATAG ids.
+const ATAG_NONE: u32 = 0;
+const ATAG_CORE: u32 = 0x5441_0001;
+const ATAG_MEM: u32 = 0x5441_0002;
+const ATAG_CMDLINE: u32 = 0x5441_0009;
+
+/// The revision bit marking the new-style revision encoding.
+const REVISION_NEW_STYLE: u32 = 1 << 23;
+
+/// A region of physical memory.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct Memory {
+    pub base: u32,
+    pub size: u32,
+}
+
+/// The SoC a board is built around.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum Processor {
+    Bcm2835,
+    Bcm2836,
+    Bcm2837,
+    Bcm2711,
+    Unknown
+}
+
+impl Processor {
+    /// Returns the ARM physical address of this SoC's peripherals.
+    pub fn io_base(self) -> usize {
+        match self {
+            Processor::Bcm2835 => 0x2000_0000,
+            Processor::Bcm2711 => 0xFE00_0000,
+            _ => 0x3F00_0000
+        }
+    }
+}
+
+/// Identifying details of the board, as reported by the firmware.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct BoardInfo {
+    /// The board revision code.
+    pub revision: u32,
+    pub serial: u64,
+    /// The memory given to the ARM cores.
+    pub arm_memory: Memory,
+    /// The memory kept by the VideoCore.
+    pub vc_memory: Memory,
+    /// The MAC address of the onboard Ethernet (or WiFi) interface.
+    pub mac_address: [u8; 6],
+}
+
+impl BoardInfo {
+    /// Queries the firmware for the board's details with a single mailbox
+    /// message. If the firmware doesn't report the ARM memory, the ATAG list
+    /// it passed at boot is consulted instead.
+    pub fn query() -> Result<BoardInfo, MailboxError> {
+        let mut message = Message::new();
+        let revision = message.push(tag::GET_BOARD_REVISION, &[], 1)?;
+        let serial = message.push(tag::GET_BOARD_SERIAL, &[], 2)?;
+        let arm_memory = message.push(tag::GET_ARM_MEMORY, &[], 2)?;
+        let vc_memory = message.push(tag::GET_VC_MEMORY, &[], 2)?;
+        let mac_address = message.push(tag::GET_MAC_ADDRESS, &[], 2)?;
+        message.send()?;
+
+        let words = |handle, len| -> Result<[u32; 2], MailboxError> {
+            let response = message.response(handle)?;
+            let mut words = [0; 2];
+            for (word, value) in words[..len].iter_mut().zip(response.iter()) {
+                *word = *value;
+            }
+            Ok(words)
+        };
+
+        let arm_memory = match words(arm_memory, 2) {
+            Ok(words) => Memory { base: words[0], size: words[1] },
+            Err(e) => atag_memory().ok_or(e)?
+        };
+
+        let serial = words(serial, 2)?;
+        let vc_memory = words(vc_memory, 2)?;
+        let mac = words(mac_address, 2)?;
+        let mut mac_address = [0; 6];
+        for (i, byte) in mac_address.iter_mut().enumerate() {
+            *byte = (mac[i / 4] >> (8 * (i % 4))) as u8;
+        }
+
+        Ok(BoardInfo {
+            revision: words(revision, 1)?[0],
+            serial: (serial[1] as u64) << 32 | serial[0] as u64,
+            arm_memory,
+            vc_memory: Memory { base: vc_memory[0], size: vc_memory[1] },
+            mac_address,
+        })
+    }
+
+    /// Returns `true` if the revision uses the new-style encoding that the
+    /// other fields are decoded from.
+    fn is_new_style(&self) -> bool {
+        self.revision & REVISION_NEW_STYLE != 0
+    }
+
+    /// Returns the board's SoC. Old-style revisions are all BCM2835 boards.
+    pub fn processor(&self) -> Processor {
+        if !self.is_new_style() {
+            return Processor::Bcm2835;
+        }
+
+        match (self.revision >> 12) & 0xF {
+            0 => Processor::Bcm2835,
+            1 => Processor::Bcm2836,
+            2 => Processor::Bcm2837,
+            3 => Processor::Bcm2711,
+            _ => Processor::Unknown
+        }
+    }
+
+    /// Returns the board's model name.
+    pub fn model(&self) -> &'static str {
+        if !self.is_new_style() {
+            return "Raspberry Pi 1";
+        }
+
+        match (self.revision >> 4) & 0xFF {
+            0x00 => "Raspberry Pi 1 Model A",
+            0x01 => "Raspberry Pi 1 Model B",
+            0x02 => "Raspberry Pi 1 Model A+",
+            0x03 => "Raspberry Pi 1 Model B+",
+            0x04 => "Raspberry Pi 2 Model B",
+            0x06 => "Compute Module 1",
+            0x08 => "Raspberry Pi 3 Model B",
+            0x09 => "Raspberry Pi Zero",
+            0x0A => "Compute Module 3",
+            0x0C => "Raspberry Pi Zero W",
+            0x0D => "Raspberry Pi 3 Model B+",
+            0x0E => "Raspberry Pi 3 Model A+",
+            0x10 => "Compute Module 3+",
+            0x11 => "Raspberry Pi 4 Model B",
+            _ => "unknown model"
+        }
+    }
+
+    /// Returns the amount of RAM fitted, in MiB, or `None` for old-style
+    /// revisions.
+    pub fn memory_mib(&self) -> Option<u32> {
+        if self.is_new_style() {
+            Some(256 << ((self.revision >> 20) & 0x7))
+        } else {
+            None
+        }
+    }
+
+    /// Returns the ARM physical address of the board's peripherals.
+    pub fn io_base(&self) -> usize {
+        self.processor().io_base()
+    }
+}
+
+/// Returns the address and size in words of the first ATAG with id `wanted`
+/// in the list the firmware passed at boot, or `None` if there is no such tag
+/// or no ATAG list (e.g. a device tree was passed instead).
+fn find_atag(wanted: u32) -> Option<(usize, usize)> {
+    // Returns the size in words and id of the ATAG at `addr`.
+    let header = |addr: usize| unsafe {
+        let header = addr as *const u32;
+        (*header, *header.offset(1))
+    };
+
+    // A list always opens with ATAG_CORE.
+    if header(ATAG_BASE).1 != ATAG_CORE {
+        return None;
+    }
+
+    let mut addr = ATAG_BASE;
+    loop {
+        let (size, id) = header(addr);
+        if id == ATAG_NONE || size == 0 {
+            return None;
+        }
+
+        if id == wanted {
+            return Some((addr, size as usize));
+        }
+
+        addr += size as usize * 4;
+    }
+}
+
+/// Returns the first memory region in the ATAG list the firmware passed at
+/// boot, or `None` if there is no ATAG list (e.g. a device tree was passed
+/// instead).
+pub fn atag_memory() -> Option<Memory> {
+    let (addr, _) = find_atag(ATAG_MEM)?;
+
+    // The data follows the two word header: the size, then the base.
+    let data = (addr + 8) as *const u32;
+    unsafe { Some(Memory { size: *data, base: *data.offset(1) }) }
+}
+
+/// Returns the kernel command line the firmware passed at boot: the contents
+/// of `cmdline.txt` on the boot partition, with the firmware's own settings
+/// prepended. Returns `None` if there is no ATAG list or it isn't valid
+/// UTF-8.
+pub fn atag_cmdline() -> Option<&'static str> {
+    let (addr, size) = find_atag(ATAG_CMDLINE)?;
+
+    // The string is NUL-terminated and padded to a whole number of words.
+    let len = size.saturating_sub(2) * 4;
+    let data = unsafe { ::core::slice::from_raw_parts((addr + 8) as *const u8, len) };
+    let len = data.iter().position(|&b| b == 0).unwrap_or(data.len());
+    ::core::str::from_utf8(&data[..len]).ok()
+}
+
+/// Returns the value of the `key=value` argument on the kernel command line
+/// (see `atag_cmdline()`), or `None` if `key` isn't given.
+pub fn cmdline_arg(key: &str) -> Option<&'static str> {
+    atag_cmdline()?
+        .split_whitespace()
+        .find(|arg| arg.len() > key.len() && arg.starts_with(key) && arg.as_bytes()[key.len()] == b'=')
+        .map(|arg| &arg[key.len() + 1..])
+}
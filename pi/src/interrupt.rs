@@ -0,0 +1,153 @@
+use common::IO_BASE;
+use volatile::prelude::*;
+use volatile::{Volatile, ReadVolatile};
+
+/// The base address of the ARM interrupt controller's registers.
+const INT_BASE: usize = IO_BASE + 0xB000 + 0x200;
+
+/// The number of GPU interrupts, spread over two banks of 32.
+pub const NUM_INTERRUPTS: usize = 64;
+
+/// The `FIQ_CONTROL` bit enabling the FIQ. The low bits select its source.
+const FIQ_ENABLE: u32 = 1 << 7;
+
+/// A GPU peripheral interrupt, numbered as in the BCM2837 documentation.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum Interrupt {
+    /// System timer compare channel `C1`.
+    Timer1 = 1,
+    /// System timer compare channel `C3`.
+    Timer3 = 3,
+    Usb = 9,
+    /// Completion of a transfer on DMA channel 0.
+    Dma0 = 16,
+    Dma1 = 17,
+    Dma2 = 18,
+    Dma3 = 19,
+    Dma4 = 20,
+    Dma5 = 21,
+    Dma6 = 22,
+    /// The auxiliary peripherals: the mini UART and the SPI1/SPI2 masters.
+    Aux = 29,
+    /// GPIO events on pins 0-27.
+    Gpio0 = 49,
+    /// GPIO events on pins 28-45.
+    Gpio1 = 50,
+    /// GPIO events on pins 46-53.
+    Gpio2 = 51,
+    /// GPIO events on any pin.
+    Gpio3 = 52,
+    /// The PL011 UART.
+    Uart = 57,
+    /// The Arasan EMMC controller.
+    Emmc = 62,
+}
+
+impl Interrupt {
+    /// The register bank holding this interrupt's bits, and its mask there.
+    fn bank_and_mask(self) -> (usize, u32) {
+        let number = self as usize;
+        (number / 32, 1 << (number % 32))
+    }
+}
+
+/// An interrupt in the ARM-specific basic bank.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum Basic {
+    ArmTimer = 0,
+    ArmMailbox = 1,
+    ArmDoorbell0 = 2,
+    ArmDoorbell1 = 3,
+    Gpu0Halted = 4,
+    Gpu1Halted = 5,
+    IllegalAccessType1 = 6,
+    IllegalAccessType0 = 7,
+}
+
+#[repr(C)]
+#[allow(non_snake_case)]
+struct Registers {
+    IRQ_BASIC_PENDING: ReadVolatile<u32>,
+    IRQ_PENDING: [ReadVolatile<u32>; 2],
+    FIQ_CONTROL: Volatile<u32>,
+    ENABLE_IRQS: [Volatile<u32>; 2],
+    ENABLE_BASIC_IRQS: Volatile<u32>,
+    DISABLE_IRQS: [Volatile<u32>; 2],
+    DISABLE_BASIC_IRQS: Volatile<u32>,
+}
+
+/// An interrupt controller. Used to enable and disable interrupts as well as
+/// to detect which interrupts are pending.
+pub struct Controller {
+    registers: &'static mut Registers
+}
+
+impl Controller {
+    /// Returns a new handle to the interrupt controller.
+    pub fn new() -> Controller {
+        Controller {
+            registers: unsafe { &mut *(INT_BASE as *mut Registers) },
+        }
+    }
+
+    /// Enables the interrupt `int`.
+    pub fn enable(&mut self, int: Interrupt) {
+        let (bank, mask) = int.bank_and_mask();
+        // Writing a 0 has no effect, so the other bits needn't be preserved.
+        self.registers.ENABLE_IRQS[bank].write(mask);
+    }
+
+    /// Disables the interrupt `int`.
+    pub fn disable(&mut self, int: Interrupt) {
+        let (bank, mask) = int.bank_and_mask();
+        self.registers.DISABLE_IRQS[bank].write(mask);
+    }
+
+    /// Disables every interrupt, basic interrupt, and the FIQ, as at reset.
+    pub fn disable_all(&mut self) {
+        self.registers.DISABLE_IRQS[0].write(!0);
+        self.registers.DISABLE_IRQS[1].write(!0);
+        self.registers.DISABLE_BASIC_IRQS.write(!0);
+        self.disable_fiq();
+    }
+
+    /// Returns `true` if `int` is pending.
+    pub fn is_pending(&self, int: Interrupt) -> bool {
+        let (bank, mask) = int.bank_and_mask();
+        self.registers.IRQ_PENDING[bank].has_mask(mask)
+    }
+
+    /// Returns the pending GPU interrupts, where bit `n` is set if interrupt
+    /// number `n` is pending.
+    pub fn pending(&self) -> u64 {
+        self.registers.IRQ_PENDING[0].read() as u64
+            | (self.registers.IRQ_PENDING[1].read() as u64) << 32
+    }
+
+    /// Raises `int` as the FIQ rather than as an IRQ, replacing any previous
+    /// FIQ source: only one interrupt can be the FIQ.
+    pub fn enable_fiq(&mut self, int: Interrupt) {
+        self.disable(int);
+        self.registers.FIQ_CONTROL.write(FIQ_ENABLE | int as u32);
+    }
+
+    /// Stops raising any interrupt as the FIQ.
+    pub fn disable_fiq(&mut self) {
+        self.registers.FIQ_CONTROL.write(0);
+    }
+
+    /// Enables the basic interrupt `int`.
+    pub fn enable_basic(&mut self, int: Basic) {
+        self.registers.ENABLE_BASIC_IRQS.write(1 << int as u32);
+    }
+
+    /// Disables the basic interrupt `int`.
+    pub fn disable_basic(&mut self, int: Basic) {
+        self.registers.DISABLE_BASIC_IRQS.write(1 << int as u32);
+    }
+
+    /// Returns `true` if the basic interrupt `int` is pending.
+    pub fn is_basic_pending(&self, int: Basic) -> bool {
+        self.registers.IRQ_BASIC_PENDING.has_mask(1 << int as u32)
+    }
+}
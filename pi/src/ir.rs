@@ -0,0 +1,240 @@
+use core::cell::UnsafeCell;
+use core::sync::atomic::{AtomicUsize, Ordering};
+
+use gpio::{self, Edge, Gpio, Input};
+use timer::Timer;
+
+/// NEC protocol timings, in microseconds.
+mod timing {
+    /// The burst opening a frame or a repeat code.
+    pub const LEADER_BURST: u32 = 9000;
+    /// The space after the leader of a frame, and of a repeat code.
+    pub const FRAME_SPACE: u32 = 4500;
+    pub const REPEAT_SPACE: u32 = 2250;
+    /// The burst before every bit's space, and closing a frame.
+    pub const BIT_BURST: u32 = 562;
+    /// The space making a bit a 0, or a 1.
+    pub const ZERO_SPACE: u32 = 562;
+    pub const ONE_SPACE: u32 = 1687;
+    /// The most between the end of a frame or repeat code and the end of a
+    /// repeat code following it; they're sent every 108ms.
+    pub const REPEAT_WINDOW: u32 = 150_000;
+}
+
+/// How far a measured time may stray from the nominal one, in percent.
+/// Receivers stretch and shrink bursts noticeably.
+const TOLERANCE_PERCENT: u32 = 35;
+
+/// The number of bits in a frame.
+const FRAME_BITS: u32 = 32;
+
+/// The number of events queued for `poll()`. Must be a power of two.
+const QUEUE_SIZE: usize = 16;
+
+/// A decoded NEC remote control code.
+#[derive(Debug, Default, Clone, Copy, PartialEq, Eq)]
+pub struct NecEvent {
+    /// The device address: 8 bits for NEC, or 16 for extended NEC, whose
+    /// frames carry no inverted address.
+    pub address: u16,
+    pub command: u8,
+    /// Whether this is a repeat code, sent while the button is held, of the
+    /// last code.
+    pub repeat: bool,
+}
+
+/// Where the decoder is in a frame, after the edge ending the period
+/// named.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+enum State {
+    /// Waiting for a leader burst.
+    Idle,
+    /// After a leader burst.
+    Leader,
+    /// After a frame's leader space, or a bit's space, with `bits` bits of
+    /// the frame received so far.
+    Space { bits: u32, count: u32 },
+    /// After a bit's burst.
+    Burst { bits: u32, count: u32 },
+    /// After a repeat code's leader space.
+    RepeatSpace,
+}
+
+/// The decoder's state, owned by the edge handler once started.
+struct Receiver {
+    number: u8,
+    pin: Gpio<Input>,
+    state: State,
+    /// The timer counter at the last edge.
+    last_edge: u32,
+    /// The last code decoded, and when it or a repeat of it ended.
+    last_event: Option<(NecEvent, u32)>,
+}
+
+static mut RECEIVER: Option<Receiver> = None;
+
+/// A single-producer single-consumer queue of events from the edge handler
+/// to `poll()`, working like `RingBuffer`.
+struct Queue {
+    events: UnsafeCell<[NecEvent; QUEUE_SIZE]>,
+    head: AtomicUsize,
+    tail: AtomicUsize,
+}
+
+unsafe impl Sync for Queue {  }
+
+static QUEUE: Queue = Queue {
+    events: UnsafeCell::new([NecEvent { address: 0, command: 0, repeat: false }; QUEUE_SIZE]),
+    head: AtomicUsize::new(0),
+    tail: AtomicUsize::new(0),
+};
+
+impl Queue {
+    /// Appends `event`, dropping it if the queue is full. Called only from
+    /// the edge handler.
+    fn push(&self, event: NecEvent) {
+        let tail = self.tail.load(Ordering::Relaxed);
+        if tail.wrapping_sub(self.head.load(Ordering::Acquire)) == QUEUE_SIZE {
+            return;
+        }
+
+        unsafe { (*self.events.get())[tail % QUEUE_SIZE] = event; }
+        self.tail.store(tail.wrapping_add(1), Ordering::Release);
+    }
+
+    /// Removes and returns the oldest event, if any.
+    fn pop(&self) -> Option<NecEvent> {
+        let head = self.head.load(Ordering::Relaxed);
+        if head == self.tail.load(Ordering::Acquire) {
+            return None;
+        }
+
+        let event = unsafe { (*self.events.get())[head % QUEUE_SIZE] };
+        self.head.store(head.wrapping_add(1), Ordering::Release);
+        Some(event)
+    }
+}
+
+/// Returns whether `measured` is within `TOLERANCE_PERCENT` of `nominal`.
+fn near(measured: u32, nominal: u32) -> bool {
+    let slack = nominal * TOLERANCE_PERCENT / 100;
+    measured >= nominal - slack && measured <= nominal + slack
+}
+
+/// Starts decoding NEC codes from an IR receiver module, such as a TSOP38238,
+/// whose output is on pin `pin`, replacing any receiver started before.
+/// Decoded codes are queued for `poll()`.
+///
+/// Edges are timed from the GPIO interrupt, so `gpio::handle_irq()` must be
+/// called for `Interrupt::Gpio3`.
+///
+/// # Panics
+///
+/// Panics if `pin` > `53`.
+pub fn start(pin: u8) {
+    stop();
+
+    // Receivers drive their output low during bursts and idle high.
+    let mut input = Gpio::new(pin).into_input_pullup();
+    input.enable_edge_detect(Edge::Both);
+    gpio::claim(&[pin], "ir");
+
+    unsafe {
+        RECEIVER = Some(Receiver {
+            number: pin,
+            pin: input,
+            state: State::Idle,
+            last_edge: Timer::new().counter_low(),
+            last_event: None,
+        });
+    }
+
+    gpio::register_edge_handler(pin, on_edge);
+}
+
+/// Stops decoding. Queued codes can still be read with `poll()`.
+pub fn stop() {
+    unsafe {
+        if let Some(mut receiver) = RECEIVER.take() {
+            gpio::unregister_edge_handler(receiver.number);
+            receiver.pin.disable_edge_detect();
+            gpio::release_owned(&[receiver.number], "ir");
+        }
+    }
+}
+
+/// Returns the oldest code decoded and not yet returned, if any. This
+/// method does not block.
+pub fn poll() -> Option<NecEvent> {
+    QUEUE.pop()
+}
+
+/// Times the period the edge on `_pin` ended and advances the decoder.
+fn on_edge(_pin: u8) {
+    let receiver = match unsafe { RECEIVER.as_mut() } {
+        Some(receiver) => receiver,
+        None => return
+    };
+
+    let now = Timer::new().counter_low();
+    let duration = now.wrapping_sub(receiver.last_edge);
+    receiver.last_edge = now;
+
+    // A high level now means a burst just ended.
+    let burst = receiver.pin.level();
+    receiver.state = match (receiver.state, burst) {
+        (_, true) if near(duration, timing::LEADER_BURST) => State::Leader,
+        (State::Leader, false) if near(duration, timing::FRAME_SPACE) => {
+            State::Space { bits: 0, count: 0 }
+        }
+        (State::Leader, false) if near(duration, timing::REPEAT_SPACE) => State::RepeatSpace,
+        (State::Space { bits, count }, true) if near(duration, timing::BIT_BURST) => {
+            State::Burst { bits, count }
+        }
+        (State::Burst { bits, count }, false)
+            if near(duration, timing::ZERO_SPACE) || near(duration, timing::ONE_SPACE) => {
+            let bit = if near(duration, timing::ONE_SPACE) { 1 } else { 0 };
+            let (bits, count) = (bits | bit << count, count + 1);
+            if count < FRAME_BITS {
+                State::Space { bits, count }
+            } else {
+                // The closing burst isn't waited for: the frame is whole.
+                if let Some(event) = decode(bits) {
+                    receiver.last_event = Some((event, now));
+                    QUEUE.push(event);
+                }
+
+                State::Idle
+            }
+        }
+        (State::RepeatSpace, true) if near(duration, timing::BIT_BURST) => {
+            if let Some((event, at)) = receiver.last_event {
+                if now.wrapping_sub(at) <= timing::REPEAT_WINDOW {
+                    receiver.last_event = Some((event, now));
+                    QUEUE.push(NecEvent { repeat: true, ..event });
+                }
+            }
+
+            State::Idle
+        }
+        _ => State::Idle
+    };
+}
+
+/// Returns the code in the 32 frame bits `bits`, received least significant
+/// bit first, or `None` if the command's check byte doesn't match.
+fn decode(bits: u32) -> Option<NecEvent> {
+    let (address, address_check) = (bits as u8, (bits >> 8) as u8);
+    let (command, command_check) = ((bits >> 16) as u8, (bits >> 24) as u8);
+    if command != !command_check {
+        return None;
+    }
+
+    let address = if address == !address_check {
+        address as u16
+    } else {
+        bits as u16
+    };
+
+    Some(NecEvent { address, command, repeat: false })
+}
@@ -0,0 +1,33 @@
+use mailbox::{self, tag, MailboxError};
+use timer::spin_sleep_ms;
+
+/// The GPIO expander pin driving the green ACT LED on the Pi 3. Expander pins
+/// are numbered from 128.
+const ACT_LED_PIN: u32 = 130;
+
+/// How long the LED stays on, and then off, for each pulse of `blink_code()`.
+const BLINK_MS: u64 = 200;
+
+/// The pause after the last pulse of `blink_code()`, separating repetitions.
+const CODE_GAP_MS: u64 = 1000;
+
+/// Turns the green ACT LED on or off. On the Pi 3 the LED hangs off the GPU's
+/// GPIO expander, so this goes through the mailbox rather than `gpio`.
+pub fn act_led(on: bool) -> Result<(), MailboxError> {
+    let mut values = [ACT_LED_PIN, on as u32];
+    mailbox::call_property(tag::SET_GPIO_STATE, &mut values)
+}
+
+/// Blinks the ACT LED `n` times followed by a pause, signalling status code
+/// `n` without a serial cable. Blocks until the pause ends; call it in a loop
+/// to repeat the code. Mailbox errors are ignored.
+pub fn blink_code(n: u32) {
+    for _ in 0..n {
+        let _ = act_led(true);
+        spin_sleep_ms(BLINK_MS);
+        let _ = act_led(false);
+        spin_sleep_ms(BLINK_MS);
+    }
+
+    spin_sleep_ms(CODE_GAP_MS);
+}
@@ -11,8 +11,53 @@
 #[cfg(feature = "std")]
 extern crate core;
 extern crate volatile;
+#[cfg(feature = "hal")]
+extern crate embedded_hal;
+#[cfg(feature = "hal")]
+extern crate nb;
 
 pub mod timer;
 pub mod uart;
+pub mod pl011;
 pub mod gpio;
 pub mod common;
+pub mod ring_buffer;
+pub mod dma;
+pub mod pwm;
+pub mod soft_pwm;
+pub mod button;
+pub mod mailbox;
+pub mod led;
+pub mod generic_timer;
+pub mod power;
+pub mod interrupt;
+pub mod local_interrupt;
+pub mod spi;
+pub mod i2c;
+pub mod rng;
+pub mod framebuffer;
+pub mod audio;
+pub mod emmc;
+pub mod sysinfo;
+pub mod clock;
+pub mod info;
+pub mod usb;
+pub mod net;
+pub mod onewire;
+pub mod ws2812;
+pub mod hd44780;
+pub mod font;
+pub mod ssd1306;
+pub mod rtc;
+pub mod mcp3008;
+pub mod servo;
+pub mod ir;
+pub mod encoder;
+pub mod nrf24;
+pub mod mcp2515;
+pub mod fat;
+pub mod bootcfg;
+pub mod crc;
+pub mod resident;
+#[cfg(feature = "std")]
+pub mod ymodem;
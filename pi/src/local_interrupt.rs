@@ -0,0 +1,152 @@
+use volatile::prelude::*;
+use volatile::{Volatile, ReadVolatile, WriteVolatile, Reserved};
+
+/// The base address of the ARM local peripherals. These are per-core and are
+/// not behind the VideoCore's `IO_BASE` window.
+const LOCAL_BASE: usize = 0x4000_0000;
+
+/// The number of cores.
+pub const NUM_CORES: usize = 4;
+
+/// The number of mailboxes each core has.
+pub const NUM_MAILBOXES: usize = 4;
+
+/// One of the four timers of a core's ARM generic timer.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum CoreTimer {
+    SecurePhysical = 0,
+    NonSecurePhysical = 1,
+    Hypervisor = 2,
+    Virtual = 3,
+}
+
+/// A source of a core's IRQ, numbered by its bit in the core's IRQ source
+/// register.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum LocalInterrupt {
+    CntPs = 0,
+    CntPns = 1,
+    CntHp = 2,
+    CntV = 3,
+    Mailbox0 = 4,
+    Mailbox1 = 5,
+    Mailbox2 = 6,
+    Mailbox3 = 7,
+    /// The GPU interrupt controller, if routed to this core.
+    Gpu = 8,
+    Pmu = 9,
+    Axi = 10,
+    LocalTimer = 11,
+}
+
+impl From<CoreTimer> for LocalInterrupt {
+    fn from(timer: CoreTimer) -> LocalInterrupt {
+        match timer {
+            CoreTimer::SecurePhysical => LocalInterrupt::CntPs,
+            CoreTimer::NonSecurePhysical => LocalInterrupt::CntPns,
+            CoreTimer::Hypervisor => LocalInterrupt::CntHp,
+            CoreTimer::Virtual => LocalInterrupt::CntV
+        }
+    }
+}
+
+#[repr(C)]
+#[allow(non_snake_case)]
+struct Registers {
+    CONTROL: Volatile<u32>,
+    __r0: Reserved<u32>,
+    PRESCALER: Volatile<u32>,
+    GPU_ROUTING: Volatile<u32>,
+    __r1: [Reserved<u32>; 12],
+    TIMER_INT_CTL: [Volatile<u32>; NUM_CORES],
+    MAILBOX_INT_CTL: [Volatile<u32>; NUM_CORES],
+    IRQ_SOURCE: [ReadVolatile<u32>; NUM_CORES],
+    FIQ_SOURCE: [ReadVolatile<u32>; NUM_CORES],
+    MAILBOX_SET: [[WriteVolatile<u32>; NUM_MAILBOXES]; NUM_CORES],
+    MAILBOX_CLR: [[Volatile<u32>; NUM_MAILBOXES]; NUM_CORES],
+}
+
+/// Returns the number of the calling core.
+pub fn core_id() -> usize {
+    let mpidr: u64;
+    unsafe { asm!("mrs $0, mpidr_el1" : "=r"(mpidr)); }
+    (mpidr & 0b11) as usize
+}
+
+/// The local interrupt controller: routes the per-core timer and mailbox
+/// interrupts, and the GPU's, to cores and reports each core's IRQ sources.
+///
+/// Core numbers above 3 panic with an out-of-bounds index.
+pub struct Controller {
+    registers: &'static mut Registers
+}
+
+impl Controller {
+    /// Returns a new handle to the local interrupt controller.
+    pub fn new() -> Controller {
+        Controller {
+            registers: unsafe { &mut *(LOCAL_BASE as *mut Registers) },
+        }
+    }
+
+    /// Routes interrupts from `core`'s `timer` to `core` as an IRQ.
+    pub fn enable_timer(&mut self, core: usize, timer: CoreTimer) {
+        self.registers.TIMER_INT_CTL[core].or_mask(1 << timer as u32);
+    }
+
+    /// Stops routing interrupts from `core`'s `timer` to `core`.
+    pub fn disable_timer(&mut self, core: usize, timer: CoreTimer) {
+        self.registers.TIMER_INT_CTL[core].and_mask(!(1 << timer as u32));
+    }
+
+    /// Raises an IRQ on `core` while any bit of its mailbox `mailbox` is set.
+    pub fn enable_mailbox(&mut self, core: usize, mailbox: usize) {
+        self.registers.MAILBOX_INT_CTL[core].or_mask(1 << mailbox);
+    }
+
+    /// Stops raising IRQs on `core` for its mailbox `mailbox`.
+    pub fn disable_mailbox(&mut self, core: usize, mailbox: usize) {
+        self.registers.MAILBOX_INT_CTL[core].and_mask(!(1 << mailbox));
+    }
+
+    /// Routes the GPU interrupt controller's IRQ to `core`. Only one core
+    /// receives it.
+    pub fn route_gpu_irq(&mut self, core: usize) {
+        let routing = self.registers.GPU_ROUTING.read() & !0b11;
+        self.registers.GPU_ROUTING.write(routing | core as u32);
+    }
+
+    /// Routes the GPU interrupt controller's FIQ to `core`. Only one core
+    /// receives it.
+    pub fn route_gpu_fiq(&mut self, core: usize) {
+        let routing = self.registers.GPU_ROUTING.read() & !0b1100;
+        self.registers.GPU_ROUTING.write(routing | (core as u32) << 2);
+    }
+
+    /// Returns `core`'s IRQ source register, where bit `n` is set if the
+    /// `LocalInterrupt` numbered `n` is pending.
+    pub fn irq_sources(&self, core: usize) -> u32 {
+        self.registers.IRQ_SOURCE[core].read()
+    }
+
+    /// Returns `true` if `int` is raising an IRQ on `core`.
+    pub fn is_pending(&self, core: usize, int: LocalInterrupt) -> bool {
+        self.registers.IRQ_SOURCE[core].has_mask(1 << int as u32)
+    }
+
+    /// Sets `bits` in `core`'s mailbox `mailbox`, interrupting `core` if the
+    /// mailbox's interrupt is enabled.
+    pub fn send(&mut self, core: usize, mailbox: usize, bits: u32) {
+        self.registers.MAILBOX_SET[core][mailbox].write(bits);
+    }
+
+    /// Returns the bits set in `core`'s mailbox `mailbox`.
+    pub fn read_mailbox(&self, core: usize, mailbox: usize) -> u32 {
+        self.registers.MAILBOX_CLR[core][mailbox].read()
+    }
+
+    /// Clears `bits` in `core`'s mailbox `mailbox`, acknowledging them.
+    pub fn clear_mailbox(&mut self, core: usize, mailbox: usize, bits: u32) {
+        self.registers.MAILBOX_CLR[core][mailbox].write(bits);
+    }
+}
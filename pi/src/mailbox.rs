@@ -0,0 +1,252 @@
+use common::IO_BASE;
+use dma::bus_address;
+use volatile::prelude::*;
+use volatile::{Volatile, ReadVolatile, Reserved};
+
+/// The base address of the ARM-to-VideoCore mailbox registers.
+const MAILBOX_REG_BASE: usize = IO_BASE + 0xB880;
+
+/// The mailbox channel for property tags, from the ARM to the VideoCore.
+const PROPERTY_CHANNEL: u32 = 8;
+
+/// The size of a property buffer, in words.
+pub const BUFFER_WORDS: usize = 128;
+
+/// The number of words a tag's header takes: its id, the size of its value
+/// buffer, and its request/response code.
+const TAG_HEADER_WORDS: usize = 3;
+
+/// The largest number of value words `call_property()` supports.
+pub const MAX_VALUES: usize = 8;
+
+/// Request/response codes in a property buffer's header.
+#[repr(u32)]
+enum Code {
+    Request = 0,
+    Success = 0x8000_0000,
+}
+
+/// The bit set in a tag's code once the firmware has responded to it. The
+/// low bits then hold the length of the response in bytes.
+const TAG_RESPONSE: u32 = 1 << 31;
+
+/// Enum representing bit fields of the `STATUS` register.
+#[repr(u32)]
+enum Status {
+    Empty = 1 << 30,
+    Full = 1 << 31,
+}
+
+/// Property tags understood by the VideoCore firmware. The value layout of
+/// each is given as `request -> response`.
+pub mod tag {
+    /// `[] -> [revision]`
+    pub const GET_FIRMWARE_REVISION: u32 = 0x0000_0001;
+    /// `[] -> [model]`
+    pub const GET_BOARD_MODEL: u32 = 0x0001_0001;
+    /// `[] -> [revision]`
+    pub const GET_BOARD_REVISION: u32 = 0x0001_0002;
+    /// `[] -> [6 bytes of MAC address, packed]`
+    pub const GET_MAC_ADDRESS: u32 = 0x0001_0003;
+    /// `[] -> [serial low, serial high]`
+    pub const GET_BOARD_SERIAL: u32 = 0x0001_0004;
+    /// `[] -> [base address, size]`
+    pub const GET_ARM_MEMORY: u32 = 0x0001_0005;
+    /// `[] -> [base address, size]`
+    pub const GET_VC_MEMORY: u32 = 0x0001_0006;
+    /// `[device, state] -> [device, state]`; state bit 0 is "on", bit 1 asks
+    /// the firmware to wait for the device to settle.
+    pub const SET_POWER_STATE: u32 = 0x0002_8001;
+    /// `[clock] -> [clock, rate in Hz]`
+    pub const GET_CLOCK_RATE: u32 = 0x0003_0002;
+    /// `[clock] -> [clock, rate in Hz]`
+    pub const GET_MAX_CLOCK_RATE: u32 = 0x0003_0004;
+    /// `[clock] -> [clock, rate in Hz]`
+    pub const GET_MIN_CLOCK_RATE: u32 = 0x0003_0007;
+    /// `[clock, rate in Hz, skip turbo] -> [clock, rate in Hz]`
+    pub const SET_CLOCK_RATE: u32 = 0x0003_8002;
+    /// `[voltage] -> [voltage, value in microvolts]`
+    pub const GET_VOLTAGE: u32 = 0x0003_0003;
+    /// `[sensor] -> [sensor, temperature in thousandths of a degree C]`
+    pub const GET_TEMPERATURE: u32 = 0x0003_0006;
+    /// `[sensor] -> [sensor, temperature in thousandths of a degree C]`
+    pub const GET_MAX_TEMPERATURE: u32 = 0x0003_000A;
+    /// Reads the state of a GPIO expander pin: `[pin] -> [pin, state]`.
+    pub const GET_GPIO_STATE: u32 = 0x0003_0041;
+    /// Sets the state of a GPIO expander pin: `[pin, state] -> [pin, state]`.
+    pub const SET_GPIO_STATE: u32 = 0x0003_8041;
+    /// `[alignment] -> [bus address, size in bytes]`
+    pub const ALLOCATE_BUFFER: u32 = 0x0004_0001;
+    /// `[] -> []`
+    pub const RELEASE_BUFFER: u32 = 0x0004_8001;
+    /// `[] -> [bytes per line]`
+    pub const GET_PITCH: u32 = 0x0004_0008;
+    /// `[width, height] -> [width, height]`
+    pub const SET_PHYSICAL_SIZE: u32 = 0x0004_8003;
+    /// `[width, height] -> [width, height]`
+    pub const SET_VIRTUAL_SIZE: u32 = 0x0004_8004;
+    /// `[bits per pixel] -> [bits per pixel]`
+    pub const SET_DEPTH: u32 = 0x0004_8005;
+    /// `[0 for BGR, 1 for RGB] -> [order]`
+    pub const SET_PIXEL_ORDER: u32 = 0x0004_8006;
+    /// `[x, y] -> [x, y]`
+    pub const SET_VIRTUAL_OFFSET: u32 = 0x0004_8009;
+}
+
+#[repr(C)]
+#[allow(non_snake_case)]
+struct Registers {
+    READ: ReadVolatile<u32>,
+    __r0: [Reserved<u32>; 3],
+    PEEK: ReadVolatile<u32>,
+    SENDER: ReadVolatile<u32>,
+    STATUS: ReadVolatile<u32>,
+    CONFIG: Volatile<u32>,
+    WRITE: Volatile<u32>,
+}
+
+/// An error from a property channel request.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum MailboxError {
+    /// The tags don't fit in the buffer.
+    BufferFull,
+    /// The firmware couldn't parse the buffer.
+    Rejected,
+    /// The firmware didn't respond to the tag with this id; it may not
+    /// support it.
+    NoResponse(u32),
+}
+
+/// The storage of a `Message`. The VideoCore ignores the low four bits of the
+/// buffer's address, so it must be 16-byte aligned.
+#[repr(C, align(16))]
+struct Buffer([u32; BUFFER_WORDS]);
+
+/// A tag appended to a `Message`, used to find its response.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct Tag {
+    /// The word index of the tag's id.
+    offset: usize,
+}
+
+/// A property channel message: a sequence of tags sent to the VideoCore
+/// together, and the responses written over them.
+///
+/// ```rust,ignore
+/// let mut message = Message::new();
+/// let serial = message.push(tag::GET_BOARD_SERIAL, &[], 2)?;
+/// message.send()?;
+/// let words = message.response(serial)?;
+/// ```
+pub struct Message {
+    buffer: Buffer,
+    /// The number of words used, including the two-word buffer header but not
+    /// the end tag.
+    len: usize,
+}
+
+impl Message {
+    /// Returns a new message with no tags.
+    pub fn new() -> Message {
+        Message { buffer: Buffer([0; BUFFER_WORDS]), len: 2 }
+    }
+
+    /// Appends the tag `id` with the request values `request`, leaving room
+    /// for a response of `response_words` words.
+    pub fn push(&mut self, id: u32, request: &[u32], response_words: usize) -> Result<Tag, MailboxError> {
+        let value_words = ::core::cmp::max(request.len(), response_words);
+        let end = self.len + TAG_HEADER_WORDS + value_words;
+        // Leave room for the end tag.
+        if end + 1 > BUFFER_WORDS {
+            return Err(MailboxError::BufferFull);
+        }
+
+        let offset = self.len;
+        let words = &mut self.buffer.0;
+        words[offset] = id;
+        words[offset + 1] = (value_words * 4) as u32;
+        words[offset + 2] = Code::Request as u32;
+        words[offset + 3..offset + 3 + request.len()].copy_from_slice(request);
+        for word in &mut words[offset + 3 + request.len()..end] {
+            *word = 0;
+        }
+
+        self.len = end;
+        Ok(Tag { offset })
+    }
+
+    /// Sends the message and blocks until the VideoCore responds.
+    pub fn send(&mut self) -> Result<(), MailboxError> {
+        let len = self.len;
+        {
+            let words = &mut self.buffer.0;
+            words[len] = 0;
+            // The total size, padded to a multiple of 16 bytes.
+            words[0] = (((len + 1) * 4 + 15) & !15) as u32;
+            words[1] = Code::Request as u32;
+        }
+
+        let registers = unsafe { &mut *(MAILBOX_REG_BASE as *mut Registers) };
+        let message = bus_address(&mut self.buffer as *mut Buffer as usize) | PROPERTY_CHANNEL;
+
+        while registers.STATUS.has_mask(Status::Full as u32) {  }
+        registers.WRITE.write(message);
+
+        // Responses for other channels aren't ours to consume, but this is the
+        // only channel in use.
+        loop {
+            while registers.STATUS.has_mask(Status::Empty as u32) {  }
+            if registers.READ.read() == message {
+                break;
+            }
+        }
+
+        // The firmware wrote the buffer behind the compiler's back.
+        unsafe { asm!("dsb sy" ::: "memory" : "volatile"); }
+
+        if self.buffer.0[1] != Code::Success as u32 {
+            return Err(MailboxError::Rejected);
+        }
+
+        Ok(())
+    }
+
+    /// Returns the response values of `tag`, once the message has been sent.
+    /// The response may be shorter than the room reserved for it.
+    pub fn response(&self, tag: Tag) -> Result<&[u32], MailboxError> {
+        let words = &self.buffer.0;
+        let code = words[tag.offset + 2];
+        if code & TAG_RESPONSE == 0 {
+            return Err(MailboxError::NoResponse(words[tag.offset]));
+        }
+
+        let capacity = words[tag.offset + 1] as usize / 4;
+        let len = ::core::cmp::min(((code & !TAG_RESPONSE) as usize + 3) / 4, capacity);
+        let start = tag.offset + TAG_HEADER_WORDS;
+        Ok(&words[start..start + len])
+    }
+}
+
+/// Sends the single property tag `id` with `values` as its request and blocks
+/// until the VideoCore responds, overwriting `values` with the response.
+///
+/// # Panics
+///
+/// Panics if `values` is longer than `MAX_VALUES`.
+pub fn call_property(id: u32, values: &mut [u32]) -> Result<(), MailboxError> {
+    if values.len() > MAX_VALUES {
+        panic!("mailbox::call_property(): {} values exceeds maximum of {}",
+               values.len(), MAX_VALUES);
+    }
+
+    let mut message = Message::new();
+    let tag = message.push(id, values, values.len())?;
+    message.send()?;
+
+    let response = message.response(tag)?;
+    for (value, response) in values.iter_mut().zip(response.iter()) {
+        *value = *response;
+    }
+
+    Ok(())
+}
@@ -0,0 +1,412 @@
+use core::time::Duration;
+
+use spi::{self, ChipSelect, Spi, SpiConfig};
+use timer::{spin_sleep_us, Instant};
+
+/// The SCLK frequency; the chip takes up to 10MHz.
+const SPI_FREQUENCY: u32 = 8_000_000;
+
+/// SPI instructions.
+mod instruction {
+    pub const RESET: u8 = 0xC0;
+    pub const READ: u8 = 0x03;
+    pub const WRITE: u8 = 0x02;
+    /// Reads RX buffer `n` from `SIDH` on, `| n << 2`, clearing its
+    /// interrupt flag.
+    pub const READ_RX_BUFFER: u8 = 0x90;
+    /// Requests sending TX buffer `n`, `| 1 << n`.
+    pub const RTS: u8 = 0x80;
+    pub const READ_STATUS: u8 = 0xA0;
+    pub const BIT_MODIFY: u8 = 0x05;
+}
+
+/// Registers.
+mod register {
+    pub const CANSTAT: u8 = 0x0E;
+    pub const CANCTRL: u8 = 0x0F;
+    pub const CNF3: u8 = 0x28;
+    pub const CNF2: u8 = 0x29;
+    pub const CNF1: u8 = 0x2A;
+    pub const CANINTE: u8 = 0x2B;
+    pub const CANINTF: u8 = 0x2C;
+    /// TX buffer `n`'s control register, `+ n * 0x10`, followed by its
+    /// `SIDH` to data registers.
+    pub const TXB0CTRL: u8 = 0x30;
+    pub const RXB0CTRL: u8 = 0x60;
+    pub const RXB1CTRL: u8 = 0x70;
+    /// The first filter and mask registers; see `filter_address()`.
+    pub const RXF0SIDH: u8 = 0x00;
+    pub const RXM0SIDH: u8 = 0x20;
+}
+
+/// `CANCTRL`/`CANSTAT`: the operation mode field.
+const MODE_MASK: u8 = 0b111 << 5;
+mod opmode {
+    pub const NORMAL: u8 = 0b000 << 5;
+    pub const LOOPBACK: u8 = 0b010 << 5;
+    pub const LISTEN_ONLY: u8 = 0b011 << 5;
+    pub const CONFIGURATION: u8 = 0b100 << 5;
+}
+
+/// `TXBnCTRL`: the buffer is waiting to be sent.
+const TXREQ: u8 = 1 << 3;
+
+/// `RXB0CTRL`: roll frames over to RX buffer 1 when buffer 0 is full.
+const BUKT: u8 = 1 << 2;
+
+/// `CANINTE`/`CANINTF` and `READ_STATUS`: a frame is in RX buffer 0, 1.
+const RX0IF: u8 = 1;
+const RX1IF: u8 = 1 << 1;
+
+/// `SIDL`: the frame has an extended identifier.
+const EXIDE: u8 = 1 << 3;
+
+/// The `DLC` register's remote request bit.
+const RTR: u8 = 1 << 6;
+
+/// `CNF2`: phase segment 2 is set by `CNF3`, and the bus is sampled once.
+const BTLMODE: u8 = 1 << 7;
+
+/// The number of TX buffers, acceptance filters and masks.
+const TX_BUFFERS: u8 = 3;
+pub const FILTERS: u8 = 6;
+pub const MASKS: u8 = 2;
+
+/// How long a mode change may take.
+const MODE_TIMEOUT_MS: u64 = 10;
+
+/// How long after a reset the oscillator takes to start.
+const RESET_US: u64 = 100;
+
+/// A frame's identifier.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum Id {
+    /// An 11-bit identifier.
+    Standard(u16),
+    /// A 29-bit identifier.
+    Extended(u32),
+}
+
+impl Id {
+    /// Returns the identifier as the chip's `SIDH`, `SIDL`, `EID8` and
+    /// `EID0` registers.
+    fn to_registers(self) -> [u8; 4] {
+        match self {
+            Id::Standard(id) => [(id >> 3) as u8, (id as u8 & 0x7) << 5, 0, 0],
+            Id::Extended(id) => [
+                (id >> 21) as u8,
+                ((id >> 18) as u8 & 0x7) << 5 | EXIDE | (id >> 16) as u8 & 0x3,
+                (id >> 8) as u8,
+                id as u8,
+            ],
+        }
+    }
+
+    /// Returns the identifier in the `SIDH` to `EID0` registers in `regs`.
+    fn from_registers(regs: &[u8]) -> Id {
+        let standard = (regs[0] as u16) << 3 | (regs[1] >> 5) as u16;
+        if regs[1] & EXIDE == 0 {
+            return Id::Standard(standard);
+        }
+
+        Id::Extended((standard as u32) << 18
+                     | ((regs[1] & 0x3) as u32) << 16
+                     | (regs[2] as u32) << 8
+                     | regs[3] as u32)
+    }
+}
+
+/// A CAN frame.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct Frame {
+    pub id: Id,
+    /// Whether this is a remote request, asking for a frame with `id` and
+    /// `len` bytes, rather than carrying data.
+    pub remote: bool,
+    /// The data length, 0 to 8.
+    pub len: u8,
+    pub data: [u8; 8],
+}
+
+impl Frame {
+    /// Returns a data frame carrying `data`, or `None` if it's longer than 8
+    /// bytes.
+    pub fn new(id: Id, data: &[u8]) -> Option<Frame> {
+        if data.len() > 8 {
+            return None;
+        }
+
+        let mut frame = Frame { id, remote: false, len: data.len() as u8, data: [0; 8] };
+        frame.data[..data.len()].copy_from_slice(data);
+        Some(frame)
+    }
+
+    /// Returns a remote request for `len` bytes, capped at 8, of the frame
+    /// with `id`.
+    pub fn remote(id: Id, len: u8) -> Frame {
+        Frame { id, remote: true, len: ::core::cmp::min(len, 8), data: [0; 8] }
+    }
+
+    /// Returns the data carried.
+    pub fn data(&self) -> &[u8] {
+        if self.remote { &[] } else { &self.data[..self.len as usize] }
+    }
+}
+
+/// The controller's operation mode.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum Mode {
+    /// Sending, receiving and acknowledging frames.
+    Normal,
+    /// Receiving frames sent without putting them on the bus, for testing.
+    Loopback,
+    /// Receiving frames without acknowledging them or sending anything.
+    ListenOnly,
+}
+
+/// Bus settings.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct Mcp2515Config {
+    /// The controller's crystal frequency: 8MHz or 16MHz on most boards.
+    pub oscillator_hz: u32,
+    /// The bit rate, e.g. 125000, 250000 or 500000.
+    pub bitrate: u32,
+    pub mode: Mode,
+}
+
+/// An error from the controller.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum CanError {
+    /// The controller didn't answer, or didn't change mode.
+    NotFound,
+    /// The bit rate can't be made from the oscillator.
+    BadBitrate,
+    /// Every TX buffer is waiting to be sent.
+    Busy,
+}
+
+/// Returns `CNF1` to `CNF3` for `bitrate` from `oscillator_hz`, with 16 time
+/// quanta per bit if that works, or as close to it as the prescaler allows,
+/// sampling at about 87.5% of the bit.
+fn bit_timing(oscillator_hz: u32, bitrate: u32) -> Option<(u8, u8, u8)> {
+    if bitrate == 0 {
+        return None;
+    }
+
+    for quanta in (8..17).rev() {
+        // A time quantum is 2 * (BRP + 1) oscillator cycles.
+        let cycles = 2 * bitrate * quanta;
+        let prescaler = oscillator_hz / cycles;
+        if oscillator_hz % cycles != 0 || prescaler == 0 || prescaler > 64 {
+            continue;
+        }
+
+        let phase2 = ::core::cmp::max(2, (quanta + 4) / 8);
+        let propagation = (quanta - 1 - phase2) / 2;
+        let phase1 = quanta - 1 - phase2 - propagation;
+
+        // A synchronization jump width of 1.
+        return Some(((prescaler - 1) as u8,
+                     BTLMODE | ((phase1 - 1) as u8) << 3 | (propagation - 1) as u8,
+                     (phase2 - 1) as u8));
+    }
+
+    None
+}
+
+/// An MCP2515 CAN controller on `SPI0`, with two RX and three TX buffers.
+///
+/// Its INT pin goes low while a received frame is waiting, so it can be
+/// caught with a GPIO edge handler to call `recv()`.
+pub struct Mcp2515 {
+    spi: Spi,
+    mode: Mode,
+}
+
+impl Mcp2515 {
+    /// Resets the controller on `spi` selected by `cs` and sets it up with
+    /// the settings in `config`, accepting every frame. `spi` is
+    /// reconfigured to suit the chip.
+    pub fn new(mut spi: Spi, cs: ChipSelect, config: Mcp2515Config) -> Result<Mcp2515, CanError> {
+        let (cnf1, cnf2, cnf3) = bit_timing(config.oscillator_hz, config.bitrate)
+            .ok_or(CanError::BadBitrate)?;
+
+        spi.set_config(SpiConfig { frequency: SPI_FREQUENCY, mode: spi::Mode::Mode0 });
+        spi.select(cs);
+
+        let mut can = Mcp2515 { spi, mode: config.mode };
+        can.spi.write(&[instruction::RESET]);
+        spin_sleep_us(RESET_US);
+
+        // The controller comes out of reset in configuration mode.
+        if can.read_register(register::CANSTAT) & MODE_MASK != opmode::CONFIGURATION {
+            return Err(CanError::NotFound);
+        }
+
+        can.write_registers(register::CNF3, &[cnf3, cnf2, cnf1]);
+        can.write_registers(register::RXB0CTRL, &[BUKT]);
+        can.write_registers(register::RXB1CTRL, &[0]);
+        can.write_registers(register::CANINTE, &[RX0IF | RX1IF]);
+        can.set_mode(config.mode)?;
+        Ok(can)
+    }
+
+    /// Returns the SPI master, giving up the controller, which is left in
+    /// its current mode.
+    pub fn release(self) -> Spi {
+        self.spi
+    }
+
+    fn read_register(&mut self, register: u8) -> u8 {
+        let mut frame = [instruction::READ, register, 0];
+        self.spi.transfer(&mut frame);
+        frame[2]
+    }
+
+    /// Writes `values` to consecutive registers from `register` on.
+    fn write_registers(&mut self, register: u8, values: &[u8]) {
+        let mut frame = [0; 2 + 13];
+        frame[0] = instruction::WRITE;
+        frame[1] = register;
+        frame[2..2 + values.len()].copy_from_slice(values);
+        self.spi.write(&frame[..2 + values.len()]);
+    }
+
+    /// Changes the bits in `mask` of `register` to those in `value`.
+    fn modify_register(&mut self, register: u8, mask: u8, value: u8) {
+        self.spi.write(&[instruction::BIT_MODIFY, register, mask, value]);
+    }
+
+    /// Requests the operation mode `mode`, one of `opmode`, and waits for it.
+    fn request_mode(&mut self, mode: u8) -> Result<(), CanError> {
+        self.modify_register(register::CANCTRL, MODE_MASK, mode);
+
+        let deadline = Instant::now() + Duration::from_millis(MODE_TIMEOUT_MS);
+        while self.read_register(register::CANSTAT) & MODE_MASK != mode {
+            if Instant::now() > deadline {
+                return Err(CanError::NotFound);
+            }
+        }
+
+        Ok(())
+    }
+
+    /// Switches to `mode`.
+    pub fn set_mode(&mut self, mode: Mode) -> Result<(), CanError> {
+        self.request_mode(match mode {
+            Mode::Normal => opmode::NORMAL,
+            Mode::Loopback => opmode::LOOPBACK,
+            Mode::ListenOnly => opmode::LISTEN_ONLY,
+        })?;
+
+        self.mode = mode;
+        Ok(())
+    }
+
+    /// Writes `registers` to the filter or mask registers at `address`,
+    /// which only takes effect in configuration mode.
+    fn write_acceptance(&mut self, address: u8, registers: [u8; 4]) -> Result<(), CanError> {
+        self.request_mode(opmode::CONFIGURATION)?;
+        self.write_registers(address, &registers);
+        let mode = self.mode;
+        self.set_mode(mode)
+    }
+
+    /// Sets acceptance mask `mask` to `bits`: a frame reaches an RX buffer
+    /// if, in every bit set in its mask, its identifier matches one of its
+    /// filters. Mask 0 covers RX buffer 0 and filters 0 and 1, and mask 1
+    /// covers RX buffer 1 and filters 2 to 5. A mask of 0 accepts every
+    /// frame, and an `Id::Standard` mask also ignores extended identifiers'
+    /// low 18 bits.
+    ///
+    /// # Panics
+    ///
+    /// Panics if `mask` >= `MASKS`.
+    pub fn set_mask(&mut self, mask: u8, bits: Id) -> Result<(), CanError> {
+        if mask >= MASKS {
+            panic!("Mcp2515: mask {} doesn't exist", mask);
+        }
+
+        let mut registers = bits.to_registers();
+        registers[1] &= !EXIDE;
+        self.write_acceptance(register::RXM0SIDH + 4 * mask, registers)
+    }
+
+    /// Sets acceptance filter `filter` to match `id`, which only matches
+    /// identifiers of its own kind.
+    ///
+    /// # Panics
+    ///
+    /// Panics if `filter` >= `FILTERS`.
+    pub fn set_filter(&mut self, filter: u8, id: Id) -> Result<(), CanError> {
+        if filter >= FILTERS {
+            panic!("Mcp2515: filter {} doesn't exist", filter);
+        }
+
+        // Filters 3 to 5 come after the masks' gap.
+        let address = register::RXF0SIDH + 4 * filter + if filter >= 3 { 4 } else { 0 };
+        self.write_acceptance(address, id.to_registers())
+    }
+
+    /// Queues `frame` for sending in a free TX buffer. This method does not
+    /// block.
+    ///
+    /// # Errors
+    ///
+    /// Returns `CanError::Busy` if every TX buffer is still waiting to be
+    /// sent.
+    pub fn send(&mut self, frame: &Frame) -> Result<(), CanError> {
+        let buffer = (0..TX_BUFFERS)
+            .find(|&n| self.read_register(register::TXB0CTRL + n * 0x10) & TXREQ == 0)
+            .ok_or(CanError::Busy)?;
+
+        let id = frame.id.to_registers();
+        let dlc = frame.len | if frame.remote { RTR } else { 0 };
+        let mut registers = [0; 13];
+        registers[..4].copy_from_slice(&id);
+        registers[4] = dlc;
+        registers[5..].copy_from_slice(&frame.data);
+
+        self.write_registers(register::TXB0CTRL + buffer * 0x10 + 1, &registers);
+        self.spi.write(&[instruction::RTS | 1 << buffer]);
+        Ok(())
+    }
+
+    /// Returns the oldest received frame, if there is one, freeing its RX
+    /// buffer. This method does not block.
+    pub fn recv(&mut self) -> Option<Frame> {
+        let mut status = [instruction::READ_STATUS, 0];
+        self.spi.transfer(&mut status);
+
+        let buffer = if status[1] & RX0IF != 0 {
+            0
+        } else if status[1] & RX1IF != 0 {
+            1
+        } else {
+            return None;
+        };
+
+        let mut regs = [0; 1 + 13];
+        regs[0] = instruction::READ_RX_BUFFER | buffer << 2;
+        self.spi.transfer(&mut regs);
+
+        let (id, dlc) = (Id::from_registers(&regs[1..5]), regs[5]);
+        let mut frame = Frame {
+            id,
+            remote: dlc & RTR != 0,
+            len: ::core::cmp::min(dlc & 0xF, 8),
+            data: [0; 8],
+        };
+
+        if !frame.remote {
+            frame.data.copy_from_slice(&regs[6..14]);
+        }
+
+        Some(frame)
+    }
+
+    /// Returns whether a received frame is waiting.
+    pub fn has_frame(&mut self) -> bool {
+        self.read_register(register::CANINTF) & (RX0IF | RX1IF) != 0
+    }
+}
@@ -0,0 +1,90 @@
+use spi::{ChipSelect, Mode, Spi, SpiConfig};
+
+/// The fastest SCLK the chip takes at 2.7V; it takes 3.6MHz at 5V.
+const SPI_FREQUENCY: u32 = 1_350_000;
+
+/// The number of input channels.
+pub const CHANNELS: u8 = 8;
+
+/// The largest reading, at or above the reference voltage.
+pub const MAX_READING: u16 = 0x3FF;
+
+/// The first byte of each conversion: a start bit, aligned so that the
+/// result ends the third byte.
+const START: u8 = 0x01;
+
+/// The second byte's single-ended mode bit. The channel follows it.
+const SINGLE_ENDED: u8 = 1 << 7;
+
+/// The shift of the channel, or pair, in the second byte.
+const CHANNEL_SHIFT: u8 = 4;
+
+/// An MCP3008 8-channel 10-bit ADC on `SPI0`.
+pub struct Mcp3008 {
+    spi: Spi,
+    /// The voltage on `VREF`, in millivolts.
+    reference_mv: u32,
+}
+
+impl Mcp3008 {
+    /// Returns the ADC on `spi` selected by `cs`, with `VREF` at
+    /// `reference_mv` millivolts. `spi` is reconfigured to suit the chip.
+    pub fn new(mut spi: Spi, cs: ChipSelect, reference_mv: u32) -> Mcp3008 {
+        spi.set_config(SpiConfig { frequency: SPI_FREQUENCY, mode: Mode::Mode0 });
+        spi.select(cs);
+        Mcp3008 { spi, reference_mv }
+    }
+
+    /// Returns the SPI master, giving up the ADC.
+    pub fn release(self) -> Spi {
+        self.spi
+    }
+
+    /// Runs a conversion with `mode` as the second byte's high nibble.
+    fn convert(&mut self, mode: u8) -> u16 {
+        let mut frame = [START, mode << CHANNEL_SHIFT, 0];
+        self.spi.transfer(&mut frame);
+
+        // The result's 10 bits come after a null bit, MSB first.
+        (frame[1] as u16 & 0x3) << 8 | frame[2] as u16
+    }
+
+    /// Returns the reading on `channel`, from 0 at `VGND` to `MAX_READING`
+    /// at `VREF`.
+    ///
+    /// # Panics
+    ///
+    /// Panics if `channel` >= `CHANNELS`.
+    pub fn read(&mut self, channel: u8) -> u16 {
+        if channel >= CHANNELS {
+            panic!("Mcp3008: channel {} doesn't exist", channel);
+        }
+
+        self.convert(SINGLE_ENDED >> CHANNEL_SHIFT | channel)
+    }
+
+    /// Returns the reading of channel `2 * pair` relative to channel
+    /// `2 * pair + 1`, or the reverse if `inverted`. Readings are 0 when the
+    /// first channel is below the second.
+    ///
+    /// # Panics
+    ///
+    /// Panics if `pair` >= `CHANNELS / 2`.
+    pub fn read_differential(&mut self, pair: u8, inverted: bool) -> u16 {
+        if pair >= CHANNELS / 2 {
+            panic!("Mcp3008: channel pair {} doesn't exist", pair);
+        }
+
+        self.convert(pair << 1 | if inverted { 1 } else { 0 })
+    }
+
+    /// Returns the voltage on `channel` in millivolts.
+    ///
+    /// # Panics
+    ///
+    /// Panics if `channel` >= `CHANNELS`.
+    pub fn read_millivolts(&mut self, channel: u8) -> u32 {
+        let reading = self.read(channel) as u32;
+        reading * self.reference_mv / (MAX_READING as u32 + 1)
+    }
+}
@@ -0,0 +1,58 @@
+use core::fmt;
+
+use usb::UsbError;
+
+/// The largest Ethernet frame a `NetDevice` sends or receives, without the
+/// frame check sequence: a 1500 byte payload, the 14 byte header and a VLAN
+/// tag.
+pub const MAX_FRAME: usize = 1518;
+
+/// A 48-bit Ethernet MAC address.
+#[derive(Debug, Default, Clone, Copy, PartialEq, Eq)]
+pub struct MacAddress(pub [u8; 6]);
+
+impl MacAddress {
+    /// The broadcast address, `ff:ff:ff:ff:ff:ff`.
+    pub const BROADCAST: MacAddress = MacAddress([0xFF; 6]);
+}
+
+impl fmt::Display for MacAddress {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        let b = &self.0;
+        write!(f, "{:02x}:{:02x}:{:02x}:{:02x}:{:02x}:{:02x}", b[0], b[1], b[2], b[3], b[4], b[5])
+    }
+}
+
+/// An error from a `NetDevice`.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum NetError {
+    /// The frame is larger than `MAX_FRAME`, or than the buffer it's read
+    /// into.
+    FrameTooLarge,
+    /// The device reported a damaged frame, which was dropped.
+    BadFrame,
+    /// Talking to the device over USB failed.
+    Usb(UsbError),
+}
+
+impl From<UsbError> for NetError {
+    fn from(error: UsbError) -> NetError {
+        NetError::Usb(error)
+    }
+}
+
+/// A device sending and receiving Ethernet frames, for the network stack.
+///
+/// Frames include the Ethernet header but not the frame check sequence,
+/// which the device adds and checks.
+pub trait NetDevice {
+    /// Returns the device's MAC address.
+    fn mac_address(&self) -> MacAddress;
+
+    /// Queues `frame` for sending.
+    fn send_frame(&mut self, frame: &[u8]) -> Result<(), NetError>;
+
+    /// Reads the next received frame into `buf` without blocking. Returns the
+    /// frame's length, or `None` if no frame has arrived.
+    fn recv_frame(&mut self, buf: &mut [u8]) -> Result<Option<usize>, NetError>;
+}
@@ -0,0 +1,430 @@
+use core::sync::atomic::{AtomicBool, Ordering};
+use core::time::Duration;
+
+use gpio::{self, Edge, Gpio, Input, Output};
+use spi::{ChipSelect, Mode, Spi, SpiConfig};
+use timer::{spin_sleep_us, Instant};
+
+/// The SCLK frequency. The chip takes up to 10MHz, but jumper wires don't.
+const SPI_FREQUENCY: u32 = 4_000_000;
+
+/// The largest payload.
+pub const MAX_PAYLOAD: usize = 32;
+
+/// The length of every address used.
+const ADDRESS_LEN: usize = 5;
+
+/// SPI commands.
+mod command {
+    pub const R_REGISTER: u8 = 0x00;
+    pub const W_REGISTER: u8 = 0x20;
+    pub const R_RX_PL_WID: u8 = 0x60;
+    pub const R_RX_PAYLOAD: u8 = 0x61;
+    pub const W_TX_PAYLOAD: u8 = 0xA0;
+    pub const FLUSH_TX: u8 = 0xE1;
+    pub const FLUSH_RX: u8 = 0xE2;
+}
+
+/// Registers.
+mod register {
+    pub const CONFIG: u8 = 0x00;
+    pub const EN_AA: u8 = 0x01;
+    pub const EN_RXADDR: u8 = 0x02;
+    pub const SETUP_AW: u8 = 0x03;
+    pub const SETUP_RETR: u8 = 0x04;
+    pub const RF_CH: u8 = 0x05;
+    pub const RF_SETUP: u8 = 0x06;
+    pub const STATUS: u8 = 0x07;
+    pub const RX_ADDR_P0: u8 = 0x0A;
+    pub const TX_ADDR: u8 = 0x10;
+    pub const FIFO_STATUS: u8 = 0x17;
+    pub const DYNPD: u8 = 0x1C;
+    pub const FEATURE: u8 = 0x1D;
+}
+
+/// `CONFIG` bits.
+mod config_bits {
+    pub const PRIM_RX: u8 = 1;
+    pub const PWR_UP: u8 = 1 << 1;
+    /// 2-byte CRCs, enabled.
+    pub const CRC16: u8 = 1 << 3 | 1 << 2;
+}
+
+/// `STATUS` bits. Writing 1 to the first three clears them.
+mod status {
+    pub const RX_DR: u8 = 1 << 6;
+    pub const TX_DS: u8 = 1 << 5;
+    pub const MAX_RT: u8 = 1 << 4;
+    pub const RX_P_NO_SHIFT: u8 = 1;
+    pub const RX_P_NO_MASK: u8 = 0b111;
+}
+
+/// `FIFO_STATUS`: the RX FIFO is empty.
+const RX_EMPTY: u8 = 1;
+
+/// `SETUP_AW` for 5-byte addresses.
+const FIVE_BYTE_ADDRESSES: u8 = 0b11;
+
+/// `FEATURE`: dynamic payload lengths.
+const EN_DPL: u8 = 1 << 2;
+
+/// Every pipe's bit in `EN_AA`, `EN_RXADDR` and `DYNPD`.
+const ALL_PIPES: u8 = 0x3F;
+
+/// How long the chip takes to power up, and to settle into receive or
+/// transmit mode, in microseconds.
+const POWER_UP_US: u64 = 1500;
+const SETTLE_US: u64 = 130;
+
+/// How long CE is pulsed to send a packet, in microseconds.
+const CE_PULSE_US: u64 = 15;
+
+/// The longest `send()` waits for the packet to be acknowledged, after every
+/// retry.
+const SEND_TIMEOUT_MS: u64 = 100;
+
+/// The radio's data rate.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum DataRate {
+    Kbps250,
+    Mbps1,
+    Mbps2,
+}
+
+/// The radio's output power.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum Power {
+    /// -18dBm.
+    Min = 0,
+    /// -12dBm.
+    Low = 1,
+    /// -6dBm.
+    High = 2,
+    /// 0dBm.
+    Max = 3,
+}
+
+/// Link settings, which both ends of a link must share but for `power`.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct Nrf24Config {
+    /// The RF channel, 0 to 125: 2400 + `channel` MHz.
+    pub channel: u8,
+    pub data_rate: DataRate,
+    pub power: Power,
+    /// How many times an unacknowledged packet is resent, 0 to 15.
+    pub retries: u8,
+    /// How long to wait for an acknowledgement before resending, in 250us
+    /// steps from 1 (250us) to 16 (4ms).
+    pub retry_delay: u8,
+}
+
+impl Default for Nrf24Config {
+    /// Returns channel 76 at 1Mbps and full power, with 15 retries 1.5ms
+    /// apart.
+    fn default() -> Nrf24Config {
+        Nrf24Config {
+            channel: 76,
+            data_rate: DataRate::Mbps1,
+            power: Power::Max,
+            retries: 15,
+            retry_delay: 6,
+        }
+    }
+}
+
+/// An error from the radio.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum Nrf24Error {
+    /// No radio answered on the SPI bus.
+    NotFound,
+    /// A payload is longer than `MAX_PAYLOAD`, or empty.
+    BadLength,
+    /// The receiver didn't acknowledge a packet after every retry.
+    NoAck,
+    /// The radio didn't finish sending.
+    TimedOut,
+}
+
+/// Set by the IRQ pin's edge handler when the radio raises it.
+static IRQ_PENDING: AtomicBool = AtomicBool::new(false);
+
+fn on_irq(_pin: u8) {
+    IRQ_PENDING.store(true, Ordering::Release);
+}
+
+/// An nRF24L01+ radio on `SPI0`, with its CE and IRQ pins on GPIO pins.
+///
+/// Packets are up to `MAX_PAYLOAD` bytes, with dynamic lengths and
+/// automatic acknowledgement and retries on every pipe. Pipe 0 receives the
+/// acknowledgements of packets sent, and pipes 1 to 5 receive packets.
+///
+/// The IRQ pin's falling edge is caught by the GPIO interrupt, so
+/// `gpio::handle_irq()` must be called for `Interrupt::Gpio3`, and only one
+/// radio can be in use at a time. `recv()` only talks to the radio once it
+/// has raised it.
+pub struct Nrf24 {
+    spi: Spi,
+    ce: Gpio<Output>,
+    irq: Gpio<Input>,
+    irq_pin: u8,
+    listening: bool,
+}
+
+impl Nrf24 {
+    /// Sets up the radio on `spi` selected by `cs`, with CE on pin `ce` and
+    /// IRQ on pin `irq`, with the settings in `config`, and powers it up in
+    /// standby. `spi` is reconfigured to suit the chip.
+    ///
+    /// # Errors
+    ///
+    /// Returns `Nrf24Error::NotFound` if the radio doesn't read back its
+    /// settings.
+    ///
+    /// # Panics
+    ///
+    /// Panics if `ce` or `irq` > `53`.
+    pub fn new(mut spi: Spi,
+               cs: ChipSelect,
+               ce: u8,
+               irq: u8,
+               config: Nrf24Config) -> Result<Nrf24, Nrf24Error> {
+        spi.set_config(SpiConfig { frequency: SPI_FREQUENCY, mode: Mode::Mode0 });
+        spi.select(cs);
+
+        let mut ce_pin = Gpio::new(ce).into_output();
+        ce_pin.clear();
+
+        let mut irq_input = Gpio::new(irq).into_input_pullup();
+        irq_input.enable_edge_detect(Edge::Falling);
+
+        let mut radio = Nrf24 { spi, ce: ce_pin, irq: irq_input, irq_pin: irq, listening: false };
+        radio.write_register(register::CONFIG, config_bits::CRC16);
+        radio.write_register(register::SETUP_AW, FIVE_BYTE_ADDRESSES);
+        if radio.read_register(register::SETUP_AW) != FIVE_BYTE_ADDRESSES {
+            return Err(Nrf24Error::NotFound);
+        }
+
+        gpio::claim(&[ce, irq], "nrf24");
+
+        let rate = match config.data_rate {
+            DataRate::Kbps250 => 1 << 5,
+            DataRate::Mbps1 => 0,
+            DataRate::Mbps2 => 1 << 3,
+        };
+
+        let delay = ::core::cmp::max(1, ::core::cmp::min(config.retry_delay, 16)) - 1;
+        let retries = ::core::cmp::min(config.retries, 15);
+        radio.write_register(register::SETUP_RETR, delay << 4 | retries);
+        radio.write_register(register::RF_CH, ::core::cmp::min(config.channel, 125));
+        radio.write_register(register::RF_SETUP, rate | (config.power as u8) << 1);
+        radio.write_register(register::FEATURE, EN_DPL);
+        radio.write_register(register::DYNPD, ALL_PIPES);
+        radio.write_register(register::EN_AA, ALL_PIPES);
+        radio.write_register(register::EN_RXADDR, 1);
+
+        radio.command(command::FLUSH_TX);
+        radio.command(command::FLUSH_RX);
+        radio.clear_status();
+
+        radio.write_register(register::CONFIG, config_bits::CRC16 | config_bits::PWR_UP);
+        spin_sleep_us(POWER_UP_US);
+
+        IRQ_PENDING.store(false, Ordering::Release);
+        gpio::register_edge_handler(irq, on_irq);
+        Ok(radio)
+    }
+
+    /// Sends `command` with `data` after it, replacing `data` with the bytes
+    /// read back, and returns the status read back in place of the command.
+    fn transfer(&mut self, command: u8, data: &mut [u8]) -> u8 {
+        let mut frame = [0; 1 + MAX_PAYLOAD];
+        frame[0] = command;
+        frame[1..1 + data.len()].copy_from_slice(data);
+
+        self.spi.transfer(&mut frame[..1 + data.len()]);
+        data.copy_from_slice(&frame[1..1 + data.len()]);
+        frame[0]
+    }
+
+    /// Sends `command` alone and returns the status.
+    fn command(&mut self, command: u8) -> u8 {
+        self.transfer(command, &mut [])
+    }
+
+    fn read_register(&mut self, register: u8) -> u8 {
+        let mut value = [0];
+        self.transfer(command::R_REGISTER | register, &mut value);
+        value[0]
+    }
+
+    fn write_register(&mut self, register: u8, value: u8) {
+        self.transfer(command::W_REGISTER | register, &mut [value]);
+    }
+
+    /// Clears every interrupt flag, releasing the IRQ pin.
+    fn clear_status(&mut self) {
+        self.write_register(register::STATUS, status::RX_DR | status::TX_DS | status::MAX_RT);
+    }
+
+    /// Sets the address packets are sent to. Pipe 0 listens on it too, for
+    /// the acknowledgements.
+    pub fn open_writing_pipe(&mut self, address: [u8; ADDRESS_LEN]) {
+        let (mut tx, mut rx) = (address, address);
+        self.transfer(command::W_REGISTER | register::TX_ADDR, &mut tx);
+        self.transfer(command::W_REGISTER | register::RX_ADDR_P0, &mut rx);
+    }
+
+    /// Starts receiving packets on pipe `pipe` sent to `address`. Pipes 2 to
+    /// 5 share every byte of pipe 1's address but the first, which is least
+    /// significant and sent last, so only `address[0]` is used for them.
+    ///
+    /// # Panics
+    ///
+    /// Panics if `pipe` isn't 1 to 5.
+    pub fn open_reading_pipe(&mut self, pipe: u8, address: [u8; ADDRESS_LEN]) {
+        if pipe == 0 || pipe > 5 {
+            panic!("Nrf24: pipe {} can't be opened for reading", pipe);
+        }
+
+        let register = register::RX_ADDR_P0 + pipe;
+        if pipe == 1 {
+            let mut address = address;
+            self.transfer(command::W_REGISTER | register, &mut address);
+        } else {
+            self.write_register(register, address[0]);
+        }
+
+        let enabled = self.read_register(register::EN_RXADDR);
+        self.write_register(register::EN_RXADDR, enabled | 1 << pipe);
+    }
+
+    /// Stops receiving packets on pipe `pipe`.
+    pub fn close_reading_pipe(&mut self, pipe: u8) {
+        if pipe > 0 && pipe <= 5 {
+            let enabled = self.read_register(register::EN_RXADDR);
+            self.write_register(register::EN_RXADDR, enabled & !(1 << pipe));
+        }
+    }
+
+    /// Switches to receive mode: packets to the open reading pipes are
+    /// acknowledged and queued for `recv()`.
+    pub fn start_listening(&mut self) {
+        let config = self.read_register(register::CONFIG);
+        self.write_register(register::CONFIG, config | config_bits::PRIM_RX);
+        self.clear_status();
+        self.ce.set();
+        spin_sleep_us(SETTLE_US);
+        self.listening = true;
+    }
+
+    /// Switches back to standby, receiving nothing.
+    pub fn stop_listening(&mut self) {
+        self.ce.clear();
+        let config = self.read_register(register::CONFIG);
+        self.write_register(register::CONFIG, config & !config_bits::PRIM_RX);
+        self.listening = false;
+    }
+
+    /// Sends `payload` to the writing pipe's address and waits for it to be
+    /// acknowledged. Listening stops while sending and then resumes.
+    pub fn send(&mut self, payload: &[u8]) -> Result<(), Nrf24Error> {
+        if payload.is_empty() || payload.len() > MAX_PAYLOAD {
+            return Err(Nrf24Error::BadLength);
+        }
+
+        let listening = self.listening;
+        if listening {
+            self.stop_listening();
+        }
+
+        self.command(command::FLUSH_TX);
+        self.write_register(register::STATUS, status::TX_DS | status::MAX_RT);
+
+        let mut data = [0; MAX_PAYLOAD];
+        data[..payload.len()].copy_from_slice(payload);
+        self.transfer(command::W_TX_PAYLOAD, &mut data[..payload.len()]);
+
+        self.ce.set();
+        spin_sleep_us(CE_PULSE_US);
+        self.ce.clear();
+
+        let deadline = Instant::now() + Duration::from_millis(SEND_TIMEOUT_MS);
+        let result = loop {
+            let flags = self.read_register(register::STATUS);
+            if flags & status::TX_DS != 0 {
+                break Ok(());
+            } else if flags & status::MAX_RT != 0 {
+                break Err(Nrf24Error::NoAck);
+            } else if Instant::now() > deadline {
+                break Err(Nrf24Error::TimedOut);
+            }
+        };
+
+        if result.is_err() {
+            self.command(command::FLUSH_TX);
+        }
+        self.write_register(register::STATUS, status::TX_DS | status::MAX_RT);
+
+        if listening {
+            self.start_listening();
+        }
+
+        result
+    }
+
+    /// Returns whether the radio may have a packet: it raised its IRQ pin,
+    /// or still holds it low.
+    fn irq_raised(&mut self) -> bool {
+        IRQ_PENDING.swap(false, Ordering::AcqRel) || !self.irq.level()
+    }
+
+    /// Moves the oldest received packet into `buf` and returns the pipe it
+    /// came in on and its length, or `None` if there isn't one. This method
+    /// does not block.
+    pub fn recv(&mut self, buf: &mut [u8; MAX_PAYLOAD]) -> Option<(u8, usize)> {
+        if !self.irq_raised() {
+            return None;
+        }
+
+        if self.read_register(register::FIFO_STATUS) & RX_EMPTY != 0 {
+            self.write_register(register::STATUS, status::RX_DR);
+            return None;
+        }
+
+        let mut len = [0];
+        let flags = self.transfer(command::R_RX_PL_WID, &mut len);
+        let pipe = (flags >> status::RX_P_NO_SHIFT) & status::RX_P_NO_MASK;
+        let len = len[0] as usize;
+        if len == 0 || len > MAX_PAYLOAD {
+            // A corrupt length: the packet can't be read.
+            self.command(command::FLUSH_RX);
+            self.write_register(register::STATUS, status::RX_DR);
+            return None;
+        }
+
+        for byte in buf.iter_mut() {
+            *byte = 0;
+        }
+        self.transfer(command::R_RX_PAYLOAD, &mut buf[..len]);
+        self.write_register(register::STATUS, status::RX_DR);
+
+        // The IRQ pin stays low while packets are left; make sure the next
+        // call looks.
+        if self.read_register(register::FIFO_STATUS) & RX_EMPTY == 0 {
+            IRQ_PENDING.store(true, Ordering::Release);
+        }
+
+        Some((pipe, len))
+    }
+
+    /// Powers the radio down, withdraws the claims on its CE and IRQ pins,
+    /// and returns the SPI master.
+    pub fn release(mut self) -> Spi {
+        self.stop_listening();
+        self.write_register(register::CONFIG, config_bits::CRC16);
+        gpio::unregister_edge_handler(self.irq_pin);
+        self.irq.disable_edge_detect();
+        gpio::release_owned(&[self.ce.number(), self.irq_pin], "nrf24");
+        self.spi
+    }
+}
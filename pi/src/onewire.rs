@@ -0,0 +1,374 @@
+use core::fmt;
+use core::time::Duration;
+
+use gpio::{self, Gpio, OpenDrain};
+use timer::{spin_sleep_us, Instant};
+
+/// Timings of the bus, in microseconds, for standard speed.
+mod timing {
+    /// How long a reset pulse holds the bus low.
+    pub const RESET_LOW: u64 = 480;
+    /// How long after a reset pulse devices are sampled for presence.
+    pub const PRESENCE_SAMPLE: u64 = 70;
+    /// The rest of the reset time slot, after the presence sample.
+    pub const RESET_RECOVERY: u64 = 410;
+    /// How long a 1 bit, or a read slot, holds the bus low.
+    pub const SHORT_LOW: u64 = 6;
+    /// How long a 0 bit holds the bus low.
+    pub const LONG_LOW: u64 = 60;
+    /// How long after the start of a read slot the bus is sampled.
+    pub const READ_SAMPLE: u64 = 9;
+    /// The length of a whole bit slot, including recovery.
+    pub const SLOT: u64 = 70;
+}
+
+/// ROM commands, addressing devices after a reset.
+mod rom_command {
+    pub const SEARCH: u8 = 0xF0;
+    pub const READ: u8 = 0x33;
+    pub const MATCH: u8 = 0x55;
+    pub const SKIP: u8 = 0xCC;
+}
+
+/// An error on a 1-Wire bus.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum OneWireError {
+    /// No device answered a reset.
+    NoPresence,
+    /// Data read from a device failed its CRC.
+    BadCrc,
+    /// A device didn't finish an operation in time.
+    TimedOut,
+    /// The device isn't the kind the driver is for.
+    WrongDevice,
+}
+
+/// A device's 64-bit ROM code: the family code, a 48-bit serial number, and
+/// a CRC of both.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct Rom(pub [u8; 8]);
+
+impl Rom {
+    /// Returns the family code, which identifies the kind of device.
+    pub fn family(&self) -> u8 {
+        self.0[0]
+    }
+
+    /// Returns `true` if the CRC in the last byte matches the rest.
+    pub fn is_valid(&self) -> bool {
+        crc8(&self.0[..7]) == self.0[7]
+    }
+}
+
+impl fmt::Display for Rom {
+    /// Writes the code as 16 hex digits, family code first.
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        for byte in self.0.iter() {
+            write!(f, "{:02x}", byte)?;
+        }
+
+        Ok(())
+    }
+}
+
+/// Returns the Dallas/Maxim CRC-8 (polynomial x^8 + x^5 + x^4 + 1) of
+/// `data`, as used by ROM codes and scratchpads.
+pub fn crc8(data: &[u8]) -> u8 {
+    let mut crc = 0u8;
+    for &byte in data {
+        let mut byte = byte;
+        for _ in 0..8 {
+            let mix = (crc ^ byte) & 1;
+            crc >>= 1;
+            if mix != 0 {
+                crc ^= 0x8C;
+            }
+            byte >>= 1;
+        }
+    }
+
+    crc
+}
+
+/// A 1-Wire bus bit-banged on a GPIO pin, at standard speed.
+///
+/// The pin is used as an open drain line; the bus needs an external pull-up,
+/// typically 4.7k to 3.3V, since the pin's own is too weak. Bit timing is
+/// measured on the system timer, so an interrupt taken mid-slot can corrupt
+/// the bit: run transactions with interrupts masked for reliable results.
+pub struct OneWire {
+    pin: Gpio<OpenDrain>
+}
+
+impl OneWire {
+    /// Returns a bus on pin number `pin`, which is switched to an open drain
+    /// line and released.
+    ///
+    /// # Panics
+    ///
+    /// Panics if `pin` > `53`.
+    pub fn new(pin: u8) -> OneWire {
+        let bus = OneWire { pin: Gpio::new(pin).into_open_drain() };
+        gpio::claim(&[pin], "onewire");
+        bus
+    }
+
+    /// Sends a reset pulse. Returns `Ok(())` if any device answered with a
+    /// presence pulse.
+    pub fn reset(&mut self) -> Result<(), OneWireError> {
+        self.pin.drive_low();
+        spin_sleep_us(timing::RESET_LOW);
+        self.pin.release();
+
+        spin_sleep_us(timing::PRESENCE_SAMPLE);
+        let present = !self.pin.level();
+        spin_sleep_us(timing::RESET_RECOVERY);
+
+        if present {
+            Ok(())
+        } else {
+            Err(OneWireError::NoPresence)
+        }
+    }
+
+    /// Writes the bit `bit` in one time slot.
+    pub fn write_bit(&mut self, bit: bool) {
+        let low = if bit { timing::SHORT_LOW } else { timing::LONG_LOW };
+
+        self.pin.drive_low();
+        spin_sleep_us(low);
+        self.pin.release();
+        spin_sleep_us(timing::SLOT - low);
+    }
+
+    /// Reads a bit in one time slot.
+    pub fn read_bit(&mut self) -> bool {
+        self.pin.drive_low();
+        spin_sleep_us(timing::SHORT_LOW);
+        self.pin.release();
+
+        spin_sleep_us(timing::READ_SAMPLE);
+        let bit = self.pin.level();
+        spin_sleep_us(timing::SLOT - timing::SHORT_LOW - timing::READ_SAMPLE);
+        bit
+    }
+
+    /// Writes `byte`, least significant bit first.
+    pub fn write_byte(&mut self, byte: u8) {
+        for i in 0..8 {
+            self.write_bit(byte & (1 << i) != 0);
+        }
+    }
+
+    /// Reads a byte, least significant bit first.
+    pub fn read_byte(&mut self) -> u8 {
+        let mut byte = 0;
+        for i in 0..8 {
+            if self.read_bit() {
+                byte |= 1 << i;
+            }
+        }
+
+        byte
+    }
+
+    /// Writes every byte in `bytes`.
+    pub fn write_bytes(&mut self, bytes: &[u8]) {
+        for &byte in bytes {
+            self.write_byte(byte);
+        }
+    }
+
+    /// Fills `buf` with bytes read from the bus.
+    pub fn read_bytes(&mut self, buf: &mut [u8]) {
+        for byte in buf.iter_mut() {
+            *byte = self.read_byte();
+        }
+    }
+
+    /// Resets the bus and addresses the device with ROM code `rom`, or every
+    /// device if `rom` is `None`, ready for a function command.
+    pub fn select(&mut self, rom: Option<&Rom>) -> Result<(), OneWireError> {
+        self.reset()?;
+        match rom {
+            Some(rom) => {
+                self.write_byte(rom_command::MATCH);
+                self.write_bytes(&rom.0);
+            }
+            None => self.write_byte(rom_command::SKIP)
+        }
+
+        Ok(())
+    }
+
+    /// Returns the ROM code of the only device on the bus. If there are
+    /// several, their codes collide and the CRC fails.
+    pub fn read_rom(&mut self) -> Result<Rom, OneWireError> {
+        self.reset()?;
+        self.write_byte(rom_command::READ);
+
+        let mut rom = Rom([0; 8]);
+        self.read_bytes(&mut rom.0);
+        if rom.is_valid() { Ok(rom) } else { Err(OneWireError::BadCrc) }
+    }
+
+    /// Finds every device on the bus with the ROM search algorithm, calling
+    /// `found` with each one's code, and returns the number found.
+    pub fn search<F: FnMut(Rom)>(&mut self, mut found: F) -> Result<usize, OneWireError> {
+        let mut rom = [0u8; 8];
+        // The bit where the last search took the 0 branch at a discrepancy
+        // it will take the 1 branch at next; 0 when there are none left.
+        let mut last_discrepancy = 0;
+        let mut count = 0;
+
+        loop {
+            self.reset()?;
+            self.write_byte(rom_command::SEARCH);
+
+            let mut discrepancy = 0;
+            for bit in 1..65 {
+                let (byte, mask) = ((bit - 1) / 8, 1 << ((bit - 1) % 8));
+                let (one, zero) = (self.read_bit(), self.read_bit());
+
+                let take_one = match (one, zero) {
+                    // Nobody answered: the devices went away mid-search.
+                    (true, true) => return Err(OneWireError::NoPresence),
+                    // Every device left has a 0, or a 1, here.
+                    (false, true) => false,
+                    (true, false) => true,
+                    // Both: follow the last search's path up to where it
+                    // turned, take the 1 branch there, and 0s after.
+                    (false, false) => {
+                        let take_one = if bit < last_discrepancy {
+                            rom[byte] & mask != 0
+                        } else {
+                            bit == last_discrepancy
+                        };
+
+                        if !take_one {
+                            discrepancy = bit;
+                        }
+
+                        take_one
+                    }
+                };
+
+                if take_one {
+                    rom[byte] |= mask;
+                } else {
+                    rom[byte] &= !mask;
+                }
+
+                self.write_bit(take_one);
+            }
+
+            let rom = Rom(rom);
+            if !rom.is_valid() {
+                return Err(OneWireError::BadCrc);
+            }
+
+            found(rom);
+            count += 1;
+
+            last_discrepancy = discrepancy;
+            if last_discrepancy == 0 {
+                return Ok(count);
+            }
+        }
+    }
+}
+
+impl Drop for OneWire {
+    /// Withdraws the claim on the bus's pin.
+    fn drop(&mut self) {
+        gpio::release_owned(&[self.pin.number()], "onewire");
+    }
+}
+
+/// DS18B20 function commands.
+mod ds18b20_command {
+    pub const CONVERT: u8 = 0x44;
+    pub const READ_SCRATCHPAD: u8 = 0xBE;
+}
+
+/// How long a 12-bit conversion can take.
+const CONVERSION_MS: u64 = 750;
+
+/// A DS18B20 temperature sensor on a 1-Wire bus. Sensors must be powered
+/// from VDD; parasite power isn't supported.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct Ds18b20 {
+    /// The sensor's ROM code, or `None` if it's alone on the bus.
+    rom: Option<Rom>,
+}
+
+impl Ds18b20 {
+    /// The DS18B20's family code.
+    pub const FAMILY: u8 = 0x28;
+
+    /// Returns the sensor with ROM code `rom`, as found by
+    /// `OneWire::search()`.
+    ///
+    /// # Errors
+    ///
+    /// Returns `OneWireError::WrongDevice` if `rom` isn't a DS18B20's.
+    pub fn new(rom: Rom) -> Result<Ds18b20, OneWireError> {
+        if rom.family() != Ds18b20::FAMILY {
+            return Err(OneWireError::WrongDevice);
+        }
+
+        Ok(Ds18b20 { rom: Some(rom) })
+    }
+
+    /// Returns the only device on the bus, which must be a DS18B20. It's
+    /// addressed without its ROM code.
+    pub fn only() -> Ds18b20 {
+        Ds18b20 { rom: None }
+    }
+
+    /// Returns the sensor's ROM code, if it's known.
+    pub fn rom(&self) -> Option<Rom> {
+        self.rom
+    }
+
+    /// Starts a temperature conversion without waiting for it to finish.
+    pub fn start_conversion(&self, bus: &mut OneWire) -> Result<(), OneWireError> {
+        bus.select(self.rom.as_ref())?;
+        bus.write_byte(ds18b20_command::CONVERT);
+        Ok(())
+    }
+
+    /// Returns the temperature from the last conversion in thousandths of a
+    /// degree Celsius.
+    pub fn read_temperature(&self, bus: &mut OneWire) -> Result<i32, OneWireError> {
+        bus.select(self.rom.as_ref())?;
+        bus.write_byte(ds18b20_command::READ_SCRATCHPAD);
+
+        let mut scratchpad = [0; 9];
+        bus.read_bytes(&mut scratchpad);
+        if crc8(&scratchpad[..8]) != scratchpad[8] {
+            return Err(OneWireError::BadCrc);
+        }
+
+        // Sixteenths of a degree, as a two's complement 16-bit number.
+        let raw = (scratchpad[1] as i16) << 8 | scratchpad[0] as i16;
+        Ok(raw as i32 * 1000 / 16)
+    }
+
+    /// Converts the temperature and returns it in thousandths of a degree
+    /// Celsius. Takes up to `CONVERSION_MS`; the sensor is polled to see when
+    /// it's done.
+    pub fn temperature(&self, bus: &mut OneWire) -> Result<i32, OneWireError> {
+        self.start_conversion(bus)?;
+
+        // The sensor reads 0s until the conversion is done.
+        let deadline = Instant::now() + Duration::from_millis(CONVERSION_MS * 2);
+        while !bus.read_bit() {
+            if Instant::now() >= deadline {
+                return Err(OneWireError::TimedOut);
+            }
+        }
+
+        self.read_temperature(bus)
+    }
+}
@@ -0,0 +1,408 @@
+use core::{cmp, fmt};
+use core::time::Duration;
+
+use volatile::prelude::*;
+use volatile::{Volatile, ReadVolatile, WriteVolatile, Reserved};
+
+use clock;
+use common::IO_BASE;
+use gpio::{self, Gpio, pin, signal};
+use dma::{self, Channel, ControlBlock};
+use timer::duration_to_us;
+use uart::{Uart, UartConfig, DataBits, Parity, StopBits, FlowControl};
+use uart::{WouldBlock, UartError, ErrorCounts};
+
+/// The base address for the `UART0` (PL011) registers.
+const UART0_REG_BASE: usize = IO_BASE + 0x201000;
+
+/// The DMA channel used by `write_dma`.
+const TX_DMA_CHANNEL: u8 = 5;
+
+/// The largest number of bytes `write_dma` queues at once.
+pub const DMA_BUFFER_SIZE: usize = 4096;
+
+#[repr(C, align(32))]
+struct DmaBuffer([u8; DMA_BUFFER_SIZE]);
+
+/// The bytes being sent by the DMA transfer started by `write_dma`. Owned by
+/// the DMA engine while the transfer is in flight.
+static mut TX_DMA_BUFFER: DmaBuffer = DmaBuffer([0; DMA_BUFFER_SIZE]);
+
+/// The control block describing the DMA transfer started by `write_dma`.
+static mut TX_DMA_BLOCK: ControlBlock = ControlBlock::new();
+
+/// Enum representing bit fields of the `UART_FR` register.
+#[repr(u32)]
+enum FrStatus {
+    RxEmpty = 1 << 4,
+    TxFull = 1 << 5,
+}
+
+/// Enum representing the receive error bits of the `UART_DR` register.
+#[repr(u32)]
+enum DrErrors {
+    Framing = 1 << 8,
+    Parity = 1 << 9,
+    Break = 1 << 10,
+    Overrun = 1 << 11,
+}
+
+/// Enum representing bit fields of the `UART_DMACR` register.
+#[repr(u32)]
+enum DmacrFlags {
+    TxDmaEnable = 1 << 1,
+}
+
+/// Enum representing bit fields of the `UART_LCRH` register.
+#[repr(u32)]
+enum LcrhFlags {
+    ParityEnable = 1 << 1,
+    EvenParity = 1 << 2,
+    TwoStopBits = 1 << 3,
+    FifoEnable = 1 << 4,
+    WordLength7 = 0b10 << 5,
+    WordLength8 = 0b11 << 5,
+}
+
+/// Enum representing bit fields of the `UART_CR` register.
+#[repr(u32)]
+enum CrFlags {
+    UartEnable = 1,
+    TxEnable = 1 << 8,
+    RxEnable = 1 << 9,
+    RtsEnable = 1 << 14,
+    CtsEnable = 1 << 15,
+}
+
+#[repr(C)]
+#[allow(non_snake_case)]
+struct Registers {
+    DR: Volatile<u32>,
+    RSRECR: Volatile<u32>,
+    __r0: [Reserved<u32>; 4],
+    FR: ReadVolatile<u32>,
+    __r1: Reserved<u32>,
+    ILPR: Volatile<u32>,
+    IBRD: Volatile<u32>,
+    FBRD: Volatile<u32>,
+    LCRH: Volatile<u32>,
+    CR: Volatile<u32>,
+    IFLS: Volatile<u32>,
+    IMSC: Volatile<u32>,
+    RIS: ReadVolatile<u32>,
+    MIS: ReadVolatile<u32>,
+    ICR: WriteVolatile<u32>,
+    DMACR: Volatile<u32>,
+}
+
+/// Returns the value of the `UART_LCRH` register for the framing in `config`
+/// with the FIFOs enabled.
+fn line_control(config: &UartConfig) -> u32 {
+    let mut lcrh = LcrhFlags::FifoEnable as u32;
+
+    lcrh |= match config.data_bits {
+        DataBits::Seven => LcrhFlags::WordLength7 as u32,
+        DataBits::Eight => LcrhFlags::WordLength8 as u32
+    };
+
+    lcrh |= match config.parity {
+        Parity::None => 0,
+        Parity::Even => LcrhFlags::ParityEnable as u32 | LcrhFlags::EvenParity as u32,
+        Parity::Odd => LcrhFlags::ParityEnable as u32
+    };
+
+    if config.stop_bits == StopBits::Two {
+        lcrh |= LcrhFlags::TwoStopBits as u32;
+    }
+
+    lcrh
+}
+
+/// Returns the integer and fractional baud rate divisors for `baud` given a
+/// reference clock of `clock` Hz.
+///
+/// The PL011 divides `clock` by `16 * divisor`, where the divisor has a 6 bit
+/// fractional part. Computing `4 * clock / baud` yields the divisor in 1/64ths;
+/// it's calculated at twice that precision so it can be rounded.
+///
+/// # Panics
+///
+/// Panics if `baud` is not achievable with a 16-bit integer divisor.
+fn baud_divisors(clock: u32, baud: u32) -> (u32, u32) {
+    // The divisor must be at least 1 and at most 0xffff with no fraction.
+    let divisor = (clock as u64 * 8).checked_div(baud as u64).map_or(0, |d| (d + 1) / 2);
+    if divisor < 1 << 6 || divisor > 0xffff << 6 {
+        panic!("Pl011Uart: BAUD rate {} unreachable with a {} Hz clock", baud, clock);
+    }
+
+    ((divisor >> 6) as u32, (divisor & 0x3f) as u32)
+}
+
+/// The Raspberry Pi's full UART, `UART0`, an ARM PL011.
+///
+/// Unlike the mini UART, the PL011 has its own reference clock, so its BAUD
+/// rate doesn't drift with the VPU core clock.
+pub struct Pl011Uart {
+    registers: &'static mut Registers,
+    timeout_us: Option<u64>,
+    errors: ErrorCounts,
+    dma: Option<Channel>,
+    /// A receive error that ended an `io::Read::read` early, to be reported
+    /// by the next.
+    pending_error: Option<UartError>,
+}
+
+impl Pl011Uart {
+    /// Initializes UART0 by disabling it, setting GPIO pins 14 and 15 to
+    /// alternative function 0 (TXD0/RXD0), setting the BAUD rate to 115200,
+    /// enabling the FIFOs with 8N1 framing, masking all interrupts, and finally
+    /// enabling the UART transmitter and receiver.
+    ///
+    /// By default, reads will never time out. To set a read timeout, use
+    /// `Uart::set_read_timeout()`.
+    pub fn new() -> Pl011Uart {
+        Pl011Uart::with_config(UartConfig::default())
+    }
+
+    /// Initializes UART0 like `new()` but with the BAUD rate, data size,
+    /// parity, stop bits, and flow control in `config`. With
+    /// `FlowControl::RtsCts`, GPIO pins 16 and 17 are set to alternative
+    /// function 3 (CTS0/RTS0).
+    ///
+    /// # Panics
+    ///
+    /// Panics if the BAUD rate in `config` can't be reached.
+    pub fn with_config(config: UartConfig) -> Pl011Uart {
+        let registers = unsafe { &mut *(UART0_REG_BASE as *mut Registers) };
+
+        // Disable the UART while it's being configured.
+        registers.CR.write(0);
+
+        // Set GPIO pins 14 and 15 to Alt 0 function.
+        Gpio::with_signal(pin::P14, signal::Txd0);
+        Gpio::with_signal(pin::P15, signal::Rxd0);
+        gpio::claim(&[14, 15], "uart");
+
+        let mut cr = CrFlags::UartEnable as u32 | CrFlags::TxEnable as u32
+            | CrFlags::RxEnable as u32;
+        if config.flow_control == FlowControl::RtsCts {
+            // Set GPIO pins 16 and 17 to Alt 3 function.
+            Gpio::with_signal(pin::P16, signal::Cts0);
+            Gpio::with_signal(pin::P17, signal::Rts0);
+            gpio::claim(&[16, 17], "uart");
+
+            cr |= CrFlags::RtsEnable as u32 | CrFlags::CtsEnable as u32;
+        } else {
+            // A UART set up earlier may have used them. Every set-up claims
+            // the same UART again, so none of its claims are left standing.
+            gpio::release_all_owned(&[16, 17], "uart");
+        }
+
+        // Clear any pending interrupts.
+        registers.ICR.write(0x7ff);
+
+        let (integer, fraction) = baud_divisors(clock::uart_rate(), config.baud);
+        registers.IBRD.write(integer);
+        registers.FBRD.write(fraction);
+
+        // Set the framing with FIFOs enabled. Writing LCRH latches the divisors.
+        registers.LCRH.write(line_control(&config));
+        // Mask all interrupts.
+        registers.IMSC.write(0);
+        // Enable UART, TX, RX, and, if requested, hardware flow control.
+        registers.CR.write(cr);
+
+        Pl011Uart {
+            registers: registers,
+            timeout_us: None,
+            errors: ErrorCounts::default(),
+            dma: None,
+            pending_error: None
+        }
+    }
+
+    /// Returns a handle to UART0 as it was last initialized, without touching
+    /// its registers or pins. For a last message, as when the kernel panics,
+    /// when the `Pl011Uart` that initialized it can't be reached.
+    ///
+    /// # Safety
+    ///
+    /// UART0 must have been initialized by `new()` or `with_config()`. Output
+    /// from the handle interleaves with any other writer's, including a DMA
+    /// transfer still in progress.
+    pub unsafe fn steal() -> Pl011Uart {
+        Pl011Uart {
+            registers: &mut *(UART0_REG_BASE as *mut Registers),
+            timeout_us: None,
+            errors: ErrorCounts::default(),
+            dma: None,
+            pending_error: None
+        }
+    }
+
+    /// Queues up to `DMA_BUFFER_SIZE` bytes from `bytes` for transmission by
+    /// the DMA engine and returns how many were queued without waiting for
+    /// them to be sent. A previous DMA transfer is waited on first. `bytes` is
+    /// copied, so it can be reused as soon as this method returns.
+    ///
+    /// Until `dma_done()` returns `true`, `try_write_byte` reports
+    /// `WouldBlock` so that output stays in order.
+    pub fn write_dma(&mut self, bytes: &[u8]) -> usize {
+        self.wait_dma();
+
+        let len = cmp::min(bytes.len(), DMA_BUFFER_SIZE);
+        if len == 0 {
+            return 0;
+        }
+
+        if self.dma.is_none() {
+            self.dma = Some(Channel::new(TX_DMA_CHANNEL));
+        }
+
+        // Let the UART pace the transfer with its TX DREQ.
+        self.registers.DMACR.write(DmacrFlags::TxDmaEnable as u32);
+
+        unsafe {
+            TX_DMA_BUFFER.0[..len].copy_from_slice(&bytes[..len]);
+
+            TX_DMA_BLOCK = ControlBlock::new();
+            TX_DMA_BLOCK.transfer_info = dma::ti::SRC_INC | dma::ti::DEST_DREQ
+                | dma::ti::WAIT_RESP | dma::ti::permap(dma::dreq::UART_TX);
+            TX_DMA_BLOCK.source = dma::bus_address(TX_DMA_BUFFER.0.as_ptr() as usize);
+            TX_DMA_BLOCK.destination = dma::bus_address(UART0_REG_BASE);
+            TX_DMA_BLOCK.length = len as u32;
+
+            // The buffer and block are statics that aren't touched again
+            // until the transfer is done.
+            self.dma.as_mut().unwrap().start(&TX_DMA_BLOCK);
+        }
+
+        len
+    }
+
+    /// Returns `true` if no transfer started by `write_dma` is in flight.
+    pub fn dma_done(&self) -> bool {
+        self.dma.as_ref().map_or(true, |channel| channel.is_done())
+    }
+
+    /// Blocks until the transfer started by `write_dma`, if any, completes.
+    pub fn wait_dma(&self) {
+        while !self.dma_done() {
+            // Spin while the DMA engine is sending.
+        }
+    }
+
+    /// Pops a byte off the RX FIFO, recording and returning the receive error
+    /// flagged for it, if any, alongside the byte.
+    fn read_data(&mut self) -> (u8, Option<UartError>) {
+        let data = self.registers.DR.read();
+
+        // A break also sets the framing error bit; report the break.
+        let error = if data & DrErrors::Break as u32 != 0 {
+            self.errors.breaks += 1;
+            Some(UartError::Break)
+        } else if data & DrErrors::Framing as u32 != 0 {
+            self.errors.framing += 1;
+            Some(UartError::Framing)
+        } else if data & DrErrors::Parity as u32 != 0 {
+            self.errors.parity += 1;
+            Some(UartError::Parity)
+        } else if data & DrErrors::Overrun as u32 != 0 {
+            self.errors.overrun += 1;
+            Some(UartError::Overrun)
+        } else {
+            None
+        };
+
+        (data as u8, error)
+    }
+}
+
+impl Uart for Pl011Uart {
+    fn set_read_timeout(&mut self, timeout: Duration) {
+        self.timeout_us = Some(duration_to_us(timeout));
+    }
+
+    fn read_timeout(&self) -> Option<Duration> {
+        self.timeout_us.map(Duration::from_micros)
+    }
+
+    fn try_write_byte(&mut self, byte: u8) -> Result<(), WouldBlock> {
+        if !self.dma_done() || self.registers.FR.read() & FrStatus::TxFull as u32 != 0 {
+            return Err(WouldBlock);
+        }
+
+        self.registers.DR.write(byte as u32);
+        Ok(())
+    }
+
+    fn has_byte(&self) -> bool {
+        self.registers.FR.read() & FrStatus::RxEmpty as u32 == 0
+    }
+
+    fn try_read_byte(&mut self) -> Option<u8> {
+        if self.has_byte() {
+            Some(self.read_data().0)
+        } else {
+            None
+        }
+    }
+
+    /// Errors are flagged per byte by the PL011; the erroneous byte is
+    /// consumed.
+    fn read_byte_checked(&mut self) -> Result<u8, UartError> {
+        self.wait_for_byte().map_err(|_| UartError::TimedOut)?;
+        match self.read_data() {
+            (byte, None) => Ok(byte),
+            (_, Some(error)) => Err(error)
+        }
+    }
+
+    fn error_counts(&self) -> ErrorCounts {
+        self.errors
+    }
+}
+
+impl fmt::Write for Pl011Uart {
+    fn write_str(&mut self, s: &str) -> Result<(), fmt::Error> {
+        for b in s.as_bytes() {
+            // Must write a CR before a NL.
+            if *b == b'\n' {
+                self.write_byte(b'\r');
+            }
+
+            self.write_byte(*b);
+        }
+
+        Ok(())
+    }
+}
+
+#[cfg(feature = "std")]
+mod uart_io {
+    use std::io;
+    use super::Pl011Uart;
+    use uart::{self, Uart};
+
+    impl io::Read for Pl011Uart {
+        fn read(&mut self, buf: &mut [u8]) -> io::Result<usize> {
+            let mut pending = self.pending_error.take();
+            let result = uart::uart_io::read(self, &mut pending, buf);
+            self.pending_error = pending;
+            result
+        }
+    }
+
+    impl io::Write for Pl011Uart {
+        fn write(&mut self, buf: &[u8]) -> io::Result<usize> {
+            for b in buf {
+                self.write_byte(*b);
+            }
+            Ok(buf.len())
+        }
+
+        fn flush(&mut self) -> Result<(), io::Error> {
+            Ok(())
+        }
+    }
+}
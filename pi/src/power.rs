@@ -0,0 +1,106 @@
+use core::time::Duration;
+
+use common::IO_BASE;
+use timer::duration_to_us;
+use volatile::prelude::*;
+use volatile::Volatile;
+
+/// The power manager's reset control register.
+const PM_RSTC: *mut Volatile<u32> = (IO_BASE + 0x10001C) as *mut Volatile<u32>;
+
+/// The power manager's reset status register. The firmware reads the boot
+/// partition to use after a reset from it.
+const PM_RSTS: *mut Volatile<u32> = (IO_BASE + 0x100020) as *mut Volatile<u32>;
+
+/// The power manager's watchdog timer register.
+const PM_WDOG: *mut Volatile<u32> = (IO_BASE + 0x100024) as *mut Volatile<u32>;
+
+/// Must be written in the top byte of every power manager register write.
+const PM_PASSWORD: u32 = 0x5A << 24;
+
+/// The watchdog counts down at 65536Hz in a 20-bit field.
+const WDOG_TICKS_PER_SEC: u64 = 65536;
+const WDOG_TIME_MASK: u32 = 0xFFFFF;
+
+/// The `RSTC` field selecting the reset performed when the watchdog expires.
+const RSTC_WRCFG_MASK: u32 = 0x30;
+/// `RSTC_WRCFG` value for a full reset.
+const RSTC_WRCFG_FULL_RESET: u32 = 0x20;
+/// Written to `RSTC` to stop the watchdog.
+const RSTC_RESET: u32 = 0x102;
+
+/// The `RSTS` bits holding the boot partition, spread over every other bit.
+const RSTS_PARTITION_MASK: u32 = 0x555;
+/// Partition 63, which the firmware treats as a request to halt.
+const RSTS_PARTITION_HALT: u32 = 0x555;
+
+/// Starts the watchdog with `ticks` ticks left, resetting the board when it
+/// expires.
+fn start_watchdog(ticks: u32) {
+    unsafe {
+        (*PM_WDOG).write(PM_PASSWORD | (ticks & WDOG_TIME_MASK));
+        let rstc = (*PM_RSTC).read() & !RSTC_WRCFG_MASK;
+        (*PM_RSTC).write(PM_PASSWORD | rstc | RSTC_WRCFG_FULL_RESET);
+    }
+}
+
+/// Resets the board, which then boots normally. Never returns.
+pub fn reboot() -> ! {
+    start_watchdog(10);
+    loop {  }
+}
+
+/// Halts the board: the firmware stops at boot instead of loading a kernel.
+/// The Pi has no way to cut its own power, so this is as close as it gets.
+/// Never returns.
+pub fn power_off() -> ! {
+    unsafe {
+        let rsts = (*PM_RSTS).read() & !RSTS_PARTITION_MASK;
+        (*PM_RSTS).write(PM_PASSWORD | rsts | RSTS_PARTITION_HALT);
+    }
+
+    reboot()
+}
+
+/// A running watchdog that resets the board unless it is kicked at least once
+/// per timeout.
+pub struct Watchdog {
+    ticks: u32
+}
+
+impl Watchdog {
+    /// The longest supported timeout, just under 16 seconds.
+    pub const MAX_TIMEOUT_MS: u64 = WDOG_TIME_MASK as u64 * 1000 / WDOG_TICKS_PER_SEC;
+
+    /// Starts the watchdog with a timeout of `timeout`.
+    ///
+    /// # Panics
+    ///
+    /// Panics if `timeout` is longer than `MAX_TIMEOUT_MS` milliseconds.
+    pub fn start(timeout: Duration) -> Watchdog {
+        let ticks = duration_to_us(timeout).saturating_mul(WDOG_TICKS_PER_SEC) / 1_000_000;
+        if ticks > WDOG_TIME_MASK as u64 {
+            panic!("Watchdog::start(): timeout of {:?} is out of range", timeout);
+        }
+
+        let watchdog = Watchdog { ticks: ::core::cmp::max(ticks as u32, 1) };
+        start_watchdog(watchdog.ticks);
+        watchdog
+    }
+
+    /// Restarts the countdown from the full timeout.
+    pub fn kick(&mut self) {
+        start_watchdog(self.ticks);
+    }
+
+    /// Returns the time left before the board is reset.
+    pub fn remaining(&self) -> Duration {
+        let ticks = unsafe { (*PM_WDOG).read() & WDOG_TIME_MASK };
+        Duration::from_micros(ticks as u64 * 1_000_000 / WDOG_TICKS_PER_SEC)
+    }
+
+    /// Stops the watchdog.
+    pub fn stop(self) {
+        unsafe { (*PM_RSTC).write(PM_PASSWORD | RSTC_RESET); }
+    }
+}
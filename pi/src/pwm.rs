@@ -0,0 +1,252 @@
+use common::IO_BASE;
+use volatile::prelude::*;
+use volatile::{Volatile, Reserved};
+
+/// The base address of the PWM controller's registers.
+const PWM_REG_BASE: usize = IO_BASE + 0x20C000;
+
+/// The clock manager's PWM clock control register.
+const CM_PWMCTL: *mut Volatile<u32> = (IO_BASE + 0x1010A0) as *mut Volatile<u32>;
+
+/// The clock manager's PWM clock divisor register.
+const CM_PWMDIV: *mut Volatile<u32> = (IO_BASE + 0x1010A4) as *mut Volatile<u32>;
+
+/// The frequency of the oscillator the PWM clock is derived from.
+const OSCILLATOR_HZ: u32 = 19_200_000;
+
+/// Enum representing bit fields of the clock manager's control register.
+#[repr(u32)]
+enum CmFlags {
+    SrcOscillator = 1,
+    Enable = 1 << 4,
+    Busy = 1 << 7,
+    Password = 0x5A << 24,
+}
+
+/// Enum representing bit fields of the `CTL` register for channel 1. The
+/// fields for channel 2 are the same, shifted left by 8, except `ClearFifo`.
+#[repr(u32)]
+enum CtlFlags {
+    Enable = 1,
+    UseFifo = 1 << 5,
+    ClearFifo = 1 << 6,
+    MarkSpace = 1 << 7,
+}
+
+/// Enum representing bit fields of the `DMAC` register.
+#[repr(u32)]
+enum DmacFlags {
+    Enable = 1 << 31,
+}
+
+/// The FIFO level below which DREQ is raised, and the level below which the
+/// PANIC signal is raised, as programmed by `enable_dma()`.
+const DMA_DREQ_THRESHOLD: u32 = 7;
+const DMA_PANIC_THRESHOLD: u32 = 7;
+
+/// The offset of the `FIF1` register from `PWM_REG_BASE`.
+const FIF1_OFFSET: usize = 0x18;
+
+#[repr(C)]
+#[allow(non_snake_case)]
+struct Registers {
+    CTL: Volatile<u32>,
+    STA: Volatile<u32>,
+    DMAC: Volatile<u32>,
+    __r0: Reserved<u32>,
+    RNG1: Volatile<u32>,
+    DAT1: Volatile<u32>,
+    FIF1: Volatile<u32>,
+    __r1: Reserved<u32>,
+    RNG2: Volatile<u32>,
+    DAT2: Volatile<u32>,
+}
+
+/// One of the PWM controller's two output channels.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum Channel {
+    /// `PWM0`, available on GPIO 12, 18, and 40.
+    Pwm0,
+    /// `PWM1`, available on GPIO 13, 19, 41, and 45.
+    Pwm1
+}
+
+/// How a channel spreads its high time across each period of `range` clock
+/// ticks.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum Mode {
+    /// The output is high for `data` ticks and then low for the rest of the
+    /// period: a conventional duty cycle, as servos expect.
+    MarkSpace,
+    /// High ticks are distributed as evenly as possible over the period,
+    /// which is easier to low-pass filter (e.g. for dimming or audio).
+    Balanced
+}
+
+/// Sets the frequency of the clock shared by both PWM channels in Hz, stopping
+/// the clock while the divisor is changed.
+///
+/// # Panics
+///
+/// Panics if `frequency` cannot be derived from the 19.2MHz oscillator by an
+/// integer divisor between 2 and 4095.
+pub fn set_clock(frequency: u32) {
+    let divisor = if frequency == 0 { 0 } else { OSCILLATOR_HZ / frequency };
+    if divisor < 2 || divisor > 0xFFF {
+        panic!("pwm::set_clock(): {}Hz is out of range", frequency);
+    }
+
+    unsafe {
+        // Stop the clock and wait for it to settle before changing it.
+        (*CM_PWMCTL).write(CmFlags::Password as u32 | CmFlags::SrcOscillator as u32);
+        while (*CM_PWMCTL).has_mask(CmFlags::Busy as u32) {  }
+
+        (*CM_PWMDIV).write(CmFlags::Password as u32 | divisor << 12);
+        (*CM_PWMCTL).write(CmFlags::Password as u32
+                           | CmFlags::SrcOscillator as u32
+                           | CmFlags::Enable as u32);
+    }
+}
+
+/// Returns the `Registers` shared by both channels.
+fn registers() -> &'static mut Registers {
+    unsafe { &mut *(PWM_REG_BASE as *mut Registers) }
+}
+
+/// Returns the ARM physical address of the FIFO register, the destination of
+/// DMA transfers feeding the FIFO.
+pub fn fifo_address() -> usize {
+    PWM_REG_BASE + FIF1_OFFSET
+}
+
+/// Discards everything in the FIFO.
+pub fn clear_fifo() {
+    registers().CTL.or_mask(CtlFlags::ClearFifo as u32);
+}
+
+/// Raises the PWM DREQ (`dma::dreq::PWM`) whenever the FIFO runs low, so a
+/// DMA channel can keep it filled.
+pub fn enable_dma() {
+    registers().DMAC.write(DmacFlags::Enable as u32
+                           | DMA_PANIC_THRESHOLD << 8
+                           | DMA_DREQ_THRESHOLD);
+}
+
+/// Stops raising the PWM DREQ.
+pub fn disable_dma() {
+    registers().DMAC.and_mask(!(DmacFlags::Enable as u32));
+}
+
+/// A single PWM output channel.
+///
+/// The channel's output pin must be routed separately, e.g. with
+/// `Gpio::with_signal(pin::P18, signal::Pwm0)`, and the PWM clock configured
+/// with `set_clock()`.
+pub struct Pwm {
+    channel: Channel,
+    registers: &'static mut Registers
+}
+
+impl Pwm {
+    /// Returns the PWM channel `channel` in mode `mode` with a period of
+    /// `range` clock ticks. The channel starts enabled with a duty cycle of 0.
+    pub fn new(channel: Channel, mode: Mode, range: u32) -> Pwm {
+        let mut pwm = Pwm { channel, registers: registers() };
+
+        pwm.disable();
+        pwm.set_range(range);
+        pwm.set_data(0);
+
+        let mode_bits = match mode {
+            Mode::MarkSpace => CtlFlags::MarkSpace as u32,
+            Mode::Balanced => 0
+        };
+
+        let shift = pwm.shift();
+        pwm.registers.CTL.and_mask(!(0xFF << shift));
+        pwm.registers.CTL.or_mask((mode_bits | CtlFlags::Enable as u32) << shift);
+        pwm
+    }
+
+    /// The offset of this channel's fields within `CTL`.
+    fn shift(&self) -> u32 {
+        match self.channel {
+            Channel::Pwm0 => 0,
+            Channel::Pwm1 => 8
+        }
+    }
+
+    /// Returns the channel this `Pwm` drives.
+    pub fn channel(&self) -> Channel {
+        self.channel
+    }
+
+    /// Starts driving the output.
+    pub fn enable(&mut self) {
+        let shift = self.shift();
+        self.registers.CTL.or_mask((CtlFlags::Enable as u32) << shift);
+    }
+
+    /// Stops driving the output, which then idles low.
+    pub fn disable(&mut self) {
+        let shift = self.shift();
+        self.registers.CTL.and_mask(!((CtlFlags::Enable as u32) << shift));
+    }
+
+    /// Takes each period's high ticks from the shared FIFO (`true`) or from
+    /// `set_data()` (`false`). When both channels use the FIFO, its words
+    /// alternate between `Pwm0` and `Pwm1`.
+    pub fn set_fifo(&mut self, enabled: bool) {
+        let mask = (CtlFlags::UseFifo as u32) << self.shift();
+        if enabled {
+            self.registers.CTL.or_mask(mask);
+        } else {
+            self.registers.CTL.and_mask(!mask);
+        }
+    }
+
+    /// Returns the length of each period in clock ticks.
+    pub fn range(&self) -> u32 {
+        match self.channel {
+            Channel::Pwm0 => self.registers.RNG1.read(),
+            Channel::Pwm1 => self.registers.RNG2.read()
+        }
+    }
+
+    /// Sets the length of each period to `range` clock ticks.
+    pub fn set_range(&mut self, range: u32) {
+        match self.channel {
+            Channel::Pwm0 => self.registers.RNG1.write(range),
+            Channel::Pwm1 => self.registers.RNG2.write(range)
+        }
+    }
+
+    /// Sets the number of high clock ticks in each period to `data`. Values
+    /// above `range()` keep the output high.
+    pub fn set_data(&mut self, data: u32) {
+        match self.channel {
+            Channel::Pwm0 => self.registers.DAT1.write(data),
+            Channel::Pwm1 => self.registers.DAT2.write(data)
+        }
+    }
+
+    /// Sets the duty cycle to `numerator / denominator` of each period.
+    ///
+    /// # Panics
+    ///
+    /// Panics if `denominator` is 0.
+    pub fn set_duty_cycle(&mut self, numerator: u32, denominator: u32) {
+        if denominator == 0 {
+            panic!("Pwm::set_duty_cycle(): denominator is 0");
+        }
+
+        let numerator = ::core::cmp::min(numerator, denominator);
+        let data = self.range() as u64 * numerator as u64 / denominator as u64;
+        self.set_data(data as u32);
+    }
+
+    /// Sets the duty cycle to `percent` percent, saturating at 100.
+    pub fn set_duty_percent(&mut self, percent: u8) {
+        self.set_duty_cycle(percent as u32, 100);
+    }
+}
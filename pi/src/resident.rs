@@ -0,0 +1,51 @@
+use core::slice;
+
+use crc::crc32;
+use volatile::prelude::*;
+use volatile::Volatile;
+
+/// Where the bootloader keeps a copy of itself while it runs, and while the
+/// kernel it boots runs, and the copy's size.
+pub const ADDR: usize = 0x4000000;
+pub const SIZE: usize = 0x40000;
+
+/// The record the bootloader leaves in the last 8 bytes of its copy before
+/// it jumps to a kernel: `MAGIC`, then the CRC-32 of the rest of the copy.
+const RECORD_ADDR: usize = ADDR + SIZE - 8;
+const MAGIC: u32 = 0x544F_4F42;
+
+fn record() -> &'static mut [Volatile<u32>; 2] {
+    unsafe { &mut *(RECORD_ADDR as *mut [Volatile<u32>; 2]) }
+}
+
+fn copy() -> &'static [u8] {
+    unsafe { slice::from_raw_parts(ADDR as *const u8, RECORD_ADDR - ADDR) }
+}
+
+/// Records that the copy of the bootloader at `ADDR` is intact, so that the
+/// kernel can re-enter it. Called by the bootloader just before it jumps to
+/// a kernel, after which the copy mustn't change.
+///
+/// # Safety
+///
+/// The bootloader's copy must be at `ADDR`, clear of its last 8 bytes.
+pub unsafe fn mark() {
+    let crc = crc32(copy());
+    let record = record();
+    record[1].write(crc);
+    record[0].write(MAGIC);
+}
+
+/// Returns the copy of the bootloader at `ADDR`, to be moved to where the
+/// firmware loads kernels and entered there, if the bootloader booted the
+/// running kernel and its copy is intact. Returns `None` if the kernel was
+/// booted some other way, such as by the firmware or from the SD card by
+/// another bootloader, or if the kernel overwrote the copy.
+pub fn image() -> Option<&'static [u8]> {
+    let record = record();
+    if record[0].read() != MAGIC || record[1].read() != crc32(copy()) {
+        return None;
+    }
+
+    Some(unsafe { slice::from_raw_parts(ADDR as *const u8, SIZE) })
+}
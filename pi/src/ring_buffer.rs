@@ -0,0 +1,81 @@
+use core::cell::UnsafeCell;
+use core::sync::atomic::{AtomicUsize, Ordering};
+
+/// The number of bytes a `RingBuffer` can hold. Must be a power of two.
+pub const RING_BUFFER_SIZE: usize = 256;
+
+/// A fixed-capacity, single-producer single-consumer queue of bytes.
+///
+/// One side (typically an interrupt handler) calls `push` while the other calls
+/// `pop`. Neither operation blocks or takes a lock: `tail` is only written by
+/// the producer and `head` only by the consumer. Both indices increase
+/// monotonically and are reduced modulo the capacity on access.
+pub struct RingBuffer {
+    buffer: UnsafeCell<[u8; RING_BUFFER_SIZE]>,
+    head: AtomicUsize,
+    tail: AtomicUsize,
+}
+
+unsafe impl Sync for RingBuffer {  }
+
+impl RingBuffer {
+    /// Returns a new, empty `RingBuffer`.
+    pub const fn new() -> RingBuffer {
+        RingBuffer {
+            buffer: UnsafeCell::new([0; RING_BUFFER_SIZE]),
+            head: AtomicUsize::new(0),
+            tail: AtomicUsize::new(0),
+        }
+    }
+
+    /// Returns the number of bytes currently in the buffer.
+    pub fn len(&self) -> usize {
+        let tail = self.tail.load(Ordering::Acquire);
+        let head = self.head.load(Ordering::Acquire);
+        tail.wrapping_sub(head)
+    }
+
+    /// Returns `true` if there are no bytes in the buffer.
+    pub fn is_empty(&self) -> bool {
+        self.len() == 0
+    }
+
+    /// Returns `true` if the buffer cannot accept any more bytes.
+    pub fn is_full(&self) -> bool {
+        self.len() == RING_BUFFER_SIZE
+    }
+
+    /// Appends `byte` to the buffer. Returns `Err(())` if the buffer is full,
+    /// in which case `byte` is discarded.
+    ///
+    /// Must only be called by the (single) producer.
+    pub fn push(&self, byte: u8) -> Result<(), ()> {
+        if self.is_full() {
+            return Err(());
+        }
+
+        let tail = self.tail.load(Ordering::Relaxed);
+        unsafe {
+            (*self.buffer.get())[tail % RING_BUFFER_SIZE] = byte;
+        }
+
+        self.tail.store(tail.wrapping_add(1), Ordering::Release);
+        Ok(())
+    }
+
+    /// Removes and returns the oldest byte in the buffer, or `None` if the
+    /// buffer is empty.
+    ///
+    /// Must only be called by the (single) consumer.
+    pub fn pop(&self) -> Option<u8> {
+        if self.is_empty() {
+            return None;
+        }
+
+        let head = self.head.load(Ordering::Relaxed);
+        let byte = unsafe { (*self.buffer.get())[head % RING_BUFFER_SIZE] };
+
+        self.head.store(head.wrapping_add(1), Ordering::Release);
+        Some(byte)
+    }
+}
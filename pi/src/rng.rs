@@ -0,0 +1,76 @@
+use common::IO_BASE;
+use volatile::prelude::*;
+use volatile::{Volatile, ReadVolatile};
+
+/// The base address of the hardware random number generator's registers.
+const RNG_REG_BASE: usize = IO_BASE + 0x104000;
+
+/// The number of initial values the generator discards while its entropy
+/// source warms up, written to `STATUS` before enabling it.
+const WARMUP_COUNT: u32 = 0x40000;
+
+/// Enum representing bit fields of the `CTRL` register.
+#[repr(u32)]
+enum CtrlFlags {
+    Enable = 1,
+}
+
+/// The `INT_MASK` bit disabling the generator's interrupt.
+const INT_OFF: u32 = 1;
+
+#[repr(C)]
+#[allow(non_snake_case)]
+struct Registers {
+    CTRL: Volatile<u32>,
+    STATUS: Volatile<u32>,
+    DATA: ReadVolatile<u32>,
+    FF_THRESHOLD: Volatile<u32>,
+    INT_MASK: Volatile<u32>,
+}
+
+/// The BCM2837 hardware random number generator.
+pub struct Rng {
+    registers: &'static mut Registers
+}
+
+impl Rng {
+    /// Returns the hardware random number generator, enabling it if needed.
+    /// The first values take a moment to arrive while it warms up.
+    pub fn new() -> Rng {
+        let registers = unsafe { &mut *(RNG_REG_BASE as *mut Registers) };
+
+        if !registers.CTRL.has_mask(CtrlFlags::Enable as u32) {
+            registers.INT_MASK.or_mask(INT_OFF);
+            registers.STATUS.write(WARMUP_COUNT);
+            registers.CTRL.or_mask(CtrlFlags::Enable as u32);
+        }
+
+        Rng { registers }
+    }
+
+    /// Returns the number of random words ready to be read.
+    pub fn available(&self) -> usize {
+        (self.registers.STATUS.read() >> 24) as usize
+    }
+
+    /// Returns a random `u32`, blocking until one is available.
+    pub fn next_u32(&mut self) -> u32 {
+        while self.available() == 0 {  }
+        self.registers.DATA.read()
+    }
+
+    /// Returns a random `u64`, blocking until one is available.
+    pub fn next_u64(&mut self) -> u64 {
+        (self.next_u32() as u64) << 32 | self.next_u32() as u64
+    }
+
+    /// Fills `buffer` with random bytes, blocking until enough are available.
+    pub fn fill_bytes(&mut self, buffer: &mut [u8]) {
+        for chunk in buffer.chunks_mut(4) {
+            let word = self.next_u32();
+            for (i, byte) in chunk.iter_mut().enumerate() {
+                *byte = (word >> (i * 8)) as u8;
+            }
+        }
+    }
+}
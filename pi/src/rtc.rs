@@ -0,0 +1,322 @@
+use core::fmt;
+use core::str::FromStr;
+
+use i2c::{I2c, I2cError};
+
+/// The address both chips answer at.
+const ADDRESS: u8 = 0x68;
+
+/// Registers. The time is in BCD in `SECONDS` to `YEAR` on both chips.
+mod register {
+    pub const SECONDS: u8 = 0x00;
+    /// The DS3231's status register.
+    pub const STATUS: u8 = 0x0F;
+}
+
+/// `SECONDS`: the DS1307's oscillator is halted.
+const CLOCK_HALT: u8 = 1 << 7;
+/// `HOURS`: the hour is in 12-hour format, and is after noon.
+const TWELVE_HOUR: u8 = 1 << 6;
+const PM: u8 = 1 << 5;
+/// `MONTH`: the DS3231's year has passed 2099.
+const CENTURY: u8 = 1 << 7;
+/// `STATUS`: the DS3231's oscillator stopped since the time was last set.
+const OSCILLATOR_STOPPED: u8 = 1 << 7;
+
+/// The first year either chip counts from.
+const BASE_YEAR: u16 = 2000;
+
+/// The days from the Unix epoch to 0000-03-01 in the proleptic Gregorian
+/// calendar, for the conversions below.
+const EPOCH_OFFSET_DAYS: u64 = 719_468;
+
+/// The days in each 400-year cycle of the Gregorian calendar.
+const DAYS_PER_ERA: u64 = 146_097;
+
+/// A calendar date and time of day, in UTC.
+#[derive(Debug, Clone, Copy, PartialEq, Eq, PartialOrd, Ord)]
+pub struct DateTime {
+    pub year: u16,
+    /// 1 to 12.
+    pub month: u8,
+    /// 1 to 31.
+    pub day: u8,
+    pub hour: u8,
+    pub minute: u8,
+    pub second: u8,
+}
+
+/// Returns whether `year` is a leap year.
+fn is_leap_year(year: u16) -> bool {
+    year % 4 == 0 && (year % 100 != 0 || year % 400 == 0)
+}
+
+/// Returns the number of days in `month` of `year`.
+fn days_in_month(year: u16, month: u8) -> u8 {
+    match month {
+        2 if is_leap_year(year) => 29,
+        2 => 28,
+        4 | 6 | 9 | 11 => 30,
+        _ => 31,
+    }
+}
+
+impl DateTime {
+    /// The Unix epoch, 1970-01-01 00:00:00.
+    pub const EPOCH: DateTime = DateTime {
+        year: 1970, month: 1, day: 1, hour: 0, minute: 0, second: 0
+    };
+
+    /// Returns whether every field is in range, with the day in the month,
+    /// and the time isn't before the Unix epoch.
+    pub fn is_valid(&self) -> bool {
+        self.year >= 1970
+            && self.month >= 1 && self.month <= 12
+            && self.day >= 1 && self.day <= days_in_month(self.year, self.month)
+            && self.hour < 24 && self.minute < 60 && self.second < 60
+    }
+
+    /// Returns the time `seconds` after the Unix epoch.
+    pub fn from_unix(seconds: u64) -> DateTime {
+        let (days, time) = (seconds / 86400, seconds % 86400);
+
+        // Days are counted in 400-year eras starting on March 1st, which
+        // puts leap days at the end of each year.
+        let days = days + EPOCH_OFFSET_DAYS;
+        let (era, day_of_era) = (days / DAYS_PER_ERA, days % DAYS_PER_ERA);
+        let year_of_era = (day_of_era - day_of_era / 1460 + day_of_era / 36524
+                           - day_of_era / 146_096) / 365;
+        let day_of_year = day_of_era - (365 * year_of_era + year_of_era / 4 - year_of_era / 100);
+        let shifted_month = (5 * day_of_year + 2) / 153;
+        let day = day_of_year - (153 * shifted_month + 2) / 5 + 1;
+        let month = if shifted_month < 10 { shifted_month + 3 } else { shifted_month - 9 };
+        let year = era * 400 + year_of_era + if month <= 2 { 1 } else { 0 };
+
+        DateTime {
+            year: year as u16,
+            month: month as u8,
+            day: day as u8,
+            hour: (time / 3600) as u8,
+            minute: (time / 60 % 60) as u8,
+            second: (time % 60) as u8,
+        }
+    }
+
+    /// Returns the days from the Unix epoch to the date.
+    fn days(&self) -> u64 {
+        let (month, day) = (self.month as u64, self.day as u64);
+        let year = self.year as u64 - if month <= 2 { 1 } else { 0 };
+        let (era, year_of_era) = (year / 400, year % 400);
+        let shifted_month = if month > 2 { month - 3 } else { month + 9 };
+        let day_of_year = (153 * shifted_month + 2) / 5 + day - 1;
+        let day_of_era = year_of_era * 365 + year_of_era / 4 - year_of_era / 100 + day_of_year;
+        era * DAYS_PER_ERA + day_of_era - EPOCH_OFFSET_DAYS
+    }
+
+    /// Returns the seconds from the Unix epoch to the time, which must be
+    /// valid.
+    pub fn to_unix(&self) -> u64 {
+        self.days() * 86400
+            + self.hour as u64 * 3600 + self.minute as u64 * 60 + self.second as u64
+    }
+
+    /// Returns the day of the week, from 0 for Sunday to 6 for Saturday.
+    pub fn weekday(&self) -> u8 {
+        // The epoch was a Thursday.
+        ((self.days() + 4) % 7) as u8
+    }
+
+    /// Parses a date as `YYYY-MM-DD` and a time of day as `HH:MM:SS`.
+    /// Returns `None` if either is malformed or the result is invalid.
+    pub fn parse(date: &str, time: &str) -> Option<DateTime> {
+        let mut date = date.split('-');
+        let mut time = time.split(':');
+
+        let datetime = DateTime {
+            year: date.next()?.parse().ok()?,
+            month: date.next()?.parse().ok()?,
+            day: date.next()?.parse().ok()?,
+            hour: time.next()?.parse().ok()?,
+            minute: time.next()?.parse().ok()?,
+            second: time.next()?.parse().ok()?,
+        };
+
+        if date.next().is_some() || time.next().is_some() || !datetime.is_valid() {
+            return None;
+        }
+
+        Some(datetime)
+    }
+}
+
+/// The error returned when parsing a `DateTime` fails.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct ParseDateTimeError;
+
+impl FromStr for DateTime {
+    type Err = ParseDateTimeError;
+
+    /// Parses an ISO 8601 time as `YYYY-MM-DDTHH:MM:SS`, or with a space
+    /// instead of the `T`.
+    fn from_str(s: &str) -> Result<DateTime, ParseDateTimeError> {
+        let mut parts = s.splitn(2, |c: char| c == 'T' || c == ' ');
+        match (parts.next(), parts.next()) {
+            (Some(date), Some(time)) => DateTime::parse(date, time).ok_or(ParseDateTimeError),
+            _ => Err(ParseDateTimeError)
+        }
+    }
+}
+
+impl fmt::Display for DateTime {
+    /// Writes the time as `YYYY-MM-DD HH:MM:SS`.
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        write!(f, "{:04}-{:02}-{:02} {:02}:{:02}:{:02}",
+               self.year, self.month, self.day, self.hour, self.minute, self.second)
+    }
+}
+
+/// Returns the binary value of the BCD byte `bcd`.
+fn from_bcd(bcd: u8) -> u8 {
+    (bcd >> 4) * 10 + (bcd & 0xF)
+}
+
+/// Returns `value`, which must be under 100, in BCD.
+fn to_bcd(value: u8) -> u8 {
+    (value / 10) << 4 | value % 10
+}
+
+/// The supported RTC chips. They answer at the same address and can't be
+/// told apart by probing.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum Chip {
+    /// The temperature compensated DS3231, counting years 2000-2199.
+    Ds3231,
+    /// The DS1307, counting years 2000-2099.
+    Ds1307,
+}
+
+/// An error reading or setting an RTC.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum RtcError {
+    /// Talking to the chip failed; `I2cError::Nack` if there's none fitted.
+    I2c(I2cError),
+    /// The oscillator has stopped, or stopped since the time was set, so
+    /// the time is wrong until it's set again.
+    Stopped,
+    /// The chip holds a time that isn't one, or a time given to set it to
+    /// is out of its range.
+    Invalid,
+}
+
+impl From<I2cError> for RtcError {
+    fn from(error: I2cError) -> RtcError {
+        RtcError::I2c(error)
+    }
+}
+
+/// A DS3231 or DS1307 real-time clock on I2C, kept in 24-hour UTC.
+pub struct Rtc {
+    i2c: I2c,
+    chip: Chip,
+}
+
+impl Rtc {
+    /// Returns the RTC of kind `chip` on `i2c`.
+    pub fn new(i2c: I2c, chip: Chip) -> Rtc {
+        Rtc { i2c, chip }
+    }
+
+    /// Returns the kind of chip.
+    pub fn chip(&self) -> Chip {
+        self.chip
+    }
+
+    /// Returns the I2C bus, giving up the RTC.
+    pub fn release(self) -> I2c {
+        self.i2c
+    }
+
+    /// Reads the DS3231's status register.
+    fn status(&mut self) -> Result<u8, I2cError> {
+        let mut status = [0];
+        self.i2c.write_read(ADDRESS, &[register::STATUS], &mut status)?;
+        Ok(status[0])
+    }
+
+    /// Returns the current time.
+    pub fn read(&mut self) -> Result<DateTime, RtcError> {
+        let mut regs = [0; 7];
+        self.i2c.write_read(ADDRESS, &[register::SECONDS], &mut regs)?;
+
+        let stopped = match self.chip {
+            Chip::Ds3231 => self.status()? & OSCILLATOR_STOPPED != 0,
+            Chip::Ds1307 => regs[0] & CLOCK_HALT != 0,
+        };
+
+        if stopped {
+            return Err(RtcError::Stopped);
+        }
+
+        let hours = regs[2];
+        let hour = if hours & TWELVE_HOUR != 0 {
+            from_bcd(hours & 0x1F) % 12 + if hours & PM != 0 { 12 } else { 0 }
+        } else {
+            from_bcd(hours & 0x3F)
+        };
+
+        let century = match self.chip {
+            Chip::Ds3231 if regs[5] & CENTURY != 0 => 100,
+            _ => 0,
+        };
+
+        let time = DateTime {
+            year: BASE_YEAR + century + from_bcd(regs[6]) as u16,
+            month: from_bcd(regs[5] & 0x1F),
+            day: from_bcd(regs[4] & 0x3F),
+            hour,
+            minute: from_bcd(regs[1] & 0x7F),
+            second: from_bcd(regs[0] & 0x7F),
+        };
+
+        if time.is_valid() { Ok(time) } else { Err(RtcError::Invalid) }
+    }
+
+    /// Sets the time to `time` and starts the oscillator if it was stopped.
+    ///
+    /// # Errors
+    ///
+    /// Returns `RtcError::Invalid` if `time` isn't valid or is out of the
+    /// chip's range.
+    pub fn set(&mut self, time: &DateTime) -> Result<(), RtcError> {
+        let last_year = match self.chip {
+            Chip::Ds3231 => BASE_YEAR + 199,
+            Chip::Ds1307 => BASE_YEAR + 99,
+        };
+
+        if !time.is_valid() || time.year < BASE_YEAR || time.year > last_year {
+            return Err(RtcError::Invalid);
+        }
+
+        let years = time.year - BASE_YEAR;
+        let century = if years >= 100 { CENTURY } else { 0 };
+
+        // Writing the seconds clears the DS1307's halt bit, starting it.
+        self.i2c.write(ADDRESS, &[
+            register::SECONDS,
+            to_bcd(time.second),
+            to_bcd(time.minute),
+            to_bcd(time.hour),
+            time.weekday() + 1,
+            to_bcd(time.day),
+            to_bcd(time.month) | century,
+            to_bcd((years % 100) as u8),
+        ])?;
+
+        if self.chip == Chip::Ds3231 {
+            let status = self.status()?;
+            self.i2c.write(ADDRESS, &[register::STATUS, status & !OSCILLATOR_STOPPED])?;
+        }
+
+        Ok(())
+    }
+}
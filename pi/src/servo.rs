@@ -0,0 +1,143 @@
+use pwm::{self, Channel, Mode, Pwm};
+use soft_pwm;
+
+/// The pulse rate servos and ESCs expect.
+pub const FREQUENCY: u32 = 50;
+
+/// The period between pulses, in microseconds.
+const PERIOD_US: u32 = 1_000_000 / FREQUENCY;
+
+/// The PWM clock set for hardware outputs: 19.2MHz divided by 16, the
+/// closest integer divisor to a 1MHz tick.
+const PWM_CLOCK_HZ: u32 = 1_200_000;
+
+/// The angle `set_angle()` maps to the longest pulse, in degrees.
+pub const MAX_ANGLE: u32 = 180;
+
+/// The pulse widths a servo or ESC takes for its end positions, or for no
+/// and full throttle, in microseconds.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct Calibration {
+    pub min_us: u32,
+    pub max_us: u32,
+}
+
+impl Default for Calibration {
+    /// Returns the nominal 1-2ms range.
+    fn default() -> Calibration {
+        Calibration { min_us: 1000, max_us: 2000 }
+    }
+}
+
+/// Where a servo's pulses are generated.
+enum Output {
+    /// A hardware PWM channel, at sub-microsecond resolution.
+    Hardware(Pwm),
+    /// A pin driven by `soft_pwm`, at 1% of the period: 200us steps.
+    Software(u8),
+}
+
+/// A hobby servo or ESC, driven with a 50Hz pulse whose width sets its
+/// position or throttle.
+pub struct Servo {
+    output: Output,
+    calibration: Calibration,
+    pulse_us: u32,
+}
+
+impl Servo {
+    /// Returns a servo driven by the PWM channel `channel`, sending no pulses
+    /// until a position is set.
+    ///
+    /// The PWM clock is set to suit, which changes the other channel's
+    /// period too, and the channel's pin must be routed separately, e.g. with
+    /// `Gpio::with_signal(pin::P18, signal::Pwm0)`.
+    pub fn hardware(channel: Channel, calibration: Calibration) -> Servo {
+        pwm::set_clock(PWM_CLOCK_HZ);
+        let range = PWM_CLOCK_HZ / FREQUENCY;
+        Servo {
+            output: Output::Hardware(Pwm::new(channel, Mode::MarkSpace, range)),
+            calibration,
+            pulse_us: 0,
+        }
+    }
+
+    /// Returns a servo driven by `soft_pwm` on pin `pin`, sending no pulses
+    /// until a position is set. `soft_pwm::start(FREQUENCY)` must have been
+    /// called. Software pulses are only as fine as 200us, a fifth of the
+    /// nominal range, so this suits ESCs and coarse positioning.
+    ///
+    /// # Panics
+    ///
+    /// Panics if `pin` > `53`.
+    pub fn software(pin: u8, calibration: Calibration) -> Servo {
+        soft_pwm::register(pin, 0);
+        Servo { output: Output::Software(pin), calibration, pulse_us: 0 }
+    }
+
+    /// Returns the calibration in use.
+    pub fn calibration(&self) -> Calibration {
+        self.calibration
+    }
+
+    /// Changes the calibration. The pulse width is kept until the next
+    /// position is set.
+    pub fn set_calibration(&mut self, calibration: Calibration) {
+        self.calibration = calibration;
+    }
+
+    /// Returns the width of the pulses being sent in microseconds, or 0 if
+    /// none are.
+    pub fn pulse_us(&self) -> u32 {
+        self.pulse_us
+    }
+
+    /// Sends pulses `pulse_us` wide, clamped to the calibrated range.
+    pub fn set_pulse_us(&mut self, pulse_us: u32) {
+        let (min, max) = (self.calibration.min_us, self.calibration.max_us);
+        let pulse_us = ::core::cmp::max(min, ::core::cmp::min(pulse_us, max));
+        self.send(pulse_us);
+    }
+
+    /// Sends pulses `pulse_us` wide, or none if it's 0.
+    fn send(&mut self, pulse_us: u32) {
+        self.pulse_us = pulse_us;
+        match self.output {
+            Output::Hardware(ref mut pwm) => {
+                pwm.set_data((pulse_us as u64 * PWM_CLOCK_HZ as u64 / 1_000_000) as u32)
+            }
+            Output::Software(pin) => {
+                let percent = (pulse_us * 100 + PERIOD_US / 2) / PERIOD_US;
+                soft_pwm::register(pin, percent as u8);
+            }
+        }
+    }
+
+    /// Returns the pulse width `numerator / denominator` of the way through
+    /// the calibrated range.
+    fn scale(&self, numerator: u32, denominator: u32) -> u32 {
+        let (min, max) = (self.calibration.min_us, self.calibration.max_us);
+        let numerator = ::core::cmp::min(numerator, denominator);
+        min + (max.saturating_sub(min)) * numerator / denominator
+    }
+
+    /// Moves a servo to `degrees`, from 0 at the shortest pulse to
+    /// `MAX_ANGLE` at the longest, saturating there.
+    pub fn set_angle(&mut self, degrees: u32) {
+        let pulse_us = self.scale(degrees, MAX_ANGLE);
+        self.send(pulse_us);
+    }
+
+    /// Sets an ESC's throttle to `percent` percent, from the shortest pulse
+    /// at 0 to the longest at 100, saturating there.
+    pub fn set_throttle(&mut self, percent: u8) {
+        let pulse_us = self.scale(percent as u32, 100);
+        self.send(pulse_us);
+    }
+
+    /// Stops sending pulses, which leaves servos free to turn. ESCs treat it
+    /// as lost signal and stop.
+    pub fn disable(&mut self) {
+        self.send(0);
+    }
+}
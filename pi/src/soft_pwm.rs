@@ -0,0 +1,129 @@
+use gpio::{self, Gpio, NUM_PINS};
+use timer::{Timer, Compare};
+
+/// The number of timer ticks in each period, and so the duty cycle
+/// resolution: one tick per percent.
+const STEPS: u32 = 100;
+
+/// The shortest supported tick, in microseconds. Shorter ticks would leave
+/// too little time between interrupts for anything else to run.
+const MIN_TICK_US: u32 = 20;
+
+/// The duty cycle in percent of each pin registered with `register()`, indexed
+/// by pin.
+static mut DUTIES: [Option<u8>; NUM_PINS] = [None; NUM_PINS];
+
+/// The length of a tick in microseconds, or 0 while stopped.
+static mut TICK_US: u32 = 0;
+
+/// The counter value the next tick is due at.
+static mut DEADLINE: u32 = 0;
+
+/// The position of the next tick within the period, in `0..STEPS`.
+static mut STEP: u32 = 0;
+
+/// Starts toggling the registered pins with a period of `1 / frequency`
+/// seconds, using compare channel `C1` of the system timer. Calling `start()`
+/// again changes the frequency.
+///
+/// # Panics
+///
+/// Panics if `frequency` is 0 or requires ticks shorter than 20us, i.e. is
+/// above 500Hz.
+pub fn start(frequency: u32) {
+    let tick_us = if frequency == 0 { 0 } else { 1_000_000 / (frequency * STEPS) };
+    if tick_us < MIN_TICK_US {
+        panic!("soft_pwm::start(): {}Hz is out of range", frequency);
+    }
+
+    let mut timer = Timer::new();
+    unsafe {
+        TICK_US = tick_us;
+        STEP = 0;
+        DEADLINE = timer.arm_in(Compare::C1, tick_us);
+    }
+}
+
+/// Stops the timer interrupt and drives every registered pin low. Pins remain
+/// registered for the next `start()`.
+pub fn stop() {
+    Timer::new().disarm(Compare::C1);
+    unsafe { TICK_US = 0; }
+    gpio::clear_mask(registered_mask(|_| true));
+}
+
+/// Registers `pin` to be driven with a duty cycle of `percent` percent,
+/// saturating at 100, or updates the duty cycle of an already registered pin.
+/// The pin is switched to an output.
+///
+/// # Panics
+///
+/// Panics if `pin` > `53`.
+pub fn register(pin: u8, percent: u8) {
+    if pin as usize >= NUM_PINS {
+        panic!("soft_pwm::register(): pin {} exceeds maximum of 53", pin);
+    }
+
+    unsafe {
+        if DUTIES[pin as usize].is_none() {
+            Gpio::new(pin).into_output().clear();
+            gpio::claim(&[pin], "soft_pwm");
+        }
+
+        DUTIES[pin as usize] = Some(::core::cmp::min(percent, 100));
+    }
+}
+
+/// Stops driving `pin`, leaving it low, if it was registered.
+pub fn unregister(pin: u8) {
+    if (pin as usize) < NUM_PINS && unsafe { DUTIES[pin as usize].is_some() } {
+        unsafe { DUTIES[pin as usize] = None; }
+        gpio::clear_mask(1 << pin);
+        gpio::release_owned(&[pin], "soft_pwm");
+    }
+}
+
+/// Returns a mask of the registered pins whose duty cycle satisfies `f`, where
+/// bit `n` corresponds to pin `n`.
+fn registered_mask<F: Fn(u8) -> bool>(f: F) -> u64 {
+    let mut mask = 0;
+    for pin in 0..NUM_PINS {
+        if let Some(duty) = unsafe { DUTIES[pin] } {
+            if f(duty) {
+                mask |= 1 << pin;
+            }
+        }
+    }
+
+    mask
+}
+
+/// Advances every registered pin by one tick and schedules the next one. Must
+/// be called from the IRQ handler when a timer interrupt is pending once
+/// `start()` has been called.
+pub fn handle_irq() {
+    let mut timer = Timer::new();
+    if !timer.acknowledge(Compare::C1) || unsafe { TICK_US } == 0 {
+        return;
+    }
+
+    let step = unsafe { STEP };
+    if step == 0 {
+        gpio::set_mask(registered_mask(|duty| duty > 0));
+    }
+    gpio::clear_mask(registered_mask(|duty| duty as u32 == step));
+
+    unsafe {
+        STEP = (step + 1) % STEPS;
+
+        // Schedule from the previous deadline so the period doesn't drift,
+        // unless it has already passed.
+        DEADLINE = DEADLINE.wrapping_add(TICK_US);
+        let now = timer.counter_low();
+        if DEADLINE.wrapping_sub(now) as i32 <= 0 {
+            DEADLINE = now.wrapping_add(TICK_US);
+        }
+
+        timer.arm_at(Compare::C1, DEADLINE);
+    }
+}
@@ -0,0 +1,222 @@
+use clock;
+use common::IO_BASE;
+use gpio::{self, Gpio, pin, signal};
+use volatile::prelude::*;
+use volatile::Volatile;
+
+/// The base address of the `SPI0` registers.
+const SPI0_REG_BASE: usize = IO_BASE + 0x204000;
+
+/// Enum representing bit fields of the `CS` register.
+#[repr(u32)]
+enum CsFlags {
+    Cpha = 1 << 2,
+    Cpol = 1 << 3,
+    ClearTx = 1 << 4,
+    ClearRx = 1 << 5,
+    Ta = 1 << 7,
+    Done = 1 << 16,
+    Rxd = 1 << 17,
+    Txd = 1 << 18,
+}
+
+/// The `CS` field selecting the chip select line.
+const CS_SELECT_MASK: u32 = 0b11;
+
+#[repr(C)]
+#[allow(non_snake_case)]
+struct Registers {
+    CS: Volatile<u32>,
+    FIFO: Volatile<u32>,
+    CLK: Volatile<u32>,
+    DLEN: Volatile<u32>,
+    LTOH: Volatile<u32>,
+    DC: Volatile<u32>,
+}
+
+/// The SPI clock polarity and phase.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum Mode {
+    /// CPOL = 0, CPHA = 0: idle low, sample on the rising edge.
+    Mode0,
+    /// CPOL = 0, CPHA = 1: idle low, sample on the falling edge.
+    Mode1,
+    /// CPOL = 1, CPHA = 0: idle high, sample on the falling edge.
+    Mode2,
+    /// CPOL = 1, CPHA = 1: idle high, sample on the rising edge.
+    Mode3
+}
+
+/// The chip select line asserted during transfers.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum ChipSelect {
+    /// `CE0`, on GPIO 8.
+    Ce0 = 0,
+    /// `CE1`, on GPIO 7.
+    Ce1 = 1
+}
+
+/// Bus settings for an SPI master: clock frequency and mode.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct SpiConfig {
+    /// The upper bound on the SCLK frequency, in Hz. The actual frequency is
+    /// the closest the clock divider can reach without exceeding it.
+    pub frequency: u32,
+    pub mode: Mode,
+}
+
+impl Default for SpiConfig {
+    /// Returns the 1MHz mode 0 configuration.
+    fn default() -> SpiConfig {
+        SpiConfig { frequency: 1_000_000, mode: Mode::Mode0 }
+    }
+}
+
+/// Returns the value of the `CLK` register that yields the fastest SCLK no
+/// faster than `frequency` given a core clock of `clock` Hz. SCLK is
+/// `clock / divisor` for an even divisor, with 0 meaning 65536.
+///
+/// # Panics
+///
+/// Panics if `frequency` is 0.
+fn clock_divisor(clock: u32, frequency: u32) -> u32 {
+    match clock::even_divisor(clock, frequency, 0x10000) {
+        Some(divisor) => divisor,
+        None => panic!("Spi: SCLK frequency of 0Hz is unreachable")
+    }
+}
+
+/// The `SPI0` master, on GPIO 7-11.
+pub struct Spi {
+    registers: &'static mut Registers
+}
+
+impl Spi {
+    /// Initializes `SPI0` with the default configuration: 1MHz, mode 0,
+    /// transferring to `CE0`.
+    pub fn new() -> Spi {
+        Spi::with_config(SpiConfig::default())
+    }
+
+    /// Initializes `SPI0` with the clock and mode in `config`, transferring to
+    /// `CE0`. GPIO pins 7-11 are set to alternative function 0.
+    ///
+    /// # Panics
+    ///
+    /// Panics if `config.frequency` is 0.
+    pub fn with_config(config: SpiConfig) -> Spi {
+        Gpio::with_signal(pin::P7, signal::Spi0Ce1);
+        Gpio::with_signal(pin::P8, signal::Spi0Ce0);
+        Gpio::with_signal(pin::P9, signal::Spi0Miso);
+        Gpio::with_signal(pin::P10, signal::Spi0Mosi);
+        Gpio::with_signal(pin::P11, signal::Spi0Sclk);
+        gpio::claim(&[7, 8, 9, 10, 11], "spi");
+
+        let registers = unsafe { &mut *(SPI0_REG_BASE as *mut Registers) };
+        let mut spi = Spi { registers };
+        spi.set_config(config);
+        spi
+    }
+
+    /// Changes the clock and mode to those in `config`.
+    ///
+    /// # Panics
+    ///
+    /// Panics if `config.frequency` is 0.
+    pub fn set_config(&mut self, config: SpiConfig) {
+        let mode = match config.mode {
+            Mode::Mode0 => 0,
+            Mode::Mode1 => CsFlags::Cpha as u32,
+            Mode::Mode2 => CsFlags::Cpol as u32,
+            Mode::Mode3 => CsFlags::Cpol as u32 | CsFlags::Cpha as u32
+        };
+
+        let select = self.registers.CS.read() & CS_SELECT_MASK;
+        self.registers.CLK.write(clock_divisor(clock::core_rate(), config.frequency));
+        self.registers.CS.write(select | mode
+                                | CsFlags::ClearTx as u32 | CsFlags::ClearRx as u32);
+    }
+
+    /// Selects the chip select line asserted by subsequent transfers.
+    pub fn select(&mut self, cs: ChipSelect) {
+        let other = self.registers.CS.read() & !CS_SELECT_MASK;
+        self.registers.CS.write(other | cs as u32);
+    }
+
+    /// Sends the bytes in `buffer` while replacing each with the byte received
+    /// in its place. The chip select line is asserted for the whole transfer.
+    pub fn transfer(&mut self, buffer: &mut [u8]) {
+        self.registers.CS.or_mask(CsFlags::ClearTx as u32
+                                  | CsFlags::ClearRx as u32
+                                  | CsFlags::Ta as u32);
+
+        // Bytes are received in the order they're sent, so `received` never
+        // overtakes `sent` and every byte is sent before it's overwritten.
+        let (mut sent, mut received) = (0, 0);
+        while received < buffer.len() {
+            while sent < buffer.len() && self.registers.CS.has_mask(CsFlags::Txd as u32) {
+                self.registers.FIFO.write(buffer[sent] as u32);
+                sent += 1;
+            }
+
+            while received < sent && self.registers.CS.has_mask(CsFlags::Rxd as u32) {
+                buffer[received] = self.registers.FIFO.read() as u8;
+                received += 1;
+            }
+        }
+
+        while !self.registers.CS.has_mask(CsFlags::Done as u32) {  }
+        self.registers.CS.and_mask(!(CsFlags::Ta as u32));
+    }
+
+    /// Sends the bytes in `bytes`, discarding those received.
+    pub fn write(&mut self, bytes: &[u8]) {
+        self.registers.CS.or_mask(CsFlags::ClearTx as u32
+                                  | CsFlags::ClearRx as u32
+                                  | CsFlags::Ta as u32);
+
+        for &byte in bytes {
+            while !self.registers.CS.has_mask(CsFlags::Txd as u32) {  }
+            self.registers.FIFO.write(byte as u32);
+
+            // Drain the RX FIFO so that it never fills and stalls the bus.
+            while self.registers.CS.has_mask(CsFlags::Rxd as u32) {
+                self.registers.FIFO.read();
+            }
+        }
+
+        while !self.registers.CS.has_mask(CsFlags::Done as u32) {  }
+        self.registers.CS.and_mask(!(CsFlags::Ta as u32));
+    }
+}
+
+impl Drop for Spi {
+    /// Withdraws the claim on GPIO pins 7-11.
+    fn drop(&mut self) {
+        gpio::release_owned(&[7, 8, 9, 10, 11], "spi");
+    }
+}
+
+#[cfg(feature = "hal")]
+mod spi_hal {
+    use embedded_hal::blocking::spi;
+    use super::Spi;
+
+    impl spi::Transfer<u8> for Spi {
+        type Error = !;
+
+        fn transfer<'w>(&mut self, words: &'w mut [u8]) -> Result<&'w [u8], !> {
+            Spi::transfer(self, words);
+            Ok(words)
+        }
+    }
+
+    impl spi::Write<u8> for Spi {
+        type Error = !;
+
+        fn write(&mut self, words: &[u8]) -> Result<(), !> {
+            Spi::write(self, words);
+            Ok(())
+        }
+    }
+}
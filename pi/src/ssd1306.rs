@@ -0,0 +1,218 @@
+use font::{Font, GLYPH_WIDTH};
+use i2c::{I2c, I2cError};
+
+/// The display's width and height in pixels.
+pub const WIDTH: usize = 128;
+pub const HEIGHT: usize = 64;
+
+/// Display memory is in pages of 8 pixel rows, one byte per column with the
+/// top row in the least significant bit.
+const PAGES: usize = HEIGHT / 8;
+
+/// The byte opening each I2C write, telling commands from display data.
+mod control {
+    pub const COMMAND: u8 = 0x00;
+    pub const DATA: u8 = 0x40;
+}
+
+/// Commands.
+mod command {
+    pub const SET_CONTRAST: u8 = 0x81;
+    pub const RESUME_FROM_RAM: u8 = 0xA4;
+    pub const NORMAL: u8 = 0xA6;
+    pub const INVERT: u8 = 0xA7;
+    pub const DISPLAY_OFF: u8 = 0xAE;
+    pub const DISPLAY_ON: u8 = 0xAF;
+    pub const SET_LOWER_COLUMN: u8 = 0x00;
+    pub const SET_HIGHER_COLUMN: u8 = 0x10;
+    pub const SET_PAGE: u8 = 0xB0;
+}
+
+/// Set up for a 128x64 module running from its internal charge pump, in
+/// page addressing mode, with column 0 and row 0 at the top left.
+const INIT: [u8; 25] = [
+    command::DISPLAY_OFF,
+    0xD5, 0x80, // Clock divider and oscillator frequency: the reset values.
+    0xA8, 0x3F, // Multiplex ratio: 64 rows.
+    0xD3, 0x00, // No vertical offset.
+    0x40,       // Start at display line 0.
+    0x8D, 0x14, // Charge pump on.
+    0x20, 0x02, // Page addressing mode.
+    0xA1,       // Mirror the columns...
+    0xC8,       // ...and the rows, for the usual mounting.
+    0xDA, 0x12, // COM pins: alternative configuration.
+    command::SET_CONTRAST, 0xCF,
+    0xD9, 0xF1, // Precharge period for the charge pump.
+    0xDB, 0x40, // VCOMH deselect level.
+    command::RESUME_FROM_RAM,
+    command::NORMAL,
+    command::DISPLAY_ON,
+];
+
+/// An SSD1306 128x64 monochrome OLED display on I2C.
+///
+/// Drawing changes an in-memory framebuffer, and `flush()` sends the
+/// columns changed since the last flush in each page, so redrawing part of
+/// the display only costs the I2C traffic for that part.
+pub struct Ssd1306 {
+    i2c: I2c,
+    addr: u8,
+    buffer: [u8; WIDTH * PAGES],
+    /// The columns `start..end` of each page changed since the last flush,
+    /// or `None` if it's unchanged.
+    dirty: [Option<(usize, usize)>; PAGES],
+}
+
+impl Ssd1306 {
+    /// The display's address with `D/C` low, as on most modules. `0x3D` is
+    /// the other.
+    pub const DEFAULT_ADDRESS: u8 = 0x3C;
+
+    /// Initializes the display at `addr` on `i2c` and blanks it.
+    pub fn new(i2c: I2c, addr: u8) -> Result<Ssd1306, I2cError> {
+        let mut display = Ssd1306 {
+            i2c,
+            addr,
+            buffer: [0; WIDTH * PAGES],
+            dirty: [Some((0, WIDTH)); PAGES],
+        };
+
+        display.commands(&INIT)?;
+        display.flush()?;
+        Ok(display)
+    }
+
+    /// Returns the I2C bus, giving up the display.
+    pub fn release(self) -> I2c {
+        self.i2c
+    }
+
+    /// Sends the command bytes in `commands`.
+    fn commands(&mut self, commands: &[u8]) -> Result<(), I2cError> {
+        let mut packet = [0; 1 + INIT.len()];
+        packet[0] = control::COMMAND;
+        packet[1..1 + commands.len()].copy_from_slice(commands);
+        self.i2c.write(self.addr, &packet[..1 + commands.len()])
+    }
+
+    /// Sets the contrast, from 0 to 255.
+    pub fn set_contrast(&mut self, contrast: u8) -> Result<(), I2cError> {
+        self.commands(&[command::SET_CONTRAST, contrast])
+    }
+
+    /// Turns the display on or off. Its contents are kept while off.
+    pub fn set_display(&mut self, on: bool) -> Result<(), I2cError> {
+        self.commands(&[if on { command::DISPLAY_ON } else { command::DISPLAY_OFF }])
+    }
+
+    /// Shows lit pixels as dark and dark ones as lit if `invert`.
+    pub fn set_inverted(&mut self, invert: bool) -> Result<(), I2cError> {
+        self.commands(&[if invert { command::INVERT } else { command::NORMAL }])
+    }
+
+    /// Marks columns `start..end` of page `page` as changed.
+    fn touch(&mut self, page: usize, start: usize, end: usize) {
+        self.dirty[page] = match self.dirty[page] {
+            Some((old_start, old_end)) => {
+                Some((::core::cmp::min(start, old_start), ::core::cmp::max(end, old_end)))
+            }
+            None => Some((start, end)),
+        };
+    }
+
+    /// Turns every pixel off.
+    pub fn clear(&mut self) {
+        for page in 0..PAGES {
+            // Only the columns with lit pixels need sending again.
+            let lit = {
+                let row = &mut self.buffer[page * WIDTH..(page + 1) * WIDTH];
+                let start = row.iter().position(|&column| column != 0);
+                let end = row.iter().rposition(|&column| column != 0);
+                for column in row.iter_mut() {
+                    *column = 0;
+                }
+
+                start.and_then(|start| end.map(|end| (start, end + 1)))
+            };
+
+            if let Some((start, end)) = lit {
+                self.touch(page, start, end);
+            }
+        }
+    }
+
+    /// Returns whether the pixel at `x`, `y` is lit, or `false` if it's off
+    /// the display.
+    pub fn pixel(&self, x: usize, y: usize) -> bool {
+        x < WIDTH && y < HEIGHT && self.buffer[y / 8 * WIDTH + x] & (1 << (y % 8)) != 0
+    }
+
+    /// Lights the pixel at `x`, `y` if `on`, or turns it off. Pixels off the
+    /// display are ignored.
+    pub fn set_pixel(&mut self, x: usize, y: usize, on: bool) {
+        if x >= WIDTH || y >= HEIGHT {
+            return;
+        }
+
+        let (index, mask) = (y / 8 * WIDTH + x, 1 << (y % 8));
+        let old = self.buffer[index];
+        let new = if on { old | mask } else { old & !mask };
+        if new != old {
+            self.buffer[index] = new;
+            self.touch(y / 8, x, x + 1);
+        }
+    }
+
+    /// Draws the glyph for `byte` in `font` with its top left at `x`, `y`,
+    /// lighting its set pixels and turning the rest off. Pixels off the
+    /// display are clipped.
+    pub fn draw_glyph(&mut self, x: usize, y: usize, font: &Font, byte: u8) {
+        for (dy, &bits) in font.glyph(byte).iter().enumerate() {
+            for dx in 0..GLYPH_WIDTH {
+                self.set_pixel(x + dx, y + dy, bits & (0x80 >> dx) != 0);
+            }
+        }
+    }
+
+    /// Draws `text` in `font` on one line with its top left at `x`, `y`,
+    /// clipped at the display's edges. Returns the `x` just past the end.
+    pub fn draw_text(&mut self, x: usize, y: usize, font: &Font, text: &str) -> usize {
+        let mut x = x;
+        for &byte in text.as_bytes() {
+            if x >= WIDTH {
+                break;
+            }
+
+            self.draw_glyph(x, y, font, byte);
+            x += GLYPH_WIDTH;
+        }
+
+        x
+    }
+
+    /// Sends the parts of the framebuffer that changed since the last flush
+    /// to the display.
+    pub fn flush(&mut self) -> Result<(), I2cError> {
+        let mut packet = [0; 1 + WIDTH];
+        packet[0] = control::DATA;
+
+        for page in 0..PAGES {
+            let (start, end) = match self.dirty[page] {
+                Some(columns) => columns,
+                None => continue
+            };
+
+            self.commands(&[command::SET_PAGE | page as u8,
+                            command::SET_LOWER_COLUMN | (start & 0xF) as u8,
+                            command::SET_HIGHER_COLUMN | (start >> 4) as u8])?;
+
+            let len = end - start;
+            let row = page * WIDTH;
+            packet[1..1 + len].copy_from_slice(&self.buffer[row + start..row + end]);
+            self.i2c.write(self.addr, &packet[..1 + len])?;
+            self.dirty[page] = None;
+        }
+
+        Ok(())
+    }
+}
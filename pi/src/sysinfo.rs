@@ -0,0 +1,31 @@
+use mailbox::{self, tag, MailboxError};
+
+/// The firmware's id for the SoC temperature sensor, its only sensor.
+const SOC_SENSOR: u32 = 0;
+
+/// The firmware's id for the VideoCore core supply voltage.
+const CORE_VOLTAGE: u32 = 1;
+
+/// Sends the tag `id` with the single request value `value` and returns the
+/// second word of its `[value, result]` response.
+fn query(id: u32, value: u32) -> Result<u32, MailboxError> {
+    let mut values = [value, 0];
+    mailbox::call_property(id, &mut values)?;
+    Ok(values[1])
+}
+
+/// Returns the SoC temperature in thousandths of a degree Celsius.
+pub fn temperature() -> Result<u32, MailboxError> {
+    query(tag::GET_TEMPERATURE, SOC_SENSOR)
+}
+
+/// Returns the SoC temperature, in thousandths of a degree Celsius, at which
+/// the firmware starts throttling the clocks.
+pub fn max_temperature() -> Result<u32, MailboxError> {
+    query(tag::GET_MAX_TEMPERATURE, SOC_SENSOR)
+}
+
+/// Returns the core supply voltage in microvolts.
+pub fn core_voltage() -> Result<u32, MailboxError> {
+    query(tag::GET_VOLTAGE, CORE_VOLTAGE)
+}
@@ -1,10 +1,37 @@
+use core::ops::{Add, AddAssign, Sub, SubAssign};
+use core::time::Duration;
+
 use common::IO_BASE;
+use interrupt::{Controller, Interrupt};
 use volatile::prelude::*;
 use volatile::{Volatile, ReadVolatile};
 
 /// The base address for the ARM system timer registers.
 const TIMER_REG_BASE: usize = IO_BASE + 0x3000;
 
+/// A compare channel of the system timer that is free for the ARM. Channels 0
+/// and 2 are used by the GPU.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum Compare {
+    C1 = 1,
+    C3 = 3
+}
+
+impl Compare {
+    /// This channel's bit in `CS`.
+    fn mask(self) -> u32 {
+        1 << self as u32
+    }
+
+    /// This channel's interrupt.
+    pub fn interrupt(self) -> Interrupt {
+        match self {
+            Compare::C1 => Interrupt::Timer1,
+            Compare::C3 => Interrupt::Timer3
+        }
+    }
+}
+
 #[repr(C)]
 #[allow(non_snake_case)]
 struct Registers {
@@ -44,26 +71,193 @@ impl Timer {
         // x += u64::from(self.registers.CLO.read());
         // x
     }
+
+    /// Returns the low 32 bits of the system timer's free-running 1MHz
+    /// counter, the value compared against by `arm_at()`. This is a
+    /// different counter from the one `read()` uses.
+    pub fn counter_low(&self) -> u32 {
+        self.registers.CLO.read()
+    }
+
+    /// Arms `compare` to match when `counter_low()` reaches `deadline` and
+    /// unmasks its interrupt, replacing any previous deadline.
+    pub fn arm_at(&mut self, compare: Compare, deadline: u32) {
+        self.registers.COMPARE[compare as usize].write(deadline);
+        Controller::new().enable(compare.interrupt());
+    }
+
+    /// Arms `compare` to match `us` microseconds from now and returns the
+    /// deadline, as passed to `arm_at()`.
+    pub fn arm_in(&mut self, compare: Compare, us: u32) -> u32 {
+        let deadline = self.counter_low().wrapping_add(us);
+        self.arm_at(compare, deadline);
+        deadline
+    }
+
+    /// Returns `true` if `compare` has matched and not been acknowledged.
+    pub fn is_pending(&self, compare: Compare) -> bool {
+        self.registers.CS.read() & compare.mask() != 0
+    }
+
+    /// Returns `true` if `compare` has matched since the last call, clearing
+    /// the match and its interrupt.
+    pub fn acknowledge(&mut self, compare: Compare) -> bool {
+        if self.is_pending(compare) {
+            // Writing a 1 clears the match.
+            self.registers.CS.write(compare.mask());
+            true
+        } else {
+            false
+        }
+    }
+
+    /// Masks `compare`'s interrupt and discards a pending match.
+    pub fn disarm(&mut self, compare: Compare) {
+        Controller::new().disable(compare.interrupt());
+        self.registers.CS.write(compare.mask());
+    }
+}
+
+/// Returns `duration` in whole microseconds, saturating at `u64::MAX`.
+pub(crate) fn duration_to_us(duration: Duration) -> u64 {
+    duration.as_secs()
+        .saturating_mul(1_000_000)
+        .saturating_add(duration.subsec_micros() as u64)
+}
+
+/// A point in time read from the system timer, with microsecond resolution.
+/// Instants never decrease.
+///
+/// Arithmetic that would overflow panics; the `checked_` methods return
+/// `None` instead.
+#[derive(Debug, Clone, Copy, PartialEq, Eq, PartialOrd, Ord, Hash)]
+pub struct Instant(u64);
+
+impl Instant {
+    /// Returns the current time.
+    pub fn now() -> Instant {
+        Instant(Timer::new().read())
+    }
+
+    /// Returns the time elapsed from `earlier` to `self`, or zero if
+    /// `earlier` is later than `self`.
+    pub fn duration_since(&self, earlier: Instant) -> Duration {
+        self.checked_duration_since(earlier).unwrap_or(Duration::from_secs(0))
+    }
+
+    /// Returns the time elapsed from `earlier` to `self`, or `None` if
+    /// `earlier` is later than `self`.
+    pub fn checked_duration_since(&self, earlier: Instant) -> Option<Duration> {
+        self.0.checked_sub(earlier.0).map(Duration::from_micros)
+    }
+
+    /// Returns the time elapsed since `self`.
+    pub fn elapsed(&self) -> Duration {
+        Instant::now().duration_since(*self)
+    }
+
+    /// Returns `self + duration`, or `None` on overflow.
+    pub fn checked_add(&self, duration: Duration) -> Option<Instant> {
+        self.0.checked_add(duration_to_us(duration)).map(Instant)
+    }
+
+    /// Returns `self - duration`, or `None` if the result would precede the
+    /// counter's start.
+    pub fn checked_sub(&self, duration: Duration) -> Option<Instant> {
+        self.0.checked_sub(duration_to_us(duration)).map(Instant)
+    }
+}
+
+impl Add<Duration> for Instant {
+    type Output = Instant;
+
+    fn add(self, duration: Duration) -> Instant {
+        self.checked_add(duration).expect("overflow when adding duration to instant")
+    }
+}
+
+impl AddAssign<Duration> for Instant {
+    fn add_assign(&mut self, duration: Duration) {
+        *self = *self + duration;
+    }
+}
+
+impl Sub<Duration> for Instant {
+    type Output = Instant;
+
+    fn sub(self, duration: Duration) -> Instant {
+        self.checked_sub(duration).expect("overflow when subtracting duration from instant")
+    }
 }
 
-/// Returns the current time in microseconds.
-pub fn current_time() -> u64 {
-    Timer::new().read()
+impl SubAssign<Duration> for Instant {
+    fn sub_assign(&mut self, duration: Duration) {
+        *self = *self - duration;
+    }
+}
+
+impl Sub<Instant> for Instant {
+    type Output = Duration;
+
+    fn sub(self, earlier: Instant) -> Duration {
+        self.checked_duration_since(earlier).expect("overflow when subtracting instants")
+    }
+}
+
+/// Spins until `duration` has passed.
+pub fn spin_sleep(duration: Duration) {
+    let start = Instant::now();
+    while start.elapsed() < duration {  }
 }
 
 /// Spins until `us` microseconds have passed.
 pub fn spin_sleep_us(us: u64) {
-    let t = Timer::new();
-    let r = t.read();
-    let target = r + us;
-    loop {
-        if t.read() >= target {
-            break
-        }
-    }
+    spin_sleep(Duration::from_micros(us));
 }
 
 /// Spins until `ms` milliseconds have passed.
 pub fn spin_sleep_ms(ms: u64) {
-    spin_sleep_us(ms * 1000);
+    spin_sleep(Duration::from_millis(ms));
+}
+
+#[cfg(feature = "hal")]
+mod timer_hal {
+    use embedded_hal::blocking::delay::{DelayMs, DelayUs};
+    use super::{Timer, spin_sleep_ms, spin_sleep_us};
+
+    impl DelayMs<u32> for Timer {
+        fn delay_ms(&mut self, ms: u32) {
+            spin_sleep_ms(ms as u64);
+        }
+    }
+
+    impl DelayMs<u16> for Timer {
+        fn delay_ms(&mut self, ms: u16) {
+            spin_sleep_ms(ms as u64);
+        }
+    }
+
+    impl DelayMs<u8> for Timer {
+        fn delay_ms(&mut self, ms: u8) {
+            spin_sleep_ms(ms as u64);
+        }
+    }
+
+    impl DelayUs<u32> for Timer {
+        fn delay_us(&mut self, us: u32) {
+            spin_sleep_us(us as u64);
+        }
+    }
+
+    impl DelayUs<u16> for Timer {
+        fn delay_us(&mut self, us: u16) {
+            spin_sleep_us(us as u64);
+        }
+    }
+
+    impl DelayUs<u8> for Timer {
+        fn delay_us(&mut self, us: u8) {
+            spin_sleep_us(us as u64);
+        }
+    }
 }
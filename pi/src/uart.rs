@@ -1,11 +1,16 @@
-use core::fmt;
+use core::{cmp, fmt};
+use core::time::Duration;
+use core::sync::atomic::{AtomicBool, AtomicUsize, Ordering};
 
 use volatile::prelude::*;
 use volatile::{Volatile, ReadVolatile, Reserved};
 
-use timer;
+use timer::{Instant, duration_to_us};
+use clock;
 use common::IO_BASE;
-use gpio::{Gpio, Function};
+use gpio::{self, Gpio, pin, signal};
+use interrupt::{Controller, Interrupt};
+use ring_buffer::RingBuffer;
 
 /// The base address for the `MU` registers.
 const MU_REG_BASE: usize = IO_BASE + 0x215040;
@@ -13,11 +18,271 @@ const MU_REG_BASE: usize = IO_BASE + 0x215040;
 /// The `AUXENB` register from page 9 of the BCM2837 documentation.
 const AUX_ENABLES: *mut Volatile<u8> = (IO_BASE + 0x215004) as *mut Volatile<u8>;
 
+/// Bytes moved out of the RX FIFO by `handle_irq()` that have not been read.
+static RX_BUFFER: RingBuffer = RingBuffer::new();
+
+/// The number of receiver overruns seen in `AUX_MU_LSR_REG`.
+static OVERRUNS: AtomicUsize = AtomicUsize::new(0);
+
+/// Set when an overrun is seen; cleared once `read_byte_checked` reports it.
+static OVERRUN_PENDING: AtomicBool = AtomicBool::new(false);
+
+/// The sequence a host sends to ask the running kernel to hand the board
+/// back to the bootloader. `handle_irq()` watches for it, unless told not
+/// to by `watch_for_reenter()`. The mini UART can't tell a break from a
+/// zero byte, so this is only ever a sequence of bytes, which binary data
+/// may well contain.
+pub const REENTER_MAGIC: [u8; 6] = [0x00, 0xFF, b'B', b'O', b'O', b'T'];
+
+/// How many bytes of `REENTER_MAGIC` `handle_irq()` has received in a row.
+static REENTER_MATCHED: AtomicUsize = AtomicUsize::new(0);
+
+/// Whether `handle_irq()` watches for `REENTER_MAGIC`.
+static REENTER_WATCHED: AtomicBool = AtomicBool::new(true);
+
+/// Set once `handle_irq()` has received `REENTER_MAGIC`.
+static REENTER_PENDING: AtomicBool = AtomicBool::new(false);
+
+/// The depth of the mini UART's TX and RX FIFOs.
+const FIFO_DEPTH: usize = 8;
+
+/// The number of data bits in a UART character.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum DataBits {
+    Seven,
+    Eight
+}
+
+/// The parity bit sent after each UART character.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum Parity {
+    None,
+    Even,
+    Odd
+}
+
+/// The number of stop bits sent after each UART character.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum StopBits {
+    One,
+    Two
+}
+
+/// Flow control used by a UART.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum FlowControl {
+    /// No flow control.
+    None,
+    /// Hardware flow control using the RTS and CTS lines on GPIO pins 17 and
+    /// 16. Both lines are treated as active low, as on TTL serial adapters.
+    RtsCts
+}
+
+/// A receive error detected by a UART.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum UartError {
+    /// The RX FIFO overflowed: one or more bytes were lost.
+    Overrun,
+    /// A byte was received without a valid stop bit.
+    Framing,
+    /// A byte was received with the wrong parity.
+    Parity,
+    /// The line was held low for longer than a full character.
+    Break,
+    /// No byte arrived before the read timeout expired.
+    TimedOut
+}
+
+/// Running totals of receive errors detected by a UART.
+#[derive(Debug, Default, Clone, Copy, PartialEq, Eq)]
+pub struct ErrorCounts {
+    pub overrun: usize,
+    pub framing: usize,
+    pub parity: usize,
+    pub breaks: usize,
+}
+
+/// Error returned by non-blocking UART writes when the TX FIFO is full.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct WouldBlock;
+
+/// A byte-oriented serial device.
+///
+/// Implemented by the mini UART and the PL011 so that consumers such as the
+/// kernel console don't depend on a specific UART.
+pub trait Uart {
+    /// Sets the read timeout used by `wait_for_byte` to `timeout`.
+    /// Implementations keep at least microsecond resolution.
+    fn set_read_timeout(&mut self, timeout: Duration);
+
+    /// Returns the read timeout set by `set_read_timeout`, if any.
+    fn read_timeout(&self) -> Option<Duration>;
+
+    /// Writes the byte `byte` if there is space in the output FIFO. Returns
+    /// `Err(WouldBlock)` without writing otherwise. This method does not block.
+    fn try_write_byte(&mut self, byte: u8) -> Result<(), WouldBlock>;
+
+    /// Returns `true` if there is at least one byte ready to be read. If this
+    /// method returns `true`, a subsequent call to `read_byte` is guaranteed to
+    /// return immediately. This method does not block.
+    fn has_byte(&self) -> bool;
+
+    /// Blocks until there is a byte ready to read. If a read timeout is set,
+    /// this method blocks for at most that amount of time. Otherwise, this
+    /// method blocks indefinitely until there is a byte to read.
+    ///
+    /// Returns `Ok(())` if a byte is ready to read. Returns `Err(())` if the
+    /// timeout expired while waiting for a byte to be ready. If this method
+    /// returns `Ok(())`, a subsequent call to `read_byte` is guaranteed to
+    /// return immediately.
+    fn wait_for_byte(&self) -> Result<(), ()> {
+        match self.read_timeout() {
+            Some(timeout) => self.wait_for_byte_timeout(timeout),
+            None => {
+                while !self.has_byte() {
+                    // Spin while waiting for a byte.
+                }
+
+                Ok(())
+            }
+        }
+    }
+
+    /// Like `wait_for_byte`, but blocks for at most `timeout` regardless of
+    /// the read timeout set with `set_read_timeout`, which is left unchanged.
+    fn wait_for_byte_timeout(&self, timeout: Duration) -> Result<(), ()> {
+        let start = Instant::now();
+
+        while !self.has_byte() {
+            if start.elapsed() > timeout {
+                return Err(())
+            }
+        }
+
+        Ok(())
+    }
+
+    /// Reads a byte if one is ready to be read and returns it. Returns `None`
+    /// otherwise. This method does not block.
+    fn try_read_byte(&mut self) -> Option<u8>;
+
+    /// Write the byte `byte`. This method blocks until there is space available
+    /// in the output FIFO.
+    fn write_byte(&mut self, byte: u8) {
+        while self.try_write_byte(byte).is_err() {
+            // Spin while TX FIFO is full.
+        }
+    }
+
+    /// Writes all of `bytes`, blocking until the last one is in the output
+    /// FIFO. Implementations may write several bytes per FIFO status check.
+    fn write_bytes(&mut self, bytes: &[u8]) {
+        for b in bytes {
+            self.write_byte(*b);
+        }
+    }
+
+    /// Reads a byte. Blocks indefinitely until a byte is ready to be read.
+    fn read_byte(&mut self) -> u8 {
+        loop {
+            if let Some(byte) = self.try_read_byte() {
+                return byte;
+            }
+        }
+    }
+
+    /// Reads a byte, blocking for at most the read timeout, and reports any
+    /// receive error detected for it.
+    ///
+    /// Returns `Err(UartError::TimedOut)` if the timeout expired. Other errors
+    /// mean the received data can't be trusted; see the implementation for
+    /// whether the offending byte is consumed. The default implementation
+    /// detects no receive errors.
+    fn read_byte_checked(&mut self) -> Result<u8, UartError> {
+        self.wait_for_byte().map_err(|_| UartError::TimedOut)?;
+        Ok(self.read_byte())
+    }
+
+    /// Returns the number of receive errors detected so far.
+    fn error_counts(&self) -> ErrorCounts {
+        ErrorCounts::default()
+    }
+}
+
+/// Line settings for a UART: BAUD rate, character framing, and flow control.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct UartConfig {
+    pub baud: u32,
+    pub data_bits: DataBits,
+    pub parity: Parity,
+    pub stop_bits: StopBits,
+    pub flow_control: FlowControl,
+}
+
+impl UartConfig {
+    /// Returns a configuration for `baud` BAUD with 8 data bits, no parity, one
+    /// stop bit, and no flow control.
+    pub const fn new(baud: u32) -> UartConfig {
+        UartConfig {
+            baud: baud,
+            data_bits: DataBits::Eight,
+            parity: Parity::None,
+            stop_bits: StopBits::One,
+            flow_control: FlowControl::None
+        }
+    }
+}
+
+impl Default for UartConfig {
+    /// Returns the 115200 BAUD 8N1 configuration.
+    fn default() -> UartConfig {
+        UartConfig::new(115200)
+    }
+}
+
+/// Returns the value of the `AUX_MU_BAUD_REG` register that most closely
+/// yields `baud` given a system clock of `clock` Hz. The mini UART's BAUD rate
+/// is `clock / (8 * (divisor + 1))`.
+///
+/// # Panics
+///
+/// Panics if `baud` is not achievable with a 16-bit divisor.
+fn baud_divisor(clock: u32, baud: u32) -> u16 {
+    let divisor = (clock as u64 + 4 * baud as u64).checked_div(8 * baud as u64).unwrap_or(0);
+    if divisor == 0 || divisor > 0x10000 {
+        panic!("MiniUart: BAUD rate {} unreachable with a {} Hz clock", baud, clock);
+    }
+
+    (divisor - 1) as u16
+}
+
 /// Enum representing bit fields of the `AUX_MU_LSR_REG` register.
 #[repr(u8)]
 enum LsrStatus {
     DataReady = 1,
+    RxOverrun = 1 << 1,
     TxAvailable = 1 << 5,
+    TxIdle = 1 << 6,
+}
+
+/// Enum representing bit fields of the `AUX_MU_CNTL_REG` register.
+#[repr(u8)]
+enum CntlFlags {
+    RxEnable = 1,
+    TxEnable = 1 << 1,
+    RxAutoFlow = 1 << 2,
+    TxAutoFlow = 1 << 3,
+    RtsAssertLow = 1 << 6,
+    CtsAssertLow = 1 << 7,
+}
+
+/// Enum representing bit fields of the `AUX_MU_IER_REG` register. The BCM2837
+/// documentation has the RX and TX bits swapped, and bits 2 and 3 must be set
+/// for the UART to raise any interrupt at all.
+#[repr(u8)]
+enum IerFlags {
+    RxEnable = 1,
+    Required = 0b11 << 2,
 }
 
 #[repr(C)]
@@ -49,7 +314,11 @@ struct Registers {
 /// The Raspberry Pi's "mini UART".
 pub struct MiniUart {
     registers: &'static mut Registers,
-    timeout: Option<u32>,
+    timeout_us: Option<u64>,
+    rx_interrupt: bool,
+    /// A receive error that ended an `io::Read::read` early, to be reported
+    /// by the next.
+    pending_error: Option<UartError>,
 }
 
 impl MiniUart {
@@ -59,11 +328,52 @@ impl MiniUart {
     /// (TXD1/RDXD1), and finally enabling the UART transmitter and receiver.
     ///
     /// By default, reads will never time out. To set a read timeout, use
-    /// `set_read_timeout()`.
+    /// `Uart::set_read_timeout()`.
     pub fn new() -> MiniUart {
+        MiniUart::with_config(UartConfig::default())
+    }
+
+    /// Initializes the mini UART like `new()` but with the BAUD rate, data
+    /// size, and flow control in `config`. The BAUD divisor is computed from the
+    /// core clock rate reported by the firmware, so it must not change while
+    /// the UART is in use.
+    ///
+    /// With `FlowControl::RtsCts`, GPIO pins 16 and 17 are set to alternative
+    /// function 5 (CTS1/RTS1) and the UART de-asserts RTS when its RX FIFO
+    /// has three or fewer free spaces and stops transmitting while CTS is
+    /// de-asserted.
+    ///
+    /// # Panics
+    ///
+    /// The mini UART has no parity support and always sends one stop bit.
+    /// Panics if `config` requests parity or two stop bits, or if its BAUD rate
+    /// can't be reached.
+    pub fn with_config(config: UartConfig) -> MiniUart {
+        if config.parity != Parity::None || config.stop_bits != StopBits::One {
+            panic!("MiniUart: unsupported framing {:?}", config);
+        }
+
+        let divisor = baud_divisor(clock::core_rate(), config.baud);
+
         // Set GPIO pins 14 and 15 to Alt 5 function.
-        Gpio::new(14).into_alt(Function::Alt5);
-        Gpio::new(15).into_alt(Function::Alt5);
+        Gpio::with_signal(pin::P14, signal::Txd1);
+        Gpio::with_signal(pin::P15, signal::Rxd1);
+        gpio::claim(&[14, 15], "uart");
+
+        let mut cntl = CntlFlags::RxEnable as u8 | CntlFlags::TxEnable as u8;
+        if config.flow_control == FlowControl::RtsCts {
+            // Set GPIO pins 16 and 17 to Alt 5 function.
+            Gpio::with_signal(pin::P16, signal::Cts1);
+            Gpio::with_signal(pin::P17, signal::Rts1);
+            gpio::claim(&[16, 17], "uart");
+
+            cntl |= CntlFlags::RxAutoFlow as u8 | CntlFlags::TxAutoFlow as u8
+                | CntlFlags::RtsAssertLow as u8 | CntlFlags::CtsAssertLow as u8;
+        } else {
+            // A UART set up earlier may have used them. Every set-up claims
+            // the same UART again, so none of its claims are left standing.
+            gpio::release_all_owned(&[16, 17], "uart");
+        }
 
         let registers = unsafe {
             // Enable the mini UART as an auxiliary device.
@@ -71,86 +381,244 @@ impl MiniUart {
             &mut *(MU_REG_BASE as *mut Registers)
         };
 
-        // Set UART to 8 bit mode.
-        registers.LCR.write(0b11);
-        // Set baud rate to 115200 (divisor of 270).
-        registers.BAUD.write(270);
-        // Enable UART TX and RX.
-        registers.CNTL.write(0b11);
+        // Set the data size: 0b11 for 8 bits, 0b00 for 7 bits.
+        registers.LCR.write(match config.data_bits {
+            DataBits::Eight => 0b11,
+            DataBits::Seven => 0b00
+        });
+        // Set the baud rate (a divisor of 270 for 115200 at 250MHz).
+        registers.BAUD.write(divisor);
+        // Enable UART TX and RX and, if requested, auto flow control.
+        registers.CNTL.write(cntl);
 
         MiniUart {
             registers: registers,
-            timeout: None
+            timeout_us: None,
+            rx_interrupt: false,
+            pending_error: None
         }
     }
 
-    /// Set the read timeout to `milliseconds` milliseconds.
-    pub fn set_read_timeout(&mut self, milliseconds: u32) {
-        self.timeout = Some(milliseconds);
+    /// Returns a handle to the mini UART as it was last initialized, without
+    /// touching its registers, its pins or the interrupt controller. For a
+    /// last message, as when the kernel panics, when the `MiniUart` that
+    /// initialized it can't be reached.
+    ///
+    /// # Safety
+    ///
+    /// The mini UART must have been initialized by `new()` or
+    /// `with_config()`. Output from the handle interleaves with any other
+    /// writer's.
+    pub unsafe fn steal() -> MiniUart {
+        MiniUart {
+            registers: &mut *(MU_REG_BASE as *mut Registers),
+            timeout_us: None,
+            rx_interrupt: false,
+            pending_error: None
+        }
     }
 
-    /// Write the byte `byte`. This method blocks until there is space available
-    /// in the output FIFO.
-    pub fn write_byte(&mut self, byte: u8) {
-        while self.registers.LSR.read() & LsrStatus::TxAvailable as u8 == 0 {
-            // Spin while TX FIFO is full.
+    /// Enables the receive interrupt for the mini UART and unmasks the AUX
+    /// line in the interrupt controller.
+    ///
+    /// Once enabled, `handle_irq()` must be called from the IRQ handler: it
+    /// moves incoming bytes into an internal ring buffer, and reads are served
+    /// from that buffer. `read_byte` then sleeps the core with `wfe` until a
+    /// byte arrives instead of busy-polling the FIFO.
+    pub fn enable_rx_interrupt(&mut self) {
+        self.registers.IER.write(IerFlags::Required as u8 | IerFlags::RxEnable as u8);
+        Controller::new().enable(Interrupt::Aux);
+        self.rx_interrupt = true;
+    }
+
+    /// Like `enable_rx_interrupt()`, but raises the AUX interrupt as the FIQ,
+    /// replacing any other FIQ source. `handle_irq()` must then be called from
+    /// the FIQ handler instead.
+    ///
+    /// FIQs stay enabled while IRQs are masked, so bytes keep moving into the
+    /// ring buffer during long IRQs-disabled sections.
+    pub fn enable_rx_fiq(&mut self) {
+        self.registers.IER.write(IerFlags::Required as u8 | IerFlags::RxEnable as u8);
+        Controller::new().enable_fiq(Interrupt::Aux);
+        self.rx_interrupt = true;
+    }
+
+    /// Returns the number of bytes currently in the TX FIFO.
+    fn tx_fifo_level(&self) -> usize {
+        ((self.registers.STAT.read() >> 24) & 0xf) as usize
+    }
+}
+
+impl Uart for MiniUart {
+    fn set_read_timeout(&mut self, timeout: Duration) {
+        self.timeout_us = Some(duration_to_us(timeout));
+    }
+
+    fn read_timeout(&self) -> Option<Duration> {
+        self.timeout_us.map(Duration::from_micros)
+    }
+
+    fn try_write_byte(&mut self, byte: u8) -> Result<(), WouldBlock> {
+        if read_lsr(self.registers) & LsrStatus::TxAvailable as u8 == 0 {
+            return Err(WouldBlock);
         }
 
         // Add to FIFO.
         self.registers.IO.write(byte);
+        Ok(())
     }
 
-    /// Returns `true` if there is at least one byte ready to be read. If this
-    /// method returns `true`, a subsequent call to `read_byte` is guaranteed to
-    /// return immediately. This method does not block.
-    pub fn has_byte(&self) -> bool {
-        (self.registers.LSR.read() & LsrStatus::DataReady as u8) != 0
+    fn write_bytes(&mut self, bytes: &[u8]) {
+        let mut remaining = bytes;
+
+        while !remaining.is_empty() {
+            // Fill whatever space the FIFO has with a single status read.
+            let free = FIFO_DEPTH - cmp::min(self.tx_fifo_level(), FIFO_DEPTH);
+            let (now, later) = remaining.split_at(cmp::min(free, remaining.len()));
+            for b in now {
+                self.registers.IO.write(*b);
+            }
+
+            remaining = later;
+        }
     }
 
-    /// Blocks until there is a byte ready to read. If a read timeout is set,
-    /// this method blocks for at most that amount of time. Otherwise, this
-    /// method blocks indefinitely until there is a byte to read.
-    ///
-    /// Returns `Ok(())` if a byte is ready to read. Returns `Err(())` if the
-    /// timeout expired while waiting for a byte to be ready. If this method
-    /// returns `Ok(())`, a subsequent call to `read_byte` is guaranteed to
-    /// return immediately.
-    pub fn wait_for_byte(&self) -> Result<(), ()> {
-        let start = timer::current_time();
+    fn has_byte(&self) -> bool {
+        if self.rx_interrupt {
+            return !RX_BUFFER.is_empty();
+        }
 
-        while !self.has_byte() {
-            match self.timeout {
-                Some(ms) => {
-                    if timer::current_time() > start + (ms as u64 * 1000 as u64) {
-                        return Err(())
-                    }
-                },
-                None => ()
-            }
+        (read_lsr(self.registers) & LsrStatus::DataReady as u8) != 0
+    }
+
+    fn try_read_byte(&mut self) -> Option<u8> {
+        if self.rx_interrupt {
+            return RX_BUFFER.pop();
         }
 
-        Ok(())
+        if self.has_byte() {
+            Some(self.registers.IO.read())
+        } else {
+            None
+        }
     }
 
-    /// Reads a byte. Blocks indefinitely until a byte is ready to be read.
-    pub fn read_byte(&mut self) -> u8 {
+    fn read_byte(&mut self) -> u8 {
+        if self.rx_interrupt {
+            loop {
+                if let Some(byte) = RX_BUFFER.pop() {
+                    return byte;
+                }
+
+                // Sleep until the next event. Returning from the IRQ handler
+                // sets the event register, so a byte buffered between the
+                // check above and `wfe` doesn't leave us sleeping.
+                unsafe { asm!("wfe" :::: "volatile"); }
+            }
+        }
+
         while !self.has_byte() {
             // Spin while waiting for a byte.
         }
 
         self.registers.IO.read()
     }
+
+    /// The mini UART can only detect receiver overruns. An overrun is reported
+    /// once, before the bytes that were received around it; those bytes are
+    /// left in place so that the next read returns them.
+    fn read_byte_checked(&mut self) -> Result<u8, UartError> {
+        self.wait_for_byte().map_err(|_| UartError::TimedOut)?;
+        if OVERRUN_PENDING.swap(false, Ordering::Relaxed) {
+            return Err(UartError::Overrun);
+        }
+
+        Ok(self.read_byte())
+    }
+
+    fn error_counts(&self) -> ErrorCounts {
+        ErrorCounts {
+            overrun: OVERRUNS.load(Ordering::Relaxed),
+            ..ErrorCounts::default()
+        }
+    }
+}
+
+/// Reads `AUX_MU_LSR_REG`. Reading the register clears its overrun flag, so
+/// every read goes through here to record overruns.
+fn read_lsr(registers: &Registers) -> u8 {
+    let lsr = registers.LSR.read();
+    if lsr & LsrStatus::RxOverrun as u8 != 0 {
+        OVERRUNS.fetch_add(1, Ordering::Relaxed);
+        OVERRUN_PENDING.store(true, Ordering::Relaxed);
+    }
+
+    lsr
+}
+
+/// Moves every byte in the mini UART's RX FIFO into the receive ring buffer,
+/// which also clears the receive interrupt. Must be called from the IRQ
+/// handler when the AUX interrupt is pending once `enable_rx_interrupt()` has
+/// been called, or from the FIQ handler after `enable_rx_fiq()`. Bytes that
+/// arrive while the ring buffer is full are dropped.
+pub fn handle_irq() {
+    let registers = unsafe { &mut *(MU_REG_BASE as *mut Registers) };
+
+    while read_lsr(registers) & LsrStatus::DataReady as u8 != 0 {
+        let byte = registers.IO.read();
+        if REENTER_WATCHED.load(Ordering::Relaxed) {
+            match_reenter_magic(byte);
+        }
+
+        let _ = RX_BUFFER.push(byte);
+    }
+}
+
+/// Advances the match of `REENTER_MAGIC` by the received `byte`.
+fn match_reenter_magic(byte: u8) {
+    let mut matched = REENTER_MATCHED.load(Ordering::Relaxed);
+    matched = if byte == REENTER_MAGIC[matched] {
+        matched + 1
+    } else if byte == REENTER_MAGIC[0] {
+        1
+    } else {
+        0
+    };
+
+    if matched == REENTER_MAGIC.len() {
+        REENTER_PENDING.store(true, Ordering::Relaxed);
+        matched = 0;
+    }
+
+    REENTER_MATCHED.store(matched, Ordering::Relaxed);
+}
+
+/// Starts or stops watching received bytes for `REENTER_MAGIC`. It should
+/// be stopped while binary data, such as a file sent over XMODEM, is
+/// received, so the data can't be taken for it.
+pub fn watch_for_reenter(watch: bool) {
+    REENTER_MATCHED.store(0, Ordering::Relaxed);
+    REENTER_WATCHED.store(watch, Ordering::Relaxed);
+}
+
+/// Returns `true` once `handle_irq()` has received `REENTER_MAGIC`. This is
+/// only a request: it's up to the caller to act on it, outside of any
+/// interrupt handler.
+pub fn reenter_requested() -> bool {
+    REENTER_PENDING.load(Ordering::Relaxed)
 }
 
 impl fmt::Write for MiniUart {
     fn write_str(&mut self, s: &str) -> Result<(), fmt::Error> {
-        for b in s.as_bytes() {
-            // Must write a CR before a NL.
-            if *b == b'\n' {
-                self.write_byte(b'\r');
-            }
+        let mut lines = s.as_bytes().split(|b| *b == b'\n');
+        if let Some(first) = lines.next() {
+            self.write_bytes(first);
+        }
 
-            self.write_byte(*b);
+        for line in lines {
+            // Must write a CR before a NL.
+            self.write_bytes(b"\r\n");
+            self.write_bytes(line);
         }
 
         Ok(())
@@ -158,34 +626,67 @@ impl fmt::Write for MiniUart {
 }
 
 #[cfg(feature = "std")]
-mod uart_io {
+pub(crate) mod uart_io {
     use std::io;
-    use super::MiniUart;
+    use super::{MiniUart, Uart, UartError};
 
-    impl io::Read for MiniUart {
-        fn read(&mut self, buf: &mut [u8]) -> io::Result<usize> {
-            match self.wait_for_byte() {
-                Err(()) => Err(io::Error::new(io::ErrorKind::TimedOut, "Read timed out.")),
-                Ok(()) => {
-                    let mut read = 0usize;
-                    let mut iter = buf.iter_mut();
-
-                    while let (Some(b), true) = (iter.next(), self.has_byte()) {
-                        *b = self.read_byte();
-                        read += 1;
-                    }
-
-                    Ok(read)
+    impl From<UartError> for io::Error {
+        fn from(error: UartError) -> io::Error {
+            match error {
+                UartError::TimedOut => io::Error::new(io::ErrorKind::TimedOut, "Read timed out."),
+                UartError::Overrun => io::Error::new(io::ErrorKind::InvalidData, "Receiver overrun."),
+                UartError::Framing => io::Error::new(io::ErrorKind::InvalidData, "Framing error."),
+                UartError::Parity => io::Error::new(io::ErrorKind::InvalidData, "Parity error."),
+                UartError::Break => io::Error::new(io::ErrorKind::InvalidData, "Break condition.")
+            }
+        }
+    }
+
+    /// Reads the bytes ready in `uart` into `buf`, blocking for at most the
+    /// read timeout for the first. A receive error after some bytes were read
+    /// ends the read early and is kept in `pending`, to be reported by the
+    /// next read, so the bytes before it aren't lost.
+    pub(crate) fn read<U: Uart>(
+        uart: &mut U,
+        pending: &mut Option<UartError>,
+        buf: &mut [u8]
+    ) -> io::Result<usize> {
+        if let Some(error) = pending.take() {
+            return Err(error.into());
+        }
+
+        uart.wait_for_byte().map_err(|_| UartError::TimedOut)?;
+
+        let mut read = 0usize;
+        let mut iter = buf.iter_mut();
+        while let (Some(b), true) = (iter.next(), uart.has_byte()) {
+            match uart.read_byte_checked() {
+                Ok(byte) => *b = byte,
+                Err(error) if read > 0 => {
+                    *pending = Some(error);
+                    break;
                 }
+                Err(error) => return Err(error.into())
             }
+
+            read += 1;
+        }
+
+        Ok(read)
+    }
+
+    impl io::Read for MiniUart {
+        fn read(&mut self, buf: &mut [u8]) -> io::Result<usize> {
+            let mut pending = self.pending_error.take();
+            let result = read(self, &mut pending, buf);
+            self.pending_error = pending;
+            result
         }
     }
 
     impl io::Write for MiniUart {
         fn write(&mut self, buf: &[u8]) -> io::Result<usize> {
-            for b in buf {
-                self.write_byte(*b);
-            }
+            self.write_bytes(buf);
             Ok(buf.len())
         }
 
@@ -194,3 +695,40 @@ mod uart_io {
         }
     }
 }
+
+#[cfg(feature = "hal")]
+mod uart_hal {
+    use embedded_hal::serial;
+    use embedded_hal::blocking;
+    use nb;
+
+    use volatile::prelude::*;
+    use super::{MiniUart, Uart, LsrStatus, read_lsr};
+
+    impl serial::Read<u8> for MiniUart {
+        type Error = !;
+
+        fn read(&mut self) -> nb::Result<u8, !> {
+            self.try_read_byte().ok_or(nb::Error::WouldBlock)
+        }
+    }
+
+    impl serial::Write<u8> for MiniUart {
+        type Error = !;
+
+        fn write(&mut self, word: u8) -> nb::Result<(), !> {
+            self.try_write_byte(word).map_err(|_| nb::Error::WouldBlock)
+        }
+
+        fn flush(&mut self) -> nb::Result<(), !> {
+            // Done once the TX FIFO is empty and the last byte has shifted out.
+            if read_lsr(self.registers) & LsrStatus::TxIdle as u8 == 0 {
+                return Err(nb::Error::WouldBlock);
+            }
+
+            Ok(())
+        }
+    }
+
+    impl blocking::serial::write::Default<u8> for MiniUart {  }
+}
@@ -0,0 +1,678 @@
+use core::cmp;
+use core::time::Duration;
+
+use common::IO_BASE;
+use dma::bus_address;
+use mailbox::{self, tag};
+use timer::{Instant, spin_sleep_ms};
+use volatile::prelude::*;
+use volatile::{Volatile, ReadVolatile, Reserved};
+
+use super::{UsbError, Speed, Direction, EndpointType, SetupPacket, Device, Endpoint, Split};
+use super::{DeviceDescriptor, request, request_type, descriptor};
+
+/// The base address of the DWC2 OTG controller's core registers.
+const USB_REG_BASE: usize = IO_BASE + 0x980000;
+
+/// The base address of the host mode registers.
+const HOST_REG_BASE: usize = USB_REG_BASE + 0x400;
+
+/// The base address of host channel 0's registers. This driver runs every
+/// transfer on channel 0.
+const CHANNEL_REG_BASE: usize = USB_REG_BASE + 0x500;
+
+/// The power and clock gating control register.
+const PCGCCTL: *mut Volatile<u32> = (USB_REG_BASE + 0xE00) as *mut Volatile<u32>;
+
+/// The firmware's power domain id for the USB controller, and the state
+/// requesting it on, waiting for it to settle.
+const POWER_DEVICE_USB: u32 = 3;
+const POWER_ON_WAIT: u32 = 0b11;
+
+/// The sizes of the receive, non-periodic transmit, and periodic transmit
+/// FIFOs, in words.
+const RX_FIFO_WORDS: u32 = 1024;
+const NPTX_FIFO_WORDS: u32 = 1024;
+const PTX_FIFO_WORDS: u32 = 1024;
+
+/// The largest transfer, in bytes. Every transfer is bounced through a buffer
+/// of this size so that the controller's DMA sees aligned memory.
+pub const MAX_TRANSFER: usize = 4096;
+
+/// How long a control transfer may keep being NAKed before it fails.
+const CONTROL_TIMEOUT_MS: u64 = 1000;
+
+/// How long a bulk or interrupt transfer may keep being NAKed before it is
+/// abandoned with `UsbError::Nak`.
+const NAK_TIMEOUT_MS: u64 = 10;
+
+/// How long the controller may take to reset, halt a channel, or see a
+/// device connect.
+const RESET_TIMEOUT_MS: u64 = 1000;
+
+/// The first address handed out by `enumerate()`.
+const FIRST_ADDRESS: u8 = 1;
+
+/// The highest device address.
+const MAX_ADDRESS: u8 = 127;
+
+/// Enum representing bit fields of the `GAHBCFG` register.
+#[repr(u32)]
+enum AhbCfg {
+    GlobalInterrupt = 1,
+    /// Broadcom specific: wait for AXI writes to land before signalling a
+    /// DMA transfer as done.
+    WaitAxiWrites = 1 << 4,
+    DmaEnable = 1 << 5,
+}
+
+/// Enum representing bit fields of the `GUSBCFG` register.
+#[repr(u32)]
+enum UsbCfg {
+    PhyInterface16 = 1 << 3,
+    UlpiUtmiSelect = 1 << 4,
+    SrpCapable = 1 << 8,
+    HnpCapable = 1 << 9,
+    UlpiExternalVbus = 1 << 20,
+    TermSelectDlPulse = 1 << 22,
+}
+
+/// Enum representing bit fields of the `GRSTCTL` register.
+#[repr(u32)]
+enum Reset {
+    CoreSoft = 1,
+    RxFifoFlush = 1 << 4,
+    TxFifoFlush = 1 << 5,
+    AhbIdle = 1 << 31,
+}
+
+/// The `GRSTCTL` `TXFNUM` value selecting every transmit FIFO.
+const RESET_ALL_TX_FIFOS: u32 = 0x10 << 6;
+
+/// Enum representing bit fields of the `HPRT` register.
+#[repr(u32)]
+enum Port {
+    Connected = 1,
+    ConnectDetected = 1 << 1,
+    Enabled = 1 << 2,
+    EnableChanged = 1 << 3,
+    OvercurrentChanged = 1 << 5,
+    Reset = 1 << 8,
+    Power = 1 << 12,
+}
+
+/// The `HPRT` bits cleared (or, for `Enabled`, acted on) by writing 1. They
+/// must be written as 0 when changing other bits.
+const PORT_WRITE_CLEAR: u32 = Port::ConnectDetected as u32
+    | Port::Enabled as u32
+    | Port::EnableChanged as u32
+    | Port::OvercurrentChanged as u32;
+
+/// The offset of the port speed field in `HPRT`.
+const PORT_SPEED_SHIFT: u32 = 17;
+
+/// Enum representing bit fields of a channel's `HCCHAR` register.
+#[repr(u32)]
+enum Char {
+    DirectionIn = 1 << 15,
+    LowSpeed = 1 << 17,
+    OddFrame = 1 << 29,
+    Disable = 1 << 30,
+    Enable = 1 << 31,
+}
+
+/// Enum representing bit fields of a channel's `HCINT` register.
+#[repr(u32)]
+enum Hcint {
+    TransferComplete = 1,
+    Halted = 1 << 1,
+    AhbError = 1 << 2,
+    Stall = 1 << 3,
+    Nak = 1 << 4,
+    Ack = 1 << 5,
+    Nyet = 1 << 6,
+    TransactionError = 1 << 7,
+    BabbleError = 1 << 8,
+    FrameOverrun = 1 << 9,
+    DataToggleError = 1 << 10,
+}
+
+/// Enum representing bit fields of a channel's `HCSPLT` register.
+#[repr(u32)]
+enum SplitCtl {
+    CompleteSplit = 1 << 16,
+    Enable = 1 << 31,
+}
+
+/// The `HCINT` bits reporting a failed transaction.
+const HCINT_ERROR_MASK: u32 = Hcint::AhbError as u32
+    | Hcint::TransactionError as u32
+    | Hcint::BabbleError as u32
+    | Hcint::FrameOverrun as u32
+    | Hcint::DataToggleError as u32;
+
+/// The packet id a channel starts a transfer with, as encoded in `HCTSIZ`.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+enum Pid {
+    Data0 = 0,
+    Data1 = 2,
+    Setup = 3,
+}
+
+#[repr(C)]
+#[allow(non_snake_case)]
+struct CoreRegisters {
+    GOTGCTL: Volatile<u32>,
+    GOTGINT: Volatile<u32>,
+    GAHBCFG: Volatile<u32>,
+    GUSBCFG: Volatile<u32>,
+    GRSTCTL: Volatile<u32>,
+    GINTSTS: Volatile<u32>,
+    GINTMSK: Volatile<u32>,
+    GRXSTSR: ReadVolatile<u32>,
+    GRXSTSP: ReadVolatile<u32>,
+    GRXFSIZ: Volatile<u32>,
+    GNPTXFSIZ: Volatile<u32>,
+    GNPTXSTS: ReadVolatile<u32>,
+    __r0: [Reserved<u32>; 4],
+    GSNPSID: ReadVolatile<u32>,
+    GHWCFG: [ReadVolatile<u32>; 4],
+    __r1: [Reserved<u32>; 43],
+    HPTXFSIZ: Volatile<u32>,
+}
+
+#[repr(C)]
+#[allow(non_snake_case)]
+struct HostRegisters {
+    HCFG: Volatile<u32>,
+    HFIR: Volatile<u32>,
+    HFNUM: ReadVolatile<u32>,
+    __r0: Reserved<u32>,
+    HPTXSTS: ReadVolatile<u32>,
+    HAINT: ReadVolatile<u32>,
+    HAINTMSK: Volatile<u32>,
+    __r1: [Reserved<u32>; 9],
+    HPRT: Volatile<u32>,
+}
+
+#[repr(C)]
+#[allow(non_snake_case)]
+struct ChannelRegisters {
+    HCCHAR: Volatile<u32>,
+    HCSPLT: Volatile<u32>,
+    HCINT: Volatile<u32>,
+    HCINTMSK: Volatile<u32>,
+    HCTSIZ: Volatile<u32>,
+    HCDMA: Volatile<u32>,
+}
+
+/// The bounce buffer the controller reads and writes transfer data from.
+#[repr(C, align(64))]
+struct Buffer([u8; MAX_TRANSFER]);
+
+static mut BUFFER: Buffer = Buffer([0; MAX_TRANSFER]);
+
+/// Where a transfer goes: an endpoint of a device.
+#[derive(Debug, Clone, Copy)]
+struct Pipe {
+    address: u8,
+    speed: Speed,
+    endpoint: u8,
+    kind: EndpointType,
+    direction: Direction,
+    max_packet_size: u16,
+    split: Option<Split>,
+}
+
+impl Pipe {
+    /// Returns the pipe to the default control endpoint of the device at
+    /// `address`, starting in the OUT direction.
+    fn control(address: u8, speed: Speed, split: Option<Split>, max_packet_size: u16) -> Pipe {
+        Pipe {
+            address, speed, split, max_packet_size,
+            endpoint: 0,
+            kind: EndpointType::Control,
+            direction: Direction::Out,
+        }
+    }
+}
+
+/// Returns the time `ms` milliseconds from now.
+fn deadline(ms: u64) -> Instant {
+    Instant::now() + Duration::from_millis(ms)
+}
+
+/// The DWC2 USB controller in host mode, driving the root port.
+///
+/// Transfers are polled and run one at a time on a single host channel, so a
+/// `Host` is not fast, but it's enough for keyboards and the onboard
+/// Ethernet. Only one `Host` should exist.
+pub struct Host {
+    core: &'static mut CoreRegisters,
+    host: &'static mut HostRegisters,
+    channel: &'static mut ChannelRegisters,
+    next_address: u8,
+}
+
+impl Host {
+    /// Powers the controller on, resets it into host mode with DMA enabled,
+    /// and powers the root port.
+    pub fn new() -> Result<Host, UsbError> {
+        let mut values = [POWER_DEVICE_USB, POWER_ON_WAIT];
+        match mailbox::call_property(tag::SET_POWER_STATE, &mut values) {
+            Ok(()) if values[1] & 1 != 0 => (),
+            _ => return Err(UsbError::PowerOn)
+        }
+
+        let mut usb = unsafe {
+            Host {
+                core: &mut *(USB_REG_BASE as *mut CoreRegisters),
+                host: &mut *(HOST_REG_BASE as *mut HostRegisters),
+                channel: &mut *(CHANNEL_REG_BASE as *mut ChannelRegisters),
+                next_address: FIRST_ADDRESS,
+            }
+        };
+
+        usb.core.GAHBCFG.and_mask(!(AhbCfg::GlobalInterrupt as u32));
+        usb.core.GUSBCFG.and_mask(!(UsbCfg::UlpiExternalVbus as u32
+                                     | UsbCfg::TermSelectDlPulse as u32));
+        usb.reset_core()?;
+
+        // Select the 8-bit UTMI+ PHY the Pi's controller is wired to.
+        usb.core.GUSBCFG.and_mask(!(UsbCfg::UlpiUtmiSelect as u32
+                                     | UsbCfg::PhyInterface16 as u32));
+        usb.reset_core()?;
+
+        usb.core.GAHBCFG.or_mask(AhbCfg::DmaEnable as u32 | AhbCfg::WaitAxiWrites as u32);
+        usb.core.GUSBCFG.and_mask(!(UsbCfg::HnpCapable as u32 | UsbCfg::SrpCapable as u32));
+
+        unsafe { (*PCGCCTL).write(0); }
+
+        // 30/60MHz PHY clock.
+        usb.host.HCFG.and_mask(!0b11);
+
+        usb.core.GRXFSIZ.write(RX_FIFO_WORDS);
+        usb.core.GNPTXFSIZ.write(NPTX_FIFO_WORDS << 16 | RX_FIFO_WORDS);
+        usb.core.HPTXFSIZ.write(PTX_FIFO_WORDS << 16 | (RX_FIFO_WORDS + NPTX_FIFO_WORDS));
+        usb.flush_fifos()?;
+
+        let port = usb.host.HPRT.read() & !PORT_WRITE_CLEAR;
+        if port & Port::Power as u32 == 0 {
+            usb.host.HPRT.write(port | Port::Power as u32);
+        }
+
+        Ok(usb)
+    }
+
+    /// Waits for `flags` to clear in `GRSTCTL`.
+    fn wait_reset(&mut self, flags: u32) -> Result<(), UsbError> {
+        let deadline = deadline(RESET_TIMEOUT_MS);
+        while self.core.GRSTCTL.read() & flags != 0 {
+            if Instant::now() > deadline {
+                return Err(UsbError::TimedOut);
+            }
+        }
+
+        Ok(())
+    }
+
+    /// Soft resets the core once its AHB master is idle.
+    fn reset_core(&mut self) -> Result<(), UsbError> {
+        let deadline = deadline(RESET_TIMEOUT_MS);
+        while !self.core.GRSTCTL.has_mask(Reset::AhbIdle as u32) {
+            if Instant::now() > deadline {
+                return Err(UsbError::TimedOut);
+            }
+        }
+
+        self.core.GRSTCTL.or_mask(Reset::CoreSoft as u32);
+        self.wait_reset(Reset::CoreSoft as u32)?;
+        spin_sleep_ms(100);
+        Ok(())
+    }
+
+    /// Discards the contents of every FIFO.
+    fn flush_fifos(&mut self) -> Result<(), UsbError> {
+        self.core.GRSTCTL.write(Reset::TxFifoFlush as u32 | RESET_ALL_TX_FIFOS);
+        self.wait_reset(Reset::TxFifoFlush as u32)?;
+        self.core.GRSTCTL.write(Reset::RxFifoFlush as u32);
+        self.wait_reset(Reset::RxFifoFlush as u32)
+    }
+
+    /// Waits for a device on the root port, resets it, and returns its
+    /// speed. The device then answers at address 0.
+    pub fn reset_port(&mut self) -> Result<Speed, UsbError> {
+        let deadline = deadline(RESET_TIMEOUT_MS);
+        while !self.host.HPRT.has_mask(Port::Connected as u32) {
+            if Instant::now() > deadline {
+                return Err(UsbError::NotConnected);
+            }
+        }
+
+        let port = self.host.HPRT.read() & !PORT_WRITE_CLEAR;
+        self.host.HPRT.write(port | Port::Reset as u32);
+        spin_sleep_ms(50);
+        self.host.HPRT.write(port & !(Port::Reset as u32));
+        spin_sleep_ms(20);
+
+        let port = self.host.HPRT.read();
+        if port & Port::Enabled as u32 == 0 {
+            return Err(UsbError::NotConnected);
+        }
+
+        Ok(match (port >> PORT_SPEED_SHIFT) & 0b11 {
+            0 => Speed::High,
+            1 => Speed::Full,
+            _ => Speed::Low
+        })
+    }
+
+    /// Resets the device on the root port and enumerates it.
+    pub fn enumerate_root(&mut self) -> Result<Device, UsbError> {
+        let speed = self.reset_port()?;
+        self.enumerate(speed, None)
+    }
+
+    /// Enumerates the device of speed `speed` that was just reset and answers
+    /// at address 0: gives it the next free address and selects its first
+    /// configuration. `split` is the hub port it's reached through if it
+    /// needs split transactions.
+    pub fn enumerate(&mut self, speed: Speed, split: Option<Split>) -> Result<Device, UsbError> {
+        if self.next_address > MAX_ADDRESS {
+            return Err(UsbError::TooLarge);
+        }
+
+        // Only the first 8 bytes are safe to read before the real maximum
+        // packet size is known.
+        let mut bytes = [0; DeviceDescriptor::LENGTH];
+        let max_packet_size0 = if speed == Speed::High { 64 } else { 8 };
+        let setup = SetupPacket::get_descriptor(descriptor::DEVICE, 0, 8);
+        self.control_pipe(Pipe::control(0, speed, split, max_packet_size0), setup, &mut bytes[..8])?;
+        let max_packet_size0 = DeviceDescriptor::parse(&bytes[..8])?.max_packet_size0 as u16;
+
+        let address = self.next_address;
+        let setup = SetupPacket {
+            request_type: request_type::STANDARD | request_type::DEVICE,
+            request: request::SET_ADDRESS,
+            value: address as u16,
+            index: 0,
+            length: 0,
+        };
+        self.control_pipe(Pipe::control(0, speed, split, max_packet_size0), setup, &mut [])?;
+        self.next_address += 1;
+        spin_sleep_ms(10);
+
+        let pipe = Pipe::control(address, speed, split, max_packet_size0);
+        let setup = SetupPacket::get_descriptor(descriptor::DEVICE, 0, bytes.len() as u16);
+        self.control_pipe(pipe, setup, &mut bytes)?;
+        let descriptor = DeviceDescriptor::parse(&bytes)?;
+
+        let mut header = [0; 9];
+        let setup = SetupPacket::get_descriptor(descriptor::CONFIGURATION, 0, header.len() as u16);
+        self.control_pipe(pipe, setup, &mut header)?;
+        if header[1] != descriptor::CONFIGURATION {
+            return Err(UsbError::BadDescriptor);
+        }
+
+        let configuration = header[5];
+        let setup = SetupPacket {
+            request_type: request_type::STANDARD | request_type::DEVICE,
+            request: request::SET_CONFIGURATION,
+            value: configuration as u16,
+            index: 0,
+            length: 0,
+        };
+        self.control_pipe(pipe, setup, &mut [])?;
+
+        Ok(Device { address, speed, split, descriptor, configuration })
+    }
+
+    /// Reads the first configuration descriptor of `device`, with the
+    /// interface and endpoint descriptors following it, into `buf`. Returns
+    /// the number of bytes read, which is less than the descriptors' total
+    /// length if `buf` is too small.
+    pub fn configuration_descriptor(&mut self, device: &Device, buf: &mut [u8]) -> Result<usize, UsbError> {
+        let len = cmp::min(buf.len(), MAX_TRANSFER) as u16;
+        let setup = SetupPacket::get_descriptor(descriptor::CONFIGURATION, 0, len);
+        self.control(device, setup, buf)
+    }
+
+    /// Performs the control transfer `setup` on `device`'s default endpoint.
+    /// `data` holds the data stage: it is sent for OUT requests and receives
+    /// the response for IN requests. Returns the length of the data stage.
+    pub fn control(&mut self, device: &Device, setup: SetupPacket, data: &mut [u8]) -> Result<usize, UsbError> {
+        let max_packet_size0 = device.descriptor.max_packet_size0 as u16;
+        let pipe = Pipe::control(device.address, device.speed, device.split, max_packet_size0);
+        self.control_pipe(pipe, setup, data)
+    }
+
+    /// Performs the control transfer `setup` on the control pipe `pipe`.
+    fn control_pipe(&mut self, mut pipe: Pipe, setup: SetupPacket, data: &mut [u8]) -> Result<usize, UsbError> {
+        let len = cmp::min(setup.length as usize, data.len());
+        if len > MAX_TRANSFER {
+            return Err(UsbError::TooLarge);
+        }
+
+        unsafe { BUFFER.0[..8].copy_from_slice(&setup.to_bytes()); }
+        self.transfer_retrying(pipe, Pid::Setup, 8)?;
+
+        let mut transferred = 0;
+        if len > 0 {
+            pipe.direction = setup.direction();
+            if pipe.direction == Direction::Out {
+                unsafe { BUFFER.0[..len].copy_from_slice(&data[..len]); }
+            }
+
+            transferred = self.transfer_retrying(pipe, Pid::Data1, len)?.0;
+            if pipe.direction == Direction::In {
+                unsafe { data[..transferred].copy_from_slice(&BUFFER.0[..transferred]); }
+            }
+        }
+
+        // The status stage runs opposite to the data stage.
+        pipe.direction = match (len, pipe.direction) {
+            (0, _) | (_, Direction::Out) => Direction::In,
+            _ => Direction::Out
+        };
+        self.transfer_retrying(pipe, Pid::Data1, 0)?;
+
+        Ok(transferred)
+    }
+
+    /// Performs a bulk or interrupt transfer on `endpoint` of `device`:
+    /// sends `data` to an OUT endpoint, or receives up to `data.len()` bytes
+    /// from an IN endpoint. Returns the number of bytes transferred.
+    ///
+    /// An endpoint with nothing to send NAKs; this returns `UsbError::Nak`
+    /// rather than waiting, so callers poll.
+    pub fn transfer(&mut self, device: &Device, endpoint: &mut Endpoint, data: &mut [u8]) -> Result<usize, UsbError> {
+        if data.len() > MAX_TRANSFER {
+            return Err(UsbError::TooLarge);
+        }
+
+        let pipe = Pipe {
+            address: device.address,
+            speed: device.speed,
+            endpoint: endpoint.number,
+            kind: endpoint.kind,
+            direction: endpoint.direction,
+            max_packet_size: endpoint.max_packet_size,
+            split: device.split,
+        };
+
+        let len = data.len();
+        if pipe.direction == Direction::Out {
+            unsafe { BUFFER.0[..len].copy_from_slice(data); }
+        }
+
+        let pid = if endpoint.toggle { Pid::Data1 } else { Pid::Data0 };
+        let (transferred, next) = self.transfer_once(pipe, pid, len, deadline(NAK_TIMEOUT_MS))?;
+        endpoint.toggle = next == Pid::Data1;
+
+        if pipe.direction == Direction::In {
+            unsafe { data[..transferred].copy_from_slice(&BUFFER.0[..transferred]); }
+        }
+
+        Ok(transferred)
+    }
+
+    /// Runs `transfer_once()`, retrying while the device NAKs until the
+    /// control transfer timeout.
+    fn transfer_retrying(&mut self, pipe: Pipe, pid: Pid, len: usize) -> Result<(usize, Pid), UsbError> {
+        let deadline = deadline(CONTROL_TIMEOUT_MS);
+        loop {
+            match self.transfer_once(pipe, pid, len, deadline) {
+                Err(UsbError::Nak) if Instant::now() < deadline => continue,
+                Err(UsbError::Nak) => return Err(UsbError::TimedOut),
+                result => return result
+            }
+        }
+    }
+
+    /// Transfers `len` bytes between the bounce buffer and `pipe` on channel
+    /// 0, starting with packet id `pid`. Returns the number of bytes
+    /// transferred and the packet id the next transfer must start with.
+    fn transfer_once(&mut self, pipe: Pipe, pid: Pid, len: usize, deadline: Instant) -> Result<(usize, Pid), UsbError> {
+        let split = match pipe.split {
+            Some(split) => split,
+            None => {
+                let status = self.run_channel(pipe, pid, 0, len, 0, deadline)?;
+                if status & Hcint::TransferComplete as u32 == 0 {
+                    return Err(UsbError::Nak);
+                }
+
+                return Ok(self.completed(pipe, len));
+            }
+        };
+
+        // A split transaction carries one packet: a start split hands it to
+        // the hub's transaction translator, which runs it on the slower bus,
+        // and complete splits collect the result, answered with NYET until
+        // it's ready.
+        let max_packet_size = cmp::max(pipe.max_packet_size as usize, 1);
+        let start = SplitCtl::Enable as u32
+            | (split.hub_address as u32 & 0x7F) << 7
+            | split.port as u32 & 0x7F;
+
+        let (mut pid, mut offset) = (pid, 0);
+        loop {
+            let packet = cmp::min(len - offset, max_packet_size);
+            let status = self.run_channel(pipe, pid, offset, packet, start, deadline)?;
+            if status & Hcint::Ack as u32 == 0 {
+                return Err(UsbError::Nak);
+            }
+
+            let complete = start | SplitCtl::CompleteSplit as u32;
+            let status = loop {
+                let status = self.run_channel(pipe, pid, offset, packet, complete, deadline)?;
+                if status & Hcint::Nyet as u32 == 0 {
+                    break status;
+                } else if Instant::now() > deadline {
+                    return Err(UsbError::TimedOut);
+                }
+            };
+
+            if status & Hcint::TransferComplete as u32 == 0 {
+                return Err(UsbError::Nak);
+            }
+
+            let (transferred, next) = self.completed(pipe, packet);
+            offset += transferred;
+            pid = next;
+            if offset >= len || transferred < packet {
+                return Ok((offset, pid));
+            }
+        }
+    }
+
+    /// Runs one transfer of `len` bytes at `offset` in the bounce buffer on
+    /// channel 0 with `HCSPLT` set to `split`, and waits for the channel to
+    /// halt. Returns the channel's `HCINT` bits if the transfer didn't fail.
+    fn run_channel(&mut self,
+                   pipe: Pipe,
+                   pid: Pid,
+                   offset: usize,
+                   len: usize,
+                   split: u32,
+                   deadline: Instant) -> Result<u32, UsbError> {
+        let max_packet_size = cmp::max(pipe.max_packet_size as usize, 1);
+        let packets = cmp::max((len + max_packet_size - 1) / max_packet_size, 1);
+
+        let mut characteristics = max_packet_size as u32 & 0x7FF
+            | (pipe.endpoint as u32 & 0xF) << 11
+            | (pipe.kind as u32) << 18
+            | 1 << 20
+            | (pipe.address as u32 & 0x7F) << 22;
+        if pipe.direction == Direction::In {
+            characteristics |= Char::DirectionIn as u32;
+        }
+        if pipe.speed == Speed::Low {
+            characteristics |= Char::LowSpeed as u32;
+        }
+        if pipe.kind == EndpointType::Interrupt && self.host.HFNUM.read() & 1 == 0 {
+            // Periodic transfers are scheduled for the next frame.
+            characteristics |= Char::OddFrame as u32;
+        }
+
+        self.channel.HCCHAR.write(characteristics);
+        self.channel.HCSPLT.write(split);
+        self.channel.HCINT.write(0xFFFF_FFFF);
+        self.channel.HCINTMSK.write(0);
+        self.channel.HCTSIZ.write(len as u32 & 0x7FFFF
+                                  | (packets as u32 & 0x3FF) << 19
+                                  | (pid as u32) << 29);
+        self.channel.HCDMA.write(unsafe { bus_address(BUFFER.0[offset..].as_ptr() as usize) });
+        self.channel.HCCHAR.write(characteristics | Char::Enable as u32);
+
+        let status = loop {
+            let status = self.channel.HCINT.read();
+            if status & Hcint::Halted as u32 != 0 {
+                break status;
+            }
+
+            if Instant::now() > deadline {
+                self.halt_channel()?;
+                return Err(if self.channel.HCINT.has_mask(Hcint::Nak as u32) {
+                    UsbError::Nak
+                } else {
+                    UsbError::TimedOut
+                });
+            }
+        };
+
+        if status & Hcint::Stall as u32 != 0 {
+            Err(UsbError::Stall)
+        } else if status & HCINT_ERROR_MASK != 0 {
+            Err(UsbError::Transaction(status & HCINT_ERROR_MASK))
+        } else {
+            Ok(status)
+        }
+    }
+
+    /// Returns the number of bytes of a `len` byte transfer on `pipe` that
+    /// completed, and the packet id the next transfer starts with.
+    fn completed(&self, pipe: Pipe, len: usize) -> (usize, Pid) {
+        let size = self.channel.HCTSIZ.read();
+        let transferred = match pipe.direction {
+            Direction::In => len - cmp::min(size as usize & 0x7FFFF, len),
+            Direction::Out => len
+        };
+
+        let next = if (size >> 29) & 0b11 == Pid::Data1 as u32 { Pid::Data1 } else { Pid::Data0 };
+        (transferred, next)
+    }
+
+    /// Stops channel 0 mid-transfer and waits for it to halt.
+    fn halt_channel(&mut self) -> Result<(), UsbError> {
+        self.channel.HCCHAR.or_mask(Char::Disable as u32 | Char::Enable as u32);
+
+        let deadline = deadline(RESET_TIMEOUT_MS);
+        while !self.channel.HCINT.has_mask(Hcint::Halted as u32) {
+            if Instant::now() > deadline {
+                return Err(UsbError::TimedOut);
+            }
+        }
+
+        Ok(())
+    }
+}
@@ -0,0 +1,204 @@
+use core::time::Duration;
+
+use timer::{Instant, spin_sleep_ms};
+
+use super::{Host, Device, Speed, Split, SetupPacket, UsbError};
+use super::{request, request_type};
+
+/// The device class of hubs.
+pub const CLASS_HUB: u8 = 9;
+
+/// The descriptor type of a hub descriptor.
+const DESCRIPTOR_HUB: u8 = 0x29;
+
+/// Hub class feature selectors for ports.
+mod feature {
+    pub const PORT_RESET: u16 = 4;
+    pub const PORT_POWER: u16 = 8;
+    pub const C_PORT_CONNECTION: u16 = 16;
+    pub const C_PORT_RESET: u16 = 20;
+}
+
+/// Enum representing bit fields of a port's status, as returned by
+/// `GET_STATUS`.
+#[repr(u32)]
+enum PortStatus {
+    Connected = 1,
+    Enabled = 1 << 1,
+    Reset = 1 << 4,
+    LowSpeed = 1 << 9,
+    HighSpeed = 1 << 10,
+}
+
+/// How long a port may take to finish resetting.
+const PORT_RESET_TIMEOUT_MS: u64 = 500;
+
+/// How long to wait after a port reset before talking to the device.
+const RESET_RECOVERY_MS: u64 = 10;
+
+/// The deepest hubs may be chained below the root port.
+const MAX_DEPTH: usize = 5;
+
+/// A configured hub and the number of ports it has.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct Hub {
+    pub device: Device,
+    pub ports: u8,
+    /// The time from powering a port on until it's usable, in milliseconds.
+    power_on_ms: u64,
+}
+
+impl Hub {
+    /// Reads the hub descriptor of `device`, which must be a hub.
+    pub fn new(host: &mut Host, device: &Device) -> Result<Hub, UsbError> {
+        if device.descriptor.class != CLASS_HUB {
+            return Err(UsbError::Unsupported);
+        }
+
+        let mut bytes = [0; 9];
+        let setup = SetupPacket {
+            request_type: request_type::IN | request_type::CLASS | request_type::DEVICE,
+            request: request::GET_DESCRIPTOR,
+            value: (DESCRIPTOR_HUB as u16) << 8,
+            index: 0,
+            length: bytes.len() as u16,
+        };
+
+        let len = host.control(device, setup, &mut bytes)?;
+        if len < 7 || bytes[1] != DESCRIPTOR_HUB {
+            return Err(UsbError::BadDescriptor);
+        }
+
+        Ok(Hub { device: *device, ports: bytes[2], power_on_ms: bytes[5] as u64 * 2 })
+    }
+
+    /// Sends the port request `request` with `value` for `port`.
+    fn port_request(&self, host: &mut Host, request: u8, value: u16, port: u8) -> Result<(), UsbError> {
+        let setup = SetupPacket {
+            request_type: request_type::CLASS | request_type::OTHER,
+            request, value,
+            index: port as u16,
+            length: 0,
+        };
+
+        host.control(&self.device, setup, &mut []).map(|_| ())
+    }
+
+    /// Returns the status (low half) and change (high half) bits of `port`.
+    fn port_status(&self, host: &mut Host, port: u8) -> Result<u32, UsbError> {
+        let mut bytes = [0; 4];
+        let setup = SetupPacket {
+            request_type: request_type::IN | request_type::CLASS | request_type::OTHER,
+            request: request::GET_STATUS,
+            value: 0,
+            index: port as u16,
+            length: bytes.len() as u16,
+        };
+
+        host.control(&self.device, setup, &mut bytes)?;
+        Ok(bytes[0] as u32 | (bytes[1] as u32) << 8 | (bytes[2] as u32) << 16 | (bytes[3] as u32) << 24)
+    }
+
+    /// Powers every port on and waits for them to become usable.
+    pub fn power_on(&self, host: &mut Host) -> Result<(), UsbError> {
+        for port in 1..(self.ports + 1) {
+            self.port_request(host, request::SET_FEATURE, feature::PORT_POWER, port)?;
+        }
+
+        spin_sleep_ms(self.power_on_ms);
+        Ok(())
+    }
+
+    /// Resets the device on `port` if one is connected and returns its speed,
+    /// or `None` if the port is empty. The device then answers at address 0.
+    pub fn reset_port(&self, host: &mut Host, port: u8) -> Result<Option<Speed>, UsbError> {
+        if self.port_status(host, port)? & PortStatus::Connected as u32 == 0 {
+            return Ok(None);
+        }
+
+        self.port_request(host, request::CLEAR_FEATURE, feature::C_PORT_CONNECTION, port)?;
+        self.port_request(host, request::SET_FEATURE, feature::PORT_RESET, port)?;
+
+        let deadline = Instant::now() + Duration::from_millis(PORT_RESET_TIMEOUT_MS);
+        let status = loop {
+            let status = self.port_status(host, port)?;
+            if status & PortStatus::Reset as u32 == 0 {
+                break status;
+            } else if Instant::now() > deadline {
+                return Err(UsbError::TimedOut);
+            }
+
+            spin_sleep_ms(1);
+        };
+
+        self.port_request(host, request::CLEAR_FEATURE, feature::C_PORT_RESET, port)?;
+        if status & PortStatus::Enabled as u32 == 0 {
+            return Ok(None);
+        }
+
+        spin_sleep_ms(RESET_RECOVERY_MS);
+        Ok(Some(if status & PortStatus::HighSpeed as u32 != 0 {
+            Speed::High
+        } else if status & PortStatus::LowSpeed as u32 != 0 {
+            Speed::Low
+        } else {
+            Speed::Full
+        }))
+    }
+
+    /// Returns the split transaction target for a device of speed `speed` on
+    /// `port`: this port if the hub is high speed and the device isn't, or
+    /// else whatever the hub itself goes through.
+    fn split(&self, port: u8, speed: Speed) -> Option<Split> {
+        if self.device.speed == Speed::High && speed != Speed::High {
+            Some(Split { hub_address: self.device.address, port })
+        } else {
+            self.device.split
+        }
+    }
+}
+
+/// Enumerates the device on the root port and every device below it,
+/// configuring hubs along the way, and calls `found` with each device that
+/// isn't a hub.
+///
+/// Devices are found once, at the time of the call; hubs' status change
+/// endpoints aren't polled, so devices plugged in later aren't noticed.
+/// Devices that fail to enumerate are skipped.
+pub fn enumerate_all<F: FnMut(&mut Host, &Device)>(host: &mut Host, mut found: F) -> Result<(), UsbError> {
+    let root = host.enumerate_root()?;
+    visit(host, &root, 0, &mut found);
+    Ok(())
+}
+
+/// Calls `found` with `device` or, if it's a hub, with the devices below it.
+fn visit(host: &mut Host, device: &Device, depth: usize, found: &mut dyn FnMut(&mut Host, &Device)) {
+    if device.descriptor.class != CLASS_HUB {
+        found(host, device);
+        return;
+    }
+
+    if depth >= MAX_DEPTH {
+        return;
+    }
+
+    let hub = match Hub::new(host, device) {
+        Ok(hub) => hub,
+        Err(_) => return
+    };
+
+    if hub.power_on(host).is_err() {
+        return;
+    }
+
+    for port in 1..(hub.ports + 1) {
+        let speed = match hub.reset_port(host, port) {
+            Ok(Some(speed)) => speed,
+            _ => continue
+        };
+
+        if let Ok(child) = host.enumerate(speed, hub.split(port, speed)) {
+            visit(host, &child, depth + 1, found);
+        }
+    }
+}
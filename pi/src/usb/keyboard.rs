@@ -0,0 +1,209 @@
+use super::{Host, Device, Endpoint, EndpointDescriptor, InterfaceDescriptor, Descriptors};
+use super::{SetupPacket, UsbError, Direction, EndpointType, descriptor, request_type};
+
+/// The interface class, subclass and protocol of a boot protocol keyboard.
+const CLASS_HID: u8 = 3;
+const SUBCLASS_BOOT: u8 = 1;
+const PROTOCOL_KEYBOARD: u8 = 1;
+
+/// HID class requests.
+mod request {
+    pub const SET_REPORT: u8 = 0x09;
+    pub const SET_IDLE: u8 = 0x0A;
+    pub const SET_PROTOCOL: u8 = 0x0B;
+}
+
+/// The `SET_PROTOCOL` value selecting the boot protocol.
+const BOOT_PROTOCOL: u16 = 0;
+
+/// The `SET_REPORT` value selecting output report 0, the LEDs.
+const LED_REPORT: u16 = 0x0200;
+
+/// The size of a boot protocol report: modifiers, a reserved byte, and up to
+/// six pressed keys.
+const REPORT_SIZE: usize = 8;
+
+/// The largest configuration descriptor read when probing.
+const MAX_CONFIGURATION: usize = 256;
+
+/// Enum representing bit fields of a report's modifier byte.
+#[repr(u8)]
+enum Modifier {
+    LeftCtrl = 1,
+    LeftShift = 1 << 1,
+    RightCtrl = 1 << 4,
+    RightShift = 1 << 5,
+}
+
+/// Enum representing bit fields of the LED output report.
+#[repr(u8)]
+enum Led {
+    CapsLock = 1 << 1,
+}
+
+/// Usage ids of keys with special handling.
+mod key {
+    pub const A: u8 = 0x04;
+    pub const Z: u8 = 0x1D;
+    pub const CAPS_LOCK: u8 = 0x39;
+    pub const DELETE: u8 = 0x4C;
+    pub const RIGHT: u8 = 0x4F;
+    pub const LEFT: u8 = 0x50;
+    pub const DOWN: u8 = 0x51;
+    pub const UP: u8 = 0x52;
+}
+
+/// The bytes typed by usage ids `0x00` to `0x38`, without and with shift.
+/// Backspace types DEL, as a terminal's would.
+const UNSHIFTED: &[u8] = b"\0\0\0\0abcdefghijklmnopqrstuvwxyz1234567890\r\x1b\x7f\t -=[]\\#;'`,./";
+const SHIFTED: &[u8] = b"\0\0\0\0ABCDEFGHIJKLMNOPQRSTUVWXYZ!@#$%^&*()\r\x1b\x7f\t _+{}|~:\"~<>?";
+
+/// A USB keyboard driven with the HID boot protocol, turning key presses into
+/// the bytes a serial terminal would send.
+pub struct Keyboard {
+    device: Device,
+    interface: u8,
+    endpoint: Endpoint,
+    previous: [u8; REPORT_SIZE],
+    caps_lock: bool,
+}
+
+impl Keyboard {
+    /// Looks for a boot keyboard interface on `device` and switches it to the
+    /// boot protocol. Returns `UsbError::Unsupported` if `device` isn't a
+    /// keyboard.
+    pub fn new(host: &mut Host, device: &Device) -> Result<Keyboard, UsbError> {
+        let mut bytes = [0; MAX_CONFIGURATION];
+        let len = host.configuration_descriptor(device, &mut bytes)?;
+
+        let mut found = None;
+        let mut interface = None;
+        for (kind, bytes) in Descriptors::new(&bytes[..len]) {
+            if kind == descriptor::INTERFACE {
+                let desc = InterfaceDescriptor::parse(bytes)?;
+                let keyboard = desc.class == CLASS_HID
+                    && desc.subclass == SUBCLASS_BOOT
+                    && desc.protocol == PROTOCOL_KEYBOARD;
+                interface = if keyboard { Some(desc.number) } else { None };
+            } else if kind == descriptor::ENDPOINT && interface.is_some() {
+                let desc = EndpointDescriptor::parse(bytes)?;
+                if desc.kind == EndpointType::Interrupt && desc.direction == Direction::In {
+                    found = interface.map(|number| (number, Endpoint::new(&desc)));
+                    break;
+                }
+            }
+        }
+
+        let (interface, endpoint) = found.ok_or(UsbError::Unsupported)?;
+        let mut keyboard = Keyboard {
+            device: *device,
+            interface, endpoint,
+            previous: [0; REPORT_SIZE],
+            caps_lock: false,
+        };
+
+        keyboard.class_request(host, request::SET_PROTOCOL, BOOT_PROTOCOL, &mut [])?;
+
+        // Only report changes. Some keyboards stall this; they report changes
+        // anyway.
+        let _ = keyboard.class_request(host, request::SET_IDLE, 0, &mut []);
+        Ok(keyboard)
+    }
+
+    /// Returns the device the keyboard is.
+    pub fn device(&self) -> &Device {
+        &self.device
+    }
+
+    /// Sends the HID class request `request` with `value` and the OUT data
+    /// stage `data` to the keyboard's interface.
+    fn class_request(&mut self, host: &mut Host, request: u8, value: u16, data: &mut [u8]) -> Result<(), UsbError> {
+        let setup = SetupPacket {
+            request_type: request_type::CLASS | request_type::INTERFACE,
+            request, value,
+            index: self.interface as u16,
+            length: data.len() as u16,
+        };
+
+        host.control(&self.device, setup, data).map(|_| ())
+    }
+
+    /// Reads the keyboard's latest report, if it has a new one, and calls
+    /// `typed` with the bytes typed by keys pressed since the last report.
+    ///
+    /// Letters honour shift and caps lock, ctrl turns them into control
+    /// characters, and the arrow keys type ANSI escape sequences. Keys held
+    /// down don't repeat.
+    pub fn poll<F: FnMut(u8)>(&mut self, host: &mut Host, mut typed: F) -> Result<(), UsbError> {
+        let mut report = [0; REPORT_SIZE];
+        let device = self.device;
+        match host.transfer(&device, &mut self.endpoint, &mut report) {
+            Ok(REPORT_SIZE) => {  }
+            Ok(_) | Err(UsbError::Nak) => return Ok(()),
+            Err(e) => return Err(e)
+        }
+
+        let modifiers = report[0];
+        for &usage in report[2..].iter() {
+            // Usage 1 means too many keys are held to tell which.
+            if usage == 1 {
+                return Ok(());
+            }
+        }
+
+        for &usage in report[2..].iter() {
+            if usage == 0 || self.previous[2..].contains(&usage) {
+                continue;
+            }
+
+            if usage == key::CAPS_LOCK {
+                self.caps_lock = !self.caps_lock;
+                let mut leds = [if self.caps_lock { Led::CapsLock as u8 } else { 0 }];
+                let _ = self.class_request(host, request::SET_REPORT, LED_REPORT, &mut leds);
+                continue;
+            }
+
+            self.press(usage, modifiers, &mut typed);
+        }
+
+        self.previous = report;
+        Ok(())
+    }
+
+    /// Calls `typed` with the bytes typed by pressing the key with usage id
+    /// `usage` while `modifiers` are held.
+    fn press<F: FnMut(u8)>(&self, usage: u8, modifiers: u8, typed: &mut F) {
+        let escape = match usage {
+            key::UP => Some(b'A'),
+            key::DOWN => Some(b'B'),
+            key::RIGHT => Some(b'C'),
+            key::LEFT => Some(b'D'),
+            _ => None
+        };
+
+        if let Some(code) = escape {
+            typed(0x1b);
+            typed(b'[');
+            return typed(code);
+        }
+
+        if usage == key::DELETE {
+            return typed(0x7f);
+        }
+
+        let usage = usage as usize;
+        if usage >= UNSHIFTED.len() || UNSHIFTED[usage] == 0 {
+            return;
+        }
+
+        let ctrl = modifiers & (Modifier::LeftCtrl as u8 | Modifier::RightCtrl as u8) != 0;
+        let mut shift = modifiers & (Modifier::LeftShift as u8 | Modifier::RightShift as u8) != 0;
+        let letter = usage >= key::A as usize && usage <= key::Z as usize;
+        if letter && self.caps_lock {
+            shift = !shift;
+        }
+
+        let byte = if shift { SHIFTED[usage] } else { UNSHIFTED[usage] };
+        typed(if ctrl && letter { byte & 0x1f } else { byte });
+    }
+}
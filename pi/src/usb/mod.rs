@@ -0,0 +1,327 @@
+//! USB host support: the DWC2 host controller driver in `host`, hub support
+//! in `hub`, and the standard requests and descriptors shared by device
+//! drivers.
+
+pub mod host;
+pub mod hub;
+pub mod keyboard;
+pub mod smsc95xx;
+
+pub use self::host::Host;
+
+/// An error from a USB transfer or from bringing up the host controller.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum UsbError {
+    /// The firmware wouldn't power the controller on.
+    PowerOn,
+    /// Nothing is connected to the port.
+    NotConnected,
+    /// The controller or device didn't finish in time.
+    TimedOut,
+    /// The device stalled the endpoint, e.g. because it doesn't support the
+    /// request.
+    Stall,
+    /// The endpoint had no data to send or wasn't ready to receive. Bulk and
+    /// interrupt transfers return this rather than waiting, so they're polled.
+    Nak,
+    /// The transfer failed on the bus; these are the channel's `HCINT` bits.
+    Transaction(u32),
+    /// The transfer doesn't fit in the controller's bounce buffer.
+    TooLarge,
+    /// A descriptor returned by the device is malformed.
+    BadDescriptor,
+    /// The device doesn't have the interface a driver needs.
+    Unsupported,
+}
+
+/// The speed a device operates at.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum Speed {
+    High,
+    Full,
+    Low
+}
+
+/// The direction of a transfer, from the host's point of view.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum Direction {
+    Out = 0,
+    In = 1
+}
+
+/// The transfer type of an endpoint, numbered as in endpoint descriptors.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum EndpointType {
+    Control = 0,
+    Isochronous = 1,
+    Bulk = 2,
+    Interrupt = 3
+}
+
+/// Standard request codes.
+pub mod request {
+    pub const GET_STATUS: u8 = 0;
+    pub const CLEAR_FEATURE: u8 = 1;
+    pub const SET_FEATURE: u8 = 3;
+    pub const SET_ADDRESS: u8 = 5;
+    pub const GET_DESCRIPTOR: u8 = 6;
+    pub const SET_CONFIGURATION: u8 = 9;
+    pub const SET_INTERFACE: u8 = 11;
+}
+
+/// Descriptor type codes.
+pub mod descriptor {
+    pub const DEVICE: u8 = 1;
+    pub const CONFIGURATION: u8 = 2;
+    pub const STRING: u8 = 3;
+    pub const INTERFACE: u8 = 4;
+    pub const ENDPOINT: u8 = 5;
+}
+
+/// Bits of a setup packet's `request_type`.
+pub mod request_type {
+    /// Data flows from the device to the host.
+    pub const IN: u8 = 1 << 7;
+    pub const STANDARD: u8 = 0 << 5;
+    pub const CLASS: u8 = 1 << 5;
+    pub const VENDOR: u8 = 2 << 5;
+    pub const DEVICE: u8 = 0;
+    pub const INTERFACE: u8 = 1;
+    pub const ENDPOINT: u8 = 2;
+    pub const OTHER: u8 = 3;
+}
+
+/// The eight bytes opening every control transfer.
+#[repr(C)]
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct SetupPacket {
+    pub request_type: u8,
+    pub request: u8,
+    pub value: u16,
+    pub index: u16,
+    pub length: u16,
+}
+
+impl SetupPacket {
+    /// Returns the direction of the data stage, if there is one.
+    pub fn direction(&self) -> Direction {
+        if self.request_type & request_type::IN != 0 { Direction::In } else { Direction::Out }
+    }
+
+    /// Returns the packet as sent on the wire, little-endian.
+    pub fn to_bytes(&self) -> [u8; 8] {
+        [self.request_type, self.request,
+         self.value as u8, (self.value >> 8) as u8,
+         self.index as u8, (self.index >> 8) as u8,
+         self.length as u8, (self.length >> 8) as u8]
+    }
+
+    /// Returns a standard `GET_DESCRIPTOR` request for `length` bytes of the
+    /// descriptor of type `kind` with index `index`.
+    pub fn get_descriptor(kind: u8, index: u8, length: u16) -> SetupPacket {
+        SetupPacket {
+            request_type: request_type::IN | request_type::STANDARD | request_type::DEVICE,
+            request: request::GET_DESCRIPTOR,
+            value: (kind as u16) << 8 | index as u16,
+            index: 0,
+            length,
+        }
+    }
+}
+
+/// Reads the little-endian `u16` at `bytes[i..i + 2]`.
+fn read_u16(bytes: &[u8], i: usize) -> u16 {
+    bytes[i] as u16 | (bytes[i + 1] as u16) << 8
+}
+
+/// The fields of a device descriptor drivers match on.
+#[derive(Debug, Default, Clone, Copy, PartialEq, Eq)]
+pub struct DeviceDescriptor {
+    pub usb_version: u16,
+    pub class: u8,
+    pub subclass: u8,
+    pub protocol: u8,
+    pub max_packet_size0: u8,
+    pub vendor_id: u16,
+    pub product_id: u16,
+    pub device_version: u16,
+    pub num_configurations: u8,
+}
+
+impl DeviceDescriptor {
+    /// The length of a device descriptor in bytes.
+    pub const LENGTH: usize = 18;
+
+    /// Parses a device descriptor. Only the first eight bytes, up to
+    /// `max_packet_size0`, are required; the rest are zero if missing.
+    pub fn parse(bytes: &[u8]) -> Result<DeviceDescriptor, UsbError> {
+        if bytes.len() < 8 || bytes[1] != descriptor::DEVICE {
+            return Err(UsbError::BadDescriptor);
+        }
+
+        let mut full = [0; DeviceDescriptor::LENGTH];
+        let len = ::core::cmp::min(bytes.len(), DeviceDescriptor::LENGTH);
+        full[..len].copy_from_slice(&bytes[..len]);
+
+        Ok(DeviceDescriptor {
+            usb_version: read_u16(&full, 2),
+            class: full[4],
+            subclass: full[5],
+            protocol: full[6],
+            max_packet_size0: full[7],
+            vendor_id: read_u16(&full, 8),
+            product_id: read_u16(&full, 10),
+            device_version: read_u16(&full, 12),
+            num_configurations: full[17],
+        })
+    }
+}
+
+/// An interface descriptor, found in a configuration's descriptors.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct InterfaceDescriptor {
+    pub number: u8,
+    pub alternate_setting: u8,
+    pub num_endpoints: u8,
+    pub class: u8,
+    pub subclass: u8,
+    pub protocol: u8,
+}
+
+impl InterfaceDescriptor {
+    /// Parses an interface descriptor.
+    pub fn parse(bytes: &[u8]) -> Result<InterfaceDescriptor, UsbError> {
+        if bytes.len() < 9 || bytes[1] != descriptor::INTERFACE {
+            return Err(UsbError::BadDescriptor);
+        }
+
+        Ok(InterfaceDescriptor {
+            number: bytes[2],
+            alternate_setting: bytes[3],
+            num_endpoints: bytes[4],
+            class: bytes[5],
+            subclass: bytes[6],
+            protocol: bytes[7],
+        })
+    }
+}
+
+/// An endpoint descriptor, found in a configuration's descriptors.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct EndpointDescriptor {
+    pub number: u8,
+    pub direction: Direction,
+    pub kind: EndpointType,
+    pub max_packet_size: u16,
+    /// The polling interval of interrupt endpoints, in frames (or for high
+    /// speed devices, as an exponent of microframes).
+    pub interval: u8,
+}
+
+impl EndpointDescriptor {
+    /// Parses an endpoint descriptor.
+    pub fn parse(bytes: &[u8]) -> Result<EndpointDescriptor, UsbError> {
+        if bytes.len() < 7 || bytes[1] != descriptor::ENDPOINT {
+            return Err(UsbError::BadDescriptor);
+        }
+
+        let kind = match bytes[3] & 0b11 {
+            0 => EndpointType::Control,
+            1 => EndpointType::Isochronous,
+            2 => EndpointType::Bulk,
+            _ => EndpointType::Interrupt
+        };
+
+        Ok(EndpointDescriptor {
+            number: bytes[2] & 0xF,
+            direction: if bytes[2] & 0x80 != 0 { Direction::In } else { Direction::Out },
+            kind,
+            max_packet_size: read_u16(bytes, 4) & 0x7FF,
+            interval: bytes[6],
+        })
+    }
+}
+
+/// An iterator over the descriptors packed in a configuration descriptor,
+/// yielding each descriptor's type and bytes.
+pub struct Descriptors<'a> {
+    bytes: &'a [u8],
+}
+
+impl<'a> Descriptors<'a> {
+    /// Returns an iterator over the descriptors in `bytes`.
+    pub fn new(bytes: &'a [u8]) -> Descriptors<'a> {
+        Descriptors { bytes }
+    }
+}
+
+impl<'a> Iterator for Descriptors<'a> {
+    type Item = (u8, &'a [u8]);
+
+    fn next(&mut self) -> Option<(u8, &'a [u8])> {
+        if self.bytes.len() < 2 {
+            return None;
+        }
+
+        let len = self.bytes[0] as usize;
+        if len < 2 || len > self.bytes.len() {
+            return None;
+        }
+
+        let (descriptor, rest) = self.bytes.split_at(len);
+        self.bytes = rest;
+        Some((descriptor[1], descriptor))
+    }
+}
+
+/// An endpoint of a device, tracking the data toggle between transfers.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct Endpoint {
+    pub number: u8,
+    pub direction: Direction,
+    pub kind: EndpointType,
+    pub max_packet_size: u16,
+    /// Whether the next packet is `DATA1` rather than `DATA0`.
+    pub(crate) toggle: bool,
+}
+
+impl Endpoint {
+    /// Returns an endpoint described by `descriptor`, starting at `DATA0`.
+    pub fn new(descriptor: &EndpointDescriptor) -> Endpoint {
+        Endpoint {
+            number: descriptor.number,
+            direction: descriptor.direction,
+            kind: descriptor.kind,
+            max_packet_size: descriptor.max_packet_size,
+            toggle: false,
+        }
+    }
+
+    /// Resets the data toggle to `DATA0`, as after `SET_CONFIGURATION` or
+    /// clearing a halt.
+    pub fn reset_toggle(&mut self) {
+        self.toggle = false;
+    }
+}
+
+/// The high speed hub port a full or low speed device is attached to. Its
+/// transfers are split transactions, run through the hub's transaction
+/// translator.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct Split {
+    pub hub_address: u8,
+    pub port: u8,
+}
+
+/// An addressed, configured device.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct Device {
+    pub address: u8,
+    pub speed: Speed,
+    /// Where split transactions go, if the device is behind a high speed hub
+    /// but isn't high speed itself.
+    pub split: Option<Split>,
+    pub descriptor: DeviceDescriptor,
+    /// The value of the configuration selected during enumeration.
+    pub configuration: u8,
+}
@@ -0,0 +1,406 @@
+use core::cmp;
+use core::time::Duration;
+
+use mailbox::{self, tag};
+use net::{NetDevice, NetError, MacAddress, MAX_FRAME};
+use timer::{Instant, spin_sleep_ms};
+
+use super::{Host, Device, Endpoint, EndpointDescriptor, Descriptors};
+use super::{SetupPacket, UsbError, Direction, EndpointType, descriptor, request_type};
+
+/// The vendor id of SMSC (now Microchip).
+const VENDOR_SMSC: u16 = 0x0424;
+
+/// The product ids of the LAN9512/LAN9514's Ethernet function and of the
+/// LAN9500.
+const PRODUCTS: [u16; 2] = [0xEC00, 0x9500];
+
+/// Vendor requests reading and writing the chip's registers.
+mod request {
+    pub const WRITE_REGISTER: u8 = 0xA0;
+    pub const READ_REGISTER: u8 = 0xA1;
+}
+
+/// The chip's registers, as addressed by the vendor requests.
+mod reg {
+    pub const TX_CFG: u16 = 0x10;
+    pub const HW_CFG: u16 = 0x14;
+    pub const PM_CTRL: u16 = 0x20;
+    pub const BURST_CAP: u16 = 0x38;
+    pub const MAC_CR: u16 = 0x100;
+    pub const ADDRH: u16 = 0x104;
+    pub const ADDRL: u16 = 0x108;
+    pub const MII_ADDR: u16 = 0x114;
+    pub const MII_DATA: u16 = 0x118;
+}
+
+/// Enum representing bit fields of the `HW_CFG` register.
+#[repr(u32)]
+enum HwCfg {
+    LiteReset = 1 << 3,
+    MultipleFrames = 1 << 5,
+    /// Answer bulk IN requests with a zero length packet rather than a NAK
+    /// when no frame is waiting.
+    BulkInEmptyResponse = 1 << 12,
+}
+
+/// Enum representing bit fields of the `PM_CTRL` register.
+#[repr(u32)]
+enum PmCtrl {
+    PhyReset = 1 << 4,
+}
+
+/// Enum representing bit fields of the `TX_CFG` register.
+#[repr(u32)]
+enum TxCfg {
+    On = 1 << 2,
+}
+
+/// Enum representing bit fields of the `MAC_CR` register.
+#[repr(u32)]
+enum MacCr {
+    RxEnable = 1 << 2,
+    TxEnable = 1 << 3,
+}
+
+/// Enum representing bit fields of the `MII_ADDR` register.
+#[repr(u32)]
+enum MiiAddr {
+    Busy = 1,
+    Write = 1 << 1,
+}
+
+/// The address of the internal PHY on the MII bus.
+const PHY_ID: u32 = 1;
+
+/// PHY registers and their bits.
+mod phy {
+    pub const BMCR: u32 = 0;
+    pub const ADVERTISE: u32 = 4;
+
+    pub const BMCR_RESET: u32 = 1 << 15;
+    pub const BMCR_AUTONEG_ENABLE: u32 = 1 << 12;
+    pub const BMCR_AUTONEG_RESTART: u32 = 1 << 9;
+
+    /// 10 and 100Mbit/s, half and full duplex, pause frames, over IEEE 802.3.
+    pub const ADVERTISE_ALL: u32 = 0x05E1;
+}
+
+/// Bits of the `TX_CMD_A` word prefixed to each transmitted frame.
+const TX_CMD_A_FIRST_SEGMENT: u32 = 1 << 13;
+const TX_CMD_A_LAST_SEGMENT: u32 = 1 << 12;
+
+/// The length of the `TX_CMD_A` and `TX_CMD_B` words before each frame.
+const TX_HEADER: usize = 8;
+
+/// The length of the status word before each received frame.
+const RX_HEADER: usize = 4;
+
+/// Bits of the status word before each received frame.
+const RX_STATUS_ERROR: u32 = 1 << 15;
+const RX_STATUS_LENGTH_SHIFT: u32 = 16;
+const RX_STATUS_LENGTH_MASK: u32 = 0x3FFF;
+
+/// The length of the frame check sequence the chip leaves on received frames.
+const FCS: usize = 4;
+
+/// The size of the buffer bulk IN transfers read into: room for one frame
+/// and its status word, rounded up to a whole number of 512-byte packets.
+const RX_BUFFER: usize = 2048;
+
+/// How long a register, PHY or reset operation may take.
+const TIMEOUT_MS: u64 = 1000;
+
+/// The LAN9512/LAN9514 USB Ethernet controller on the Pi 1B+, 2B and 3B.
+///
+/// The driver holds only the device and its endpoints; every operation takes
+/// the host the device hangs off. `bind()` pairs the two as a `NetDevice`.
+pub struct Smsc95xx {
+    device: Device,
+    bulk_in: Endpoint,
+    bulk_out: Endpoint,
+    mac: MacAddress,
+    /// Bytes received but not yet handed out: frames arrive with their
+    /// status words, possibly several per transfer.
+    rx: [u8; RX_BUFFER],
+    rx_start: usize,
+    rx_end: usize,
+}
+
+impl Smsc95xx {
+    /// Brings up the chip if `device` is one: resets it, programs the MAC
+    /// address the firmware assigned the board, starts autonegotiation, and
+    /// enables the transmitter and receiver. Returns
+    /// `UsbError::Unsupported` if `device` isn't an SMSC95xx.
+    pub fn new(host: &mut Host, device: &Device) -> Result<Smsc95xx, UsbError> {
+        let descriptor = &device.descriptor;
+        if descriptor.vendor_id != VENDOR_SMSC || !PRODUCTS.contains(&descriptor.product_id) {
+            return Err(UsbError::Unsupported);
+        }
+
+        let (bulk_in, bulk_out) = Smsc95xx::endpoints(host, device)?;
+        let mut smsc = Smsc95xx {
+            device: *device,
+            bulk_in, bulk_out,
+            mac: board_mac_address(),
+            rx: [0; RX_BUFFER],
+            rx_start: 0,
+            rx_end: 0,
+        };
+
+        smsc.reset(host)?;
+        Ok(smsc)
+    }
+
+    /// Returns the bulk IN and OUT endpoints of `device`.
+    fn endpoints(host: &mut Host, device: &Device) -> Result<(Endpoint, Endpoint), UsbError> {
+        let mut bytes = [0; 256];
+        let len = host.configuration_descriptor(device, &mut bytes)?;
+
+        let (mut bulk_in, mut bulk_out) = (None, None);
+        for (kind, bytes) in Descriptors::new(&bytes[..len]) {
+            if kind != descriptor::ENDPOINT {
+                continue;
+            }
+
+            let desc = EndpointDescriptor::parse(bytes)?;
+            match (desc.kind, desc.direction) {
+                (EndpointType::Bulk, Direction::In) => bulk_in = Some(Endpoint::new(&desc)),
+                (EndpointType::Bulk, Direction::Out) => bulk_out = Some(Endpoint::new(&desc)),
+                _ => {  }
+            }
+        }
+
+        match (bulk_in, bulk_out) {
+            (Some(bulk_in), Some(bulk_out)) => Ok((bulk_in, bulk_out)),
+            _ => Err(UsbError::Unsupported)
+        }
+    }
+
+    /// Returns the device the controller is.
+    pub fn device(&self) -> &Device {
+        &self.device
+    }
+
+    /// Returns the controller's MAC address.
+    pub fn mac_address(&self) -> MacAddress {
+        self.mac
+    }
+
+    /// Pairs the controller with `host` as a `NetDevice`.
+    pub fn bind<'a>(&'a mut self, host: &'a mut Host) -> Bound<'a> {
+        Bound { smsc: self, host }
+    }
+
+    /// Reads the register `index`.
+    fn read_register(&self, host: &mut Host, index: u16) -> Result<u32, UsbError> {
+        let mut bytes = [0; 4];
+        let setup = SetupPacket {
+            request_type: request_type::IN | request_type::VENDOR | request_type::DEVICE,
+            request: request::READ_REGISTER,
+            value: 0,
+            index,
+            length: bytes.len() as u16,
+        };
+
+        host.control(&self.device, setup, &mut bytes)?;
+        Ok(read_u32(&bytes, 0))
+    }
+
+    /// Writes `value` to the register `index`.
+    fn write_register(&self, host: &mut Host, index: u16, value: u32) -> Result<(), UsbError> {
+        let mut bytes = [value as u8, (value >> 8) as u8, (value >> 16) as u8, (value >> 24) as u8];
+        let setup = SetupPacket {
+            request_type: request_type::VENDOR | request_type::DEVICE,
+            request: request::WRITE_REGISTER,
+            value: 0,
+            index,
+            length: bytes.len() as u16,
+        };
+
+        host.control(&self.device, setup, &mut bytes).map(|_| ())
+    }
+
+    /// Sets the bits of `mask` in the register `index`, then waits for the
+    /// chip to clear them again.
+    fn self_clearing(&self, host: &mut Host, index: u16, mask: u32) -> Result<(), UsbError> {
+        let value = self.read_register(host, index)?;
+        self.write_register(host, index, value | mask)?;
+
+        let deadline = Instant::now() + Duration::from_millis(TIMEOUT_MS);
+        while self.read_register(host, index)? & mask != 0 {
+            if Instant::now() > deadline {
+                return Err(UsbError::TimedOut);
+            }
+        }
+
+        Ok(())
+    }
+
+    /// Writes `value` to the PHY register `index` over the MII bus.
+    fn write_phy(&self, host: &mut Host, index: u32, value: u32) -> Result<(), UsbError> {
+        self.wait_mii(host)?;
+        self.write_register(host, reg::MII_DATA, value)?;
+        self.write_register(host, reg::MII_ADDR, PHY_ID << 11 | index << 6
+                            | MiiAddr::Write as u32 | MiiAddr::Busy as u32)?;
+        self.wait_mii(host)
+    }
+
+    /// Reads the PHY register `index` over the MII bus.
+    fn read_phy(&self, host: &mut Host, index: u32) -> Result<u32, UsbError> {
+        self.wait_mii(host)?;
+        self.write_register(host, reg::MII_ADDR, PHY_ID << 11 | index << 6 | MiiAddr::Busy as u32)?;
+        self.wait_mii(host)?;
+        Ok(self.read_register(host, reg::MII_DATA)? & 0xFFFF)
+    }
+
+    /// Waits for the MII bus to finish the last PHY access.
+    fn wait_mii(&self, host: &mut Host) -> Result<(), UsbError> {
+        let deadline = Instant::now() + Duration::from_millis(TIMEOUT_MS);
+        while self.read_register(host, reg::MII_ADDR)? & MiiAddr::Busy as u32 != 0 {
+            if Instant::now() > deadline {
+                return Err(UsbError::TimedOut);
+            }
+        }
+
+        Ok(())
+    }
+
+    /// Resets the chip and its PHY and brings the link up.
+    fn reset(&mut self, host: &mut Host) -> Result<(), UsbError> {
+        self.self_clearing(host, reg::HW_CFG, HwCfg::LiteReset as u32)?;
+        self.self_clearing(host, reg::PM_CTRL, PmCtrl::PhyReset as u32)?;
+
+        let mac = self.mac.0;
+        self.write_register(host, reg::ADDRL, mac[0] as u32 | (mac[1] as u32) << 8
+                            | (mac[2] as u32) << 16 | (mac[3] as u32) << 24)?;
+        self.write_register(host, reg::ADDRH, mac[4] as u32 | (mac[5] as u32) << 8)?;
+
+        // One frame per bulk transfer, and no NAKs when none are waiting, so
+        // polling for frames doesn't wait out a timeout.
+        let hw_cfg = self.read_register(host, reg::HW_CFG)?;
+        self.write_register(host, reg::HW_CFG, (hw_cfg & !(HwCfg::MultipleFrames as u32))
+                            | HwCfg::BulkInEmptyResponse as u32)?;
+        self.write_register(host, reg::BURST_CAP, 0)?;
+
+        self.write_phy(host, phy::BMCR, phy::BMCR_RESET)?;
+        let deadline = Instant::now() + Duration::from_millis(TIMEOUT_MS);
+        while self.read_phy(host, phy::BMCR)? & phy::BMCR_RESET != 0 {
+            if Instant::now() > deadline {
+                return Err(UsbError::TimedOut);
+            }
+
+            spin_sleep_ms(1);
+        }
+
+        self.write_phy(host, phy::ADVERTISE, phy::ADVERTISE_ALL)?;
+        self.write_phy(host, phy::BMCR, phy::BMCR_AUTONEG_ENABLE | phy::BMCR_AUTONEG_RESTART)?;
+
+        let mac_cr = self.read_register(host, reg::MAC_CR)?;
+        self.write_register(host, reg::MAC_CR, mac_cr | MacCr::TxEnable as u32 | MacCr::RxEnable as u32)?;
+        self.write_register(host, reg::TX_CFG, TxCfg::On as u32)
+    }
+
+    /// Sends the Ethernet frame `frame`, without its frame check sequence.
+    pub fn send_frame(&mut self, host: &mut Host, frame: &[u8]) -> Result<(), NetError> {
+        if frame.len() > MAX_FRAME {
+            return Err(NetError::FrameTooLarge);
+        }
+
+        let mut packet = [0; TX_HEADER + MAX_FRAME];
+        let len = frame.len() as u32;
+        let command_a = len | TX_CMD_A_FIRST_SEGMENT | TX_CMD_A_LAST_SEGMENT;
+        for i in 0..4 {
+            packet[i] = (command_a >> (8 * i)) as u8;
+            packet[4 + i] = (len >> (8 * i)) as u8;
+        }
+
+        packet[TX_HEADER..TX_HEADER + frame.len()].copy_from_slice(frame);
+
+        let device = self.device;
+        host.transfer(&device, &mut self.bulk_out, &mut packet[..TX_HEADER + frame.len()])?;
+        Ok(())
+    }
+
+    /// Reads the next received Ethernet frame, without its frame check
+    /// sequence, into `buf`. Returns its length, or `None` if no frame has
+    /// arrived. A frame that doesn't fit in `buf` is dropped.
+    pub fn recv_frame(&mut self, host: &mut Host, buf: &mut [u8]) -> Result<Option<usize>, NetError> {
+        if self.rx_end - self.rx_start < RX_HEADER {
+            let device = self.device;
+            let received = match host.transfer(&device, &mut self.bulk_in, &mut self.rx) {
+                Ok(received) => received,
+                Err(UsbError::Nak) => 0,
+                Err(e) => return Err(e.into())
+            };
+
+            self.rx_start = 0;
+            self.rx_end = received;
+            if received < RX_HEADER {
+                return Ok(None);
+            }
+        }
+
+        let start = self.rx_start;
+        let status = read_u32(&self.rx, start);
+        let len = ((status >> RX_STATUS_LENGTH_SHIFT) & RX_STATUS_LENGTH_MASK) as usize;
+
+        // Frames are padded to a whole number of words.
+        let frame = start + RX_HEADER;
+        let end = cmp::min(frame + len, self.rx_end);
+        self.rx_start = cmp::min((end + 3) & !3, self.rx_end);
+
+        if status & RX_STATUS_ERROR != 0 || len < FCS || end - frame < len {
+            return Err(NetError::BadFrame);
+        }
+
+        let len = len - FCS;
+        if len > buf.len() {
+            return Err(NetError::FrameTooLarge);
+        }
+
+        buf[..len].copy_from_slice(&self.rx[frame..frame + len]);
+        Ok(Some(len))
+    }
+}
+
+/// Reads the little-endian `u32` at `bytes[i..i + 4]`.
+fn read_u32(bytes: &[u8], i: usize) -> u32 {
+    bytes[i] as u32 | (bytes[i + 1] as u32) << 8 | (bytes[i + 2] as u32) << 16 | (bytes[i + 3] as u32) << 24
+}
+
+/// Returns the MAC address the firmware assigned the board, or a locally
+/// administered address if it won't say.
+fn board_mac_address() -> MacAddress {
+    let mut values = [0; 2];
+    if mailbox::call_property(tag::GET_MAC_ADDRESS, &mut values).is_err() {
+        return MacAddress([0x02, 0, 0, 0, 0, 1]);
+    }
+
+    let mut mac = [0; 6];
+    for (i, byte) in mac.iter_mut().enumerate() {
+        *byte = (values[i / 4] >> (8 * (i % 4))) as u8;
+    }
+
+    MacAddress(mac)
+}
+
+/// An `Smsc95xx` paired with the host it hangs off, usable as a `NetDevice`.
+pub struct Bound<'a> {
+    smsc: &'a mut Smsc95xx,
+    host: &'a mut Host,
+}
+
+impl<'a> NetDevice for Bound<'a> {
+    fn mac_address(&self) -> MacAddress {
+        self.smsc.mac
+    }
+
+    fn send_frame(&mut self, frame: &[u8]) -> Result<(), NetError> {
+        self.smsc.send_frame(self.host, frame)
+    }
+
+    fn recv_frame(&mut self, buf: &mut [u8]) -> Result<Option<usize>, NetError> {
+        self.smsc.recv_frame(self.host, buf)
+    }
+}
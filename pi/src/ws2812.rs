@@ -0,0 +1,136 @@
+use spi::{Mode, Spi, SpiConfig};
+
+/// The SCLK frequency: three SPI bits per 1.25us WS2812 bit.
+const SPI_FREQUENCY: u32 = 2_400_000;
+
+/// The SPI bits encoding a 1 and a 0: high for two thirds of the bit, or for
+/// one third.
+const ONE: u32 = 0b110;
+const ZERO: u32 = 0b100;
+
+/// The SPI bytes encoding one color byte.
+const BYTES_PER_COLOR: usize = 3;
+
+/// The SPI bytes encoding one pixel.
+const BYTES_PER_PIXEL: usize = 3 * BYTES_PER_COLOR;
+
+/// The zero bytes sent after the pixels, holding the line low for the 300us
+/// that newer parts need to latch them; older ones need only 50us.
+const RESET_BYTES: usize = 300 * SPI_FREQUENCY as usize / 8 / 1_000_000;
+
+/// The longest strip that can be driven.
+pub const MAX_PIXELS: usize = 256;
+
+/// Gamma correction for a gamma of 2.8, mapping a linear intensity to the
+/// PWM duty the LEDs need to look that bright.
+const GAMMA: [u8; 256] = [
+      0,   0,   0,   0,   0,   0,   0,   0,   0,   0,   0,   0,   0,   0,   0,   0,
+      0,   0,   0,   0,   0,   0,   0,   0,   0,   0,   0,   0,   1,   1,   1,   1,
+      1,   1,   1,   1,   1,   1,   1,   1,   1,   2,   2,   2,   2,   2,   2,   2,
+      2,   3,   3,   3,   3,   3,   3,   3,   4,   4,   4,   4,   4,   5,   5,   5,
+      5,   6,   6,   6,   6,   7,   7,   7,   7,   8,   8,   8,   9,   9,   9,  10,
+     10,  10,  11,  11,  11,  12,  12,  13,  13,  13,  14,  14,  15,  15,  16,  16,
+     17,  17,  18,  18,  19,  19,  20,  20,  21,  21,  22,  22,  23,  24,  24,  25,
+     25,  26,  27,  27,  28,  29,  29,  30,  31,  32,  32,  33,  34,  35,  35,  36,
+     37,  38,  39,  39,  40,  41,  42,  43,  44,  45,  46,  47,  48,  49,  50,  50,
+     51,  52,  54,  55,  56,  57,  58,  59,  60,  61,  62,  63,  64,  66,  67,  68,
+     69,  70,  72,  73,  74,  75,  77,  78,  79,  81,  82,  83,  85,  86,  87,  89,
+     90,  92,  93,  95,  96,  98,  99, 101, 102, 104, 105, 107, 109, 110, 112, 114,
+    115, 117, 119, 120, 122, 124, 126, 127, 129, 131, 133, 135, 137, 138, 140, 142,
+    144, 146, 148, 150, 152, 154, 156, 158, 160, 162, 164, 167, 169, 171, 173, 175,
+    177, 180, 182, 184, 186, 189, 191, 193, 196, 198, 200, 203, 205, 208, 210, 213,
+    215, 218, 220, 223, 225, 228, 231, 233, 236, 239, 241, 244, 247, 249, 252, 255,
+];
+
+/// Returns the gamma corrected duty for the linear intensity `value`.
+pub fn gamma(value: u8) -> u8 {
+    GAMMA[value as usize]
+}
+
+/// A pixel's color, as linear 8-bit intensities.
+#[derive(Debug, Default, Clone, Copy, PartialEq, Eq)]
+pub struct Rgb {
+    pub r: u8,
+    pub g: u8,
+    pub b: u8,
+}
+
+impl Rgb {
+    pub const BLACK: Rgb = Rgb { r: 0, g: 0, b: 0 };
+    pub const WHITE: Rgb = Rgb { r: 255, g: 255, b: 255 };
+
+    /// Returns the color with intensities `r`, `g` and `b`.
+    pub const fn new(r: u8, g: u8, b: u8) -> Rgb {
+        Rgb { r, g, b }
+    }
+}
+
+/// Writes the SPI bits encoding `byte`, most significant bit first, into
+/// `out`.
+fn encode(byte: u8, out: &mut [u8]) {
+    let mut bits = 0u32;
+    for i in (0..8).rev() {
+        bits = bits << 3 | if byte & (1 << i) != 0 { ONE } else { ZERO };
+    }
+
+    out[0] = (bits >> 16) as u8;
+    out[1] = (bits >> 8) as u8;
+    out[2] = bits as u8;
+}
+
+/// A strip of WS2812 (NeoPixel) LEDs driven from `SPI0`'s MOSI, GPIO 10.
+///
+/// Each bit of the 800kHz waveform is three SPI bits, so a frame is sent
+/// from the SPI FIFO with no timing done by the CPU. The FIFO is fed by
+/// polling though, and an interrupt that stalls it for more than 50us cuts
+/// the frame short: mask interrupts around `set_pixels()` on long strips.
+pub struct Ws2812 {
+    spi: Spi,
+    gamma: bool,
+    buffer: [u8; MAX_PIXELS * BYTES_PER_PIXEL + RESET_BYTES],
+}
+
+impl Ws2812 {
+    /// Returns a driver for the strip whose data input is on GPIO 10, taking
+    /// over `SPI0`. Colors are gamma corrected.
+    pub fn new() -> Ws2812 {
+        let spi = Spi::with_config(SpiConfig { frequency: SPI_FREQUENCY, mode: Mode::Mode0 });
+        Ws2812 {
+            spi,
+            gamma: true,
+            buffer: [0; MAX_PIXELS * BYTES_PER_PIXEL + RESET_BYTES],
+        }
+    }
+
+    /// Enables or disables gamma correction of the colors sent.
+    pub fn set_gamma_correction(&mut self, enabled: bool) {
+        self.gamma = enabled;
+    }
+
+    /// Sets the first `pixels.len()` LEDs of the strip to the colors in
+    /// `pixels`, in order from the data input. The rest keep their colors.
+    ///
+    /// # Panics
+    ///
+    /// Panics if `pixels` is longer than `MAX_PIXELS`.
+    pub fn set_pixels(&mut self, pixels: &[Rgb]) {
+        if pixels.len() > MAX_PIXELS {
+            panic!("Ws2812: {} pixels is more than {}", pixels.len(), MAX_PIXELS);
+        }
+
+        for (pixel, out) in pixels.iter().zip(self.buffer.chunks_mut(BYTES_PER_PIXEL)) {
+            // The LEDs take green first.
+            let colors = [pixel.g, pixel.r, pixel.b];
+            for (&color, out) in colors.iter().zip(out.chunks_mut(BYTES_PER_COLOR)) {
+                encode(if self.gamma { gamma(color) } else { color }, out);
+            }
+        }
+
+        let len = pixels.len() * BYTES_PER_PIXEL;
+        for byte in self.buffer[len..len + RESET_BYTES].iter_mut() {
+            *byte = 0;
+        }
+
+        self.spi.write(&self.buffer[..len + RESET_BYTES]);
+    }
+}
@@ -0,0 +1,347 @@
+use std::io;
+use std::str;
+
+use crc::crc16;
+
+/// Protocol bytes.
+mod byte {
+    pub const SOH: u8 = 0x01;
+    pub const STX: u8 = 0x02;
+    pub const EOT: u8 = 0x04;
+    pub const ACK: u8 = 0x06;
+    pub const NAK: u8 = 0x15;
+    pub const CAN: u8 = 0x18;
+    /// Sent in place of `NAK` to ask for the next header or file with
+    /// CRC-16 packets.
+    pub const CRC: u8 = b'C';
+}
+
+/// One in this many requests to start that go unanswered asks for
+/// checksummed packets rather than CRC-16 ones, for XMODEM senders that
+/// only checksum.
+const CHECKSUM_EVERY: usize = 4;
+
+/// What senders pad the last packet of a file with.
+pub const PAD: u8 = 0x1A;
+
+/// The payload sizes of `SOH` and `STX` packets.
+const SHORT_PACKET: usize = 128;
+const LONG_PACKET: usize = 1024;
+
+/// The longest file name kept from a header.
+pub const MAX_NAME: usize = 64;
+
+/// How many bad or missing packets in a row end a transfer.
+const MAX_RETRIES: usize = 10;
+
+/// A file's header block: its name, and its exact size if the sender gave
+/// one.
+#[derive(Clone, Copy)]
+pub struct Header {
+    name: [u8; MAX_NAME],
+    name_len: usize,
+    pub size: Option<usize>,
+}
+
+impl Header {
+    /// Parses the payload of block 0: the name and, optionally, the size in
+    /// decimal followed by other fields, each ending in a NUL or space.
+    fn parse(block: &[u8]) -> Header {
+        let name_len = block.iter().position(|&b| b == 0).unwrap_or(block.len());
+        let fields = block.get(name_len + 1..).unwrap_or(&[]);
+        let size_len = fields.iter().position(|&b| b == 0 || b == b' ').unwrap_or(fields.len());
+        let size = str::from_utf8(&fields[..size_len]).ok().and_then(|s| s.parse().ok());
+
+        let mut header = Header { name: [0; MAX_NAME], name_len: 0, size };
+        header.name_len = ::std::cmp::min(name_len, MAX_NAME);
+        header.name[..header.name_len].copy_from_slice(&block[..header.name_len]);
+        header
+    }
+
+    /// Returns the file's name, or `"?"` if it isn't UTF-8.
+    pub fn name(&self) -> &str {
+        str::from_utf8(&self.name[..self.name_len]).unwrap_or("?")
+    }
+}
+
+/// A packet received.
+enum Packet {
+    /// A block numbered `seq` whose `len` bytes are in the buffer.
+    Block { seq: u8, len: usize },
+    /// An `EOT`, ending a file.
+    End,
+}
+
+/// Returns the arithmetic checksum of `data`: the sum of its bytes.
+fn checksum(data: &[u8]) -> u8 {
+    data.iter().fold(0, |sum, &byte| sum.wrapping_add(byte))
+}
+
+/// The receiving side of a YMODEM batch transfer: files are announced by a
+/// header block carrying their name and size, and sent in 128 or 1024 byte
+/// blocks with CRC-16 checks.
+///
+/// Plain XMODEM senders are understood too. One that sends the first block
+/// of data straight away sends a single file, announced as having no name
+/// or size. One that doesn't answer requests for CRC-16 packets is asked
+/// for packets with the arithmetic checksum instead.
+///
+/// `next_file()` and `receive_file()` are called in turn for each file,
+/// until `next_file()` finds the batch's end.
+pub struct Ymodem<R> {
+    inner: R,
+    buffer: [u8; LONG_PACKET],
+    /// Whether the sender has answered, after which timeouts are retried.
+    started: bool,
+    /// How many requests to start have gone unanswered.
+    unanswered: usize,
+    /// Whether packets carry a CRC-16 rather than a checksum.
+    crc: bool,
+    /// Whether the sender sends headers, rather than one file without any.
+    batch: bool,
+    /// The header of the file `receive_file()` takes next.
+    pending: Option<Header>,
+    /// The file's first packet, its block already in the buffer, if the
+    /// sender sent it without a header.
+    first_packet: Option<Packet>,
+}
+
+fn invalid(msg: &'static str) -> io::Error {
+    io::Error::new(io::ErrorKind::InvalidData, msg)
+}
+
+impl<R: io::Read + io::Write> Ymodem<R> {
+    /// Returns a receiver talking over `inner`, whose reads should time out
+    /// after about a second so that lost packets are asked for again.
+    pub fn new(inner: R) -> Ymodem<R> {
+        Ymodem::with_unanswered(inner, 0)
+    }
+
+    /// Like `new()`, but as if `unanswered` requests to start had already
+    /// gone unanswered. A caller that makes a new receiver for each wait
+    /// passes on the last one's `unanswered()` so the requests keep
+    /// alternating between CRC-16 and checksummed packets.
+    pub fn with_unanswered(inner: R, unanswered: usize) -> Ymodem<R> {
+        Ymodem {
+            inner,
+            buffer: [0; LONG_PACKET],
+            started: false,
+            unanswered,
+            crc: true,
+            batch: true,
+            pending: None,
+            first_packet: None,
+        }
+    }
+
+    /// Returns how many requests to start have gone unanswered.
+    pub fn unanswered(&self) -> usize {
+        self.unanswered
+    }
+
+    fn read_byte(&mut self) -> io::Result<u8> {
+        let mut byte = [0];
+        self.inner.read_exact(&mut byte)?;
+        Ok(byte[0])
+    }
+
+    fn write_byte(&mut self, byte: u8) -> io::Result<()> {
+        self.inner.write_all(&[byte])
+    }
+
+    /// Tells the sender the transfer is cancelled, after which it sends
+    /// nothing more.
+    pub fn cancel(&mut self) {
+        let _ = self.inner.write_all(&[byte::CAN, byte::CAN]);
+    }
+
+    /// Reads and discards bytes until the line goes quiet, so a retry starts
+    /// at a packet boundary.
+    fn purge(&mut self) {
+        while self.read_byte().is_ok() {  }
+    }
+
+    /// Reads one packet.
+    fn read_packet(&mut self) -> io::Result<Packet> {
+        let len = match self.read_byte()? {
+            byte::SOH => SHORT_PACKET,
+            byte::STX => LONG_PACKET,
+            byte::EOT => return Ok(Packet::End),
+            byte::CAN => {
+                return Err(io::Error::new(io::ErrorKind::ConnectionAborted,
+                                          "Transfer cancelled by sender."))
+            }
+            _ => return Err(invalid("Expected a packet."))
+        };
+
+        let (seq, seq_check) = (self.read_byte()?, self.read_byte()?);
+        self.inner.read_exact(&mut self.buffer[..len])?;
+        let intact = if self.crc {
+            let crc = (self.read_byte()? as u16) << 8 | self.read_byte()? as u16;
+            crc == crc16(&self.buffer[..len])
+        } else {
+            self.read_byte()? == checksum(&self.buffer[..len])
+        };
+
+        if seq != !seq_check || !intact {
+            return Err(invalid("Corrupt packet."));
+        }
+
+        Ok(Packet::Block { seq, len })
+    }
+
+    /// Returns what asks the sender for a header or a file's first block:
+    /// `C` for CRC-16 packets, `NAK` for checksummed ones.
+    fn request(&self) -> u8 {
+        if self.crc { byte::CRC } else { byte::NAK }
+    }
+
+    /// Sends `reply` and reads the packet it asks for. Corrupt or missing
+    /// packets are asked for again, with `NAK`, up to `MAX_RETRIES` times,
+    /// except that a timeout before the sender has answered at all is
+    /// returned straight away.
+    fn exchange(&mut self, mut reply: u8) -> io::Result<Packet> {
+        for _ in 0..MAX_RETRIES {
+            self.write_byte(reply)?;
+            let error = match self.read_packet() {
+                Ok(packet) => {
+                    self.started = true;
+                    return Ok(packet);
+                }
+                Err(error) => error
+            };
+
+            match error.kind() {
+                io::ErrorKind::TimedOut if !self.started => {
+                    self.unanswered += 1;
+                    return Err(error);
+                }
+                io::ErrorKind::TimedOut => {  }
+                io::ErrorKind::InvalidData => self.purge(),
+                _ => return Err(error)
+            }
+
+            if reply != byte::CRC {
+                reply = byte::NAK;
+            }
+        }
+
+        self.cancel();
+        Err(io::Error::new(io::ErrorKind::TimedOut, "Too many retries."))
+    }
+
+    /// Waits for the next file's header and returns it, or `None` at the end
+    /// of the batch.
+    ///
+    /// # Errors
+    ///
+    /// Returns an error of kind `TimedOut` if the sender hasn't started the
+    /// transfer yet, in which case this method can be called again to keep
+    /// waiting.
+    pub fn next_file(&mut self) -> io::Result<Option<Header>> {
+        if !self.batch {
+            // An XMODEM sender's one file has been received.
+            self.batch = true;
+            self.started = false;
+            return Ok(None);
+        }
+
+        if !self.started {
+            self.crc = self.unanswered % CHECKSUM_EVERY != CHECKSUM_EVERY - 1;
+        }
+
+        let request = self.request();
+        let len = match self.exchange(request)? {
+            Packet::Block { seq: 0, len } => len,
+            // An XMODEM sender's first block, or its `EOT` for no data.
+            packet @ Packet::Block { seq: 1, .. } | packet @ Packet::End => {
+                self.batch = false;
+                self.first_packet = Some(packet);
+                let header = Header::parse(&[]);
+                self.pending = Some(header);
+                return Ok(Some(header));
+            }
+            _ => {
+                self.cancel();
+                return Err(invalid("Expected a header block."));
+            }
+        };
+
+        self.write_byte(byte::ACK)?;
+        if self.buffer[0] == 0 {
+            // An empty name ends the batch.
+            self.started = false;
+            return Ok(None);
+        }
+
+        let header = Header::parse(&self.buffer[..len]);
+        self.pending = Some(header);
+        Ok(Some(header))
+    }
+
+    /// Receives the file whose header `next_file()` just returned into
+    /// `output`, and returns its length: the size given in the header, or
+    /// every byte received, padding included, if there wasn't one.
+    pub fn receive_file(&mut self, output: &mut [u8]) -> io::Result<usize> {
+        let header = match self.pending.take() {
+            Some(header) => header,
+            None => return Err(io::Error::new(io::ErrorKind::Other, "No file announced."))
+        };
+
+        let mut expected = 1u8;
+        let mut received = 0;
+        let mut packet = match self.first_packet.take() {
+            Some(packet) => packet,
+            None => {
+                let request = self.request();
+                self.exchange(request)?
+            }
+        };
+        loop {
+            match packet {
+                Packet::Block { seq, len } if seq == expected => {
+                    let len = match header.size {
+                        Some(size) => ::std::cmp::min(len, size.saturating_sub(received)),
+                        None => len
+                    };
+
+                    if received + len > output.len() {
+                        self.cancel();
+                        return Err(io::Error::new(io::ErrorKind::WriteZero, "File too large."));
+                    }
+
+                    output[received..received + len].copy_from_slice(&self.buffer[..len]);
+                    received += len;
+                    expected = expected.wrapping_add(1);
+                    packet = self.exchange(byte::ACK)?;
+                }
+                // Our acknowledgement of the header was lost: the sender
+                // waits for the request for data again.
+                Packet::Block { seq: 0, .. } if expected == 1 => {
+                    self.write_byte(byte::ACK)?;
+                    let request = self.request();
+                    packet = self.exchange(request)?;
+                }
+                // Our acknowledgement of the last block was lost.
+                Packet::Block { seq, .. } if seq == expected.wrapping_sub(1) => {
+                    packet = self.exchange(byte::ACK)?;
+                }
+                Packet::Block { .. } => {
+                    self.cancel();
+                    return Err(invalid("Packet out of sequence."));
+                }
+                Packet::End => {
+                    // Senders repeat the first EOT, which guards against
+                    // noise ending the file.
+                    match self.exchange(byte::NAK)? {
+                        Packet::End => {
+                            self.write_byte(byte::ACK)?;
+                            return Ok(received);
+                        }
+                        other => packet = other
+                    }
+                }
+            }
+        }
+    }
+}